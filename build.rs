@@ -0,0 +1,88 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Generate the gRPC service, with a vendored protoc so none needs to be installed
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/pistachio.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("protoc is vendored for this platform");
+        std::env::set_var("PROTOC", protoc);
+        tonic_prost_build::compile_protos("proto/pistachio.proto").expect("proto/pistachio.proto is valid");
+    }
+
+    // Embed build metadata shown by --version and exported as pistachio_build_info
+    println!("cargo:rustc-env=PISTACHIO_GIT_COMMIT={}", git_commit());
+    println!("cargo:rustc-env=PISTACHIO_BUILD_DATE={}", build_date());
+    println!("cargo:rustc-env=PISTACHIO_FEATURES={}", features());
+    println!("cargo:rustc-env=PISTACHIO_RUPS_VERSION={}", rups_version());
+    println!("cargo:rerun-if-env-changed=PISTACHIO_GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+}
+
+/// Returns the abbreviated commit being built, which can be given in `PISTACHIO_GIT_COMMIT` when
+/// building outside of a git checkout, such as in a container.
+fn git_commit() -> String {
+    if let Ok(commit) = env::var("PISTACHIO_GIT_COMMIT") {
+        return commit;
+    }
+    Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map_or_else(|| String::from("unknown"), |commit| commit.trim().to_string())
+}
+
+/// Returns the UTC date of the build, or of `SOURCE_DATE_EPOCH` for reproducible builds.
+fn build_date() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+    let days = i64::try_from(secs / 86400).unwrap_or(i64::MAX);
+
+    // Convert days since the epoch to a civil date
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Returns the enabled cargo features, sorted and separated by commas.
+fn features() -> String {
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| Some(name.strip_prefix("CARGO_FEATURE_")?.to_ascii_lowercase().replace('_', "-")))
+        .filter(|feature| feature != "default")
+        .collect();
+    features.sort();
+    features.join(",")
+}
+
+/// Returns the version of rups locked in `Cargo.lock`.
+fn rups_version() -> String {
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap_or_default()).join("Cargo.lock");
+    let lock = fs::read_to_string(lock).unwrap_or_default();
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line == "name = \"rups\"" {
+            if let Some(version) = lines.next().and_then(|line| line.strip_prefix("version = \"")) {
+                return version.trim_end_matches('"').to_string();
+            }
+        }
+    }
+    String::from("unknown")
+}
@@ -0,0 +1,290 @@
+//! Simple threshold alerts evaluated on every poll, for deployments without Alertmanager.
+//!
+//! A rule is written as `NAME=VARIABLE OPERATOR VALUE [for SECONDS]`, such as
+//! `low_charge=battery.charge < 20` or `on_battery=ups.status contains OB for 60`. The operators
+//! `<`, `<=`, `>` and `>=` compare numbers, `==` and `!=` compare numbers or text, and `contains`
+//! matches a whole word, such as a flag of `ups.status`.
+
+use crate::events::Event;
+use crate::sink::Sink;
+use crate::{Error, Variable};
+use log::debug;
+use prometheus::{register_gauge_vec_with_registry, GaugeVec, Registry};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+/// How a rule compares a variable with its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// The variable is a number less than the value.
+    Less,
+    /// The variable is a number less than or equal to the value.
+    LessOrEqual,
+    /// The variable is a number greater than the value.
+    Greater,
+    /// The variable is a number greater than or equal to the value.
+    GreaterOrEqual,
+    /// The variable equals the value, as numbers if both are numbers.
+    Equal,
+    /// The variable does not equal the value, as numbers if both are numbers.
+    NotEqual,
+    /// The value is one of the space separated words of the variable.
+    Contains,
+}
+
+impl Operator {
+    const ALL: &'static [(Operator, &'static str)] = &[
+        (Operator::LessOrEqual, "<="),
+        (Operator::GreaterOrEqual, ">="),
+        (Operator::Equal, "=="),
+        (Operator::NotEqual, "!="),
+        (Operator::Less, "<"),
+        (Operator::Greater, ">"),
+        (Operator::Contains, "contains"),
+    ];
+
+    /// Returns the operator as written in a rule.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        Operator::ALL
+            .iter()
+            .find(|(operator, _)| *operator == self)
+            .map_or("", |(_, name)| name)
+    }
+
+    /// Compares the value of a variable with the value of a rule.
+    fn matches(self, actual: &str, expected: &str) -> bool {
+        let numbers = actual.trim().parse::<f64>().ok().zip(expected.parse::<f64>().ok());
+        match (self, numbers) {
+            (Operator::Less, Some((actual, expected))) => actual < expected,
+            (Operator::LessOrEqual, Some((actual, expected))) => actual <= expected,
+            (Operator::Greater, Some((actual, expected))) => actual > expected,
+            (Operator::GreaterOrEqual, Some((actual, expected))) => actual >= expected,
+            (Operator::Equal, Some((actual, expected))) => actual == expected,
+            (Operator::NotEqual, Some((actual, expected))) => actual != expected,
+            (Operator::Equal, None) => actual == expected,
+            (Operator::NotEqual, None) => actual != expected,
+            (Operator::Contains, _) => actual.split_whitespace().any(|word| word == expected),
+            _ => false,
+        }
+    }
+}
+
+/// A threshold rule, which raises an alert while the condition holds for long enough.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AlertRule {
+    /// Name of the alert.
+    pub name: String,
+    /// Name of the variable compared.
+    pub var: String,
+    /// How the variable is compared.
+    pub operator: Operator,
+    /// Value the variable is compared with.
+    pub value: String,
+    /// How long the condition must hold before the alert is raised.
+    pub duration: Duration,
+}
+
+impl FromStr for AlertRule {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<AlertRule, Error> {
+        let invalid = || Error::Parse(format!("expected NAME=VARIABLE OPERATOR VALUE [for SECONDS], got `{input}`"));
+        let (name, condition) = input.split_once('=').ok_or_else(invalid)?;
+        let words: Vec<&str> = condition.split_whitespace().collect();
+        let (condition, duration) = match words[..] {
+            [.., "for", seconds] => {
+                let seconds: u64 = seconds.trim_end_matches('s').parse().map_err(|_| invalid())?;
+                (&words[..words.len() - 2], Duration::from_secs(seconds))
+            }
+            _ => (&words[..], Duration::ZERO),
+        };
+        let [var, operator, value] = condition[..] else {
+            return Err(invalid());
+        };
+        let operator = Operator::ALL
+            .iter()
+            .find(|(_, name)| *name == operator)
+            .map(|(operator, _)| *operator)
+            .ok_or_else(invalid)?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        Ok(AlertRule {
+            name: name.to_string(),
+            var: var.to_string(),
+            operator,
+            value: value.to_string(),
+            duration,
+        })
+    }
+}
+
+impl TryFrom<String> for AlertRule {
+    type Error = Error;
+
+    fn try_from(input: String) -> Result<AlertRule, Error> {
+        input.parse()
+    }
+}
+
+impl From<AlertRule> for String {
+    fn from(rule: AlertRule) -> String {
+        rule.to_string()
+    }
+}
+
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={} {} {}", self.name, self.var, self.operator.as_str(), self.value)?;
+        if !self.duration.is_zero() {
+            write!(f, " for {}", self.duration.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// The state of one rule between polls.
+#[derive(Debug, Default)]
+struct RuleState {
+    since: Option<Instant>,
+    active: bool,
+}
+
+/// A sink that evaluates alert rules on every poll, exports whether each alert is active as
+/// `pistachio_alert_active`, and sends [`Event::AlertRaised`] and [`Event::AlertResolved`]
+/// events when an alert starts and stops.
+#[derive(Debug)]
+pub struct Alerts {
+    rules: Vec<AlertRule>,
+    states: HashMap<String, RuleState>,
+    events: Option<Sender<Event>>,
+    gauge: GaugeVec,
+}
+
+impl Alerts {
+    /// Registers the alert gauge, with every alert inactive. Events are sent to `events` if
+    /// given, such as the external events channel of [`crate::monitor_with_events`].
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the gauge cannot be registered with Prometheus.
+    pub fn new(rules: Vec<AlertRule>, events: Option<Sender<Event>>, registry: &Registry) -> crate::Result<Alerts> {
+        let gauge = register_gauge_vec_with_registry!("pistachio_alert_active", "Whether an alert rule is active", &["alert"], registry)?;
+        for rule in &rules {
+            gauge.with_label_values(&[&rule.name]).set(0.0);
+        }
+        Ok(Alerts {
+            rules,
+            states: HashMap::new(),
+            events,
+            gauge,
+        })
+    }
+
+    /// Evaluates every rule against a poll made at `now`, returning the events caused.
+    fn evaluate(&mut self, values: &HashMap<String, String>, now: Instant) -> Vec<Event> {
+        let mut events = Vec::new();
+        for rule in &self.rules {
+            let state = self.states.entry(rule.name.clone()).or_default();
+            let holds = values
+                .get(&rule.var)
+                .is_some_and(|actual| rule.operator.matches(actual, &rule.value));
+            if !holds {
+                state.since = None;
+            } else if state.since.is_none() {
+                state.since = Some(now);
+            }
+            let active = state.since.is_some_and(|since| now.saturating_duration_since(since) >= rule.duration);
+            if active != state.active {
+                state.active = active;
+                self.gauge.with_label_values(&[&rule.name]).set(if active { 1.0 } else { 0.0 });
+                events.push(if active {
+                    Event::AlertRaised {
+                        alert: rule.name.clone(),
+                        rule: rule.to_string(),
+                    }
+                } else {
+                    Event::AlertResolved { alert: rule.name.clone() }
+                });
+            }
+        }
+        events
+    }
+}
+
+impl Sink for Alerts {
+    fn name(&self) -> &str {
+        "alerts"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn StdError>> {
+        let values = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        for event in self.evaluate(&values, Instant::now()) {
+            match &self.events {
+                Some(sender) => sender.send(event)?,
+                None => debug!("{event}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule: AlertRule = "on_battery=ups.status contains OB for 60".parse().unwrap();
+        assert_eq!(rule.name, "on_battery");
+        assert_eq!(rule.var, "ups.status");
+        assert_eq!(rule.operator, Operator::Contains);
+        assert_eq!(rule.value, "OB");
+        assert_eq!(rule.duration, Duration::from_secs(60));
+        assert_eq!(rule.to_string(), "on_battery=ups.status contains OB for 60");
+        let rule: AlertRule = "low_charge=battery.charge <= 20".parse().unwrap();
+        assert_eq!(rule.operator, Operator::LessOrEqual);
+        assert_eq!(rule.duration, Duration::ZERO);
+        assert!("battery.charge < 20".parse::<AlertRule>().is_err());
+        assert!("low=battery.charge ~ 20".parse::<AlertRule>().is_err());
+        assert!("low=battery.charge < 20 for ever".parse::<AlertRule>().is_err());
+    }
+
+    #[test]
+    fn evaluate_rules() {
+        let rules = vec![
+            "low_charge=battery.charge < 20".parse().unwrap(),
+            "on_battery=ups.status contains OB for 60".parse().unwrap(),
+        ];
+        let mut alerts = Alerts::new(rules, None, &Registry::new()).unwrap();
+        let start = Instant::now();
+        assert!(alerts.evaluate(&values(&[("battery.charge", "100"), ("ups.status", "OL")]), start).is_empty());
+
+        let on_battery = values(&[("battery.charge", "19.5"), ("ups.status", "OB DISCHRG")]);
+        let events = alerts.evaluate(&on_battery, start);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], Event::AlertRaised { alert, .. } if alert == "low_charge"));
+        assert_eq!(alerts.gauge.with_label_values(&["on_battery"]).get(), 0.0);
+
+        // The status must hold for a minute
+        let events = alerts.evaluate(&on_battery, start + Duration::from_secs(60));
+        assert!(matches!(&events[..], [Event::AlertRaised { alert, .. }] if alert == "on_battery"));
+        assert_eq!(alerts.gauge.with_label_values(&["on_battery"]).get(), 1.0);
+
+        let events = alerts.evaluate(&values(&[("ups.status", "OL")]), start + Duration::from_secs(70));
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| matches!(event, Event::AlertResolved { .. })));
+    }
+}
@@ -0,0 +1,254 @@
+//! A backend that reads a UPS managed by apcupsd through its Network Information Server (NIS),
+//! so units managed by apcupsd can be monitored without migrating to NUT.
+//!
+//! The `status` report of apcupsd is translated to the NUT variables it corresponds to, following
+//! the `apcupsd-ups` driver of NUT, so the rest of the exporter works the same as with a NUT
+//! server. apcupsd serves a single UPS, so the name of the UPS is only used in metrics.
+
+use crate::client::UpsClient;
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Maximum time allowed for connecting to apcupsd and reading its report.
+const NIS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How the value of an apcupsd field is converted to the value of a NUT variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conversion {
+    /// The number at the start of the value, without its unit, such as `230.0` of `230.0 Volts`.
+    Number,
+    /// A number of minutes, converted to seconds.
+    Minutes,
+    /// The value as it is.
+    Text,
+}
+
+/// Fields of the apcupsd status report, with the NUT variables they correspond to.
+const FIELDS: &[(&str, &str, Conversion)] = &[
+    ("BCHARGE", "battery.charge", Conversion::Number),
+    ("MBATTCHG", "battery.charge.low", Conversion::Number),
+    ("TIMELEFT", "battery.runtime", Conversion::Minutes),
+    ("MINTIMEL", "battery.runtime.low", Conversion::Minutes),
+    ("BATTV", "battery.voltage", Conversion::Number),
+    ("NOMBATTV", "battery.voltage.nominal", Conversion::Number),
+    ("BATTDATE", "battery.date", Conversion::Text),
+    ("LINEV", "input.voltage", Conversion::Number),
+    ("NOMINV", "input.voltage.nominal", Conversion::Number),
+    ("LINEFREQ", "input.frequency", Conversion::Number),
+    ("HITRANS", "input.transfer.high", Conversion::Number),
+    ("LOTRANS", "input.transfer.low", Conversion::Number),
+    ("OUTPUTV", "output.voltage", Conversion::Number),
+    ("NOMOUTV", "output.voltage.nominal", Conversion::Number),
+    ("LOADPCT", "ups.load", Conversion::Number),
+    ("ITEMP", "ups.temperature", Conversion::Number),
+    ("NOMPOWER", "ups.realpower.nominal", Conversion::Number),
+    ("NOMAPNT", "ups.power.nominal", Conversion::Number),
+    ("MODEL", "ups.model", Conversion::Text),
+    ("SERIALNO", "ups.serial", Conversion::Text),
+    ("FIRMWARE", "ups.firmware", Conversion::Text),
+    ("MANDATE", "ups.mfr.date", Conversion::Text),
+];
+
+/// Words of the apcupsd `STATUS` field, with the flags of `ups.status` they correspond to.
+const STATUS_FLAGS: &[(&str, &str)] = &[
+    ("ONLINE", "OL"),
+    ("ONBATT", "OB"),
+    ("LOWBATT", "LB"),
+    ("REPLACEBATT", "RB"),
+    ("OVERLOAD", "OVER"),
+    ("TRIM", "TRIM"),
+    ("BOOST", "BOOST"),
+    ("CAL", "CAL"),
+    ("SHUTTING", "FSD"),
+];
+
+/// A client that reads a UPS from apcupsd, instead of from a NUT server.
+#[derive(Debug, Clone)]
+pub struct ApcupsdClient {
+    host: String,
+    port: u16,
+}
+
+impl ApcupsdClient {
+    /// Creates a client for the apcupsd NIS at the given host and port, which is usually `3551`.
+    /// A new connection is made for every request, the same as `apcaccess` does.
+    #[must_use]
+    pub fn new(host: &str, port: u16) -> ApcupsdClient {
+        ApcupsdClient {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    /// Sends a command to apcupsd, returning every line of its response.
+    fn request(&self, command: &str) -> io::Result<Vec<String>> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to an address", self.host)))?;
+        let mut stream = TcpStream::connect_timeout(&addr, NIS_TIMEOUT)?;
+        stream.set_read_timeout(Some(NIS_TIMEOUT))?;
+        stream.set_write_timeout(Some(NIS_TIMEOUT))?;
+        let len = u16::try_from(command.len()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        stream.write_all(&len.to_be_bytes())?;
+        stream.write_all(command.as_bytes())?;
+        read_lines(&mut stream)
+    }
+}
+
+/// Reads a response of the NIS protocol, in which every line is preceded by its length as a
+/// big-endian 16 bit number, and the end is marked by a line of length zero.
+fn read_lines<R: Read>(reader: &mut R) -> io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    loop {
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        let len = usize::from(u16::from_be_bytes(len));
+        if len == 0 {
+            return Ok(lines);
+        }
+        let mut line = vec![0; len];
+        reader.read_exact(&mut line)?;
+        lines.push(String::from_utf8_lossy(&line).trim_end().to_string());
+    }
+}
+
+/// Translates the lines of a status report, such as `BCHARGE  : 100.0 Percent`, into NUT
+/// variables.
+fn translate(lines: &[String]) -> Vec<(String, String)> {
+    let fields: HashMap<&str, &str> = lines
+        .iter()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    let mut vars = vec![(String::from("ups.mfr"), String::from("APC"))];
+    for (key, name, conversion) in FIELDS {
+        let Some(value) = fields.get(key) else {
+            continue;
+        };
+        let number = value.split_whitespace().next().and_then(|number| number.parse::<f64>().ok());
+        let value = match (conversion, number) {
+            (Conversion::Number, Some(number)) => number.to_string(),
+            (Conversion::Minutes, Some(minutes)) => (minutes * 60.0).round().to_string(),
+            (Conversion::Text, _) => value.to_string(),
+            _ => continue,
+        };
+        vars.push((name.to_string(), value));
+    }
+    if let Some(status) = fields.get("STATUS") {
+        let flags: Vec<&str> = status
+            .split_whitespace()
+            .filter_map(|word| STATUS_FLAGS.iter().find(|(apc, _)| *apc == word).map(|(_, flag)| *flag))
+            .collect();
+        vars.push((String::from("ups.status"), flags.join(" ")));
+    }
+    vars
+}
+
+impl UpsClient for ApcupsdClient {
+    fn list_vars(&mut self, _ups_name: &str) -> Result<Vec<Variable>> {
+        let lines = self.request("status").map_err(Error::Connection)?;
+        Ok(translate(&lines)
+            .into_iter()
+            .map(|(name, value)| Variable::new(&name, value))
+            .collect())
+    }
+
+    fn get_var_description(&mut self, _ups_name: &str, var_name: &str) -> Result<String> {
+        let description = crate::metadata::known_description(var_name).unwrap_or("Description unavailable");
+        Ok(description.to_string())
+    }
+
+    fn get_var_type(&mut self, _ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        let numeric = FIELDS
+            .iter()
+            .any(|(_, name, conversion)| *name == var_name && *conversion != Conversion::Text);
+        let kind = if numeric { "NUMBER" } else { "STRING:64" };
+        VariableDefinition::parse(var_name, &[kind])
+    }
+
+    fn list_var_enum(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_var_range(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<VariableRange>> {
+        Ok(Vec::new())
+    }
+
+    fn list_clients(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_commands(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn get_command_description(&mut self, _ups_name: &str, _command: &str) -> Result<String> {
+        Err(Error::Protocol(rups::NutError::CmdNotSupported))
+    }
+
+    fn close(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const STATUS: &[&str] = &[
+        "APC      : 001,036,0857",
+        "MODEL    : Back-UPS RS 1500G ",
+        "STATUS   : ONBATT LOWBATT ",
+        "LINEV    : 0.0 Volts",
+        "LOADPCT  : 19.0 Percent",
+        "BCHARGE  : 8.0 Percent",
+        "TIMELEFT : 2.5 Minutes",
+        "NOMPOWER : 865 Watts",
+        "END APC  : 2024-10-01 12:00:00 +0000",
+    ];
+
+    #[test]
+    fn read_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0; 8];
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(&command, b"\0\x06status");
+            for line in STATUS {
+                let line = format!("{line}\n");
+                stream.write_all(&u16::try_from(line.len()).unwrap().to_be_bytes()).unwrap();
+                stream.write_all(line.as_bytes()).unwrap();
+            }
+            stream.write_all(&[0, 0]).unwrap();
+        });
+
+        let mut client = ApcupsdClient::new("127.0.0.1", port);
+        let vars: HashMap<String, String> = client
+            .list_vars("ups")
+            .unwrap()
+            .into_iter()
+            .map(|var| (var.name().to_string(), var.value().to_string()))
+            .collect();
+        server.join().unwrap();
+        assert_eq!(vars["ups.status"], "OB LB");
+        assert_eq!(vars["ups.model"], "Back-UPS RS 1500G");
+        assert_eq!(vars["input.voltage"], "0");
+        assert_eq!(vars["battery.charge"], "8");
+        assert_eq!(vars["battery.runtime"], "150");
+        assert_eq!(vars["ups.realpower.nominal"], "865");
+        assert!(!vars.contains_key("output.voltage"));
+        assert_eq!(client.get_var_description("ups", "battery.runtime").unwrap(), "Battery runtime (seconds)");
+        assert!(client.get_var_type("ups", "ups.load").unwrap().is_number());
+
+        // Nothing is listening once the server is gone
+        assert!(matches!(client.list_vars("ups"), Err(Error::Connection(_))));
+    }
+}
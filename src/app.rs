@@ -0,0 +1,556 @@
+//! The complete exporter, for running it as a binary or embedding it in a larger application.
+
+use crate::audit::AuditLog;
+use crate::connection::{ConnectionManager, ManagedClient};
+use crate::control::ControlApi;
+use crate::cost::Pricing;
+use crate::crash::CrashNotifier;
+use crate::events::{Event, EventBus};
+use crate::metadata::{CommandMetadata, VarMetadata};
+use crate::http::{Listeners, Response, Server};
+use crate::record::{Recorder, Rotation};
+use crate::replay::ReplayClient;
+use crate::sink::Sink;
+use crate::state::{Accumulator, State};
+use crate::watchdog::Watchdog;
+use crate::{Backend, Config, Error, Output, Result, UpsClient};
+use log::{error, info, warn};
+use prometheus::{register_int_counter_vec_with_registry, Registry};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Runs the exporter with the given configuration: connects to the NUT server, creates metrics
+/// for every variable of the UPS, starts the HTTP server and all configured sinks, and monitors
+/// the UPS until `shutdown` is set. Metrics are registered in a registry of their own, and every
+/// listener is closed before this returns, so the exporter can be run again in the same process.
+///
+/// # Errors
+///
+/// An error will be returned if any part of the exporter cannot be started, such as if the NUT
+/// server is unreachable or the HTTP server cannot bind to its address. Once monitoring has
+/// started, failures are logged and retried instead.
+pub fn run(config: &Config, shutdown: &AtomicBool) -> Result<()> {
+    run_in(config, &new_registry()?, &EventBus::new(), shutdown)
+}
+
+/// Runs the exporter like [`run`], with its metrics registered in `registry`, such as the
+/// registry an application embedding the exporter serves its own metrics from, and every event
+/// sent to the subscribers of `bus`, including those raised by alerts, load shedding, and
+/// instant commands.
+///
+/// # Errors
+///
+/// An error will be returned like with [`run`], or if a metric of the exporter is already
+/// registered in `registry`.
+pub fn run_in(config: &Config, registry: &Registry, bus: &EventBus, shutdown: &AtomicBool) -> Result<()> {
+    config.validate()?;
+    for option in config.ignored_by_read_only() {
+        warn!("--{} is ignored in read-only mode, set --read-only=false to use it", option.replace('_', "-"));
+    }
+    match config.backend {
+        Backend::Nut => run_nut(config, registry, bus, shutdown),
+        Backend::Apcupsd => {
+            let mut client = crate::apcupsd::ApcupsdClient::new(&config.ups_host, config.ups_port);
+            let metadata = crate::metadata::get_metadata(&mut client, &config.ups_name)?;
+            serve(config, client, (metadata, Vec::new()), Server::new(), mpsc::channel(), (registry, bus), shutdown)
+        }
+        Backend::Snmp => {
+            let mut client = crate::snmp::SnmpClient::new(&config.ups_host, config.ups_port, &config.snmp_community);
+            let metadata = crate::metadata::get_metadata(&mut client, &config.ups_name)?;
+            serve(config, client, (metadata, Vec::new()), Server::new(), mpsc::channel(), (registry, bus), shutdown)
+        }
+        Backend::Modbus => {
+            let map = config
+                .modbus_register_map
+                .as_deref()
+                .ok_or_else(|| Error::Config(String::from("the modbus backend requires a register map")))?;
+            let map = crate::modbus::RegisterMap::load(map)?;
+            let mut client = crate::modbus::ModbusClient::new(&config.ups_host, config.ups_port, map);
+            let metadata = crate::metadata::get_metadata(&mut client, &config.ups_name)?;
+            serve(config, client, (metadata, Vec::new()), Server::new(), mpsc::channel(), (registry, bus), shutdown)
+        }
+        Backend::Usbhid => run_usbhid(config, registry, bus, shutdown),
+    }
+}
+
+/// Polls a UPS through a NUT server and appends every poll to a file, without exporting metrics,
+/// until `shutdown` is set. The file is written in the format picked by its extension, like with
+/// `--record`.
+///
+/// # Errors
+///
+/// An error will be returned if the configuration is invalid, if the UPS is not read through a
+/// NUT server, or if the file cannot be opened. Failed polls are logged and retried instead.
+pub fn record(config: &Config, path: &Path, shutdown: &AtomicBool) -> Result<()> {
+    config.validate()?;
+    if config.backend != Backend::Nut {
+        return Err(Error::Config(format!(
+            "polls can only be recorded from a NUT server, use --record to record the {} backend",
+            config.backend
+        )));
+    }
+    let mut recorder = Recorder::open(path, Rotation::default()).map_err(|source| Error::Io {
+        context: format!("failed to open record file {}", path.display()),
+        source,
+    })?;
+    let manager = Arc::new(ConnectionManager::new());
+    let mut client = ManagedClient::new(manager, &config.ups_host, config.ups_port);
+    info!("Every poll of UPS {} will be recorded to {}", config.ups_name, path.display());
+    let mut recorded = 0;
+    for result in crate::snapshots(&mut client, &config.ups_name, Duration::from_secs(config.poll_rate)).until(shutdown) {
+        match result {
+            Ok(snapshot) => match recorder.publish(&snapshot.variables()) {
+                Ok(()) => recorded += 1,
+                Err(err) => warn!("Failed to record poll: {err}"),
+            },
+            Err(err) => warn!("Failed to poll the UPS: {err}"),
+        }
+    }
+    info!("Recorded {recorded} polls to {}", path.display());
+    client.close()
+}
+
+/// Runs the exporter with polls replayed by `client` instead of read from a UPS, until
+/// `shutdown` is set. Polls are paced by the recording instead of the poll rate, at up to one
+/// poll per second.
+///
+/// # Errors
+///
+/// An error will be returned if any part of the exporter cannot be started, such as if the HTTP
+/// server cannot bind to its address.
+pub fn replay(config: &Config, client: ReplayClient, shutdown: &AtomicBool) -> Result<()> {
+    config.validate()?;
+    let config = Config {
+        poll_rate: 1,
+        ..config.clone()
+    };
+    let metadata = crate::metadata::get_metadata(&mut client.clone(), &config.ups_name)?;
+    serve(&config, client, (metadata, Vec::new()), Server::new(), mpsc::channel(), (&new_registry()?, &EventBus::new()), shutdown)
+}
+
+/// Creates the registry of a run, with the metrics of the process itself where they are
+/// available.
+fn new_registry() -> Result<Registry> {
+    let registry = Registry::new();
+    #[cfg(all(feature = "cli", target_os = "linux"))]
+    registry.register(Box::new(prometheus::process_collector::ProcessCollector::for_self()))?;
+    Ok(registry)
+}
+
+/// Monitors a UPS through a NUT server, which is also the only backend that can run instant
+/// commands and set variables.
+fn run_nut(config: &Config, registry: &Registry, bus: &EventBus, shutdown: &AtomicBool) -> Result<()> {
+    // Connect to the NUT server and get list of available UPS vars
+    let manager = Arc::new(ConnectionManager::new().max_idle(config.metadata_connections));
+    let metadata = crate::metadata::get_metadata_concurrently(
+        &mut manager.lease(&config.ups_host, config.ups_port)?,
+        || manager.lease(&config.ups_host, config.ups_port),
+        &config.ups_name,
+        config.metadata_connections,
+    )
+    .inspect_err(|err| {
+        if let Some(explanation) = crate::explain_unavailable(config, err) {
+            error!("{explanation}");
+        }
+    })?;
+    let commands = crate::metadata::get_commands(&mut manager.lease(&config.ups_host, config.ups_port)?, &config.ups_name)
+        .unwrap_or_else(|err| {
+            warn!("Failed to list instant commands of the UPS: {err}");
+            Vec::new()
+        });
+
+    let mut server = Server::new();
+    let (events, received) = mpsc::channel();
+    let api = ControlApi::new(&config.ups_host, config.ups_port, &config.ups_name)
+        .with_events(events.clone())
+        .with_audit(open_audit_log(config)?);
+    if config.enable_commands && !config.read_only {
+        let api = api.clone();
+        server = server.route("POST", "/api/v1/command", move |request| api.handle_command(request));
+        info!("Instant commands can be run with POST /api/v1/command");
+    }
+    if config.enable_set_vars && !config.read_only {
+        server = server.route("POST", "/api/v1/variable", move |request| api.handle_set_var(request));
+        info!("Writable variables can be set with POST /api/v1/variable");
+    }
+    // Requests that hang are abandoned and made again on a new connection
+    let (host, port) = (config.ups_host.clone(), config.ups_port);
+    let timeout = Duration::from_secs(config.poll_rate * u64::from(config.poll_stall_threshold));
+    let errors = register_int_counter_vec_with_registry!(
+        "pistachio_nut_errors_total",
+        "Number of errors of requests to the NUT server, by type of error",
+        &["error_type"],
+        registry
+    )?;
+    let connect = move || ManagedClient::new(Arc::clone(&manager), &host, port).count_errors(errors.clone());
+    let client = Watchdog::new(connect, timeout, registry)?;
+    serve(config, client, (metadata, commands), server, (events, received), (registry, bus), shutdown)
+}
+
+/// Monitors a UPS connected over USB, read directly without a NUT server.
+#[cfg(feature = "usbhid")]
+fn run_usbhid(config: &Config, registry: &Registry, bus: &EventBus, shutdown: &AtomicBool) -> Result<()> {
+    let mut client = crate::usbhid::HidClient::open(config.usbhid_device.as_deref())?;
+    info!("The UPS will be read directly from {}", client.path().display());
+    let metadata = crate::metadata::get_metadata(&mut client, &config.ups_name)?;
+    serve(config, client, (metadata, Vec::new()), Server::new(), mpsc::channel(), (registry, bus), shutdown)
+}
+
+#[cfg(not(feature = "usbhid"))]
+fn run_usbhid(_config: &Config, _registry: &Registry, _bus: &EventBus, _shutdown: &AtomicBool) -> Result<()> {
+    Err(Error::Config(String::from("the usbhid backend requires pistachio to be built with the `usbhid` feature")))
+}
+
+/// Creates metrics for every variable of the UPS in `registry`, starts the HTTP server and all
+/// configured sinks, and monitors the UPS with `client` until `shutdown` is set. Events sent to
+/// the channel are published along with those of the polling loop, to the sinks and to `bus`.
+fn serve<C: UpsClient>(
+    config: &Config,
+    mut client: C,
+    (mut metadata, commands): (Vec<VarMetadata>, Vec<CommandMetadata>),
+    server: Server,
+    (events, received): (Sender<Event>, Receiver<Event>),
+    (registry, bus): (&Registry, &EventBus),
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    crate::metadata::apply_help_texts(&mut metadata, &config.help_texts);
+    // Create Prometheus metrics from available ups variables
+    let metrics = crate::Metrics::build_from_metadata(&metadata, registry)?;
+    info!("{} gauges will be exported", metrics.count());
+    crate::version::register_build_info(registry)?;
+    metrics.update_commands(&commands);
+
+    // Serve the gRPC API before the metadata is moved into the HTTP route
+    #[cfg(feature = "grpc")]
+    let grpc = match config.grpc_port {
+        Some(port) => {
+            let addrs = resolve_bind_addrs(config, port)?;
+            let grpc = crate::grpc::Grpc::start(&addrs, &config.ups_name, &metadata).map_err(|source| Error::Io {
+                context: format!("failed to start gRPC server on {}", config.bind_ip),
+                source,
+            })?;
+            info!("The gRPC API will be served on {}", grpc.local_addr());
+            Some(grpc)
+        }
+        None => None,
+    };
+
+    // Set up sinks for polled variables and HTTP routes
+    let metadata = serde_json::json!({ "ups": config.ups_name, "variables": metadata });
+    let commands = serde_json::json!({ "ups": config.ups_name, "commands": commands });
+    let labels = host_label(config)?.map(|host| vec![(String::from("host"), host)]).unwrap_or_default();
+    let renamer = crate::naming::Renamer::new(config.naming_scheme, &config.ups_name, metrics.metric_vars());
+    if config.naming_scheme != crate::naming::NamingScheme::Pistachio {
+        info!("Gauges of UPS variables will be named in the {} scheme", config.naming_scheme);
+    }
+    let last_poll = crate::openmetrics::LastPoll::default();
+    let poll_time = last_poll.clone();
+    let ready_poll_time = last_poll.clone();
+    let started = SystemTime::now();
+    let max_staleness = config.ready_max_staleness.map(Duration::from_secs);
+    let gathered = registry.clone();
+    let server = server
+        .route("GET", "/metrics", move |request| crate::http::serve_metrics(request, &gathered, poll_time.get(), &labels, &renamer))
+        .route("GET", "/ready", move |_| crate::http::serve_ready(ready_poll_time.get(), started, max_staleness, SystemTime::now()))
+        .route("GET", "/api/v1/variables", move |_| Response::json(200, &metadata))
+        .route("GET", "/api/v1/commands", move |_| Response::json(200, &commands));
+    let (mut sinks, server) = create_sinks(config, server, events, registry)?;
+    let _notifiers = crash_notifiers(config);
+    sinks.push(Box::new(last_poll));
+    sinks.push(Box::new(bus.clone()));
+    #[cfg(feature = "grpc")]
+    sinks.extend(grpc.map(|grpc| Box::new(grpc) as Box<dyn Sink>));
+
+    let _listeners = match config.output {
+        Output::Prometheus => Some(start_http(config, server, registry)?),
+        Output::InfluxStdout => {
+            sinks.push(Box::new(crate::influx::InfluxStdout::new(&config.ups_name)));
+            info!("Metrics will be written to standard output in InfluxDB line protocol instead of served over HTTP");
+            None
+        }
+        Output::CollectdExec => {
+            // The exec plugin of collectd passes the host name to report values for
+            let host = std::env::var("COLLECTD_HOSTNAME")
+                .ok()
+                .or_else(crate::hostname)
+                .unwrap_or_else(|| String::from("localhost"));
+            let interval = Duration::from_secs(config.poll_rate);
+            sinks.push(Box::new(crate::collectd::CollectdExec::new(&host, &config.ups_name, interval)));
+            info!("Metrics will be written to standard output as collectd PUTVAL commands for host {host} instead of served over HTTP");
+            None
+        }
+    };
+
+    crate::monitor_with_events(config, &mut client, &metrics, &mut sinks, shutdown, &received);
+    client.close()
+}
+
+/// Starts serving metrics and the routes of the server over HTTP, until the returned listeners
+/// are dropped.
+fn start_http(config: &Config, mut server: Server, registry: &Registry) -> Result<Listeners> {
+    if let Some(max) = config.http_max_connections {
+        server = server.max_connections(max);
+    }
+    if let Some(per_minute) = config.http_rate_limit {
+        server = server.rate_limit(per_minute);
+    }
+    if let Some(level) = config.http_access_log.level() {
+        server = server.access_log(level);
+    }
+    if let Some(idle_timeout) = config.http_idle_timeout {
+        server = server.keep_alive(Duration::from_secs(idle_timeout));
+    }
+    server = server
+        .timeouts(Duration::from_secs(config.http_read_timeout), Duration::from_secs(config.http_write_timeout))
+        .max_header_bytes(config.http_max_header_bytes);
+    let requests = register_int_counter_vec_with_registry!(
+        "pistachio_http_requests_total",
+        "Number of HTTP requests served, by path and status code",
+        &["path", "code"],
+        registry
+    )?;
+    server = server.count_requests(requests).bind_retries(config.bind_retries).cors(config.cors_origins.clone());
+    if !config.api_tokens.is_empty() {
+        server = server.api_tokens(config.api_tokens.clone());
+        info!("HTTP requests must be sent with one of {} API tokens", config.api_tokens.len());
+    }
+    #[cfg(feature = "tls")]
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+        let files = crate::tls::TlsFiles {
+            cert: cert.clone(),
+            key: key.clone(),
+            client_ca: config.tls_client_ca.clone(),
+        };
+        let tls = crate::tls::ReloadableConfig::from(files.load()?);
+        tls.watch(files);
+        server = server.tls(tls).client_scopes(config.tls_client_scopes.clone());
+        match &config.tls_client_ca {
+            Some(path) => info!("Serving HTTPS to clients with a certificate signed by {}", path.display()),
+            None => info!("Serving HTTPS"),
+        }
+    }
+    if server.routes().any(|(_, path)| path.starts_with("/api/v1/")) {
+        let document = crate::openapi::document(server.routes().chain([("GET", crate::openapi::PATH)]));
+        server = server.route("GET", crate::openapi::PATH, move |_| Response::json(200, &document));
+    }
+    let bind_addrs = resolve_bind_addrs(config, config.bind_port)?;
+    server.start(&bind_addrs).map_err(|source| Error::Io {
+        context: format!("failed to start HTTP server on {}", config.bind_ip),
+        source,
+    })
+}
+
+/// Opens the audit log file, or returns an audit log that only logs entries if there is none.
+/// Entries are written with a single append, so every part of the exporter can open the file.
+fn open_audit_log(config: &Config) -> Result<AuditLog> {
+    let Some(path) = &config.audit_log else {
+        return Ok(AuditLog::default());
+    };
+    AuditLog::open(path).map_err(|source| Error::Io {
+        context: format!("could not open audit log {}", path.display()),
+        source,
+    })
+}
+
+/// Returns the value of the `host` label added to every metric, if enabled.
+fn host_label(config: &Config) -> Result<Option<String>> {
+    if let Some(name) = &config.host_label_env {
+        return match std::env::var(name) {
+            Ok(host) if !host.trim().is_empty() => Ok(Some(host.trim().to_string())),
+            _ => Err(Error::Config(format!("the host label was to be taken from {name}, which is not set"))),
+        };
+    }
+    if !config.host_label {
+        return Ok(None);
+    }
+    match crate::hostname() {
+        Some(host) => Ok(Some(host)),
+        None => Err(Error::Config(String::from("the host name could not be found for the host label"))),
+    }
+}
+
+/// Resolves the addresses to serve on from `--bind-ip`, with the given port.
+fn resolve_bind_addrs(config: &Config, port: u16) -> Result<Vec<SocketAddr>> {
+    crate::http::resolve(&config.bind_ip, port).map_err(|source| Error::Io {
+        context: format!("failed to resolve bind address {}", config.bind_ip),
+        source,
+    })
+}
+
+/// Creates every sink enabled in the configuration, with their metrics in `registry`, adding any
+/// HTTP routes they serve to the server. Sinks that raise events of their own send them to
+/// `events`.
+fn create_sinks(config: &Config, mut server: Server, events: Sender<Event>, registry: &Registry) -> Result<(Vec<Box<dyn Sink>>, Server)> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    let state = match &config.state_file {
+        Some(path) if path.exists() => State::load(path).unwrap_or_else(|err| {
+            warn!("Could not load state from {}, starting fresh: {err}", path.display());
+            State::default()
+        }),
+        _ => State::default(),
+    };
+    let journal = crate::journal::Journal::new(&config.ups_name, config.journal_size, config.journal_file.as_deref(), registry)?;
+    let max_gap = Duration::from_secs(config.poll_rate * 3);
+    let mut accumulator = Accumulator::new(state, config.state_file.as_deref(), max_gap, registry)?
+        .with_save_interval(Duration::from_secs(config.state_save_interval))
+        .with_outages(journal.outages());
+    if let Some(price) = config.energy_price {
+        accumulator = accumulator.with_pricing(Pricing::new(price, config.energy_price_periods.clone()), registry)?;
+        info!("The cost of energy will be estimated at {price} per kWh");
+    }
+    sinks.push(Box::new(accumulator));
+    let rated_runtime = config.battery_rated_runtime.map(Duration::from_secs);
+    let expected_life = Duration::from_secs(config.battery_expected_life * 365 * 86400);
+    sinks.push(Box::new(crate::health::BatteryHealth::new(rated_runtime, expected_life, registry)?));
+    sinks.push(Box::new(crate::health::Calibrations::new(registry)?));
+    sinks.push(Box::new(crate::predict::RuntimePredictor::new(registry)?));
+    sinks.push(Box::new(crate::predict::ChargeTimePredictor::new(registry)?));
+    let tolerances = crate::power::Tolerances {
+        sag_percent: config.voltage_sag_percent,
+        swell_percent: config.voltage_swell_percent,
+        frequency_hz: config.frequency_tolerance,
+    };
+    sinks.push(Box::new(crate::power::PowerQuality::new(tolerances, registry)?));
+    sinks.push(Box::new(crate::dates::DateMetrics::new(config.date_timezone, config.date_formats.clone(), registry)?));
+    if let Some(group) = config.sample_group() {
+        let window = Duration::from_secs(config.sample_window.unwrap_or(config.poll_rate));
+        info!("{} will be sampled every {} seconds", config.sample_vars.join(", "), config.sample_interval);
+        sinks.push(Box::new(crate::sampling::Sampler::new(group, window, registry)));
+    }
+    let reader = journal.reader();
+    server = server.route("GET", "/api/v1/events", move |request| reader.handle(request));
+    sinks.push(Box::new(journal));
+    #[cfg(feature = "ui")]
+    {
+        let dashboard = crate::ui::Dashboard::new(&config.ups_name);
+        let reader = dashboard.reader();
+        server = server
+            .route("GET", "/ui", crate::ui::DashboardReader::page)
+            .route("GET", "/ui/state", move |request| reader.state(request));
+        sinks.push(Box::new(dashboard));
+        info!("A dashboard will be served at /ui");
+    }
+    let shutdown_command = config.shutdown_command.as_deref().filter(|_| !config.read_only);
+    let audit = match shutdown_command {
+        Some(_) => open_audit_log(config)?,
+        None => AuditLog::default(),
+    };
+    sinks.push(Box::new(crate::shutdown::ForcedShutdown::new(&config.ups_name, shutdown_command, registry)?.with_audit(audit)));
+    if let Some(command) = shutdown_command {
+        info!("`{command}` will be run if the UPS starts a forced shutdown");
+    }
+    if !config.alerts.is_empty() {
+        sinks.push(Box::new(crate::alerts::Alerts::new(config.alerts.clone(), Some(events.clone()), registry)?));
+        info!("{} alert rules will be evaluated on every poll", config.alerts.len());
+    }
+    if !config.shed_tiers.is_empty() {
+        sinks.push(Box::new(crate::shed::LoadShedding::new(config.shed_tiers.clone(), Some(events), registry)?));
+        info!("{} load-shedding tiers will be recommended during outages", config.shed_tiers.len());
+    }
+    // Sinks that push to external services only publish while this replica holds the lease
+    let leader = match &config.ha_lease_file {
+        Some(path) => {
+            let id = config.ha_id.clone().unwrap_or_else(crate::ha::default_id);
+            let lease = crate::ha::Lease::new(path, &id, max_gap, registry)?;
+            let leader = lease.leader();
+            sinks.push(Box::new(lease));
+            info!("Replica {id} will publish to external services only while it holds the lease {}", path.display());
+            Some(leader)
+        }
+        None => None,
+    };
+    let gate = |sink: Box<dyn Sink>| -> Box<dyn Sink> {
+        match &leader {
+            Some(leader) => Box::new(crate::ha::Gated::new(sink, Arc::clone(leader))),
+            None => sink,
+        }
+    };
+    if let Some(url) = &config.ping_url {
+        sinks.push(gate(Box::new(crate::ping::Pinger::new(url))));
+        info!("A ping will be sent to {url} after every successful poll");
+    }
+    if let Some(path) = &config.record {
+        let rotation = crate::record::Rotation {
+            max_size: config.record_max_size.map(|mb| mb * 1024 * 1024),
+            max_age: config.record_max_age.map(|hours| Duration::from_secs(hours * 3600)),
+            keep: config.record_keep,
+        };
+        let recorder = crate::record::Recorder::open(path, rotation).map_err(|source| Error::Io {
+            context: format!("could not open record file {}", path.display()),
+            source,
+        })?;
+        sinks.push(Box::new(recorder));
+        info!("Polled variables will be recorded to {}", path.display());
+    }
+    if let Some(server) = &config.zabbix_server {
+        let host = config.zabbix_host.as_ref().unwrap_or(&config.ups_name);
+        let keys = config.zabbix_keys.iter().cloned().collect();
+        sinks.push(gate(Box::new(crate::zabbix::Zabbix::new(server, host, &config.zabbix_key_template, keys))));
+        info!("Values will be sent to Zabbix server {server} for host {host}");
+    }
+    #[cfg(feature = "nats")]
+    if let Some(url) = &config.nats_url {
+        let nats = crate::nats::Nats::new(url, &config.nats_subject, &config.ups_name)
+            .map_err(|err| Error::Config(format!("invalid NATS configuration: {err}")))?;
+        sinks.push(gate(Box::new(nats)));
+        info!("Events will be published to NATS subjects under {}", config.nats_subject);
+    }
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = &config.otlp_endpoint {
+        sinks.push(gate(Box::new(crate::otlp::Otlp::new(endpoint, &config.otlp_service_name, &config.ups_name))));
+        info!("Polls and events will be sent to the OpenTelemetry collector at {endpoint}");
+    }
+    #[cfg(feature = "cloudwatch")]
+    if let Some(region) = &config.cloudwatch_region {
+        let credentials = crate::cloudwatch::Credentials::from_env()
+            .map_err(|err| Error::Config(format!("could not read AWS credentials from the environment: {err}")))?;
+        let mut dimensions = config.cloudwatch_dimensions.clone();
+        if dimensions.is_empty() {
+            dimensions.push((String::from("UPS"), config.ups_name.clone()));
+        }
+        let interval = Duration::from_secs(config.cloudwatch_interval);
+        sinks.push(gate(Box::new(crate::cloudwatch::CloudWatch::new(
+            credentials,
+            region,
+            &config.cloudwatch_namespace,
+            dimensions,
+            &config.cloudwatch_vars,
+            interval,
+        ))));
+        info!("Metrics will be published to CloudWatch namespace {} in {region}", config.cloudwatch_namespace);
+    }
+    #[cfg(feature = "history")]
+    if let Some(path) = &config.history_db {
+        let retention = Duration::from_secs(config.history_retention * 3600);
+        let history = crate::history::History::open(path, retention).map_err(|err| Error::Io {
+            context: format!("could not open history database {}", path.display()),
+            source: std::io::Error::other(err),
+        })?;
+        sinks.push(Box::new(history.recorder(&config.history_vars)));
+        server = server.route("GET", "/api/v1/history", move |request| history.handle(request));
+        info!("History will be recorded to {}", path.display());
+    }
+    Ok((sinks, server))
+}
+
+/// Registers the sinks that crashes are reported to, which are unregistered once the returned
+/// notifiers are dropped.
+#[cfg(feature = "nats")]
+fn crash_notifiers(config: &Config) -> Vec<CrashNotifier> {
+    let Some(url) = &config.nats_url else {
+        return Vec::new();
+    };
+    match crate::nats::Nats::new(url, &config.nats_subject, &config.ups_name) {
+        Ok(notifier) => vec![crate::crash::notify_on_crash(Box::new(notifier))],
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(not(feature = "nats"))]
+fn crash_notifiers(_config: &Config) -> Vec<CrashNotifier> {
+    Vec::new()
+}
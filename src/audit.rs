@@ -0,0 +1,233 @@
+//! Audit log of every action that changes the UPS or acts on its behalf: instant commands run,
+//! variables set, and shutdown commands run on a forced shutdown.
+//!
+//! Every entry is logged with the `pistachio::audit` target, with its fields attached as `audit_`
+//! followed by their name, so it ends up in the system journal with `--log-target journald`. With
+//! `--audit-log`, entries are also appended to a file as JSON lines, each written and synced to
+//! disk with a single append, so the file can be made append-only with `chattr +a`.
+
+use crate::time::format_rfc3339;
+use log::{error, Level, Record};
+use serde_json::{json, Value};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// Log target of audit entries.
+pub const TARGET: &str = "pistachio::audit";
+
+/// An action that changed the UPS or acted on its behalf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// An instant command was run on the UPS.
+    Command {
+        /// Name of the command.
+        command: String,
+        /// Parameter of the command, if any.
+        param: Option<String>,
+    },
+    /// A writable variable of the UPS was set.
+    SetVar {
+        /// Name of the variable.
+        name: String,
+        /// New value of the variable.
+        value: String,
+    },
+    /// The shell command configured for forced shutdowns was run.
+    ShutdownCommand {
+        /// The shell command.
+        command: String,
+    },
+}
+
+impl Action {
+    /// Returns a short, stable identifier for the kind of action, such as `command`.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Action::Command { .. } => "command",
+            Action::SetVar { .. } => "set_var",
+            Action::ShutdownCommand { .. } => "shutdown_command",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Command { command, param: Some(param) } => write!(f, "ran command {command} with {param:?}"),
+            Action::Command { command, param: None } => write!(f, "ran command {command}"),
+            Action::SetVar { name, value } => write!(f, "set {name} to {value:?}"),
+            Action::ShutdownCommand { command } => write!(f, "ran shutdown command `{command}`"),
+        }
+    }
+}
+
+/// A record of one action, who made it, and how it went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// The action.
+    pub action: Action,
+    /// Name of the UPS the action was made on.
+    pub ups: String,
+    /// Address of the client that asked for the action, or `None` if pistachio made it itself.
+    pub source: Option<SocketAddr>,
+    /// NUT user the action was made as, or `None` if pistachio made it itself.
+    pub user: Option<String>,
+    /// Result of the action, such as `succeeded` or `failed`.
+    pub result: String,
+    /// Why the action failed, if it did.
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Serializes the entry as a JSON object, stamped with the given time.
+    #[must_use]
+    pub fn to_json(&self, time: SystemTime) -> Value {
+        let mut value = json!({
+            "timestamp": format_rfc3339(time),
+            "action": self.action.kind(),
+            "ups": self.ups,
+            "source": self.source.map(|source| source.ip().to_string()),
+            "user": self.user,
+            "result": self.result,
+            "error": self.error,
+        });
+        match &self.action {
+            Action::Command { command, param } => {
+                value["command"] = json!(command);
+                value["param"] = json!(param);
+            }
+            Action::SetVar { name, value: new } => {
+                value["name"] = json!(name);
+                value["value"] = json!(new);
+            }
+            Action::ShutdownCommand { command } => value["command"] = json!(command),
+        }
+        value
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "User {user}")?,
+            None => f.write_str("Pistachio")?,
+        }
+        if let Some(source) = self.source {
+            write!(f, " from {}", source.ip())?;
+        }
+        write!(f, " {} on UPS {}: {}", self.action, self.ups, self.result)?;
+        if let Some(error) = &self.error {
+            write!(f, ": {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Where audit entries are recorded. Clones record to the same file.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    file: Option<Arc<Mutex<(PathBuf, File)>>>,
+}
+
+impl AuditLog {
+    /// Opens a file that entries are appended to, creating it if needed, on top of logging them.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the file cannot be opened.
+    pub fn open(path: &Path) -> io::Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            file: Some(Arc::new(Mutex::new((path.to_path_buf(), file)))),
+        })
+    }
+
+    /// Records an entry made now. Failing to write it to the file is logged as an error.
+    pub fn record(&self, entry: &AuditEntry) {
+        let json = entry.to_json(SystemTime::now());
+        if Level::Info <= log::max_level() {
+            let fields: Vec<(String, String)> = json
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter(|(name, _)| *name != "timestamp")
+                .filter_map(|(name, value)| match value {
+                    Value::Null => None,
+                    Value::String(value) => Some((format!("audit_{name}"), value.clone())),
+                    value => Some((format!("audit_{name}"), value.to_string())),
+                })
+                .collect();
+            log::logger().log(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target(TARGET)
+                    .args(format_args!("{entry}"))
+                    .key_values(&fields.as_slice())
+                    .build(),
+            );
+        }
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+            let (path, file) = &mut *file;
+            let line = format!("{json}\n");
+            if let Err(err) = file.write_all(line.as_bytes()).and_then(|()| file.sync_data()) {
+                error!(target: TARGET, "Failed to write to audit log {}: {err}", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn record_entries() {
+        let path = std::env::temp_dir().join(format!("pistachio-audit-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let log = AuditLog::open(&path).unwrap();
+        let command = AuditEntry {
+            action: Action::Command {
+                command: String::from("beeper.mute"),
+                param: None,
+            },
+            ups: String::from("ups"),
+            source: Some("192.0.2.7:51234".parse().unwrap()),
+            user: Some(String::from("admin")),
+            result: String::from("succeeded"),
+            error: None,
+        };
+        assert_eq!(command.to_string(), "User admin from 192.0.2.7 ran command beeper.mute on UPS ups: succeeded");
+        let shutdown = AuditEntry {
+            action: Action::ShutdownCommand {
+                command: String::from("false"),
+            },
+            ups: String::from("ups"),
+            source: None,
+            user: None,
+            result: String::from("failed"),
+            error: Some(String::from("exit status: 1")),
+        };
+        log.record(&command);
+        log.clone().record(&shutdown);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["action"], "command");
+        assert_eq!(lines[0]["command"], "beeper.mute");
+        assert_eq!(lines[0]["source"], "192.0.2.7");
+        assert_eq!(lines[0]["user"], "admin");
+        assert_eq!(lines[1]["action"], "shutdown_command");
+        assert_eq!(lines[1]["user"], Value::Null);
+        assert_eq!(lines[1]["error"], "exit status: 1");
+        fs::remove_file(&path).unwrap();
+    }
+}
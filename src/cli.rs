@@ -0,0 +1,841 @@
+//! Parsing of the configuration from the command line and the environment, for the binary.
+//!
+//! [`Args`] and its conversion into a [`Config`] require the `cli` feature, which is enabled by
+//! default, so the library can be used without `clap`.
+
+use crate::simulate::Scenario;
+use crate::top::SortColumn;
+use crate::{alerts, auth, cost, dates, groups, logging, metadata, naming, shed};
+use crate::{AccessLogLevel, Backend, Config, MetricIdleAction, Output, UpsTarget};
+use crate::{
+    DEFAULT_BATTERY_EXPECTED_LIFE, DEFAULT_BIND_IP, DEFAULT_BIND_PORT, DEFAULT_BIND_RETRIES, DEFAULT_HTTP_MAX_HEADER_BYTES,
+    DEFAULT_HTTP_READ_TIMEOUT, DEFAULT_HTTP_WRITE_TIMEOUT, DEFAULT_JOURNAL_SIZE, DEFAULT_METADATA_CONNECTIONS,
+    DEFAULT_FAILURE_GRACE_POLLS, DEFAULT_POLL_RATE, DEFAULT_POLL_STALL_THRESHOLD, DEFAULT_RECORD_KEEP, DEFAULT_SAMPLE_INTERVAL, DEFAULT_SIMULATE_DELAY, DEFAULT_SNMP_COMMUNITY,
+    DEFAULT_STATE_SAVE_INTERVAL, DEFAULT_TOP_INTERVAL, DEFAULT_UPS_HOST, DEFAULT_UPS_NAME, DEFAULT_UPS_PORT, DEFAULT_ZABBIX_KEY_TEMPLATE,
+    DEFAULT_FREQUENCY_TOLERANCE, DEFAULT_VOLTAGE_SAG_PERCENT, DEFAULT_VOLTAGE_SWELL_PERCENT,
+};
+#[cfg(feature = "cloudwatch")]
+use crate::{DEFAULT_CLOUDWATCH_INTERVAL, DEFAULT_CLOUDWATCH_NAMESPACE};
+#[cfg(feature = "history")]
+use crate::DEFAULT_HISTORY_RETENTION;
+#[cfg(any(feature = "history", feature = "cloudwatch"))]
+use crate::DEFAULT_KEY_VARS;
+#[cfg(feature = "nats")]
+use crate::DEFAULT_NATS_SUBJECT;
+#[cfg(feature = "otlp")]
+use crate::DEFAULT_OTLP_SERVICE_NAME;
+use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// A collection of arguments to be parsed from the command line or environment, which can be
+/// converted into a [Config].
+#[derive(Parser, Debug)]
+#[command(version, long_version = crate::version::LONG_VERSION, about, long_about = None)]
+pub struct Args {
+    /// Name of the UPS to monitor. Default is `ups`.
+    #[arg(long, env, default_value_t = String::from(DEFAULT_UPS_NAME))]
+    pub ups_name: String,
+    /// Hostname or IP address of the NUT server to monitor, without a scheme or port. IPv6
+    /// addresses may be written in brackets. Default is `127.0.0.1`.
+    #[arg(long, env, default_value_t = String::from(DEFAULT_UPS_HOST), value_parser = parse_host)]
+    pub ups_host: String,
+    /// Port of the NUT server to monitor. Default is `3493`.
+    #[arg(long, env, default_value_t = DEFAULT_UPS_PORT)]
+    pub ups_port: u16,
+    /// UPS to monitor, written like in NUT as `ups@host:port`, instead of the name, host, and port.
+    /// Can be repeated, but only one UPS can be monitored for now.
+    #[arg(long, env, value_delimiter = ',', conflicts_with_all = ["ups_name", "ups_host", "ups_port"])]
+    pub ups: Vec<UpsTarget>,
+    /// Where the variables of the UPS are read from: a NUT server, apcupsd, an SNMP agent or a
+    /// Modbus TCP device at `--ups-host` and `--ups-port`, or a USB UPS read directly with
+    /// `usbhid`. Default is `nut`.
+    #[arg(long, env, value_enum, default_value_t = Backend::Nut)]
+    pub backend: Backend,
+    /// Path to the hidraw device of the UPS, such as `/dev/hidraw0`, when read with the `usbhid`
+    /// backend. Default is the first UPS found.
+    #[cfg(feature = "usbhid")]
+    #[arg(long, env)]
+    pub usbhid_device: Option<PathBuf>,
+    /// SNMP v2c community used to read the UPS with the `snmp` backend. Default is `public`.
+    #[arg(long, env, default_value_t = String::from(DEFAULT_SNMP_COMMUNITY))]
+    pub snmp_community: String,
+    /// Path to a JSON file describing the registers of the device, required by the `modbus`
+    /// backend.
+    #[arg(long, env)]
+    pub modbus_register_map: Option<PathBuf>,
+    /// Where the metrics of the UPS are written: served over HTTP for Prometheus, or written to
+    /// standard output in InfluxDB line protocol with `influx-stdout`, to run as a Telegraf
+    /// `execd` plugin, or as `PUTVAL` commands with `collectd-exec`, to run under the `exec` plugin
+    /// of collectd, without opening an HTTP port. Default is `prometheus`.
+    #[arg(long, env, value_enum, default_value_t = Output::Prometheus)]
+    pub output: Output,
+    /// Poll the UPS once, write its metrics to the output, then exit. Requires an output written
+    /// to standard output.
+    #[arg(long, env)]
+    pub once: bool,
+    /// IP address or host name on which the exporter will serve metrics. A host name is served on
+    /// every address it resolves to, and `::` serves both IPv6 and IPv4. Default is `0.0.0.0`.
+    #[arg(long, env, default_value = DEFAULT_BIND_IP)]
+    pub bind_ip: String,
+    /// Port on which the exporter will serve metrics. Default is `9120`.
+    #[arg(long, env, default_value_t = DEFAULT_BIND_PORT)]
+    pub bind_port: u16,
+    /// Number of times binding to an address that is in use is retried, waiting longer before
+    /// every retry, such as while a previous instance is still shutting down. Default is `5`.
+    #[arg(long, env, default_value_t = DEFAULT_BIND_RETRIES)]
+    pub bind_retries: u32,
+    /// Maximum number of HTTP connections handled at once, beyond which connections are rejected.
+    /// Disabled by default.
+    #[arg(long, env)]
+    pub http_max_connections: Option<usize>,
+    /// Maximum number of HTTP requests per minute from each IP address, beyond which requests are
+    /// rejected. Disabled by default.
+    #[arg(long, env)]
+    pub http_rate_limit: Option<u32>,
+    /// Comma-separated list of origins, such as `https://dashboard.example.com`, whose web pages
+    /// may read the JSON API, or `*` for any origin. Disabled by default.
+    #[arg(long, env, value_delimiter = ',')]
+    pub cors_origins: Vec<String>,
+    /// Comma-separated list of `TOKEN=SCOPE[+SCOPE...]` API tokens, one of which every HTTP
+    /// request but `/ready` must then be sent with, granting the scope it needs: `metrics:read`,
+    /// `status:read`, or `command:execute`. Disabled by default.
+    #[arg(long, env, value_delimiter = ',', hide_env_values = true)]
+    pub api_tokens: Vec<auth::ApiToken>,
+    /// Path to a PEM certificate chain to serve HTTPS with, together with `--tls-key`. Disabled
+    /// by default.
+    #[cfg(feature = "tls")]
+    #[arg(long, env, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// Path to the PEM private key of the certificate set with `--tls-cert`.
+    #[cfg(feature = "tls")]
+    #[arg(long, env, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Path to PEM certificates of a CA that HTTPS clients must then present a certificate signed
+    /// by. Disabled by default.
+    #[cfg(feature = "tls")]
+    #[arg(long, env)]
+    pub tls_client_ca: Option<PathBuf>,
+    /// Comma-separated list of `NAME=SCOPE[+SCOPE...]` scopes granted to HTTPS clients by the
+    /// common name of their certificate, so they need no API token. Disabled by default.
+    #[cfg(feature = "tls")]
+    #[arg(long, env, value_delimiter = ',')]
+    pub tls_client_scopes: Vec<auth::ClientScopes>,
+    /// Level at which every HTTP request is logged with its method, path, status, duration, and
+    /// remote address, or `off`. Default is `debug`.
+    #[arg(long, env, value_enum, default_value_t = AccessLogLevel::Debug)]
+    pub http_access_log: AccessLogLevel,
+    /// Time in seconds to wait for a client to send its request, beyond which the connection is
+    /// closed. Default is `10`.
+    #[arg(long, env, default_value_t = DEFAULT_HTTP_READ_TIMEOUT)]
+    pub http_read_timeout: u64,
+    /// Time in seconds to wait for a client to accept a response, beyond which the connection is
+    /// closed. Default is `10`.
+    #[arg(long, env, default_value_t = DEFAULT_HTTP_WRITE_TIMEOUT)]
+    pub http_write_timeout: u64,
+    /// Time in seconds to keep an HTTP connection open for further requests once a response has
+    /// been sent. Disabled by default, closing connections after every response.
+    #[arg(long, env)]
+    pub http_idle_timeout: Option<u64>,
+    /// Maximum combined size in bytes of the request line and headers of an HTTP request, beyond
+    /// which requests are rejected. Default is `8192`.
+    #[arg(long, env, default_value_t = DEFAULT_HTTP_MAX_HEADER_BYTES)]
+    pub http_max_header_bytes: usize,
+    /// Time in seconds between requests to the NUT server. Must be at least 1 second. Default is `10`.
+    #[arg(long, env, default_value_t = DEFAULT_POLL_RATE, value_parser = clap::value_parser!(u64).range(1..))]
+    pub poll_rate: u64,
+    /// Comma-separated list of `PATTERN [PATTERN...]=SECONDS` groups of variables polled at
+    /// intervals of their own instead of the poll rate, such as `battery.* ups.status=2`, where
+    /// `*` matches any characters. Disabled by default.
+    #[arg(long, env, value_delimiter = ',')]
+    pub poll_groups: Vec<groups::PollGroup>,
+    /// Comma-separated list of variables, such as `input.voltage`, to sample every
+    /// `--sample-interval` seconds, exporting their minimum, maximum, and average over the
+    /// sampling window. `*` matches any characters. Disabled by default.
+    #[arg(long, env, value_delimiter = ',')]
+    pub sample_vars: Vec<String>,
+    /// Time in seconds between samples of the variables of `--sample-vars`. Must be shorter than
+    /// the poll rate. Default is `1`.
+    #[arg(long, env, default_value_t = DEFAULT_SAMPLE_INTERVAL, value_parser = clap::value_parser!(u64).range(1..))]
+    pub sample_interval: u64,
+    /// Time in seconds over which the minimum, maximum, and average of sampled variables are
+    /// exported, which should match the scrape interval. Default is the poll rate.
+    #[arg(long, env)]
+    pub sample_window: Option<u64>,
+    /// Number of connections over which the descriptions and types of variables are fetched at
+    /// startup. Must be at least 1. Default is `4`.
+    #[arg(long, env, default_value_t = DEFAULT_METADATA_CONNECTIONS, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub metadata_connections: usize,
+    /// Number of poll intervals a request to the NUT server may take before its connection is
+    /// considered hung and recreated. Must be at least 1. Default is `3`.
+    #[arg(long, env, default_value_t = DEFAULT_POLL_STALL_THRESHOLD, value_parser = clap::value_parser!(u32).range(1..))]
+    pub poll_stall_threshold: u32,
+    /// Number of failed polls in a row during which gauges keep their last values, before they
+    /// are reset and the connection is reported as lost. Default is `0`.
+    #[arg(long, env, default_value_t = DEFAULT_FAILURE_GRACE_POLLS)]
+    pub failure_grace_polls: u32,
+    /// Time in seconds after which the gauge of a variable that is no longer reported by the UPS
+    /// is removed from `/metrics`. Disabled by default.
+    #[arg(long, env)]
+    pub metric_idle_timeout: Option<u64>,
+    /// What happens to the gauge of a variable after `--metric-idle-timeout`: `remove` it from
+    /// `/metrics`, or set it to `nan`. Default is `remove`.
+    #[arg(long, env, value_enum, default_value_t = MetricIdleAction::Remove)]
+    pub metric_idle_action: MetricIdleAction,
+    /// Adds a `host` label with the host name of the machine to every metric, for when metrics are
+    /// collected without Prometheus setting `instance`. Disabled by default.
+    #[arg(long, env)]
+    pub host_label: bool,
+    /// Name of an environment variable to take the `host` label from instead of the host name,
+    /// such as a Kubernetes node name set with the downward API. Implies `--host-label`.
+    #[arg(long, env)]
+    pub host_label_env: Option<String>,
+    /// Names and labels the gauges of UPS variables are exported with: `pistachio`, `hon95` for
+    /// those of `HON95/prometheus-nut-exporter`, or `nut_exporter` for those of
+    /// `DRuggeri/nut_exporter`, so existing dashboards keep working. Default is `pistachio`.
+    #[arg(long, env, value_enum, default_value_t = naming::NamingScheme::Pistachio)]
+    pub naming_scheme: naming::NamingScheme,
+    /// Semicolon-separated list of `VAR=TEXT` descriptions replacing those given by the server,
+    /// or `VAR+=TEXT` descriptions appended to them, used as the help text of gauges, such as
+    /// `ups.load=Load on the UPS, in percent of its capacity`. Semicolons are used because
+    /// descriptions often contain commas. Variables the server does not describe are described
+    /// from a built-in list of common NUT variables. Disabled by default.
+    #[arg(long, env, value_delimiter = ';')]
+    pub help_texts: Vec<metadata::HelpText>,
+    /// Path to a file in which counters and accumulated values are saved periodically and on
+    /// shutdown, and restored from at startup. Disabled by default.
+    #[arg(long, env)]
+    pub state_file: Option<PathBuf>,
+    /// Time in seconds between saves of the state file while polling. Must be at least 1 second.
+    /// Default is `60`.
+    #[arg(long, env, default_value_t = DEFAULT_STATE_SAVE_INTERVAL, value_parser = clap::value_parser!(u64).range(1..))]
+    pub state_save_interval: u64,
+    /// Number of recent events kept in the journal served at `/api/v1/events`. Must be at least 1.
+    /// Default is `1000`.
+    #[arg(long, env, default_value_t = DEFAULT_JOURNAL_SIZE, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub journal_size: usize,
+    /// Path to a file in which the journal of recent events is saved, so it is restored at
+    /// startup. Disabled by default.
+    #[arg(long, env)]
+    pub journal_file: Option<PathBuf>,
+    /// Price of electricity per kWh, used to estimate the cost of the energy delivered to the load.
+    /// Disabled by default.
+    #[arg(long, env)]
+    pub energy_price: Option<f64>,
+    /// Comma-separated list of `HH:MM-HH:MM=PRICE` periods of the day, in UTC, during which a
+    /// different price per kWh applies, such as `22:00-06:00=0.12`. Requires `--energy-price`.
+    #[arg(long, env, value_delimiter = ',')]
+    pub energy_price_periods: Vec<cost::PricePeriod>,
+    /// Runtime in seconds of the UPS on a full battery at its usual load, when the battery was new,
+    /// against which the runtime factor of the battery health score is measured. Disabled by
+    /// default.
+    #[arg(long, env)]
+    pub battery_rated_runtime: Option<u64>,
+    /// Time in years a battery is expected to last, against which the age factor of the battery
+    /// health score is measured. Default is `4`.
+    #[arg(long, env, default_value_t = DEFAULT_BATTERY_EXPECTED_LIFE, value_parser = clap::value_parser!(u64).range(1..))]
+    pub battery_expected_life: u64,
+    /// Percentage below the nominal input voltage at which a voltage sag is counted. Default is
+    /// `10`.
+    #[arg(long, env, default_value_t = DEFAULT_VOLTAGE_SAG_PERCENT)]
+    pub voltage_sag_percent: f64,
+    /// Percentage above the nominal input voltage at which a voltage swell is counted. Default is
+    /// `10`.
+    #[arg(long, env, default_value_t = DEFAULT_VOLTAGE_SWELL_PERCENT)]
+    pub voltage_swell_percent: f64,
+    /// Deviation in hertz from the nominal input frequency at which a frequency deviation is
+    /// counted. Default is `1`.
+    #[arg(long, env, default_value_t = DEFAULT_FREQUENCY_TOLERANCE)]
+    pub frequency_tolerance: f64,
+    /// Time zone that dates and times reported by the UPS, such as `ups.date` and `ups.time`, are
+    /// in, as `UTC` or an offset such as `+02:00`. Only fixed offsets are supported, so a UPS
+    /// clock that follows daylight saving time is an hour off for part of the year, and should be
+    /// set to UTC instead. Default is `UTC`.
+    #[arg(long, env, default_value_t = dates::TimeZone::UTC)]
+    pub date_timezone: dates::TimeZone,
+    /// Comma-separated list of `VAR=FORMAT` formats of variables whose dates and times are not
+    /// in one of the detected formats, such as `battery.date=%d/%m/%Y`. Disabled by default.
+    #[arg(long, env, value_delimiter = ',')]
+    pub date_formats: Vec<dates::DateFormat>,
+    /// Comma-separated list of `NAME=VARIABLE OPERATOR VALUE [for SECONDS]` alert rules evaluated
+    /// on every poll, such as `on_battery=ups.status contains OB for 60`. Disabled by default.
+    #[arg(long, env, value_delimiter = ',')]
+    pub alerts: Vec<alerts::AlertRule>,
+    /// Comma-separated list of `NAME=SECONDS/PERCENT` load-shedding tiers, in the order they
+    /// should be shed, such as `lab=900/80,nas=600/50`. A tier is recommended for shedding once
+    /// the UPS is on battery and the runtime or charge drops to its threshold. Disabled by default.
+    #[arg(long, env, value_delimiter = ',')]
+    pub shed_tiers: Vec<shed::ShedTier>,
+    /// Shell command run when the UPS starts a forced shutdown, with the `UPS_NAME` and
+    /// `UPS_STATUS` environment variables set. Disabled by default.
+    #[arg(long, env)]
+    pub shutdown_command: Option<String>,
+    /// Enable the `POST /api/v1/command` endpoint for running instant commands on the UPS.
+    /// Requests must authenticate with the credentials of a NUT user allowed to run the command.
+    /// Disabled by default.
+    #[arg(long, env)]
+    pub enable_commands: bool,
+    /// Enable the `POST /api/v1/variable` endpoint for setting writable variables of the UPS.
+    /// Requests must authenticate with the credentials of a NUT user allowed to set the variable.
+    /// Disabled by default.
+    #[arg(long, env)]
+    pub enable_set_vars: bool,
+    /// Disable everything that changes the UPS or acts on its behalf, which are
+    /// `--enable-commands`, `--enable-set-vars`, and `--shutdown-command`, whatever else is set.
+    /// Set to `false` to use them. Default is `true`.
+    #[arg(long, env, default_value_t = true, action = clap::ArgAction::Set)]
+    pub read_only: bool,
+    /// Path to a file to which every command run, variable set, and shutdown command run is
+    /// appended as a JSON line, on top of being logged with the `pistachio::audit` target.
+    /// Disabled by default.
+    #[arg(long, env)]
+    pub audit_log: Option<PathBuf>,
+    /// Print the type, description, and allowed values of every variable of the UPS as JSON, then
+    /// exit.
+    #[arg(long)]
+    pub dump_metadata: bool,
+    /// Where log messages are written: `stderr`, `syslog` through `/dev/log`, or `journald`, which
+    /// keeps the priority of every message and the fields of events. Only `stderr` can be used
+    /// with the `tracing` feature. Default is `stderr`.
+    #[arg(long, env, value_enum, default_value_t = logging::LogTarget::Stderr)]
+    pub log_target: logging::LogTarget,
+    /// Level of log messages to show: `off`, `error`, `warn`, `info`, `debug`, or `trace`, for
+    /// every module. Default is to filter messages with `RUST_LOG`, or to show `info` if it is not
+    /// set.
+    #[arg(long, env, value_enum)]
+    pub log_level: Option<logging::LogLevel>,
+    /// Whether log messages on standard error start with a timestamp: `rfc3339` or `none`.
+    /// Default is `rfc3339`.
+    #[arg(long, env, value_enum, default_value_t = logging::LogTimestamps::Rfc3339)]
+    pub log_timestamps: logging::LogTimestamps,
+    /// When log messages on standard error are colored: `auto` when it is a terminal, `always`, or
+    /// `never`. Default is `auto`.
+    #[arg(long, env, value_enum, default_value_t = logging::LogColor::Auto)]
+    pub log_color: logging::LogColor,
+    /// URL to send a GET request to after every successful poll, such as a Healthchecks.io check.
+    /// Disabled by default.
+    #[arg(long, env)]
+    pub ping_url: Option<String>,
+    /// Path to a lease file shared by replicas monitoring the same UPS, so only the leader pings,
+    /// pushes values, and publishes events to external services. Disabled by default.
+    #[arg(long, env)]
+    pub ha_lease_file: Option<PathBuf>,
+    /// Identity of this replica in the lease file. Default is the host name and process ID.
+    #[arg(long, env)]
+    pub ha_id: Option<String>,
+    /// Time in seconds without a successful poll after which `/ready` answers `503`, so a load
+    /// balancer can send scrapes to another replica. Disabled by default, always answering `200`.
+    #[arg(long, env)]
+    pub ready_max_staleness: Option<u64>,
+    /// Path to a file to which the variables from every poll will be appended, as JSON lines if
+    /// the file extension is `.jsonl` and as CSV otherwise. Disabled by default.
+    #[arg(long, env)]
+    pub record: Option<PathBuf>,
+    /// Size in megabytes at which the record file is rotated. Disabled by default.
+    #[arg(long, env)]
+    pub record_max_size: Option<u64>,
+    /// Age in hours at which the record file is rotated. Disabled by default.
+    #[arg(long, env)]
+    pub record_max_age: Option<u64>,
+    /// Number of rotated record files to keep. Default is `5`.
+    #[arg(long, env, default_value_t = DEFAULT_RECORD_KEEP)]
+    pub record_keep: usize,
+    /// Address of a Zabbix server or proxy to send values to with the trapper protocol, with an
+    /// optional port. Disabled by default.
+    #[arg(long, env)]
+    pub zabbix_server: Option<String>,
+    /// Name of the host in Zabbix that values are sent for. Default is the name of the UPS.
+    #[arg(long, env)]
+    pub zabbix_host: Option<String>,
+    /// Template for Zabbix item keys, where `{var}` is replaced by the variable name. Default is
+    /// `pistachio[{var}]`.
+    #[arg(long, env, default_value_t = String::from(DEFAULT_ZABBIX_KEY_TEMPLATE))]
+    pub zabbix_key_template: String,
+    /// Comma-separated list of `variable=key` pairs overriding the Zabbix item key of specific
+    /// variables.
+    #[arg(long, env, value_delimiter = ',', value_parser = parse_key_value)]
+    pub zabbix_keys: Vec<(String, String)>,
+    /// URL of a NATS server to publish events to, such as `nats://localhost:4222`. Disabled by
+    /// default.
+    #[cfg(feature = "nats")]
+    #[arg(long, env)]
+    pub nats_url: Option<String>,
+    /// Subject prefix for events published to NATS. Default is `pistachio.events`.
+    #[cfg(feature = "nats")]
+    #[arg(long, env, default_value_t = String::from(DEFAULT_NATS_SUBJECT))]
+    pub nats_subject: String,
+    /// URL of an OpenTelemetry collector to send every poll to as a trace span and every event to
+    /// as a log record with OTLP over HTTP, such as `http://localhost:4318`. Disabled by default.
+    #[cfg(feature = "otlp")]
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+    /// Name of the service that spans and log records are sent for. Default is `pistachio`.
+    #[cfg(feature = "otlp")]
+    #[arg(long, env = "OTEL_SERVICE_NAME", default_value_t = String::from(DEFAULT_OTLP_SERVICE_NAME))]
+    pub otlp_service_name: String,
+    /// Path to a SQLite database in which to record poll history. Disabled by default.
+    #[cfg(feature = "history")]
+    #[arg(long, env)]
+    pub history_db: Option<PathBuf>,
+    /// Comma-separated list of variables to record in the history database on every poll.
+    #[cfg(feature = "history")]
+    #[arg(long, env, value_delimiter = ',', default_values = DEFAULT_KEY_VARS)]
+    pub history_vars: Vec<String>,
+    /// Number of hours to retain records in the history database. Default is `168`.
+    #[cfg(feature = "history")]
+    #[arg(long, env, default_value_t = DEFAULT_HISTORY_RETENTION)]
+    pub history_retention: u64,
+    /// AWS region to publish metrics to CloudWatch in. Disabled by default.
+    #[cfg(feature = "cloudwatch")]
+    #[arg(long, env)]
+    pub cloudwatch_region: Option<String>,
+    /// CloudWatch namespace to publish metrics in. Default is `Pistachio`.
+    #[cfg(feature = "cloudwatch")]
+    #[arg(long, env, default_value_t = String::from(DEFAULT_CLOUDWATCH_NAMESPACE))]
+    pub cloudwatch_namespace: String,
+    /// Comma-separated list of `Name=Value` dimensions to attach to CloudWatch metrics. Default is
+    /// `UPS=<UPS_NAME>`.
+    #[cfg(feature = "cloudwatch")]
+    #[arg(long, env, value_delimiter = ',', value_parser = parse_key_value)]
+    pub cloudwatch_dimensions: Vec<(String, String)>,
+    /// Comma-separated list of variables to publish to CloudWatch.
+    #[cfg(feature = "cloudwatch")]
+    #[arg(long, env, value_delimiter = ',', default_values = DEFAULT_KEY_VARS)]
+    pub cloudwatch_vars: Vec<String>,
+    /// Time in seconds between publishes to CloudWatch. Default is `60`.
+    #[cfg(feature = "cloudwatch")]
+    #[arg(long, env, default_value_t = DEFAULT_CLOUDWATCH_INTERVAL)]
+    pub cloudwatch_interval: u64,
+    /// Port on which to serve the gRPC API, on the same IP address as metrics. The API is served
+    /// without TLS or authentication, so it cannot be used with `--api-tokens` or
+    /// `--tls-client-ca`. Disabled by default.
+    #[cfg(feature = "grpc")]
+    #[arg(long, env)]
+    pub grpc_port: Option<u16>,
+    /// Tool to run instead of the exporter.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Tools that can be run instead of the exporter.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Serve a simulated UPS over the NUT protocol, for demos and for developing dashboards
+    /// without hardware.
+    Simulate(SimulateArgs),
+    /// Poll the UPS and record every poll to a file, without exporting metrics.
+    Record(RecordArgs),
+    /// Export metrics from polls recorded with `record` or `--record`, instead of from a UPS.
+    Replay(ReplayArgs),
+    /// Show the variables added, removed, or changed between two snapshots of a UPS, and exit
+    /// with status 1 if there are any.
+    Diff(DiffArgs),
+    /// Show a live table of the status, charge, load, runtime, and age of the last poll of
+    /// several UPSes.
+    Top(TopArgs),
+}
+
+/// Arguments of the `simulate` command.
+#[derive(clap::Args, Debug)]
+pub struct SimulateArgs {
+    /// Address on which to serve the NUT protocol. Default is `127.0.0.1:3493`.
+    #[arg(long, default_value_t = SocketAddr::from(([127, 0, 0, 1], DEFAULT_UPS_PORT)))]
+    pub listen: SocketAddr,
+    /// Name of the simulated UPS. Default is `ups`.
+    #[arg(long, default_value_t = String::from(DEFAULT_UPS_NAME))]
+    pub name: String,
+    /// How the simulated UPS behaves: `online`, `outage` to go on battery and drain it,
+    /// `flapping` to switch between online and on battery, or `stale` to stop updating its
+    /// variables. Default is `online`.
+    #[arg(long, value_enum, default_value_t = Scenario::Online)]
+    pub scenario: Scenario,
+    /// Time in seconds until the scenario starts, or between the changes of a flapping UPS.
+    /// Default is `30`.
+    #[arg(long, default_value_t = DEFAULT_SIMULATE_DELAY)]
+    pub delay: u64,
+}
+
+/// Arguments of the `record` command.
+#[derive(clap::Args, Debug)]
+pub struct RecordArgs {
+    /// Path to the file every poll is appended to, as JSON lines for `.jsonl`, `.ndjson`, and
+    /// `.json` files and as CSV otherwise.
+    #[arg(long)]
+    pub output: PathBuf,
+}
+
+/// Arguments of the `replay` command.
+#[derive(clap::Args, Debug)]
+pub struct ReplayArgs {
+    /// Path to the file of recorded polls.
+    pub input: PathBuf,
+    /// How many times faster than they were recorded the polls are replayed, such as `10`.
+    /// Default is `1`.
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f64,
+    /// Exit once every poll has been replayed, instead of exporting the last one until stopped.
+    #[arg(long)]
+    pub exit: bool,
+}
+
+/// Arguments of the `diff` command.
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// First snapshot: a JSON file written by `--dump-metadata` or served by `/api/v1/variables`,
+    /// a recording, of which the last poll is used, or a UPS to poll, such as `ups@nut.local`.
+    pub before: String,
+    /// Second snapshot, read the same way as the first.
+    pub after: String,
+    /// Only show variables that were added or removed, not those whose values changed.
+    #[arg(long)]
+    pub ignore_values: bool,
+}
+
+/// Arguments of the `top` command.
+#[derive(clap::Args, Debug)]
+pub struct TopArgs {
+    /// UPS to show, written like in NUT as `ups@host:port`. Default is every UPS given with
+    /// `--ups`, or the UPS given by `--ups-name`, `--ups-host`, and `--ups-port`.
+    pub targets: Vec<UpsTarget>,
+    /// Column the table is sorted by: `name`, `status` with failed polls and UPSes on battery
+    /// first, `charge` and `runtime` lowest first, `load` highest first, or `age` oldest first.
+    /// Default is `name`.
+    #[arg(long, value_enum, default_value_t = SortColumn::Name)]
+    pub sort: SortColumn,
+    /// Time in seconds between polls of each UPS. Default is `2`.
+    #[arg(long, default_value_t = DEFAULT_TOP_INTERVAL)]
+    pub interval: u64,
+}
+
+/// Parses a `key=value` pair from the command line.
+fn parse_key_value(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got `{input}`")),
+    }
+}
+
+/// Parses a host name or IP address from the command line, stripping a scheme such as `tcp://`,
+/// a trailing slash, and the brackets of an IPv6 address.
+fn parse_host(input: &str) -> Result<String, String> {
+    let host = input.trim();
+    let host = host.split_once("://").map_or(host, |(_, rest)| rest).trim_end_matches('/');
+    if host.is_empty() {
+        return Err(String::from("the host must not be empty"));
+    }
+    if let Some(bracketed) = host.strip_prefix('[') {
+        return match bracketed.split_once(']') {
+            Some((address, "")) if address.parse::<std::net::Ipv6Addr>().is_ok() => Ok(address.to_string()),
+            Some((_, "")) => Err(format!("`{input}` is not a valid IPv6 address")),
+            Some(_) => Err(format!("`{input}` has a port, which must be given with --ups-port instead")),
+            None => Err(format!("`{input}` has an IPv6 address that is not closed with `]`")),
+        };
+    }
+    if host.contains(':') {
+        if host.parse::<std::net::Ipv6Addr>().is_ok() {
+            return Ok(host.to_string());
+        }
+        return Err(format!("`{input}` has a port, which must be given with --ups-port instead"));
+    }
+    if host.contains(|c: char| c.is_whitespace() || c == '/' || c == '@') {
+        return Err(format!("`{input}` is not a host name or IP address"));
+    }
+    Ok(host.to_string())
+}
+
+impl From<Args> for Config {
+    fn from(args: Args) -> Config {
+        Config {
+            ups_name: args.ups.first().map_or(args.ups_name, |target| target.name.clone()),
+            ups_host: args.ups.first().map_or(args.ups_host, |target| target.host.clone()),
+            ups_port: args.ups.first().map_or(args.ups_port, |target| target.port),
+            targets: args.ups,
+            backend: args.backend,
+            output: args.output,
+            once: args.once,
+            #[cfg(feature = "usbhid")]
+            usbhid_device: args.usbhid_device,
+            snmp_community: args.snmp_community,
+            modbus_register_map: args.modbus_register_map,
+            bind_ip: args.bind_ip,
+            bind_port: args.bind_port,
+            bind_retries: args.bind_retries,
+            http_max_connections: args.http_max_connections,
+            http_rate_limit: args.http_rate_limit,
+            cors_origins: args.cors_origins,
+            api_tokens: args.api_tokens,
+            #[cfg(feature = "tls")]
+            tls_cert: args.tls_cert,
+            #[cfg(feature = "tls")]
+            tls_key: args.tls_key,
+            #[cfg(feature = "tls")]
+            tls_client_ca: args.tls_client_ca,
+            #[cfg(feature = "tls")]
+            tls_client_scopes: args.tls_client_scopes,
+            http_access_log: args.http_access_log,
+            http_read_timeout: args.http_read_timeout,
+            http_write_timeout: args.http_write_timeout,
+            http_idle_timeout: args.http_idle_timeout,
+            http_max_header_bytes: args.http_max_header_bytes,
+            poll_rate: args.poll_rate,
+            metadata_connections: args.metadata_connections,
+            poll_stall_threshold: args.poll_stall_threshold,
+            failure_grace_polls: args.failure_grace_polls,
+            metric_idle_timeout: args.metric_idle_timeout,
+            metric_idle_action: args.metric_idle_action,
+            host_label: args.host_label,
+            host_label_env: args.host_label_env,
+            naming_scheme: args.naming_scheme,
+            help_texts: args.help_texts,
+            state_file: args.state_file,
+            state_save_interval: args.state_save_interval,
+            journal_size: args.journal_size,
+            journal_file: args.journal_file,
+            energy_price: args.energy_price,
+            energy_price_periods: args.energy_price_periods,
+            battery_rated_runtime: args.battery_rated_runtime,
+            battery_expected_life: args.battery_expected_life,
+            voltage_sag_percent: args.voltage_sag_percent,
+            voltage_swell_percent: args.voltage_swell_percent,
+            frequency_tolerance: args.frequency_tolerance,
+            date_timezone: args.date_timezone,
+            date_formats: args.date_formats,
+            alerts: args.alerts,
+            shed_tiers: args.shed_tiers,
+            poll_groups: args.poll_groups,
+            sample_vars: args.sample_vars,
+            sample_interval: args.sample_interval,
+            sample_window: args.sample_window,
+            shutdown_command: args.shutdown_command,
+            enable_commands: args.enable_commands,
+            enable_set_vars: args.enable_set_vars,
+            read_only: args.read_only,
+            audit_log: args.audit_log,
+            ping_url: args.ping_url,
+            ha_lease_file: args.ha_lease_file,
+            ha_id: args.ha_id,
+            ready_max_staleness: args.ready_max_staleness,
+            record: args.record,
+            record_max_size: args.record_max_size,
+            record_max_age: args.record_max_age,
+            record_keep: args.record_keep,
+            zabbix_server: args.zabbix_server,
+            zabbix_host: args.zabbix_host,
+            zabbix_key_template: args.zabbix_key_template,
+            zabbix_keys: args.zabbix_keys,
+            #[cfg(feature = "nats")]
+            nats_url: args.nats_url,
+            #[cfg(feature = "nats")]
+            nats_subject: args.nats_subject,
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: args.otlp_endpoint,
+            #[cfg(feature = "otlp")]
+            otlp_service_name: args.otlp_service_name,
+            #[cfg(feature = "history")]
+            history_db: args.history_db,
+            #[cfg(feature = "history")]
+            history_vars: args.history_vars,
+            #[cfg(feature = "history")]
+            history_retention: args.history_retention,
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_region: args.cloudwatch_region,
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_namespace: args.cloudwatch_namespace,
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_dimensions: args.cloudwatch_dimensions,
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_vars: args.cloudwatch_vars,
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_interval: args.cloudwatch_interval,
+            #[cfg(feature = "grpc")]
+            grpc_port: args.grpc_port,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hosts() {
+        assert_eq!(parse_host("nut.local").unwrap(), "nut.local");
+        assert_eq!(parse_host(" tcp://nut.local/ ").unwrap(), "nut.local");
+        assert_eq!(parse_host("[::1]").unwrap(), "::1");
+        assert_eq!(parse_host("fe80::1").unwrap(), "fe80::1");
+        assert_eq!(parse_host("").unwrap_err(), "the host must not be empty");
+        assert_eq!(parse_host("tcp://").unwrap_err(), "the host must not be empty");
+        assert!(parse_host("nut.local:3493").unwrap_err().contains("--ups-port"));
+        assert!(parse_host("[::1]:3493").unwrap_err().contains("--ups-port"));
+        assert!(parse_host("[nut.local]").is_err());
+        assert!(parse_host("ups@nut.local").is_err());
+        assert!(Args::try_parse_from(["pistachio", "--ups-host", ""]).is_err());
+    }
+
+    #[test]
+    fn parse_default_args() {
+        let args = Args::parse();
+        assert_eq!(args.ups_name, DEFAULT_UPS_NAME);
+        assert_eq!(args.ups_host, DEFAULT_UPS_HOST);
+        assert_eq!(args.ups_port, DEFAULT_UPS_PORT);
+        assert!(args.ups.is_empty());
+        assert_eq!(args.backend, Backend::Nut);
+        assert_eq!(args.output, Output::Prometheus);
+        assert!(!args.once);
+        #[cfg(feature = "usbhid")]
+        assert_eq!(args.usbhid_device, None);
+        assert_eq!(args.snmp_community, DEFAULT_SNMP_COMMUNITY);
+        assert_eq!(args.modbus_register_map, None);
+        assert_eq!(args.bind_ip, DEFAULT_BIND_IP);
+        assert_eq!(args.bind_port, DEFAULT_BIND_PORT);
+        assert_eq!(args.bind_retries, DEFAULT_BIND_RETRIES);
+        assert_eq!(args.poll_rate, DEFAULT_POLL_RATE);
+        assert_eq!(args.metadata_connections, DEFAULT_METADATA_CONNECTIONS);
+        assert_eq!(args.poll_stall_threshold, DEFAULT_POLL_STALL_THRESHOLD);
+        assert_eq!(args.failure_grace_polls, DEFAULT_FAILURE_GRACE_POLLS);
+        assert_eq!(args.state_file, None);
+        assert_eq!(args.state_save_interval, DEFAULT_STATE_SAVE_INTERVAL);
+        assert_eq!(args.http_max_connections, None);
+        assert_eq!(args.http_rate_limit, None);
+        assert!(args.cors_origins.is_empty());
+        assert!(args.api_tokens.is_empty());
+        assert_eq!(args.http_access_log, AccessLogLevel::Debug);
+        assert_eq!(args.http_read_timeout, DEFAULT_HTTP_READ_TIMEOUT);
+        assert_eq!(args.http_write_timeout, DEFAULT_HTTP_WRITE_TIMEOUT);
+        assert_eq!(args.http_idle_timeout, None);
+        assert_eq!(args.http_max_header_bytes, DEFAULT_HTTP_MAX_HEADER_BYTES);
+        assert_eq!(args.journal_size, DEFAULT_JOURNAL_SIZE);
+        assert_eq!(args.journal_file, None);
+        assert_eq!(args.energy_price, None);
+        assert!(args.energy_price_periods.is_empty());
+        assert_eq!(args.battery_rated_runtime, None);
+        assert_eq!(args.battery_expected_life, DEFAULT_BATTERY_EXPECTED_LIFE);
+        assert_eq!(args.voltage_sag_percent, DEFAULT_VOLTAGE_SAG_PERCENT);
+        assert_eq!(args.voltage_swell_percent, DEFAULT_VOLTAGE_SWELL_PERCENT);
+        assert_eq!(args.frequency_tolerance, DEFAULT_FREQUENCY_TOLERANCE);
+        assert_eq!(args.date_timezone, dates::TimeZone::UTC);
+        assert!(args.date_formats.is_empty());
+        assert!(args.alerts.is_empty());
+        assert!(args.shed_tiers.is_empty());
+        assert!(args.poll_groups.is_empty());
+        assert!(args.sample_vars.is_empty());
+        assert_eq!(args.sample_interval, DEFAULT_SAMPLE_INTERVAL);
+        assert_eq!(args.sample_window, None);
+        assert_eq!(args.shutdown_command, None);
+        assert!(!args.enable_commands);
+        assert!(!args.enable_set_vars);
+        assert!(args.read_only);
+        assert_eq!(args.audit_log, None);
+        assert!(!args.dump_metadata);
+        assert_eq!(args.log_target, logging::LogTarget::Stderr);
+        assert_eq!(args.log_level, None);
+        assert_eq!(args.log_timestamps, logging::LogTimestamps::Rfc3339);
+        assert_eq!(args.log_color, logging::LogColor::Auto);
+        assert_eq!(args.ping_url, None);
+        assert_eq!(args.ha_lease_file, None);
+        assert_eq!(args.ha_id, None);
+        assert_eq!(args.ready_max_staleness, None);
+        assert_eq!(args.metric_idle_action, MetricIdleAction::Remove);
+        assert!(!args.host_label);
+        assert_eq!(args.host_label_env, None);
+        assert_eq!(args.naming_scheme, naming::NamingScheme::Pistachio);
+        assert!(args.help_texts.is_empty());
+        assert_eq!(args.record, None);
+        assert_eq!(args.record_max_size, None);
+        assert_eq!(args.record_max_age, None);
+        assert_eq!(args.record_keep, DEFAULT_RECORD_KEEP);
+        assert_eq!(args.zabbix_server, None);
+        assert_eq!(args.zabbix_host, None);
+        assert_eq!(args.zabbix_key_template, DEFAULT_ZABBIX_KEY_TEMPLATE);
+        assert!(args.zabbix_keys.is_empty());
+        #[cfg(feature = "nats")]
+        {
+            assert_eq!(args.nats_url, None);
+            assert_eq!(args.nats_subject, DEFAULT_NATS_SUBJECT);
+        }
+        #[cfg(feature = "otlp")]
+        {
+            assert_eq!(args.otlp_endpoint, None);
+            assert_eq!(args.otlp_service_name, DEFAULT_OTLP_SERVICE_NAME);
+        }
+        #[cfg(feature = "tls")]
+        {
+            assert_eq!(args.tls_cert, None);
+            assert_eq!(args.tls_key, None);
+            assert_eq!(args.tls_client_ca, None);
+            assert!(args.tls_client_scopes.is_empty());
+        }
+        #[cfg(feature = "history")]
+        {
+            assert_eq!(args.history_db, None);
+            assert_eq!(args.history_vars, DEFAULT_KEY_VARS);
+            assert_eq!(args.history_retention, DEFAULT_HISTORY_RETENTION);
+        }
+        #[cfg(feature = "cloudwatch")]
+        {
+            assert_eq!(args.cloudwatch_region, None);
+            assert_eq!(args.cloudwatch_namespace, DEFAULT_CLOUDWATCH_NAMESPACE);
+            assert!(args.cloudwatch_dimensions.is_empty());
+            assert_eq!(args.cloudwatch_vars, DEFAULT_KEY_VARS);
+            assert_eq!(args.cloudwatch_interval, DEFAULT_CLOUDWATCH_INTERVAL);
+        }
+        #[cfg(feature = "grpc")]
+        assert_eq!(args.grpc_port, None);
+        assert!(args.command.is_none());
+    }
+
+    #[test]
+    fn convert_args_into_config() {
+        assert_eq!(Config::builder().build().unwrap(), Config::from(Args::parse_from(["pistachio"])));
+        let config = Config::from(Args::parse_from(["pistachio", "--ups", "rack@nut.local:3494"]));
+        assert_eq!((config.ups_name.as_str(), config.ups_host.as_str(), config.ups_port), ("rack", "nut.local", 3494));
+        let config = Config::from(Args::parse_from(["pistachio", "--enable-commands", "--read-only=false"]));
+        assert!(config.enable_commands && !config.read_only);
+    }
+
+    #[test]
+    fn parse_simulate_command() {
+        let args = Args::parse_from(["pistachio", "simulate", "--scenario", "outage"]);
+        let Some(Command::Simulate(simulate)) = args.command else {
+            panic!("expected the simulate command");
+        };
+        assert_eq!(simulate.listen, SocketAddr::from(([127, 0, 0, 1], DEFAULT_UPS_PORT)));
+        assert_eq!(simulate.name, DEFAULT_UPS_NAME);
+        assert_eq!(simulate.scenario, Scenario::Outage);
+        assert_eq!(simulate.delay, DEFAULT_SIMULATE_DELAY);
+    }
+
+    #[test]
+    fn parse_record_and_replay_commands() {
+        let args = Args::parse_from(["pistachio", "record", "--output", "session.jsonl"]);
+        assert!(matches!(args.command, Some(Command::Record(record)) if record.output.as_os_str() == "session.jsonl"));
+        let args = Args::parse_from(["pistachio", "replay", "session.jsonl"]);
+        let Some(Command::Replay(replay)) = args.command else {
+            panic!("expected the replay command");
+        };
+        assert_eq!(replay.input, PathBuf::from("session.jsonl"));
+        assert_eq!(replay.speed, 1.0);
+        assert!(!replay.exit);
+    }
+
+    #[test]
+    fn parse_diff_command() {
+        let args = Args::parse_from(["pistachio", "diff", "before.json", "ups@nut.local", "--ignore-values"]);
+        let Some(Command::Diff(diff)) = args.command else {
+            panic!("expected the diff command");
+        };
+        assert_eq!((diff.before.as_str(), diff.after.as_str()), ("before.json", "ups@nut.local"));
+        assert!(diff.ignore_values);
+    }
+
+    #[test]
+    fn parse_top_command() {
+        let args = Args::parse_from(["pistachio", "top", "rack@nut.local", "desk@nut.local:3494", "--sort", "charge"]);
+        let Some(Command::Top(top)) = args.command else {
+            panic!("expected the top command");
+        };
+        assert_eq!(top.targets.iter().map(ToString::to_string).collect::<Vec<_>>(), ["rack@nut.local:3493", "desk@nut.local:3494"]);
+        assert_eq!(top.sort, SortColumn::Charge);
+        assert_eq!(top.interval, DEFAULT_TOP_INTERVAL);
+    }
+}
@@ -0,0 +1,178 @@
+//! The interface pistachio uses to talk to a UPS, so the polling logic does not depend on a
+//! specific NUT client implementation.
+
+use crate::variable::dedup;
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use log::warn;
+use std::collections::HashSet;
+use std::fmt;
+
+/// A client that can read variables from the UPS devices of a NUT server, or any other source
+/// that can present its data the same way.
+pub trait UpsClient {
+    /// Returns all variables of the given UPS, along with their current values.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the variables cannot be retrieved, such as if the connection
+    /// was lost or the UPS does not exist.
+    fn list_vars(&mut self, ups_name: &str) -> Result<Vec<Variable>>;
+
+    /// Returns a single variable of the given UPS, along with its current value. By default, all
+    /// variables are listed and the one asked for is picked from them.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the variable cannot be retrieved, or if the UPS does not have
+    /// it.
+    fn get_var(&mut self, ups_name: &str, var_name: &str) -> Result<Variable> {
+        self.list_vars(ups_name)?
+            .into_iter()
+            .find(|var| var.name() == var_name)
+            .ok_or(Error::Protocol(rups::NutError::VarNotSupported))
+    }
+
+    /// Returns the description of a variable of the given UPS.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the description cannot be retrieved.
+    fn get_var_description(&mut self, ups_name: &str, var_name: &str) -> Result<String>;
+
+    /// Returns the type of a variable of the given UPS, such as whether it is a number, a
+    /// string, or one of an enumeration of values, and whether it is writable.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the type cannot be retrieved.
+    fn get_var_type(&mut self, ups_name: &str, var_name: &str) -> Result<VariableDefinition>;
+
+    /// Returns the values allowed for an enumerated variable of the given UPS.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the values cannot be retrieved.
+    fn list_var_enum(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<String>>;
+
+    /// Returns the ranges of values allowed for a numeric variable of the given UPS.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the ranges cannot be retrieved.
+    fn list_var_range(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<VariableRange>>;
+
+    /// Returns the addresses of the clients logged in to the given UPS, such as `upsmon`
+    /// instances.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the clients cannot be retrieved.
+    fn list_clients(&mut self, ups_name: &str) -> Result<Vec<String>>;
+
+    /// Returns the names of the instant commands supported by the given UPS.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the commands cannot be retrieved.
+    fn list_commands(&mut self, ups_name: &str) -> Result<Vec<String>>;
+
+    /// Returns the description of an instant command of the given UPS.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the description cannot be retrieved.
+    fn get_command_description(&mut self, ups_name: &str, command: &str) -> Result<String>;
+
+    /// Gracefully closes the client.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the client could not be closed cleanly.
+    fn close(self) -> Result<()>
+    where
+        Self: Sized;
+}
+
+/// A connection to a NUT server, which is the client used to talk to a UPS unless another
+/// backend is configured.
+///
+/// Variables are listed sorted by name, and a variable reported more than once by a buggy driver
+/// is only listed once, with the value reported last.
+pub struct Connection {
+    inner: rups::blocking::Connection,
+    /// Variables that were reported more than once with different values, which are only warned
+    /// about the first time.
+    conflicting: HashSet<String>,
+}
+
+impl Connection {
+    /// Opens a new connection to the NUT server at `host` and `port`.
+    ///
+    /// # Errors
+    ///
+    /// An [`Error::Config`] will be returned if the host and port are not a valid address, and a
+    /// connection or protocol error will be returned if the NUT server cannot be reached.
+    pub fn open(host: &str, port: u16) -> Result<Connection> {
+        let rups_host = rups::Host::try_from((host.to_string(), port))
+            .map_err(|err| Error::Config(format!("invalid UPS host {host}:{port}: {err}")))?;
+        let rups_config = rups::ConfigBuilder::new().with_host(rups_host).build();
+        Ok(Connection {
+            inner: rups::blocking::Connection::new(&rups_config)?,
+            conflicting: HashSet::new(),
+        })
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Connection")
+    }
+}
+
+impl UpsClient for Connection {
+    fn list_vars(&mut self, ups_name: &str) -> Result<Vec<Variable>> {
+        let (vars, conflicting) = dedup(self.inner.list_vars(ups_name)?.into_iter().map(Variable::from).collect());
+        for name in conflicting {
+            if self.conflicting.insert(name.clone()) {
+                warn!("UPS {ups_name} reported variable {name} more than once with different values, using the last one");
+            }
+        }
+        Ok(vars)
+    }
+
+    fn get_var(&mut self, ups_name: &str, var_name: &str) -> Result<Variable> {
+        Ok(self.inner.get_var(ups_name, var_name)?.into())
+    }
+
+    fn get_var_description(&mut self, ups_name: &str, var_name: &str) -> Result<String> {
+        Ok(self.inner.get_var_description(ups_name, var_name)?)
+    }
+
+    fn get_var_type(&mut self, ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        Ok(self.inner.get_var_type(ups_name, var_name)?.into())
+    }
+
+    fn list_var_enum(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<String>> {
+        Ok(self.inner.list_var_enum(ups_name, var_name)?)
+    }
+
+    fn list_var_range(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<VariableRange>> {
+        Ok(self.inner.list_var_range(ups_name, var_name)?.into_iter().map(VariableRange::from).collect())
+    }
+
+    fn list_clients(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        Ok(self.inner.list_clients(ups_name)?)
+    }
+
+    fn list_commands(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        Ok(self.inner.list_commands(ups_name)?)
+    }
+
+    fn get_command_description(&mut self, ups_name: &str, command: &str) -> Result<String> {
+        Ok(self.inner.get_command_description(ups_name, command)?)
+    }
+
+    fn close(self) -> Result<()> {
+        Ok(self.inner.close()?)
+    }
+}
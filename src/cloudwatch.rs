@@ -0,0 +1,272 @@
+//! Publishing of UPS metrics to AWS CloudWatch.
+//!
+//! Metrics are sent with the `PutMetricData` action of the CloudWatch query API, signed with AWS
+//! Signature Version 4, so no AWS SDK or async runtime is required. Credentials are read from the
+//! standard `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, and `AWS_SESSION_TOKEN` environment
+//! variables.
+
+use crate::sink::Sink;
+use crate::time::Civil;
+use crate::Variable;
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::error::Error;
+use std::fmt::Write;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use ureq::Agent;
+
+/// Maximum time allowed for a single request to CloudWatch.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of metrics allowed in a single `PutMetricData` request.
+const MAX_METRICS_PER_REQUEST: usize = 1000;
+
+/// CloudWatch units for common UPS variables. Variables not listed here are sent without a unit.
+const UNITS: &[(&str, &str)] = &[
+    ("battery.charge", "Percent"),
+    ("battery.runtime", "Seconds"),
+    ("ups.load", "Percent"),
+];
+
+/// Credentials used to sign requests.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    /// AWS access key ID.
+    pub access_key_id: String,
+    /// AWS secret access key.
+    pub secret_access_key: String,
+    /// Session token, when using temporary credentials.
+    pub session_token: Option<String>,
+}
+
+impl Credentials {
+    /// Reads credentials from the standard AWS environment variables.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the access key ID or secret access key are not set.
+    pub fn from_env() -> Result<Credentials, env::VarError> {
+        Ok(Credentials {
+            access_key_id: env::var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: env::var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+/// A sink that publishes selected numeric variables to CloudWatch at a fixed interval.
+#[derive(Debug)]
+pub struct CloudWatch {
+    agent: Agent,
+    credentials: Credentials,
+    region: String,
+    namespace: String,
+    dimensions: Vec<(String, String)>,
+    vars: Vec<String>,
+    interval: Duration,
+    last_publish: Option<Instant>,
+}
+
+impl CloudWatch {
+    /// Creates a sink that publishes the given variables to a CloudWatch namespace in a region,
+    /// at most once per interval, with the given dimensions attached to every metric.
+    #[must_use]
+    pub fn new(
+        credentials: Credentials,
+        region: &str,
+        namespace: &str,
+        dimensions: Vec<(String, String)>,
+        vars: &[String],
+        interval: Duration,
+    ) -> CloudWatch {
+        let agent = Agent::config_builder()
+            .timeout_global(Some(REQUEST_TIMEOUT))
+            .build()
+            .into();
+        CloudWatch {
+            agent,
+            credentials,
+            region: region.to_string(),
+            namespace: namespace.to_string(),
+            dimensions,
+            vars: vars.to_vec(),
+            interval,
+            last_publish: None,
+        }
+    }
+
+    /// Builds the form-encoded body of a `PutMetricData` request.
+    fn request_body(&self, vars: &[Variable]) -> Option<String> {
+        let mut body = format!("Action=PutMetricData&Version=2010-08-01&Namespace={}", uri_encode(&self.namespace));
+        let mut count = 0;
+        for var in vars.iter().filter(|var| self.vars.iter().any(|v| v == var.name())) {
+            let Ok(value) = var.value().trim().parse::<f64>() else {
+                continue;
+            };
+            count += 1;
+            if count > MAX_METRICS_PER_REQUEST {
+                break;
+            }
+            let member = format!("MetricData.member.{count}");
+            let _ = write!(body, "&{member}.MetricName={}&{member}.Value={value}", uri_encode(&crate::gauge_name(var.name())));
+            if let Some((_, unit)) = UNITS.iter().find(|(name, _)| *name == var.name()) {
+                let _ = write!(body, "&{member}.Unit={unit}");
+            }
+            for (i, (name, value)) in self.dimensions.iter().enumerate() {
+                let dimension = format!("{member}.Dimensions.member.{}", i + 1);
+                let _ = write!(body, "&{dimension}.Name={}&{dimension}.Value={}", uri_encode(name), uri_encode(value));
+            }
+        }
+        (count > 0).then_some(body)
+    }
+}
+
+impl Sink for CloudWatch {
+    fn name(&self) -> &str {
+        "cloudwatch"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        if self.last_publish.is_some_and(|last| last.elapsed() < self.interval) {
+            return Ok(());
+        }
+        self.last_publish = Some(Instant::now());
+        let Some(body) = self.request_body(vars) else {
+            debug!("No variables to publish to CloudWatch");
+            return Ok(());
+        };
+
+        let host = format!("monitoring.{}.amazonaws.com", self.region);
+        let headers = sign(&self.credentials, &self.region, &host, &body, SystemTime::now());
+        let url = format!("https://{host}/");
+        let agent = self.agent.clone();
+
+        // Send in the background so a slow endpoint never delays the next poll
+        thread::spawn(move || {
+            let mut request = agent.post(&url);
+            for (name, value) in &headers {
+                request = request.header(name, value);
+            }
+            match request.send(body) {
+                Ok(_) => debug!("Published metrics to CloudWatch"),
+                Err(err) => warn!("Failed to publish metrics to CloudWatch: {err}"),
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Signs a `PutMetricData` request with AWS Signature Version 4, returning all headers that must
+/// be sent with it.
+fn sign(credentials: &Credentials, region: &str, host: &str, body: &str, time: SystemTime) -> Vec<(String, String)> {
+    let c = Civil::from_system_time(time);
+    let date = format!("{:04}{:02}{:02}", c.year, c.month, c.day);
+    let amz_date = format!("{date}T{:02}{:02}{:02}Z", c.hour, c.minute, c.second);
+
+    let mut headers = vec![
+        (String::from("content-type"), String::from("application/x-www-form-urlencoded; charset=utf-8")),
+        (String::from("host"), host.to_string()),
+        (String::from("x-amz-date"), amz_date.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push((String::from("x-amz-security-token"), token.clone()));
+    }
+
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{name}:{value}\n")).collect();
+    let signed_headers = headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+    let canonical_request = format!("POST\n/\n\n{canonical_headers}\n{signed_headers}\n{}", hex_sha256(body.as_bytes()));
+
+    let scope = format!("{date}/{region}/monitoring/aws4_request");
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}", hex_sha256(canonical_request.as_bytes()));
+    let key = signing_key(&credentials.secret_access_key, &date, region, "monitoring");
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    headers.push((
+        String::from("authorization"),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credentials.access_key_id
+        ),
+    ));
+    headers.retain(|(name, _)| name != "host");
+    headers
+}
+
+/// Derives the Signature Version 4 signing key for a date, region, and service.
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{secret}").as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
+
+/// Percent-encodes a string as required by Signature Version 4, leaving only unreserved
+/// characters as they are.
+fn uri_encode(input: &str) -> String {
+    input.bytes().fold(String::with_capacity(input.len()), |mut out, byte| {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            out.push(char::from(byte));
+        } else {
+            let _ = write!(out, "%{byte:02X}");
+        }
+        out
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive_signing_key() {
+        // Example from the AWS Signature Version 4 documentation
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+        assert_eq!(hex(&key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+    }
+
+    #[test]
+    fn build_request_body() {
+        let credentials = Credentials {
+            access_key_id: String::from("AKID"),
+            secret_access_key: String::from("secret"),
+            session_token: None,
+        };
+        let dimensions = vec![(String::from("UPS"), String::from("my ups"))];
+        let vars = [String::from("battery.charge"), String::from("ups.status")];
+        let cloudwatch = CloudWatch::new(credentials, "us-east-1", "Pistachio", dimensions, &vars, Duration::ZERO);
+        let polled = vec![
+            Variable::new("battery.charge", String::from("95")),
+            Variable::new("ups.status", String::from("OL")),
+            Variable::new("ups.load", String::from("20")),
+        ];
+        let body = cloudwatch.request_body(&polled).unwrap();
+        assert_eq!(
+            body,
+            "Action=PutMetricData&Version=2010-08-01&Namespace=Pistachio\
+            &MetricData.member.1.MetricName=ups_battery_charge&MetricData.member.1.Value=95\
+            &MetricData.member.1.Unit=Percent\
+            &MetricData.member.1.Dimensions.member.1.Name=UPS&MetricData.member.1.Dimensions.member.1.Value=my%20ups"
+        );
+        assert!(cloudwatch.request_body(&polled[1..]).is_none());
+    }
+}
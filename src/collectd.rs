@@ -0,0 +1,111 @@
+//! Writing of UPS variables to standard output as `PUTVAL` commands of the collectd plain text
+//! protocol, for running pistachio under the `exec` plugin of collectd.
+//!
+//! Variables that the `nut` plugin of collectd also reads are written with its types, such as
+//! `percent-charge` for `battery.charge`, so existing graphs keep working. Other numeric variables
+//! are written as a `gauge` named after the variable.
+
+use crate::sink::Sink;
+use crate::Variable;
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the plugin in the identifier of every value, the same as the `nut` plugin of collectd.
+const PLUGIN: &str = "nut";
+
+/// Types and type instances of the `nut` plugin of collectd, by variable.
+const NUT_TYPES: &[(&str, &str)] = &[
+    ("battery.charge", "percent-charge"),
+    ("battery.current", "current-battery"),
+    ("battery.runtime", "timeleft-battery"),
+    ("battery.temperature", "temperature-battery"),
+    ("battery.voltage", "voltage-battery"),
+    ("battery.voltage.nominal", "voltage-nominal"),
+    ("input.current", "current-input"),
+    ("input.frequency", "frequency-input"),
+    ("input.voltage", "voltage-input"),
+    ("output.current", "current-output"),
+    ("output.frequency", "frequency-output"),
+    ("output.voltage", "voltage-output"),
+    ("ups.load", "percent-load"),
+    ("ups.power", "power-ups"),
+    ("ups.realpower", "power-watts"),
+    ("ups.temperature", "temperature-ups"),
+];
+
+/// A sink that writes the numeric variables of every poll to standard output as `PUTVAL`
+/// commands for the `exec` plugin of collectd.
+#[derive(Debug, Clone)]
+pub struct CollectdExec {
+    host: String,
+    ups_name: String,
+    interval: Duration,
+}
+
+impl CollectdExec {
+    /// Creates a sink that writes values of the given UPS for `host`, which is usually taken from
+    /// `COLLECTD_HOSTNAME`, polled every `interval`.
+    #[must_use]
+    pub fn new(host: &str, ups_name: &str, interval: Duration) -> CollectdExec {
+        CollectdExec {
+            host: host.to_string(),
+            ups_name: ups_name.to_string(),
+            interval,
+        }
+    }
+
+    /// Encodes the numeric variables of a poll as `PUTVAL` commands at the given time.
+    fn encode(&self, vars: &[Variable], time: SystemTime) -> String {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut commands = String::new();
+        for var in vars {
+            let Some(value) = crate::parse_number(var.value()).filter(|value| value.is_finite()) else {
+                continue;
+            };
+            let name = var.name();
+            let type_name = match NUT_TYPES.iter().find(|(var_name, _)| *var_name == name) {
+                Some((_, type_name)) => (*type_name).to_string(),
+                None => format!("gauge-{name}"),
+            };
+            let identifier = format!("{}/{PLUGIN}-{}/{type_name}", self.host, self.ups_name).replace('"', "");
+            commands.push_str(&format!("PUTVAL \"{identifier}\" interval={} {secs}:{value}\n", self.interval.as_secs()));
+        }
+        commands
+    }
+}
+
+impl Sink for CollectdExec {
+    fn name(&self) -> &str {
+        "collectd-exec"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let commands = self.encode(vars, SystemTime::now());
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(commands.as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_putval_commands() {
+        let collectd = CollectdExec::new("server", "rack", Duration::from_secs(10));
+        let vars = [
+            Variable::new("battery.charge", String::from("87")),
+            Variable::new("ups.status", String::from("OL")),
+            Variable::new("ups.realpower.nominal", String::from("900")),
+        ];
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            collectd.encode(&vars, time),
+            "PUTVAL \"server/nut-rack/percent-charge\" interval=10 1700000000:87\n\
+             PUTVAL \"server/nut-rack/gauge-ups.realpower.nominal\" interval=10 1700000000:900\n"
+        );
+    }
+}
@@ -0,0 +1,1431 @@
+//! Configuration of the exporter, independent of how it was provided.
+//!
+//! [`Config`] can be built in code with [`Config::builder`], converted from the command line
+//! arguments parsed into `Args` with the `cli` feature, or deserialized with serde from any format, in which
+//! case omitted options take their default values.
+
+use crate::alerts::AlertRule;
+use crate::auth::ApiToken;
+#[cfg(feature = "tls")]
+use crate::auth::ClientScopes;
+use crate::cost::PricePeriod;
+use crate::dates::{DateFormat, TimeZone};
+use crate::groups::PollGroup;
+use crate::metadata::HelpText;
+use crate::naming::NamingScheme;
+use crate::shed::ShedTier;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Where the variables of the UPS are read from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// A NUT server, which supports every feature of the exporter.
+    #[default]
+    Nut,
+    /// The Network Information Server of apcupsd, usually on port 3551.
+    Apcupsd,
+    /// An SNMP agent with the UPS-MIB of RFC 1628, usually on port 161.
+    Snmp,
+    /// A Modbus TCP device, usually on port 502, read with a register map file.
+    Modbus,
+    /// A USB HID Power Device read directly through Linux hidraw, without a NUT server. Requires
+    /// the `usbhid` feature.
+    Usbhid,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Nut => "nut",
+            Backend::Apcupsd => "apcupsd",
+            Backend::Snmp => "snmp",
+            Backend::Modbus => "modbus",
+            Backend::Usbhid => "usbhid",
+        })
+    }
+}
+
+/// Where the metrics of the UPS are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Output {
+    /// Served over HTTP for Prometheus to scrape.
+    #[default]
+    Prometheus,
+    /// Written to standard output in InfluxDB line protocol, for a Telegraf `execd` plugin,
+    /// without serving HTTP.
+    InfluxStdout,
+    /// Written to standard output as `PUTVAL` commands, for the `exec` plugin of collectd,
+    /// without serving HTTP.
+    CollectdExec,
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Output::Prometheus => "prometheus",
+            Output::InfluxStdout => "influx-stdout",
+            Output::CollectdExec => "collectd-exec",
+        })
+    }
+}
+
+/// Level at which HTTP requests are written to the access log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogLevel {
+    /// Requests are not logged.
+    Off,
+    /// Requests are logged as errors.
+    Error,
+    /// Requests are logged as warnings.
+    Warn,
+    /// Requests are logged as information, which is shown by default.
+    Info,
+    /// Requests are logged as debug messages, which are hidden by default.
+    #[default]
+    Debug,
+    /// Requests are logged as trace messages.
+    Trace,
+}
+
+impl AccessLogLevel {
+    /// Returns the level of log messages, or `None` if requests are not logged.
+    #[must_use]
+    pub fn level(self) -> Option<log::Level> {
+        match self {
+            AccessLogLevel::Off => None,
+            AccessLogLevel::Error => Some(log::Level::Error),
+            AccessLogLevel::Warn => Some(log::Level::Warn),
+            AccessLogLevel::Info => Some(log::Level::Info),
+            AccessLogLevel::Debug => Some(log::Level::Debug),
+            AccessLogLevel::Trace => Some(log::Level::Trace),
+        }
+    }
+}
+
+/// What happens to the gauge of a variable that has not been reported for the idle timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum MetricIdleAction {
+    /// The gauge is removed from `/metrics` until the variable is reported again.
+    #[default]
+    Remove,
+    /// The gauge is set to NaN until the variable is reported again.
+    Nan,
+}
+
+/// A UPS to monitor, written like in NUT as `ups[@host[:port]]`, such as
+/// `myups@nut.example.com:3493`. The host and port default to `127.0.0.1` and `3493`, and an IPv6
+/// address with a port is written in brackets, such as `myups@[::1]:3493`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpsTarget {
+    /// Name of the UPS.
+    pub name: String,
+    /// Hostname of the NUT server.
+    pub host: String,
+    /// Port of the NUT server.
+    pub port: u16,
+}
+
+impl FromStr for UpsTarget {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<UpsTarget> {
+        let invalid = |reason: &str| Error::Parse(format!("invalid UPS target `{input}`, expected ups[@host[:port]]: {reason}"));
+        let (name, address) = match input.split_once('@') {
+            Some((name, address)) => (name, Some(address)),
+            None => (input, None),
+        };
+        if name.is_empty() {
+            return Err(invalid("the UPS name is empty"));
+        }
+        let (host, port) = match address {
+            None => (crate::DEFAULT_UPS_HOST, None),
+            Some(address) => match address.strip_prefix('[') {
+                Some(bracketed) => {
+                    let (host, rest) = bracketed.split_once(']').ok_or_else(|| invalid("the IPv6 address is not closed"))?;
+                    match rest {
+                        "" => (host, None),
+                        rest => (host, Some(rest.strip_prefix(':').ok_or_else(|| invalid("expected a port after the address"))?)),
+                    }
+                }
+                // An IPv6 address without brackets has no port
+                None if address.matches(':').count() > 1 => (address, None),
+                None => match address.split_once(':') {
+                    Some((host, port)) => (host, Some(port)),
+                    None => (address, None),
+                },
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid("the host is empty"));
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid("the port is not a number between 0 and 65535"))?,
+            None => crate::DEFAULT_UPS_PORT,
+        };
+        Ok(UpsTarget {
+            name: name.to_string(),
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for UpsTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "{}@[{}]:{}", self.name, self.host, self.port)
+        } else {
+            write!(f, "{}@{}:{}", self.name, self.host, self.port)
+        }
+    }
+}
+
+/// Complete configuration of the exporter. Every field has the same meaning as the command line
+/// option of the same name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Name of the UPS to monitor.
+    pub ups_name: String,
+    /// Hostname of the NUT server to monitor.
+    pub ups_host: String,
+    /// Port of the NUT server to monitor.
+    pub ups_port: u16,
+    /// UPS to monitor given as `ups@host:port` targets, which take the place of the name, host,
+    /// and port. Only one UPS can be monitored for now.
+    #[serde(skip)]
+    pub targets: Vec<UpsTarget>,
+    /// Where the variables of the UPS are read from.
+    pub backend: Backend,
+    /// Path to the hidraw device of the UPS, when read with the `usbhid` backend.
+    #[cfg(feature = "usbhid")]
+    pub usbhid_device: Option<PathBuf>,
+    /// SNMP v2c community used to read the UPS with the `snmp` backend.
+    pub snmp_community: String,
+    /// Path to the register map of the device, when read with the `modbus` backend.
+    pub modbus_register_map: Option<PathBuf>,
+    /// Where the metrics of the UPS are written.
+    pub output: Output,
+    /// Whether the UPS is polled only once, after which pistachio exits.
+    pub once: bool,
+    /// IP address or host name on which the exporter will serve metrics.
+    pub bind_ip: String,
+    /// Port on which the exporter will serve metrics.
+    pub bind_port: u16,
+    /// Number of times binding to an address that is in use is retried.
+    pub bind_retries: u32,
+    /// Maximum number of HTTP connections handled at once.
+    pub http_max_connections: Option<usize>,
+    /// Maximum number of HTTP requests per minute from each IP address.
+    pub http_rate_limit: Option<u32>,
+    /// Origins whose web pages may read the JSON API, or `*` for any origin.
+    pub cors_origins: Vec<String>,
+    /// API tokens, one of which every HTTP request but `/ready` must be sent with if any are
+    /// set.
+    pub api_tokens: Vec<ApiToken>,
+    /// Path to the PEM certificate chain to serve HTTPS with.
+    #[cfg(feature = "tls")]
+    pub tls_cert: Option<PathBuf>,
+    /// Path to the PEM private key of the certificate.
+    #[cfg(feature = "tls")]
+    pub tls_key: Option<PathBuf>,
+    /// Path to the PEM certificates of the CA that clients must present a certificate signed by.
+    #[cfg(feature = "tls")]
+    pub tls_client_ca: Option<PathBuf>,
+    /// Scopes granted to clients by the common name of their certificate.
+    #[cfg(feature = "tls")]
+    pub tls_client_scopes: Vec<ClientScopes>,
+    /// Level at which HTTP requests are written to the access log.
+    pub http_access_log: AccessLogLevel,
+    /// Time in seconds to wait for a client to send its request.
+    pub http_read_timeout: u64,
+    /// Time in seconds to wait for a client to accept a response.
+    pub http_write_timeout: u64,
+    /// Time in seconds to keep an HTTP connection open for further requests.
+    pub http_idle_timeout: Option<u64>,
+    /// Maximum combined size in bytes of the request line and headers of an HTTP request.
+    pub http_max_header_bytes: usize,
+    /// Time in seconds between requests to the NUT server.
+    pub poll_rate: u64,
+    /// Groups of variables polled at intervals of their own.
+    pub poll_groups: Vec<PollGroup>,
+    /// Variables sampled more often than the poll rate, to export their minimum, maximum, and
+    /// average.
+    pub sample_vars: Vec<String>,
+    /// Time in seconds between samples of the sampled variables.
+    pub sample_interval: u64,
+    /// Time in seconds over which statistics of sampled variables are exported, or the poll rate
+    /// if not set.
+    pub sample_window: Option<u64>,
+    /// Number of connections over which variable metadata is fetched at startup.
+    pub metadata_connections: usize,
+    /// Number of poll intervals a request to the NUT server may take before its connection is
+    /// recreated.
+    pub poll_stall_threshold: u32,
+    /// Number of failed polls in a row during which gauges keep their last values.
+    pub failure_grace_polls: u32,
+    /// Time in seconds after which the gauge of a variable that is no longer reported is removed.
+    pub metric_idle_timeout: Option<u64>,
+    /// What happens to the gauge of a variable that has not been reported for the idle timeout.
+    pub metric_idle_action: MetricIdleAction,
+    /// Whether every metric is labeled with the host name of the machine.
+    pub host_label: bool,
+    /// Environment variable to take the `host` label from instead of the host name.
+    pub host_label_env: Option<String>,
+    /// Names and labels the gauges of UPS variables are exported with.
+    pub naming_scheme: NamingScheme,
+    /// Descriptions replacing or extending those given by the server, used as the help text of
+    /// gauges.
+    pub help_texts: Vec<HelpText>,
+    /// Path to a file in which counters and accumulated values are saved.
+    pub state_file: Option<PathBuf>,
+    /// Time in seconds between saves of the state file while polling.
+    pub state_save_interval: u64,
+    /// Number of recent events kept in the journal.
+    pub journal_size: usize,
+    /// Path to a file in which the journal of recent events is saved.
+    pub journal_file: Option<PathBuf>,
+    /// Price of electricity per kWh.
+    pub energy_price: Option<f64>,
+    /// Periods of the day during which a different price per kWh applies.
+    pub energy_price_periods: Vec<PricePeriod>,
+    /// Runtime in seconds of the UPS on a new, fully charged battery at its usual load.
+    pub battery_rated_runtime: Option<u64>,
+    /// Time in years a battery is expected to last.
+    pub battery_expected_life: u64,
+    /// Percentage below the nominal input voltage at which a voltage sag is counted.
+    pub voltage_sag_percent: f64,
+    /// Percentage above the nominal input voltage at which a voltage swell is counted.
+    pub voltage_swell_percent: f64,
+    /// Deviation in hertz from the nominal input frequency at which a frequency deviation is
+    /// counted.
+    pub frequency_tolerance: f64,
+    /// Time zone that dates and times reported by the UPS are in.
+    pub date_timezone: TimeZone,
+    /// Formats of the variables whose dates and times are not in one of the detected formats.
+    pub date_formats: Vec<DateFormat>,
+    /// Alert rules evaluated on every poll.
+    pub alerts: Vec<AlertRule>,
+    /// Load-shedding tiers, in the order they should be shed.
+    pub shed_tiers: Vec<ShedTier>,
+    /// Shell command run when the UPS starts a forced shutdown.
+    pub shutdown_command: Option<String>,
+    /// Whether the HTTP endpoint for running instant commands is enabled.
+    pub enable_commands: bool,
+    /// Whether the HTTP endpoint for setting writable variables is enabled.
+    pub enable_set_vars: bool,
+    /// Whether everything that changes the UPS or acts on its behalf is disabled, whatever other
+    /// options are set.
+    pub read_only: bool,
+    /// Path to a file to which every action that changes the UPS or acts on its behalf is
+    /// appended.
+    pub audit_log: Option<PathBuf>,
+    /// URL to send a GET request to after every successful poll.
+    pub ping_url: Option<String>,
+    /// Path to a lease file shared by replicas, so only the leader publishes to external services.
+    pub ha_lease_file: Option<PathBuf>,
+    /// Identity of this replica in the lease file.
+    pub ha_id: Option<String>,
+    /// Time in seconds without a successful poll after which `/ready` answers `503`.
+    pub ready_max_staleness: Option<u64>,
+    /// Path to a file to which the variables from every poll will be appended.
+    pub record: Option<PathBuf>,
+    /// Size in megabytes at which the record file is rotated.
+    pub record_max_size: Option<u64>,
+    /// Age in hours at which the record file is rotated.
+    pub record_max_age: Option<u64>,
+    /// Number of rotated record files to keep.
+    pub record_keep: usize,
+    /// Address of a Zabbix server or proxy to send values to.
+    pub zabbix_server: Option<String>,
+    /// Name of the host in Zabbix that values are sent for.
+    pub zabbix_host: Option<String>,
+    /// Template for Zabbix item keys.
+    pub zabbix_key_template: String,
+    /// Zabbix item keys overriding the template for specific variables.
+    pub zabbix_keys: Vec<(String, String)>,
+    /// URL of a NATS server to publish events to.
+    #[cfg(feature = "nats")]
+    pub nats_url: Option<String>,
+    /// Subject prefix for events published to NATS.
+    #[cfg(feature = "nats")]
+    pub nats_subject: String,
+    /// URL of an OpenTelemetry collector to send polls and events to.
+    #[cfg(feature = "otlp")]
+    pub otlp_endpoint: Option<String>,
+    /// Name of the service that spans and log records are sent for.
+    #[cfg(feature = "otlp")]
+    pub otlp_service_name: String,
+    /// Path to a SQLite database in which to record poll history.
+    #[cfg(feature = "history")]
+    pub history_db: Option<PathBuf>,
+    /// Variables to record in the history database on every poll.
+    #[cfg(feature = "history")]
+    pub history_vars: Vec<String>,
+    /// Number of hours to retain records in the history database.
+    #[cfg(feature = "history")]
+    pub history_retention: u64,
+    /// AWS region to publish metrics to CloudWatch in.
+    #[cfg(feature = "cloudwatch")]
+    pub cloudwatch_region: Option<String>,
+    /// CloudWatch namespace to publish metrics in.
+    #[cfg(feature = "cloudwatch")]
+    pub cloudwatch_namespace: String,
+    /// Dimensions to attach to CloudWatch metrics.
+    #[cfg(feature = "cloudwatch")]
+    pub cloudwatch_dimensions: Vec<(String, String)>,
+    /// Variables to publish to CloudWatch.
+    #[cfg(feature = "cloudwatch")]
+    pub cloudwatch_vars: Vec<String>,
+    /// Time in seconds between publishes to CloudWatch.
+    #[cfg(feature = "cloudwatch")]
+    pub cloudwatch_interval: u64,
+    /// Port on which to serve the gRPC API.
+    #[cfg(feature = "grpc")]
+    pub grpc_port: Option<u16>,
+}
+
+impl Config {
+    /// Returns a builder starting from the default configuration.
+    #[must_use]
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Checks that every option has a valid value. This is done by [`ConfigBuilder::build`], but
+    /// should also be done after deserializing a configuration.
+    ///
+    /// # Errors
+    ///
+    /// An [`Error::Config`] will be returned if an option has an invalid value, such as a poll
+    /// rate of zero.
+    pub fn validate(&self) -> Result<()> {
+        if self.poll_rate == 0 {
+            return Err(Error::Config(String::from("poll rate must be at least 1 second")));
+        }
+        if self.ups_host.trim().is_empty() {
+            return Err(Error::Config(String::from("the host of the UPS must not be empty")));
+        }
+        if self.bind_ip.trim().is_empty() {
+            return Err(Error::Config(String::from("the address to bind to must not be empty")));
+        }
+        if self.http_max_connections == Some(0) {
+            return Err(Error::Config(String::from("at least 1 HTTP connection must be allowed")));
+        }
+        if self.http_rate_limit == Some(0) {
+            return Err(Error::Config(String::from("at least 1 HTTP request per minute must be allowed")));
+        }
+        if self.ready_max_staleness.is_some_and(|seconds| seconds < self.poll_rate) {
+            return Err(Error::Config(String::from("the maximum staleness for readiness must be at least the poll rate")));
+        }
+        if self.http_read_timeout == 0 || self.http_write_timeout == 0 || self.http_idle_timeout == Some(0) {
+            return Err(Error::Config(String::from("HTTP timeouts must be at least 1 second")));
+        }
+        if self.http_max_header_bytes < 256 {
+            return Err(Error::Config(String::from("HTTP requests must be allowed at least 256 bytes of headers")));
+        }
+        for origin in &self.cors_origins {
+            let scheme = origin.strip_prefix("https://").or_else(|| origin.strip_prefix("http://"));
+            if origin != "*" && scheme.is_none_or(|host| host.is_empty() || host.contains('/')) {
+                return Err(Error::Config(format!(
+                    "CORS origin {origin:?} must be `*` or a scheme and host without a path, such as https://example.com"
+                )));
+            }
+        }
+        for (index, token) in self.api_tokens.iter().enumerate() {
+            if token.token.len() < crate::auth::MIN_TOKEN_LENGTH {
+                return Err(Error::Config(format!("API tokens must be at least {} characters long", crate::auth::MIN_TOKEN_LENGTH)));
+            }
+            if self.api_tokens[..index].iter().any(|other| other.token == token.token) {
+                return Err(Error::Config(String::from("an API token is defined more than once")));
+            }
+        }
+        #[cfg(feature = "tls")]
+        {
+            if self.tls_cert.is_some() != self.tls_key.is_some() {
+                return Err(Error::Config(String::from("a TLS certificate and its private key must be set together")));
+            }
+            if self.tls_client_ca.is_some() && self.tls_cert.is_none() {
+                return Err(Error::Config(String::from("a client CA requires a TLS certificate")));
+            }
+            if !self.tls_client_scopes.is_empty() && self.tls_client_ca.is_none() {
+                return Err(Error::Config(String::from("client certificate scopes require a client CA")));
+            }
+            for (index, client) in self.tls_client_scopes.iter().enumerate() {
+                if self.tls_client_scopes[..index].iter().any(|other| other.common_name == client.common_name) {
+                    return Err(Error::Config(format!("scopes are granted to client {} more than once", client.common_name)));
+                }
+            }
+        }
+        // The gRPC API is served without authentication, so it would bypass both
+        #[cfg(feature = "grpc")]
+        if self.grpc_port.is_some() && !self.api_tokens.is_empty() {
+            return Err(Error::Config(String::from("the gRPC API does not check API tokens, so it cannot be served along with them")));
+        }
+        #[cfg(all(feature = "grpc", feature = "tls"))]
+        if self.grpc_port.is_some() && self.tls_client_ca.is_some() {
+            return Err(Error::Config(String::from(
+                "the gRPC API does not require client certificates, so it cannot be served along with a client CA",
+            )));
+        }
+        if self.state_save_interval == 0 {
+            return Err(Error::Config(String::from("state save interval must be at least 1 second")));
+        }
+        if self.metadata_connections == 0 {
+            return Err(Error::Config(String::from("at least 1 metadata connection is required")));
+        }
+        if self.poll_stall_threshold == 0 {
+            return Err(Error::Config(String::from("poll stall threshold must be at least 1 poll")));
+        }
+        if self.journal_size == 0 {
+            return Err(Error::Config(String::from("the journal must hold at least 1 event")));
+        }
+        if self.energy_price.is_some_and(|price| !price.is_finite() || price < 0.0) {
+            return Err(Error::Config(String::from("energy price must not be negative")));
+        }
+        if self.energy_price.is_none() && !self.energy_price_periods.is_empty() {
+            return Err(Error::Config(String::from("energy price periods require an energy price")));
+        }
+        if self.battery_rated_runtime == Some(0) || self.battery_expected_life == 0 {
+            return Err(Error::Config(String::from("battery rated runtime and expected life must not be zero")));
+        }
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+        if !positive(self.voltage_sag_percent) || self.voltage_sag_percent >= 100.0 || !positive(self.voltage_swell_percent) {
+            return Err(Error::Config(String::from("voltage sag and swell percentages must be positive, and sags below 100")));
+        }
+        if !positive(self.frequency_tolerance) {
+            return Err(Error::Config(String::from("the frequency tolerance must be positive")));
+        }
+        for (index, rule) in self.alerts.iter().enumerate() {
+            if self.alerts[..index].iter().any(|other| other.name == rule.name) {
+                return Err(Error::Config(format!("alert {} is defined more than once", rule.name)));
+            }
+        }
+        for (index, format) in self.date_formats.iter().enumerate() {
+            if self.date_formats[..index].iter().any(|other| other.variable == format.variable) {
+                return Err(Error::Config(format!("the date format of {} is defined more than once", format.variable)));
+            }
+        }
+        for (index, help) in self.help_texts.iter().enumerate() {
+            if self.help_texts[..index].iter().any(|other| other.variable == help.variable) {
+                return Err(Error::Config(format!("the help text of {} is defined more than once", help.variable)));
+            }
+        }
+        for (index, tier) in self.shed_tiers.iter().enumerate() {
+            if self.shed_tiers[..index].iter().any(|other| other.name == tier.name) {
+                return Err(Error::Config(format!("load-shedding tier {} is defined more than once", tier.name)));
+            }
+        }
+        if self.backend == Backend::Modbus && self.modbus_register_map.is_none() {
+            return Err(Error::Config(String::from("the modbus backend requires a register map")));
+        }
+        if self.backend != Backend::Nut && (self.enable_commands || self.enable_set_vars) {
+            return Err(Error::Config(String::from("commands and setting variables require the nut backend")));
+        }
+        if self.output != Output::Prometheus && (self.enable_commands || self.enable_set_vars) {
+            return Err(Error::Config(format!(
+                "commands and setting variables are served over HTTP, which the {} output does not serve",
+                self.output
+            )));
+        }
+        if self.once && self.output == Output::Prometheus {
+            return Err(Error::Config(String::from("polling once requires an output written to standard output")));
+        }
+        if self.ups_name.is_empty() {
+            return Err(Error::Config(String::from("UPS name must not be empty")));
+        }
+        if self.targets.len() > 1 {
+            return Err(Error::Config(format!(
+                "only one UPS can be monitored, but {} targets were given",
+                self.targets.len()
+            )));
+        }
+        if !self.sample_vars.is_empty() && (self.sample_interval == 0 || self.sample_interval >= self.poll_rate) {
+            return Err(Error::Config(String::from("the sample interval must be at least 1 second and shorter than the poll rate")));
+        }
+        if self.sample_window.is_some_and(|window| window < self.sample_interval) {
+            return Err(Error::Config(String::from("the sampling window must be at least the sample interval")));
+        }
+        Ok(())
+    }
+
+    /// Returns the options that change the UPS or act on its behalf which are set, but disabled
+    /// by read-only mode.
+    #[must_use]
+    pub fn ignored_by_read_only(&self) -> Vec<&'static str> {
+        if !self.read_only {
+            return Vec::new();
+        }
+        [
+            ("enable_commands", self.enable_commands),
+            ("enable_set_vars", self.enable_set_vars),
+            ("shutdown_command", self.shutdown_command.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect()
+    }
+
+    /// Returns the group of sampled variables, polled at the sample interval, if any are sampled.
+    pub(crate) fn sample_group(&self) -> Option<PollGroup> {
+        (!self.sample_vars.is_empty()).then(|| PollGroup {
+            patterns: self.sample_vars.clone(),
+            interval: std::time::Duration::from_secs(self.sample_interval),
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            ups_name: String::from(crate::DEFAULT_UPS_NAME),
+            ups_host: String::from(crate::DEFAULT_UPS_HOST),
+            ups_port: crate::DEFAULT_UPS_PORT,
+            targets: Vec::new(),
+            backend: Backend::Nut,
+            output: Output::Prometheus,
+            once: false,
+            #[cfg(feature = "usbhid")]
+            usbhid_device: None,
+            snmp_community: String::from(crate::DEFAULT_SNMP_COMMUNITY),
+            modbus_register_map: None,
+            bind_ip: String::from(crate::DEFAULT_BIND_IP),
+            bind_port: crate::DEFAULT_BIND_PORT,
+            bind_retries: crate::DEFAULT_BIND_RETRIES,
+            http_max_connections: None,
+            http_rate_limit: None,
+            cors_origins: Vec::new(),
+            api_tokens: Vec::new(),
+            #[cfg(feature = "tls")]
+            tls_cert: None,
+            #[cfg(feature = "tls")]
+            tls_key: None,
+            #[cfg(feature = "tls")]
+            tls_client_ca: None,
+            #[cfg(feature = "tls")]
+            tls_client_scopes: Vec::new(),
+            http_access_log: AccessLogLevel::Debug,
+            http_read_timeout: crate::DEFAULT_HTTP_READ_TIMEOUT,
+            http_write_timeout: crate::DEFAULT_HTTP_WRITE_TIMEOUT,
+            http_idle_timeout: None,
+            http_max_header_bytes: crate::DEFAULT_HTTP_MAX_HEADER_BYTES,
+            poll_rate: crate::DEFAULT_POLL_RATE,
+            metadata_connections: crate::DEFAULT_METADATA_CONNECTIONS,
+            poll_stall_threshold: crate::DEFAULT_POLL_STALL_THRESHOLD,
+            failure_grace_polls: crate::DEFAULT_FAILURE_GRACE_POLLS,
+            metric_idle_timeout: None,
+            metric_idle_action: MetricIdleAction::Remove,
+            host_label: false,
+            host_label_env: None,
+            naming_scheme: NamingScheme::Pistachio,
+            help_texts: Vec::new(),
+            state_file: None,
+            state_save_interval: crate::DEFAULT_STATE_SAVE_INTERVAL,
+            journal_size: crate::DEFAULT_JOURNAL_SIZE,
+            journal_file: None,
+            energy_price: None,
+            energy_price_periods: Vec::new(),
+            battery_rated_runtime: None,
+            battery_expected_life: crate::DEFAULT_BATTERY_EXPECTED_LIFE,
+            voltage_sag_percent: crate::DEFAULT_VOLTAGE_SAG_PERCENT,
+            voltage_swell_percent: crate::DEFAULT_VOLTAGE_SWELL_PERCENT,
+            frequency_tolerance: crate::DEFAULT_FREQUENCY_TOLERANCE,
+            date_timezone: TimeZone::UTC,
+            date_formats: Vec::new(),
+            alerts: Vec::new(),
+            shed_tiers: Vec::new(),
+            poll_groups: Vec::new(),
+            sample_vars: Vec::new(),
+            sample_interval: crate::DEFAULT_SAMPLE_INTERVAL,
+            sample_window: None,
+            shutdown_command: None,
+            enable_commands: false,
+            enable_set_vars: false,
+            read_only: true,
+            audit_log: None,
+            ping_url: None,
+            ha_lease_file: None,
+            ha_id: None,
+            ready_max_staleness: None,
+            record: None,
+            record_max_size: None,
+            record_max_age: None,
+            record_keep: crate::DEFAULT_RECORD_KEEP,
+            zabbix_server: None,
+            zabbix_host: None,
+            zabbix_key_template: String::from(crate::DEFAULT_ZABBIX_KEY_TEMPLATE),
+            zabbix_keys: Vec::new(),
+            #[cfg(feature = "nats")]
+            nats_url: None,
+            #[cfg(feature = "nats")]
+            nats_subject: String::from(crate::DEFAULT_NATS_SUBJECT),
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: None,
+            #[cfg(feature = "otlp")]
+            otlp_service_name: String::from(crate::DEFAULT_OTLP_SERVICE_NAME),
+            #[cfg(feature = "history")]
+            history_db: None,
+            #[cfg(feature = "history")]
+            history_vars: crate::DEFAULT_KEY_VARS.iter().map(|var| var.to_string()).collect(),
+            #[cfg(feature = "history")]
+            history_retention: crate::DEFAULT_HISTORY_RETENTION,
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_region: None,
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_namespace: String::from(crate::DEFAULT_CLOUDWATCH_NAMESPACE),
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_dimensions: Vec::new(),
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_vars: crate::DEFAULT_KEY_VARS.iter().map(|var| var.to_string()).collect(),
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch_interval: crate::DEFAULT_CLOUDWATCH_INTERVAL,
+            #[cfg(feature = "grpc")]
+            grpc_port: None,
+        }
+    }
+}
+
+/// A builder for [Config], created by [`Config::builder`]. Options that are not set keep their
+/// default values.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Sets the name of the UPS to monitor.
+    #[must_use]
+    pub fn ups_name(mut self, ups_name: &str) -> ConfigBuilder {
+        self.config.ups_name = ups_name.to_string();
+        self
+    }
+
+    /// Sets the hostname of the NUT server to monitor.
+    #[must_use]
+    pub fn ups_host(mut self, ups_host: &str) -> ConfigBuilder {
+        self.config.ups_host = ups_host.to_string();
+        self
+    }
+
+    /// Sets the port of the NUT server to monitor.
+    #[must_use]
+    pub fn ups_port(mut self, ups_port: u16) -> ConfigBuilder {
+        self.config.ups_port = ups_port;
+        self
+    }
+
+    /// Adds a UPS to monitor as a target, which takes the place of the name, host, and port if it
+    /// is the first.
+    #[must_use]
+    pub fn target(mut self, target: UpsTarget) -> ConfigBuilder {
+        if self.config.targets.is_empty() {
+            self.config.ups_name.clone_from(&target.name);
+            self.config.ups_host.clone_from(&target.host);
+            self.config.ups_port = target.port;
+        }
+        self.config.targets.push(target);
+        self
+    }
+
+    /// Sets where the variables of the UPS are read from.
+    #[must_use]
+    pub fn backend(mut self, backend: Backend) -> ConfigBuilder {
+        self.config.backend = backend;
+        self
+    }
+
+    /// Sets where the metrics of the UPS are written.
+    #[must_use]
+    pub fn output(mut self, output: Output) -> ConfigBuilder {
+        self.config.output = output;
+        self
+    }
+
+    /// Sets whether the UPS is polled only once, after which pistachio exits.
+    #[must_use]
+    pub fn once(mut self, once: bool) -> ConfigBuilder {
+        self.config.once = once;
+        self
+    }
+
+    /// Sets the path to the hidraw device of the UPS, when read with the `usbhid` backend.
+    #[cfg(feature = "usbhid")]
+    #[must_use]
+    pub fn usbhid_device(mut self, path: PathBuf) -> ConfigBuilder {
+        self.config.usbhid_device = Some(path);
+        self
+    }
+
+    /// Sets the SNMP v2c community used to read the UPS with the `snmp` backend.
+    #[must_use]
+    pub fn snmp_community(mut self, community: &str) -> ConfigBuilder {
+        self.config.snmp_community = community.to_string();
+        self
+    }
+
+    /// Sets the path to the register map of the device, when read with the `modbus` backend.
+    #[must_use]
+    pub fn modbus_register_map(mut self, path: PathBuf) -> ConfigBuilder {
+        self.config.modbus_register_map = Some(path);
+        self
+    }
+
+    /// Sets the IP address or host name on which the exporter will serve metrics.
+    #[must_use]
+    pub fn bind_ip(mut self, bind_ip: &str) -> ConfigBuilder {
+        self.config.bind_ip = bind_ip.to_string();
+        self
+    }
+
+    /// Sets the port on which the exporter will serve metrics.
+    #[must_use]
+    pub fn bind_port(mut self, bind_port: u16) -> ConfigBuilder {
+        self.config.bind_port = bind_port;
+        self
+    }
+
+    /// Sets the number of times binding to an address that is in use is retried.
+    #[must_use]
+    pub fn bind_retries(mut self, retries: u32) -> ConfigBuilder {
+        self.config.bind_retries = retries;
+        self
+    }
+
+    /// Sets the maximum number of HTTP connections handled at once.
+    #[must_use]
+    pub fn http_max_connections(mut self, max: usize) -> ConfigBuilder {
+        self.config.http_max_connections = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of HTTP requests per minute from each IP address.
+    #[must_use]
+    pub fn http_rate_limit(mut self, per_minute: u32) -> ConfigBuilder {
+        self.config.http_rate_limit = Some(per_minute);
+        self
+    }
+
+    /// Sets the origins whose web pages may read the JSON API, or `*` for any origin.
+    #[must_use]
+    pub fn cors_origins(mut self, origins: &[&str]) -> ConfigBuilder {
+        self.config.cors_origins = origins.iter().map(|origin| origin.to_string()).collect();
+        self
+    }
+
+    /// Adds an API token, requiring every HTTP request but `/ready` to be sent with a token
+    /// granting the scope it needs.
+    #[must_use]
+    pub fn api_token(mut self, token: ApiToken) -> ConfigBuilder {
+        self.config.api_tokens.push(token);
+        self
+    }
+
+    /// Serves HTTPS with the certificate chain and private key in the given PEM files.
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub fn tls(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> ConfigBuilder {
+        self.config.tls_cert = Some(cert.into());
+        self.config.tls_key = Some(key.into());
+        self
+    }
+
+    /// Requires HTTPS clients to present a certificate signed by a CA in the given PEM file.
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub fn tls_client_ca(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.config.tls_client_ca = Some(path.into());
+        self
+    }
+
+    /// Grants scopes to HTTPS clients by the common name of their certificate.
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub fn tls_client_scope(mut self, client: ClientScopes) -> ConfigBuilder {
+        self.config.tls_client_scopes.push(client);
+        self
+    }
+
+    /// Sets the level at which HTTP requests are written to the access log.
+    #[must_use]
+    pub fn http_access_log(mut self, level: AccessLogLevel) -> ConfigBuilder {
+        self.config.http_access_log = level;
+        self
+    }
+
+    /// Sets the times in seconds to wait for a client to send its request, and to accept the
+    /// response.
+    #[must_use]
+    pub fn http_timeouts(mut self, read: u64, write: u64) -> ConfigBuilder {
+        self.config.http_read_timeout = read;
+        self.config.http_write_timeout = write;
+        self
+    }
+
+    /// Sets the time in seconds to keep an HTTP connection open for further requests.
+    #[must_use]
+    pub fn http_idle_timeout(mut self, seconds: u64) -> ConfigBuilder {
+        self.config.http_idle_timeout = Some(seconds);
+        self
+    }
+
+    /// Sets the maximum combined size in bytes of the request line and headers of an HTTP request.
+    #[must_use]
+    pub fn http_max_header_bytes(mut self, max: usize) -> ConfigBuilder {
+        self.config.http_max_header_bytes = max;
+        self
+    }
+
+    /// Sets the time in seconds between requests to the NUT server.
+    #[must_use]
+    pub fn poll_rate(mut self, poll_rate: u64) -> ConfigBuilder {
+        self.config.poll_rate = poll_rate;
+        self
+    }
+
+    /// Sets the variables sampled every `interval` seconds, to export their minimum, maximum, and
+    /// average.
+    #[must_use]
+    pub fn sample_vars(mut self, vars: &[&str], interval: u64) -> ConfigBuilder {
+        self.config.sample_vars = vars.iter().map(|var| var.to_string()).collect();
+        self.config.sample_interval = interval;
+        self
+    }
+
+    /// Sets the time in seconds over which statistics of sampled variables are exported.
+    #[must_use]
+    pub fn sample_window(mut self, seconds: u64) -> ConfigBuilder {
+        self.config.sample_window = Some(seconds);
+        self
+    }
+
+    /// Adds a group of variables polled at an interval of its own.
+    #[must_use]
+    pub fn poll_group(mut self, group: PollGroup) -> ConfigBuilder {
+        self.config.poll_groups.push(group);
+        self
+    }
+
+    /// Sets the number of connections over which variable metadata is fetched at startup.
+    #[must_use]
+    pub fn metadata_connections(mut self, connections: usize) -> ConfigBuilder {
+        self.config.metadata_connections = connections;
+        self
+    }
+
+    /// Sets the number of poll intervals a request to the NUT server may take before its
+    /// connection is recreated.
+    #[must_use]
+    pub fn poll_stall_threshold(mut self, polls: u32) -> ConfigBuilder {
+        self.config.poll_stall_threshold = polls;
+        self
+    }
+
+    /// Sets the number of failed polls in a row during which gauges keep their last values, before
+    /// they are reset and the connection is reported as lost.
+    #[must_use]
+    pub fn failure_grace_polls(mut self, polls: u32) -> ConfigBuilder {
+        self.config.failure_grace_polls = polls;
+        self
+    }
+
+    /// Sets the time in seconds after which the gauge of a variable that is no longer reported is
+    /// removed.
+    #[must_use]
+    pub fn metric_idle_timeout(mut self, seconds: u64) -> ConfigBuilder {
+        self.config.metric_idle_timeout = Some(seconds);
+        self
+    }
+
+    /// Sets what happens to the gauge of a variable that has not been reported for the idle
+    /// timeout.
+    #[must_use]
+    pub fn metric_idle_action(mut self, action: MetricIdleAction) -> ConfigBuilder {
+        self.config.metric_idle_action = action;
+        self
+    }
+
+    /// Enables labeling every metric with the host name of the machine.
+    #[must_use]
+    pub fn host_label(mut self, enable: bool) -> ConfigBuilder {
+        self.config.host_label = enable;
+        self
+    }
+
+    /// Sets the environment variable to take the `host` label from instead of the host name.
+    #[must_use]
+    pub fn host_label_env(mut self, name: &str) -> ConfigBuilder {
+        self.config.host_label_env = Some(name.to_string());
+        self
+    }
+
+    /// Sets the names and labels the gauges of UPS variables are exported with.
+    #[must_use]
+    pub fn naming_scheme(mut self, scheme: NamingScheme) -> ConfigBuilder {
+        self.config.naming_scheme = scheme;
+        self
+    }
+
+    /// Adds a description replacing or extending the one the server gives a variable.
+    #[must_use]
+    pub fn help_text(mut self, help: HelpText) -> ConfigBuilder {
+        self.config.help_texts.push(help);
+        self
+    }
+
+    /// Sets the path of the state file.
+    #[must_use]
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.config.state_file = Some(path.into());
+        self
+    }
+
+    /// Sets the time in seconds between saves of the state file while polling.
+    #[must_use]
+    pub fn state_save_interval(mut self, seconds: u64) -> ConfigBuilder {
+        self.config.state_save_interval = seconds;
+        self
+    }
+
+    /// Sets the number of recent events kept in the journal.
+    #[must_use]
+    pub fn journal_size(mut self, size: usize) -> ConfigBuilder {
+        self.config.journal_size = size;
+        self
+    }
+
+    /// Sets the path of the file in which the journal of recent events is saved.
+    #[must_use]
+    pub fn journal_file(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.config.journal_file = Some(path.into());
+        self
+    }
+
+    /// Sets the price of electricity per kWh.
+    #[must_use]
+    pub fn energy_price(mut self, price: f64) -> ConfigBuilder {
+        self.config.energy_price = Some(price);
+        self
+    }
+
+    /// Adds a period of the day during which a different price per kWh applies.
+    #[must_use]
+    pub fn energy_price_period(mut self, period: PricePeriod) -> ConfigBuilder {
+        self.config.energy_price_periods.push(period);
+        self
+    }
+
+    /// Sets the runtime in seconds of the UPS on a new, fully charged battery at its usual load.
+    #[must_use]
+    pub fn battery_rated_runtime(mut self, seconds: u64) -> ConfigBuilder {
+        self.config.battery_rated_runtime = Some(seconds);
+        self
+    }
+
+    /// Sets the time in years a battery is expected to last.
+    #[must_use]
+    pub fn battery_expected_life(mut self, years: u64) -> ConfigBuilder {
+        self.config.battery_expected_life = years;
+        self
+    }
+
+    /// Sets the percentages below and above the nominal input voltage at which voltage sags and
+    /// swells are counted.
+    #[must_use]
+    pub fn voltage_tolerances(mut self, sag_percent: f64, swell_percent: f64) -> ConfigBuilder {
+        self.config.voltage_sag_percent = sag_percent;
+        self.config.voltage_swell_percent = swell_percent;
+        self
+    }
+
+    /// Sets the deviation in hertz from the nominal input frequency at which a frequency
+    /// deviation is counted.
+    #[must_use]
+    pub fn frequency_tolerance(mut self, hertz: f64) -> ConfigBuilder {
+        self.config.frequency_tolerance = hertz;
+        self
+    }
+
+    /// Sets the time zone that dates and times reported by the UPS are in.
+    #[must_use]
+    pub fn date_timezone(mut self, timezone: TimeZone) -> ConfigBuilder {
+        self.config.date_timezone = timezone;
+        self
+    }
+
+    /// Adds the format a variable reports its date or time in.
+    #[must_use]
+    pub fn date_format(mut self, format: DateFormat) -> ConfigBuilder {
+        self.config.date_formats.push(format);
+        self
+    }
+
+    /// Adds an alert rule evaluated on every poll.
+    #[must_use]
+    pub fn alert(mut self, rule: AlertRule) -> ConfigBuilder {
+        self.config.alerts.push(rule);
+        self
+    }
+
+    /// Adds a load-shedding tier, shed after the tiers added before it.
+    #[must_use]
+    pub fn shed_tier(mut self, tier: ShedTier) -> ConfigBuilder {
+        self.config.shed_tiers.push(tier);
+        self
+    }
+
+    /// Sets a shell command run when the UPS starts a forced shutdown.
+    #[must_use]
+    pub fn shutdown_command(mut self, command: &str) -> ConfigBuilder {
+        self.config.shutdown_command = Some(command.to_string());
+        self
+    }
+
+    /// Enables the HTTP endpoint for running instant commands.
+    #[must_use]
+    pub fn enable_commands(mut self, enable: bool) -> ConfigBuilder {
+        self.config.enable_commands = enable;
+        self
+    }
+
+    /// Enables the HTTP endpoint for setting writable variables.
+    #[must_use]
+    pub fn enable_set_vars(mut self, enable: bool) -> ConfigBuilder {
+        self.config.enable_set_vars = enable;
+        self
+    }
+
+    /// Sets whether everything that changes the UPS or acts on its behalf is disabled, which it is
+    /// by default.
+    #[must_use]
+    pub fn read_only(mut self, read_only: bool) -> ConfigBuilder {
+        self.config.read_only = read_only;
+        self
+    }
+
+    /// Sets the path of a file to which every action that changes the UPS or acts on its behalf
+    /// is appended.
+    #[must_use]
+    pub fn audit_log(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.config.audit_log = Some(path.into());
+        self
+    }
+
+    /// Sets the URL to ping after every successful poll.
+    #[must_use]
+    pub fn ping_url(mut self, url: &str) -> ConfigBuilder {
+        self.config.ping_url = Some(url.to_string());
+        self
+    }
+
+    /// Sets the path of the lease file shared by replicas.
+    #[must_use]
+    pub fn ha_lease_file(mut self, path: PathBuf) -> ConfigBuilder {
+        self.config.ha_lease_file = Some(path);
+        self
+    }
+
+    /// Sets the identity of this replica in the lease file.
+    #[must_use]
+    pub fn ha_id(mut self, id: &str) -> ConfigBuilder {
+        self.config.ha_id = Some(id.to_string());
+        self
+    }
+
+    /// Sets the time in seconds without a successful poll after which `/ready` answers `503`.
+    #[must_use]
+    pub fn ready_max_staleness(mut self, seconds: u64) -> ConfigBuilder {
+        self.config.ready_max_staleness = Some(seconds);
+        self
+    }
+
+    /// Sets the path of the file to record polled variables to.
+    #[must_use]
+    pub fn record(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.config.record = Some(path.into());
+        self
+    }
+
+    /// Sets the size in megabytes at which the record file is rotated.
+    #[must_use]
+    pub fn record_max_size(mut self, megabytes: u64) -> ConfigBuilder {
+        self.config.record_max_size = Some(megabytes);
+        self
+    }
+
+    /// Sets the age in hours at which the record file is rotated.
+    #[must_use]
+    pub fn record_max_age(mut self, hours: u64) -> ConfigBuilder {
+        self.config.record_max_age = Some(hours);
+        self
+    }
+
+    /// Sets the number of rotated record files to keep.
+    #[must_use]
+    pub fn record_keep(mut self, keep: usize) -> ConfigBuilder {
+        self.config.record_keep = keep;
+        self
+    }
+
+    /// Sets the address of the Zabbix server or proxy to send values to.
+    #[must_use]
+    pub fn zabbix_server(mut self, server: &str) -> ConfigBuilder {
+        self.config.zabbix_server = Some(server.to_string());
+        self
+    }
+
+    /// Sets the name of the host in Zabbix that values are sent for.
+    #[must_use]
+    pub fn zabbix_host(mut self, host: &str) -> ConfigBuilder {
+        self.config.zabbix_host = Some(host.to_string());
+        self
+    }
+
+    /// Sets the template for Zabbix item keys.
+    #[must_use]
+    pub fn zabbix_key_template(mut self, template: &str) -> ConfigBuilder {
+        self.config.zabbix_key_template = template.to_string();
+        self
+    }
+
+    /// Overrides the Zabbix item key of a variable.
+    #[must_use]
+    pub fn zabbix_key(mut self, var_name: &str, key: &str) -> ConfigBuilder {
+        self.config.zabbix_keys.push((var_name.to_string(), key.to_string()));
+        self
+    }
+
+    /// Sets the URL of the NATS server to publish events to.
+    #[cfg(feature = "nats")]
+    #[must_use]
+    pub fn nats_url(mut self, url: &str) -> ConfigBuilder {
+        self.config.nats_url = Some(url.to_string());
+        self
+    }
+
+    /// Sets the subject prefix for events published to NATS.
+    #[cfg(feature = "nats")]
+    #[must_use]
+    pub fn nats_subject(mut self, subject: &str) -> ConfigBuilder {
+        self.config.nats_subject = subject.to_string();
+        self
+    }
+
+    /// Sets the URL of the OpenTelemetry collector to send polls and events to.
+    #[cfg(feature = "otlp")]
+    #[must_use]
+    pub fn otlp_endpoint(mut self, url: &str) -> ConfigBuilder {
+        self.config.otlp_endpoint = Some(url.to_string());
+        self
+    }
+
+    /// Sets the name of the service that spans and log records are sent for.
+    #[cfg(feature = "otlp")]
+    #[must_use]
+    pub fn otlp_service_name(mut self, name: &str) -> ConfigBuilder {
+        self.config.otlp_service_name = name.to_string();
+        self
+    }
+
+    /// Sets the path of the history database.
+    #[cfg(feature = "history")]
+    #[must_use]
+    pub fn history_db(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.config.history_db = Some(path.into());
+        self
+    }
+
+    /// Sets the variables to record in the history database.
+    #[cfg(feature = "history")]
+    #[must_use]
+    pub fn history_vars(mut self, vars: &[&str]) -> ConfigBuilder {
+        self.config.history_vars = vars.iter().map(|var| var.to_string()).collect();
+        self
+    }
+
+    /// Sets the number of hours to retain records in the history database.
+    #[cfg(feature = "history")]
+    #[must_use]
+    pub fn history_retention(mut self, hours: u64) -> ConfigBuilder {
+        self.config.history_retention = hours;
+        self
+    }
+
+    /// Sets the AWS region to publish metrics to CloudWatch in.
+    #[cfg(feature = "cloudwatch")]
+    #[must_use]
+    pub fn cloudwatch_region(mut self, region: &str) -> ConfigBuilder {
+        self.config.cloudwatch_region = Some(region.to_string());
+        self
+    }
+
+    /// Sets the CloudWatch namespace to publish metrics in.
+    #[cfg(feature = "cloudwatch")]
+    #[must_use]
+    pub fn cloudwatch_namespace(mut self, namespace: &str) -> ConfigBuilder {
+        self.config.cloudwatch_namespace = namespace.to_string();
+        self
+    }
+
+    /// Adds a dimension to attach to CloudWatch metrics.
+    #[cfg(feature = "cloudwatch")]
+    #[must_use]
+    pub fn cloudwatch_dimension(mut self, name: &str, value: &str) -> ConfigBuilder {
+        self.config.cloudwatch_dimensions.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the variables to publish to CloudWatch.
+    #[cfg(feature = "cloudwatch")]
+    #[must_use]
+    pub fn cloudwatch_vars(mut self, vars: &[&str]) -> ConfigBuilder {
+        self.config.cloudwatch_vars = vars.iter().map(|var| var.to_string()).collect();
+        self
+    }
+
+    /// Sets the time in seconds between publishes to CloudWatch.
+    #[cfg(feature = "cloudwatch")]
+    #[must_use]
+    pub fn cloudwatch_interval(mut self, seconds: u64) -> ConfigBuilder {
+        self.config.cloudwatch_interval = seconds;
+        self
+    }
+
+    /// Sets the port on which to serve the gRPC API.
+    #[cfg(feature = "grpc")]
+    #[must_use]
+    pub fn grpc_port(mut self, port: u16) -> ConfigBuilder {
+        self.config.grpc_port = Some(port);
+        self
+    }
+
+    /// Validates the options and returns the configuration.
+    ///
+    /// # Errors
+    ///
+    /// An [`Error::Config`] will be returned if an option has an invalid value, such as a poll
+    /// rate of zero.
+    pub fn build(self) -> Result<Config> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_config() {
+        let config = Config::builder()
+            .ups_name("rack")
+            .poll_rate(5)
+            .zabbix_key("ups.status", "ups.state")
+            .build()
+            .unwrap();
+        assert_eq!(config.ups_name, "rack");
+        assert_eq!(config.poll_rate, 5);
+        assert_eq!(config.zabbix_keys, vec![(String::from("ups.status"), String::from("ups.state"))]);
+        assert!(matches!(Config::builder().poll_rate(0).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().metadata_connections(0).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().poll_stall_threshold(0).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().state_save_interval(0).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().http_rate_limit(0).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().http_timeouts(0, 10).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().voltage_tolerances(100.0, 10.0).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().frequency_tolerance(f64::NAN).build(), Err(Error::Config(_))));
+        let format: DateFormat = "battery.date=%d/%m/%Y".parse().unwrap();
+        assert!(matches!(Config::builder().date_format(format.clone()).date_format(format).build(), Err(Error::Config(_))));
+        let help: HelpText = "ups.load=Load on the UPS (percent)".parse().unwrap();
+        assert!(matches!(Config::builder().help_text(help.clone()).help_text(help).build(), Err(Error::Config(_))));
+        let tier: ShedTier = "lab=900/80".parse().unwrap();
+        assert!(matches!(Config::builder().shed_tier(tier.clone()).shed_tier(tier).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().poll_rate(10).sample_vars(&["input.voltage"], 10).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().sample_vars(&["input.voltage"], 2).sample_window(1).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().poll_rate(10).ready_max_staleness(5).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().http_idle_timeout(0).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().http_max_header_bytes(16).build(), Err(Error::Config(_))));
+        assert!(Config::builder().cors_origins(&["*", "http://localhost:3000"]).build().is_ok());
+        assert!(matches!(Config::builder().cors_origins(&["https://example.com/"]).build(), Err(Error::Config(_))));
+        let token: ApiToken = "dashboard-0123456789=status:read".parse().unwrap();
+        assert!(Config::builder().api_token(token.clone()).build().is_ok());
+        assert!(matches!(Config::builder().api_token(token.clone()).api_token(token.clone()).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().api_token("short=status:read".parse().unwrap()).build(), Err(Error::Config(_))));
+        #[cfg(feature = "grpc")]
+        assert!(matches!(Config::builder().api_token(token).grpc_port(9122).build(), Err(Error::Config(_))));
+        #[cfg(feature = "tls")]
+        {
+            let client: ClientScopes = "dashboard=status:read".parse().unwrap();
+            let builder = Config::builder().tls("cert.pem", "key.pem").tls_client_ca("ca.pem");
+            assert!(builder.clone().tls_client_scope(client.clone()).build().is_ok());
+            assert!(matches!(builder.tls_client_scope(client.clone()).tls_client_scope(client.clone()).build(), Err(Error::Config(_))));
+            assert!(matches!(Config::builder().tls_client_ca("ca.pem").build(), Err(Error::Config(_))));
+            assert!(matches!(Config::builder().tls("cert.pem", "key.pem").tls_client_scope(client).build(), Err(Error::Config(_))));
+            #[cfg(feature = "grpc")]
+            assert!(matches!(Config::builder().tls("cert.pem", "key.pem").tls_client_ca("ca.pem").grpc_port(9122).build(), Err(Error::Config(_))));
+        }
+        assert!(matches!(Config::builder().bind_ip("").build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().ups_host(" ").build(), Err(Error::Config(_))));
+        let period: PricePeriod = "22:00-06:00=0.12".parse().unwrap();
+        assert!(matches!(Config::builder().energy_price_period(period).build(), Err(Error::Config(_))));
+        assert!(Config::builder().energy_price(0.3).energy_price_period(period).build().is_ok());
+        assert!(matches!(Config::builder().backend(Backend::Usbhid).enable_commands(true).build(), Err(Error::Config(_))));
+        let builder = Config::builder().enable_commands(true).shutdown_command("systemctl poweroff");
+        assert_eq!(builder.clone().build().unwrap().ignored_by_read_only(), ["enable_commands", "shutdown_command"]);
+        assert!(builder.read_only(false).build().unwrap().ignored_by_read_only().is_empty());
+        assert!(matches!(Config::builder().backend(Backend::Modbus).build(), Err(Error::Config(_))));
+        assert!(Config::builder().backend(Backend::Modbus).modbus_register_map(PathBuf::from("ups.json")).build().is_ok());
+        assert!(matches!(Config::builder().output(Output::InfluxStdout).enable_set_vars(true).build(), Err(Error::Config(_))));
+        assert!(matches!(Config::builder().once(true).build(), Err(Error::Config(_))));
+        assert!(Config::builder().output(Output::CollectdExec).once(true).build().is_ok());
+
+        let target: UpsTarget = "other@nut.local".parse().unwrap();
+        assert!(matches!(Config::builder().target(target.clone()).target(target).build(), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parse_ups_targets() {
+        let target: UpsTarget = "myups@nut.example.com:3493".parse().unwrap();
+        assert_eq!(target.name, "myups");
+        assert_eq!(target.host, "nut.example.com");
+        assert_eq!(target.port, 3493);
+        let target: UpsTarget = "myups".parse().unwrap();
+        assert_eq!(target.to_string(), "myups@127.0.0.1:3493");
+        let target: UpsTarget = "myups@[::1]:3494".parse().unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("::1", 3494));
+        assert_eq!(target.to_string(), "myups@[::1]:3494");
+        assert_eq!("myups@fd00::1".parse::<UpsTarget>().unwrap().host, "fd00::1");
+        assert!("@nut.local".parse::<UpsTarget>().is_err());
+        assert!("myups@".parse::<UpsTarget>().is_err());
+        assert!("myups@nut.local:ups".parse::<UpsTarget>().is_err());
+        assert!("myups@[::1".parse::<UpsTarget>().is_err());
+    }
+
+    #[test]
+    fn deserialize_config() {
+        let config: Config = serde_json::from_str(r#"{"ups_name": "rack", "zabbix_keys": [["ups.status", "ups.state"]]}"#).unwrap();
+        assert_eq!(config.ups_name, "rack");
+        assert_eq!(config.poll_rate, crate::DEFAULT_POLL_RATE);
+        assert_eq!(config.zabbix_keys, vec![(String::from("ups.status"), String::from("ups.state"))]);
+        let config: Config = serde_json::from_str(r#"{"naming_scheme": "nut_exporter"}"#).unwrap();
+        assert_eq!(config.naming_scheme, NamingScheme::NutExporter);
+        let config: Config = serde_json::from_str(r#"{"http_access_log": "info"}"#).unwrap();
+        assert_eq!(config.http_access_log.level(), Some(log::Level::Info));
+        let round_trip: Config = serde_json::from_value(serde_json::to_value(&config).unwrap()).unwrap();
+        assert_eq!(round_trip, config);
+        assert!(serde_json::from_str::<Config>(r#"{"ups_nmae": "rack"}"#).is_err());
+        let invalid: Config = serde_json::from_str(r#"{"poll_rate": 0}"#).unwrap();
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,423 @@
+//! Management of connections to NUT servers, so connections can be shared by everything that
+//! reads from a server and are transparently replaced when they break.
+
+use crate::client::{Connection, UpsClient};
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use log::{debug, info};
+use prometheus::IntCounterVec;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Default number of idle connections kept open for each server.
+const DEFAULT_MAX_IDLE: usize = 2;
+
+/// A function that opens a new connection to the NUT server at a host and port.
+type Connector<C> = Box<dyn Fn(&str, u16) -> Result<C> + Send + Sync>;
+
+/// Opens a new connection to the NUT server at `host` and `port`.
+///
+/// # Errors
+///
+/// An [`Error::Config`] will be returned if the host and port are not a valid address, and a
+/// connection or protocol error will be returned if the NUT server cannot be reached.
+pub fn connect(host: &str, port: u16) -> Result<Connection> {
+    Connection::open(host, port)
+}
+
+/// Returns true if an error means the connection it happened on can no longer be used, as
+/// opposed to an error reported by the server, such as an unknown UPS.
+fn is_broken(err: &Error) -> bool {
+    err.error_type().breaks_connection()
+}
+
+/// Owns the connections to one or more NUT servers, handing them out as [`Lease`]s and keeping
+/// a few idle connections to each server open for reuse.
+pub struct ConnectionManager<C = Connection> {
+    connect: Connector<C>,
+    idle: Mutex<HashMap<(String, u16), Vec<C>>>,
+    max_idle: usize,
+}
+
+impl ConnectionManager<Connection> {
+    /// Creates a manager of connections to NUT servers.
+    #[must_use]
+    pub fn new() -> ConnectionManager<Connection> {
+        ConnectionManager::with_connector(connect)
+    }
+}
+
+impl Default for ConnectionManager<Connection> {
+    fn default() -> ConnectionManager<Connection> {
+        ConnectionManager::new()
+    }
+}
+
+impl<C: UpsClient> ConnectionManager<C> {
+    /// Creates a manager that opens connections with the given function, such as one returning a
+    /// [`crate::testing::MockUpsClient`].
+    #[must_use]
+    pub fn with_connector(connect: impl Fn(&str, u16) -> Result<C> + Send + Sync + 'static) -> ConnectionManager<C> {
+        ConnectionManager {
+            connect: Box::new(connect),
+            idle: Mutex::new(HashMap::new()),
+            max_idle: DEFAULT_MAX_IDLE,
+        }
+    }
+
+    /// Sets the number of idle connections kept open for each server.
+    #[must_use]
+    pub fn max_idle(mut self, max_idle: usize) -> ConnectionManager<C> {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Leases a connection to the server at `host` and `port`, reusing an idle one if possible.
+    /// The connection is returned to the manager when the lease is dropped, unless it broke.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if there is no idle connection and a new one cannot be opened.
+    pub fn lease(&self, host: &str, port: u16) -> Result<Lease<'_, C>> {
+        let key = (host.to_string(), port);
+        let idle = self.lock().get_mut(&key).and_then(Vec::pop);
+        match idle {
+            Some(client) => Ok(self.wrap(key, client, true)),
+            None => self.lease_new(key),
+        }
+    }
+
+    /// Leases a newly opened connection, ignoring any idle ones.
+    fn lease_new(&self, key: (String, u16)) -> Result<Lease<'_, C>> {
+        let client = (self.connect)(&key.0, key.1)?;
+        debug!("Opened new connection to NUT server at {}:{}", key.0, key.1);
+        Ok(self.wrap(key, client, false))
+    }
+
+    fn wrap(&self, key: (String, u16), client: C, reused: bool) -> Lease<'_, C> {
+        Lease {
+            manager: self,
+            key,
+            client: Some(client),
+            reused,
+            broken: false,
+        }
+    }
+
+    /// Returns the number of idle connections to the server at `host` and `port`.
+    #[must_use]
+    pub fn idle_count(&self, host: &str, port: u16) -> usize {
+        self.lock().get(&(host.to_string(), port)).map_or(0, Vec::len)
+    }
+
+    /// Gracefully closes every idle connection.
+    pub fn close_idle(&self) {
+        let idle: Vec<_> = self.lock().drain().flat_map(|(_, clients)| clients).collect();
+        for client in idle {
+            if let Err(err) = client.close() {
+                debug!("Failed to close idle connection: {err}");
+            }
+        }
+    }
+
+    /// Takes back a connection at the end of a lease.
+    fn release(&self, key: (String, u16), client: C) {
+        let mut idle = self.lock();
+        let clients = idle.entry(key).or_default();
+        if clients.len() < self.max_idle {
+            clients.push(client);
+        } else {
+            drop(idle);
+            if let Err(err) = client.close() {
+                debug!("Failed to close surplus connection: {err}");
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, u16), Vec<C>>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<C> fmt::Debug for ConnectionManager<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionManager").field("max_idle", &self.max_idle).finish_non_exhaustive()
+    }
+}
+
+/// A connection leased from a [`ConnectionManager`]. Any error that breaks the connection is
+/// noticed, so a broken connection is never handed out again.
+pub struct Lease<'a, C: UpsClient> {
+    manager: &'a ConnectionManager<C>,
+    key: (String, u16),
+    client: Option<C>,
+    reused: bool,
+    broken: bool,
+}
+
+impl<C: UpsClient> Lease<'_, C> {
+    /// Returns true if the connection was idle before this lease, rather than newly opened.
+    #[must_use]
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Runs a request on the connection, marking it as broken if the request fails because of it.
+    fn request<T>(&mut self, f: impl FnOnce(&mut C) -> Result<T>) -> Result<T> {
+        let client = self.client.as_mut().expect("a lease always holds a client until dropped");
+        let result = f(client);
+        if let Err(err) = &result {
+            self.broken |= is_broken(err);
+        }
+        result
+    }
+}
+
+impl<C: UpsClient> UpsClient for Lease<'_, C> {
+    fn list_vars(&mut self, ups_name: &str) -> Result<Vec<Variable>> {
+        self.request(|client| client.list_vars(ups_name))
+    }
+
+    fn get_var(&mut self, ups_name: &str, var_name: &str) -> Result<Variable> {
+        self.request(|client| client.get_var(ups_name, var_name))
+    }
+
+    fn get_var_description(&mut self, ups_name: &str, var_name: &str) -> Result<String> {
+        self.request(|client| client.get_var_description(ups_name, var_name))
+    }
+
+    fn get_var_type(&mut self, ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        self.request(|client| client.get_var_type(ups_name, var_name))
+    }
+
+    fn list_var_enum(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<String>> {
+        self.request(|client| client.list_var_enum(ups_name, var_name))
+    }
+
+    fn list_var_range(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<VariableRange>> {
+        self.request(|client| client.list_var_range(ups_name, var_name))
+    }
+
+    fn list_clients(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        self.request(|client| client.list_clients(ups_name))
+    }
+
+    fn list_commands(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        self.request(|client| client.list_commands(ups_name))
+    }
+
+    fn get_command_description(&mut self, ups_name: &str, command: &str) -> Result<String> {
+        self.request(|client| client.get_command_description(ups_name, command))
+    }
+
+    /// Ends the lease, returning the connection to the manager.
+    fn close(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<C: UpsClient> Drop for Lease<'_, C> {
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else { return };
+        if self.broken {
+            debug!("Discarding broken connection to NUT server at {}:{}", self.key.0, self.key.1);
+        } else {
+            self.manager.release(self.key.clone(), client);
+        }
+    }
+}
+
+impl<C: UpsClient> fmt::Debug for Lease<'_, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lease")
+            .field("host", &self.key.0)
+            .field("port", &self.key.1)
+            .field("reused", &self.reused)
+            .field("broken", &self.broken)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [`UpsClient`] for one NUT server that leases a connection from a [`ConnectionManager`] for
+/// every request. If a reused connection turns out to be broken, such as after the server was
+/// restarted, the request is retried once on a new connection.
+#[derive(Debug)]
+pub struct ManagedClient<C: UpsClient = Connection> {
+    manager: Arc<ConnectionManager<C>>,
+    host: String,
+    port: u16,
+    errors: Option<IntCounterVec>,
+}
+
+impl<C: UpsClient> ManagedClient<C> {
+    /// Creates a client for the server at `host` and `port`.
+    #[must_use]
+    pub fn new(manager: Arc<ConnectionManager<C>>, host: &str, port: u16) -> ManagedClient<C> {
+        ManagedClient {
+            manager,
+            host: host.to_string(),
+            port,
+            errors: None,
+        }
+    }
+
+    /// Counts every error of a request in `counter`, labelled with the [`ErrorType`] of the error,
+    /// including errors of requests that succeeded once retried on a new connection.
+    ///
+    /// [`ErrorType`]: crate::ErrorType
+    #[must_use]
+    pub fn count_errors(mut self, counter: IntCounterVec) -> ManagedClient<C> {
+        self.errors = Some(counter);
+        self
+    }
+
+    /// Counts an error, if errors are counted.
+    fn count(&self, err: &Error) {
+        if let Some(errors) = &self.errors {
+            errors.with_label_values(&[err.error_type().as_str()]).inc();
+        }
+    }
+
+    /// Returns the manager the client leases connections from.
+    #[must_use]
+    pub fn manager(&self) -> &Arc<ConnectionManager<C>> {
+        &self.manager
+    }
+
+    fn request<T>(&self, f: impl Fn(&mut Lease<'_, C>) -> Result<T>) -> Result<T> {
+        let result = self.manager.lease(&self.host, self.port).and_then(|mut lease| match f(&mut lease) {
+            Err(err) if lease.reused && is_broken(&err) => {
+                drop(lease);
+                self.count(&err);
+                #[cfg(feature = "tracing")]
+                let _span = tracing::info_span!("reconnect", host = %self.host, port = self.port).entered();
+                info!("Reconnecting to NUT server at {}:{} after error of type {}: {err}", self.host, self.port, err.error_type().as_str());
+                f(&mut self.manager.lease_new((self.host.clone(), self.port))?)
+            }
+            result => result,
+        });
+        if let Err(err) = &result {
+            self.count(err);
+        }
+        result
+    }
+}
+
+impl<C: UpsClient> UpsClient for ManagedClient<C> {
+    fn list_vars(&mut self, ups_name: &str) -> Result<Vec<Variable>> {
+        self.request(|lease| lease.list_vars(ups_name))
+    }
+
+    fn get_var(&mut self, ups_name: &str, var_name: &str) -> Result<Variable> {
+        self.request(|lease| lease.get_var(ups_name, var_name))
+    }
+
+    fn get_var_description(&mut self, ups_name: &str, var_name: &str) -> Result<String> {
+        self.request(|lease| lease.get_var_description(ups_name, var_name))
+    }
+
+    fn get_var_type(&mut self, ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        self.request(|lease| lease.get_var_type(ups_name, var_name))
+    }
+
+    fn list_var_enum(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<String>> {
+        self.request(|lease| lease.list_var_enum(ups_name, var_name))
+    }
+
+    fn list_var_range(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<VariableRange>> {
+        self.request(|lease| lease.list_var_range(ups_name, var_name))
+    }
+
+    fn list_clients(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        self.request(|lease| lease.list_clients(ups_name))
+    }
+
+    fn list_commands(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        self.request(|lease| lease.list_commands(ups_name))
+    }
+
+    fn get_command_description(&mut self, ups_name: &str, command: &str) -> Result<String> {
+        self.request(|lease| lease.get_command_description(ups_name, command))
+    }
+
+    fn close(self) -> Result<()> {
+        self.manager.close_idle();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockUpsClient;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Returns a manager whose connections serve one poll and then fail, along with the number of
+    /// connections it has opened.
+    fn flaky_manager() -> (Arc<ConnectionManager<MockUpsClient>>, Arc<AtomicUsize>) {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connects);
+        let manager = ConnectionManager::with_connector(move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(MockUpsClient::new().with_var("ups.status", "OL").then_poll().then_fail())
+        });
+        (Arc::new(manager), connects)
+    }
+
+    #[test]
+    fn reuse_leased_connections() {
+        let (manager, connects) = flaky_manager();
+        let lease = manager.lease("nut.local", 3493).unwrap();
+        assert!(!lease.is_reused());
+        drop(lease);
+        assert_eq!(manager.idle_count("nut.local", 3493), 1);
+        let mut lease = manager.lease("nut.local", 3493).unwrap();
+        assert!(lease.is_reused());
+        assert_eq!(connects.load(Ordering::Relaxed), 1);
+
+        // A connection that broke is discarded instead of returned
+        lease.list_vars("ups").unwrap();
+        assert!(lease.list_vars("ups").is_err());
+        drop(lease);
+        assert_eq!(manager.idle_count("nut.local", 3493), 0);
+
+        // Connections to different servers are kept apart
+        drop(manager.lease("other.local", 3493).unwrap());
+        assert_eq!(manager.idle_count("nut.local", 3493), 0);
+        assert_eq!(connects.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn reconnect_broken_connections() {
+        let (manager, connects) = flaky_manager();
+        let mut client = ManagedClient::new(Arc::clone(&manager), "nut.local", 3493);
+        for _ in 0..3 {
+            assert_eq!(client.list_vars("ups").unwrap().len(), 1);
+        }
+        assert_eq!(connects.load(Ordering::Relaxed), 3);
+
+        // A new connection that fails is not retried
+        let manager = Arc::new(ConnectionManager::with_connector(|_, _| Ok(MockUpsClient::new().then_fail())));
+        let mut client = ManagedClient::new(manager, "nut.local", 3493);
+        assert!(matches!(client.list_vars("ups"), Err(Error::Connection(_))));
+    }
+
+    #[test]
+    fn count_errors_by_type() {
+        let (manager, _) = flaky_manager();
+        let errors = IntCounterVec::new(prometheus::Opts::new("pistachio_nut_errors_total", "Errors"), &["error_type"]).unwrap();
+        let mut client = ManagedClient::new(manager, "nut.local", 3493).count_errors(errors.clone());
+        client.list_vars("ups").unwrap();
+        // The broken connection is counted even though the retry succeeds
+        client.list_vars("ups").unwrap();
+        assert_eq!(errors.with_label_values(&["network"]).get(), 1);
+
+        let manager = Arc::new(ConnectionManager::with_connector(|_, _| {
+            Ok(MockUpsClient::new().then_fail_with(Error::Protocol(rups::NutError::AccessDenied)))
+        }));
+        let mut client = ManagedClient::new(manager, "nut.local", 3493).count_errors(errors.clone());
+        assert!(client.list_vars("ups").is_err());
+        assert_eq!(errors.with_label_values(&["auth"]).get(), 1);
+        assert_eq!(errors.with_label_values(&["network"]).get(), 1);
+    }
+}
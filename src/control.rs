@@ -0,0 +1,621 @@
+//! Control of a UPS through authenticated NUT commands, such as instant commands and setting
+//! writable variables.
+//!
+//! rups only implements the read-only part of the NUT protocol, so the few commands needed here
+//! are sent over a short-lived connection of their own. Every operation logs in with the
+//! credentials it is given, leaving authorization entirely to `upsd.users` on the NUT server.
+
+use crate::audit::{Action, AuditEntry, AuditLog};
+use crate::events::Event;
+use crate::http::{Request, Response};
+use crate::{Error, Result};
+use log::{debug, warn};
+use serde::Deserialize;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Maximum time allowed for connecting to and exchanging data with the NUT server.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum time to wait for the result of a tracked instant command.
+const TRACKING_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Time between checks of the result of a tracked instant command.
+const TRACKING_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The result of an instant command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandResult {
+    /// The server accepted the command, but does not support tracking, so whether it succeeded
+    /// is unknown.
+    Accepted,
+    /// The driver ran the command successfully.
+    Succeeded,
+    /// The driver failed to run the command, for the given reason.
+    Failed(String),
+    /// The command had not finished when pistachio stopped waiting for it.
+    Pending,
+}
+
+impl CommandResult {
+    /// Returns a short, stable identifier for the result, such as `succeeded`.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandResult::Accepted => "accepted",
+            CommandResult::Succeeded => "succeeded",
+            CommandResult::Failed(_) => "failed",
+            CommandResult::Pending => "pending",
+        }
+    }
+}
+
+/// A NUT user name and password, as configured in `upsd.users`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// Name of the user.
+    pub username: String,
+    /// Password of the user.
+    pub password: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Sends authenticated commands to a NUT server on behalf of a user.
+#[derive(Debug, Clone)]
+pub struct Controller {
+    host: String,
+    port: u16,
+    credentials: Credentials,
+}
+
+impl Controller {
+    /// Creates a controller for the NUT server at the given host and port.
+    #[must_use]
+    pub fn new(host: &str, port: u16, credentials: Credentials) -> Controller {
+        Controller {
+            host: host.to_string(),
+            port,
+            credentials,
+        }
+    }
+
+    /// Runs an instant command, such as `beeper.mute` or `test.battery.start.quick`, with an
+    /// optional parameter.
+    ///
+    /// If the server supports tracking, this waits for the driver to report whether the command
+    /// succeeded, otherwise the command is only known to have been accepted.
+    ///
+    /// # Errors
+    ///
+    /// An [`Error::Config`] will be returned if the command name is invalid, and a connection or
+    /// protocol error if the server cannot be reached or refuses the command, such as when the
+    /// user is not allowed to run it.
+    pub fn run_command(&self, ups_name: &str, command: &str, param: Option<&str>) -> Result<CommandResult> {
+        validate_name("UPS", ups_name)?;
+        validate_name("command", command)?;
+        let mut line = format!("INSTCMD {ups_name} {command}");
+        if let Some(param) = param {
+            line.push(' ');
+            line.push_str(&quote(param)?);
+        }
+        let mut session = self.session()?;
+        let tracking = match session.request("SET TRACKING ON") {
+            Ok(_) => true,
+            Err(Error::Protocol(err)) => {
+                debug!("NUT server does not support tracking commands: {err}");
+                false
+            }
+            Err(err) => return Err(err),
+        };
+        let response = session.request(&line)?;
+        let result = match response.strip_prefix("OK TRACKING ").filter(|_| tracking) {
+            Some(id) => session.wait_for(id.trim())?,
+            None => CommandResult::Accepted,
+        };
+        session.logout();
+        Ok(result)
+    }
+
+    /// Sets a writable variable, such as `battery.charge.low`, to a new value.
+    ///
+    /// # Errors
+    ///
+    /// An [`Error::Config`] will be returned if the variable name is invalid or the value contains
+    /// control characters, and a connection or protocol error if the server cannot be reached or
+    /// refuses the change, such as when the variable is read-only or the value is out of range.
+    pub fn set_var(&self, ups_name: &str, var_name: &str, value: &str) -> Result<()> {
+        validate_name("UPS", ups_name)?;
+        validate_name("variable", var_name)?;
+        let value = quote(value)?;
+        let mut session = self.session()?;
+        session.request(&format!("SET VAR {ups_name} {var_name} {value}"))?;
+        session.logout();
+        Ok(())
+    }
+
+    /// Connects to the server and authenticates.
+    fn session(&self) -> Result<Session> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(Error::Connection)?
+            .next()
+            .ok_or_else(|| Error::Config(format!("UPS host {} did not resolve", self.host)))?;
+        let stream = TcpStream::connect_timeout(&addr, CONTROL_TIMEOUT).map_err(Error::Connection)?;
+        stream.set_read_timeout(Some(CONTROL_TIMEOUT)).map_err(Error::Connection)?;
+        stream.set_write_timeout(Some(CONTROL_TIMEOUT)).map_err(Error::Connection)?;
+        let mut session = Session {
+            reader: BufReader::new(stream.try_clone().map_err(Error::Connection)?),
+            stream,
+        };
+        let (username, password) = (quote(&self.credentials.username)?, quote(&self.credentials.password)?);
+        session.request(&format!("USERNAME {username}"))?;
+        session.request(&format!("PASSWORD {password}"))?;
+        Ok(session)
+    }
+}
+
+/// Body of a request to run an instant command.
+#[derive(Debug, Deserialize)]
+struct CommandRequest {
+    command: String,
+    param: Option<String>,
+}
+
+/// Body of a request to set a variable.
+#[derive(Debug, Deserialize)]
+struct SetVarRequest {
+    name: String,
+    value: String,
+}
+
+/// The HTTP API for controlling a UPS. Requests must be authenticated with HTTP Basic
+/// authentication using the NUT user name and password of a user allowed to make the change in
+/// `upsd.users`. Every command run and variable set is recorded to the audit log, whether or not
+/// it succeeded.
+#[derive(Debug, Clone)]
+pub struct ControlApi {
+    host: String,
+    port: u16,
+    ups_name: String,
+    events: Option<Sender<Event>>,
+    audit: AuditLog,
+}
+
+impl ControlApi {
+    /// Creates an API that controls the given UPS of the NUT server at `host` and `port`.
+    #[must_use]
+    pub fn new(host: &str, port: u16, ups_name: &str) -> ControlApi {
+        ControlApi {
+            host: host.to_string(),
+            port,
+            ups_name: ups_name.to_string(),
+            events: None,
+            audit: AuditLog::default(),
+        }
+    }
+
+    /// Records every command run and variable set to `audit`, instead of only logging them.
+    #[must_use]
+    pub fn with_audit(mut self, audit: AuditLog) -> ControlApi {
+        self.audit = audit;
+        self
+    }
+
+    /// Records an action made on behalf of the client of a request.
+    fn audit(&self, action: Action, request: &Request, result: &str, error: Option<String>) {
+        self.audit.record(&AuditEntry {
+            action,
+            ups: self.ups_name.clone(),
+            source: Some(request.remote_addr),
+            user: request.basic_auth().map(|(username, _)| username),
+            result: result.to_string(),
+            error,
+        });
+    }
+
+    /// Sends an [`Event::CommandFinished`] to `events` whenever a tracked command finishes.
+    #[must_use]
+    pub fn with_events(mut self, events: Sender<Event>) -> ControlApi {
+        self.events = Some(events);
+        self
+    }
+
+    /// Returns a controller acting with the credentials of the request, or the response to send
+    /// if there are none.
+    fn controller(&self, request: &Request) -> Result<Controller, Response> {
+        let (username, password) = request.basic_auth().ok_or_else(Response::unauthorized)?;
+        Ok(Controller::new(&self.host, self.port, Credentials { username, password }))
+    }
+
+    /// Handles a `POST` request to run an instant command, with a JSON body such as
+    /// `{"command": "beeper.mute"}`.
+    #[must_use]
+    pub fn handle_command(&self, request: &Request) -> Response {
+        let controller = match self.controller(request) {
+            Ok(controller) => controller,
+            Err(response) => return response,
+        };
+        let body: CommandRequest = match serde_json::from_slice(&request.body) {
+            Ok(body) => body,
+            Err(err) => return Response::text(400, &format!("Invalid command request: {err}\n")),
+        };
+        let outcome = controller.run_command(&self.ups_name, &body.command, body.param.as_deref());
+        let action = Action::Command {
+            command: body.command.clone(),
+            param: body.param.clone(),
+        };
+        match &outcome {
+            Ok(CommandResult::Failed(reason)) => self.audit(action, request, "failed", Some(reason.clone())),
+            Ok(result) => self.audit(action, request, result.as_str(), None),
+            Err(err) => self.audit(action, request, "error", Some(err.to_string())),
+        }
+        match outcome {
+            Ok(result) => {
+                let mut json = serde_json::json!({"ups": self.ups_name, "command": body.command, "status": result.as_str()});
+                let error = match &result {
+                    CommandResult::Failed(reason) => Some(reason.clone()),
+                    _ => None,
+                };
+                if let Some(error) = &error {
+                    json["error"] = error.clone().into();
+                }
+                if matches!(result, CommandResult::Succeeded | CommandResult::Failed(_)) {
+                    let event = Event::CommandFinished { command: body.command, error };
+                    if let Some(events) = &self.events {
+                        let _ = events.send(event);
+                    }
+                }
+                let status = match result {
+                    CommandResult::Accepted | CommandResult::Succeeded => 200,
+                    CommandResult::Pending => 202,
+                    CommandResult::Failed(_) => 502,
+                };
+                Response::json(status, &json)
+            }
+            Err(err) => {
+                warn!("Failed to run command {} from {}: {err}", body.command, request.remote_addr);
+                error_response(&err)
+            }
+        }
+    }
+
+    /// Handles a `POST` request to set a writable variable, with a JSON body such as
+    /// `{"name": "battery.charge.low", "value": "20"}`.
+    #[must_use]
+    pub fn handle_set_var(&self, request: &Request) -> Response {
+        let controller = match self.controller(request) {
+            Ok(controller) => controller,
+            Err(response) => return response,
+        };
+        let body: SetVarRequest = match serde_json::from_slice(&request.body) {
+            Ok(body) => body,
+            Err(err) => return Response::text(400, &format!("Invalid variable request: {err}\n")),
+        };
+        let outcome = controller.set_var(&self.ups_name, &body.name, &body.value);
+        let action = Action::SetVar {
+            name: body.name.clone(),
+            value: body.value.clone(),
+        };
+        match &outcome {
+            Ok(()) => self.audit(action, request, "succeeded", None),
+            Err(err) => self.audit(action, request, "error", Some(err.to_string())),
+        }
+        match outcome {
+            Ok(()) => Response::json(
+                200,
+                &serde_json::json!({"ups": self.ups_name, "name": body.name, "value": body.value, "status": "ok"}),
+            ),
+            Err(err) => {
+                warn!("Failed to set {} from {}: {err}", body.name, request.remote_addr);
+                error_response(&err)
+            }
+        }
+    }
+}
+
+/// Converts an error from the NUT server into an HTTP response.
+fn error_response(err: &Error) -> Response {
+    let status = match err {
+        Error::Config(_) => 400,
+        Error::Protocol(
+            rups::NutError::AccessDenied
+            | rups::NutError::InvalidUsername
+            | rups::NutError::InvalidPassword
+            | rups::NutError::UsernameRequired
+            | rups::NutError::PasswordRequired,
+        ) => 403,
+        Error::Protocol(
+            rups::NutError::CmdNotSupported
+            | rups::NutError::VarNotSupported
+            | rups::NutError::InvalidArgument
+            | rups::NutError::InvalidValue
+            | rups::NutError::ReadOnly
+            | rups::NutError::TooLong,
+        ) => 400,
+        _ => 502,
+    };
+    Response::text(status, &format!("{err}\n"))
+}
+
+/// An open connection to the NUT server.
+struct Session {
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl Session {
+    /// Sends a command and returns the `OK` response, or the error reported by the server.
+    fn request(&mut self, line: &str) -> Result<String> {
+        let response = self.exchange(line)?;
+        if !response.starts_with("OK") {
+            return Err(Error::Protocol(rups::NutError::UnexpectedResponse));
+        }
+        Ok(response)
+    }
+
+    /// Waits for the result of a tracked command.
+    fn wait_for(&mut self, id: &str) -> Result<CommandResult> {
+        let deadline = Instant::now() + TRACKING_TIMEOUT;
+        loop {
+            match self.exchange(&format!("GET TRACKING {}", quote(id)?)) {
+                Ok(response) if response == "SUCCESS" => return Ok(CommandResult::Succeeded),
+                Ok(response) if response == "PENDING" => {}
+                Ok(_) => return Err(Error::Protocol(rups::NutError::UnexpectedResponse)),
+                Err(Error::Protocol(err)) => return Ok(CommandResult::Failed(err.to_string())),
+                Err(err) => return Err(err),
+            }
+            if Instant::now() >= deadline {
+                return Ok(CommandResult::Pending);
+            }
+            thread::sleep(TRACKING_INTERVAL);
+        }
+    }
+
+    /// Sends a command and returns the response, or the error reported by the server.
+    fn exchange(&mut self, line: &str) -> Result<String> {
+        debug!("Sending {} to NUT server", line.split(' ').next().unwrap_or_default());
+        self.stream
+            .write_all(format!("{line}\n").as_bytes())
+            .map_err(Error::Connection)?;
+        let mut response = String::new();
+        if self.reader.read_line(&mut response).map_err(Error::Connection)? == 0 {
+            return Err(Error::Connection(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "NUT server closed the connection",
+            )));
+        }
+        let response = response.trim_end();
+        if let Some(code) = response.strip_prefix("ERR ") {
+            let code = code.split_whitespace().next().unwrap_or_default();
+            return Err(Error::Protocol(nut_error(code)));
+        }
+        Ok(response.to_string())
+    }
+
+    /// Ends the session, ignoring any failure since the work is already done.
+    fn logout(mut self) {
+        let _ = self.request("LOGOUT");
+    }
+}
+
+/// Checks that a UPS, command, or variable name only contains characters allowed by NUT, so it
+/// can be sent without quoting.
+fn validate_name(kind: &str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Config(format!("invalid {kind} name `{name}`")))
+    }
+}
+
+/// Quotes an argument for the NUT protocol, escaping quotes and backslashes.
+///
+/// # Errors
+///
+/// An [`Error::Config`] will be returned if the argument contains control characters, such as
+/// line breaks, which would end the line and send what follows as another request.
+fn quote(value: &str) -> Result<String> {
+    if value.chars().any(char::is_control) {
+        return Err(Error::Config(String::from("arguments must not contain control characters")));
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    Ok(quoted)
+}
+
+/// Converts an error code sent by the NUT server into an error.
+fn nut_error(code: &str) -> rups::NutError {
+    match code {
+        "ACCESS-DENIED" => rups::NutError::AccessDenied,
+        "UNKNOWN-UPS" => rups::NutError::UnknownUps,
+        "VAR-NOT-SUPPORTED" => rups::NutError::VarNotSupported,
+        "CMD-NOT-SUPPORTED" => rups::NutError::CmdNotSupported,
+        "INVALID-ARGUMENT" => rups::NutError::InvalidArgument,
+        "INSTCMD-FAILED" => rups::NutError::InstCmdFailed,
+        "SET-FAILED" => rups::NutError::SetFailed,
+        "READONLY" => rups::NutError::ReadOnly,
+        "TOO-LONG" => rups::NutError::TooLong,
+        "FEATURE-NOT-SUPPORTED" => rups::NutError::FeatureNotSupported,
+        "FEATURE-NOT-CONFIGURED" => rups::NutError::FeatureNotConfigured,
+        "DRIVER-NOT-CONNECTED" => rups::NutError::DriverNotConnected,
+        "DATA-STALE" => rups::NutError::DataStale,
+        "INVALID-PASSWORD" => rups::NutError::InvalidPassword,
+        "INVALID-USERNAME" => rups::NutError::InvalidUsername,
+        "USERNAME-REQUIRED" => rups::NutError::UsernameRequired,
+        "PASSWORD-REQUIRED" => rups::NutError::PasswordRequired,
+        "UNKNOWN-COMMAND" => rups::NutError::UnknownCommand,
+        "INVALID-VALUE" => rups::NutError::InvalidValue,
+        _ => rups::NutError::Generic(code.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves one connection, answering each line with the next canned response.
+    fn serve(responses: &'static [&'static str]) -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut lines = Vec::new();
+            for response in responses {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                lines.push(line.trim_end().to_string());
+                stream.write_all(format!("{response}\n").as_bytes()).unwrap();
+            }
+            lines
+        });
+        (port, server)
+    }
+
+    fn credentials() -> Credentials {
+        Credentials {
+            username: String::from("admin"),
+            password: String::from("p\"ss"),
+        }
+    }
+
+    #[test]
+    fn run_instant_command() {
+        let (port, server) = serve(&["OK", "OK", "ERR UNKNOWN-COMMAND", "OK", "OK Goodbye"]);
+        let controller = Controller::new("127.0.0.1", port, credentials());
+        assert_eq!(controller.run_command("ups", "beeper.mute", None).unwrap(), CommandResult::Accepted);
+        let lines = server.join().unwrap();
+        assert_eq!(
+            lines,
+            ["USERNAME \"admin\"", "PASSWORD \"p\\\"ss\"", "SET TRACKING ON", "INSTCMD ups beeper.mute", "LOGOUT"]
+        );
+    }
+
+    #[test]
+    fn track_instant_command() {
+        let (port, server) = serve(&["OK", "OK", "OK", "OK TRACKING 1bd31808", "PENDING", "SUCCESS", "OK Goodbye"]);
+        let controller = Controller::new("127.0.0.1", port, credentials());
+        assert_eq!(controller.run_command("ups", "beeper.mute", None).unwrap(), CommandResult::Succeeded);
+        let lines = server.join().unwrap();
+        assert_eq!(lines[4], "GET TRACKING \"1bd31808\"");
+
+        let (port, server) = serve(&["OK", "OK", "OK", "OK TRACKING 1bd31808", "ERR INSTCMD-FAILED", "OK Goodbye"]);
+        let controller = Controller::new("127.0.0.1", port, credentials());
+        let result = controller.run_command("ups", "test.battery.start", None).unwrap();
+        assert!(matches!(result, CommandResult::Failed(_)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn report_refused_commands() {
+        let (port, server) = serve(&["OK", "OK", "OK", "ERR ACCESS-DENIED"]);
+        let controller = Controller::new("127.0.0.1", port, credentials());
+        let err = controller.run_command("ups", "load.off", None).unwrap_err();
+        assert!(matches!(err, Error::Protocol(rups::NutError::AccessDenied)));
+        server.join().unwrap();
+        assert!(matches!(controller.run_command("ups", "load.off\nLOGOUT", None), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn reject_control_characters() {
+        let (port, server) = serve(&["OK", "OK", "ERR UNKNOWN-COMMAND", "OK", "OK Goodbye"]);
+        let controller = Controller::new("127.0.0.1", port, credentials());
+        let err = controller.run_command("ups", "beeper.mute", Some("x\"\nFSD ups\n")).unwrap_err();
+        assert!(matches!(err, Error::Config(_)));
+        assert_eq!(error_response(&err).status, 400);
+        controller.run_command("ups", "beeper.mute", None).unwrap();
+        let lines = server.join().unwrap();
+        assert_eq!(
+            lines,
+            ["USERNAME \"admin\"", "PASSWORD \"p\\\"ss\"", "SET TRACKING ON", "INSTCMD ups beeper.mute", "LOGOUT"]
+        );
+
+        let (port, server) = serve(&["OK", "OK"]);
+        let credentials = Credentials {
+            username: String::from("admin\nFSD ups"),
+            password: String::from("secret"),
+        };
+        let controller = Controller::new("127.0.0.1", port, credentials);
+        assert!(matches!(controller.run_command("ups", "beeper.mute", None), Err(Error::Config(_))));
+        assert!(server.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn require_authentication() {
+        let api = ControlApi::new("127.0.0.1", 1, "ups");
+        let request = Request {
+            method: String::from("POST"),
+            path: String::from("/api/v1/command"),
+            query: std::collections::HashMap::new(),
+            headers: std::collections::HashMap::new(),
+            body: br#"{"command": "beeper.mute"}"#.to_vec(),
+            remote_addr: std::net::SocketAddr::from(([127, 0, 0, 1], 1234)),
+            client_cn: None,
+        };
+        assert_eq!(api.handle_command(&request).status, 401);
+        assert_eq!(api.handle_set_var(&request).status, 401);
+    }
+
+    #[test]
+    fn reject_injected_values() {
+        let (port, server) = serve(&["OK", "OK", "OK", "OK Goodbye"]);
+        let api = ControlApi::new("127.0.0.1", port, "ups");
+        let request = |body: &[u8]| Request {
+            method: String::from("POST"),
+            path: String::from("/api/v1/variable"),
+            query: std::collections::HashMap::new(),
+            headers: [(String::from("authorization"), String::from("Basic YWRtaW46c2VjcmV0"))].into(),
+            body: body.to_vec(),
+            remote_addr: std::net::SocketAddr::from(([127, 0, 0, 1], 1234)),
+            client_cn: None,
+        };
+        let injected = request(br#"{"name": "battery.charge.low", "value": "20\"\nINSTCMD ups load.off\n"}"#);
+        assert_eq!(api.handle_set_var(&injected).status, 400);
+        assert_eq!(api.handle_set_var(&request(br#"{"name": "battery.charge.low", "value": "20"}"#)).status, 200);
+        let lines = server.join().unwrap();
+        assert_eq!(lines, ["USERNAME \"admin\"", "PASSWORD \"secret\"", "SET VAR ups battery.charge.low \"20\"", "LOGOUT"]);
+    }
+
+    #[test]
+    fn set_writable_variable() {
+        let (port, server) = serve(&["OK", "OK", "OK", "OK Goodbye"]);
+        let controller = Controller::new("127.0.0.1", port, credentials());
+        controller.set_var("ups", "battery.charge.low", "20").unwrap();
+        let lines = server.join().unwrap();
+        assert_eq!(lines[2], "SET VAR ups battery.charge.low \"20\"");
+
+        let (port, server) = serve(&["OK", "OK", "ERR READONLY"]);
+        let controller = Controller::new("127.0.0.1", port, credentials());
+        let err = controller.set_var("ups", "ups.mfr", "APC").unwrap_err();
+        assert!(matches!(err, Error::Protocol(rups::NutError::ReadOnly)));
+        assert_eq!(error_response(&err).status, 400);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+//! Prices of electricity, used to estimate what the energy delivered to the load costs.
+
+use crate::time::Civil;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// A price per kWh that applies during part of every day, written as `HH:MM-HH:MM=PRICE`, such
+/// as `22:00-06:00=0.12`. Times are in UTC, and a period may run past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PricePeriod {
+    /// Minute of the day at which the period starts.
+    pub start: u32,
+    /// Minute of the day at which the period ends, exclusive.
+    pub end: u32,
+    /// Price per kWh during the period.
+    pub price: f64,
+}
+
+impl PricePeriod {
+    /// Returns true if the given minute of the day is within the period.
+    #[must_use]
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for PricePeriod {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<PricePeriod, Error> {
+        let invalid = || Error::Parse(format!("expected HH:MM-HH:MM=PRICE, got `{input}`"));
+        let (times, price) = input.split_once('=').ok_or_else(invalid)?;
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let price: f64 = price.trim().parse().map_err(|_| invalid())?;
+        if !price.is_finite() || price < 0.0 {
+            return Err(invalid());
+        }
+        Ok(PricePeriod {
+            start: parse_minute(start).ok_or_else(invalid)?,
+            end: parse_minute(end).ok_or_else(invalid)?,
+            price,
+        })
+    }
+}
+
+impl TryFrom<String> for PricePeriod {
+    type Error = Error;
+
+    fn try_from(input: String) -> Result<PricePeriod, Error> {
+        input.parse()
+    }
+}
+
+impl From<PricePeriod> for String {
+    fn from(period: PricePeriod) -> String {
+        period.to_string()
+    }
+}
+
+impl fmt::Display for PricePeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}={}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60,
+            self.price
+        )
+    }
+}
+
+/// Parses a time of day such as `06:30` into the minute of the day.
+fn parse_minute(input: &str) -> Option<u32> {
+    let (hour, minute) = input.trim().split_once(':')?;
+    let hour: u32 = hour.parse().ok()?;
+    let minute: u32 = minute.parse().ok()?;
+    (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+}
+
+/// A price per kWh, optionally varying with the time of day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pricing {
+    price: f64,
+    periods: Vec<PricePeriod>,
+}
+
+impl Pricing {
+    /// Creates a pricing where `price` applies whenever none of the `periods` does. If periods
+    /// overlap, the first one listed applies.
+    #[must_use]
+    pub fn new(price: f64, periods: Vec<PricePeriod>) -> Pricing {
+        Pricing { price, periods }
+    }
+
+    /// Returns the price per kWh at the given time.
+    #[must_use]
+    pub fn price_at(&self, time: SystemTime) -> f64 {
+        let civil = Civil::from_system_time(time);
+        let minute = u32::try_from(civil.hour * 60 + civil.minute).unwrap_or_default();
+        self.periods
+            .iter()
+            .find(|period| period.contains(minute))
+            .map_or(self.price, |period| period.price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn parse_price_periods() {
+        let period: PricePeriod = "22:00-06:30=0.12".parse().unwrap();
+        assert_eq!(period, PricePeriod { start: 1320, end: 390, price: 0.12 });
+        assert_eq!(period.to_string(), "22:00-06:30=0.12");
+        assert!(period.contains(23 * 60));
+        assert!(period.contains(6 * 60));
+        assert!(!period.contains(12 * 60));
+        assert!("22:00=0.12".parse::<PricePeriod>().is_err());
+        assert!("24:00-06:00=0.12".parse::<PricePeriod>().is_err());
+        assert!("22:00-06:00=-1".parse::<PricePeriod>().is_err());
+    }
+
+    #[test]
+    fn price_by_time_of_day() {
+        let pricing = Pricing::new(0.30, vec!["22:00-06:00=0.12".parse().unwrap(), "17:00-20:00=0.45".parse().unwrap()]);
+        let at = |hour: u64| UNIX_EPOCH + Duration::from_secs(hour * 3600);
+        assert_eq!(pricing.price_at(at(2)), 0.12);
+        assert_eq!(pricing.price_at(at(12)), 0.30);
+        assert_eq!(pricing.price_at(at(18)), 0.45);
+        assert_eq!(pricing.price_at(at(22)), 0.12);
+    }
+}
@@ -0,0 +1,120 @@
+//! Handling of panics, which are logged with the thread and location they happened at, reported
+//! as an [`Event::ExporterCrashed`] to the notifiers registered for it, and turned into a distinct
+//! exit code so supervisors can tell a crash from a clean exit or a configuration error.
+//!
+//! Without the hook, a panic on a thread other than the main thread, such as one serving an HTTP
+//! request, would only end that thread and leave the exporter running in a broken state.
+
+use crate::events::Event;
+use crate::sink::Sink;
+use log::{error, warn};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::panic::{self, PanicHookInfo};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+/// Exit code after a panic, which is `EX_SOFTWARE` from `sysexits.h`.
+pub const CRASH_EXIT_CODE: i32 = 70;
+
+/// Sinks to report a crash to, separate from those of the polling loop since the panic may have
+/// happened while one of those was in use. Each is kept with the ID of its [`CrashNotifier`].
+static NOTIFIERS: Mutex<Vec<(u64, Box<dyn Sink + Send>)>> = Mutex::new(Vec::new());
+
+/// ID of the next notifier registered.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A sink registered with [`notify_on_crash`], which no longer has crashes reported to it once
+/// this is dropped, so an exporter that is run again does not report to the sinks of every run.
+#[derive(Debug)]
+#[must_use = "the sink is unregistered when this is dropped"]
+pub struct CrashNotifier {
+    id: u64,
+}
+
+impl Drop for CrashNotifier {
+    fn drop(&mut self) {
+        NOTIFIERS.lock().unwrap_or_else(PoisonError::into_inner).retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// Installs a panic hook that logs the panic, reports it to the notifiers, and exits with
+/// [`CRASH_EXIT_CODE`].
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let message = describe(info);
+        error!("Pistachio crashed: {message}");
+        let backtrace = Backtrace::capture();
+        if backtrace.status() == BacktraceStatus::Captured {
+            error!("Backtrace of the crash:\n{backtrace}");
+        }
+        notify(&Event::ExporterCrashed { message });
+        process::exit(CRASH_EXIT_CODE);
+    }));
+}
+
+/// Registers a sink to report a crash to, until the returned notifier is dropped.
+pub fn notify_on_crash(sink: Box<dyn Sink + Send>) -> CrashNotifier {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    NOTIFIERS.lock().unwrap_or_else(PoisonError::into_inner).push((id, sink));
+    CrashNotifier { id }
+}
+
+/// Returns the message of a panic, along with the thread and location it happened at.
+fn describe(info: &PanicHookInfo<'_>) -> String {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    let thread = thread::current();
+    let location = info.location().map_or_else(String::new, |location| format!(" at {location}"));
+    format!("{message} in thread {}{location}", thread.name().unwrap_or("unnamed"))
+}
+
+/// Reports an event to every notifier, skipping them if a notifier is the one that panicked.
+fn notify(event: &Event) {
+    let Ok(mut notifiers) = NOTIFIERS.try_lock() else {
+        return;
+    };
+    for (_, notifier) in notifiers.iter_mut() {
+        if let Err(err) = notifier.event(event) {
+            warn!("Failed to report the crash to {}: {err}", notifier.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use std::sync::mpsc::{self, Sender};
+
+    struct Notifier(Sender<Event>);
+
+    impl Sink for Notifier {
+        fn name(&self) -> &str {
+            "notifier"
+        }
+
+        fn event(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+            self.0.send(event.clone())?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn report_crashes() {
+        let (sender, received) = mpsc::channel();
+        let notifier = notify_on_crash(Box::new(Notifier(sender)));
+        notify(&Event::ExporterCrashed { message: String::from("boom") });
+        assert_eq!(received.try_recv().unwrap(), Event::ExporterCrashed { message: String::from("boom") });
+
+        // Dropping the notifier unregisters the sink
+        drop(notifier);
+        notify(&Event::ExporterCrashed { message: String::from("boom") });
+        assert!(received.try_recv().is_err());
+    }
+}
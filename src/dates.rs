@@ -0,0 +1,453 @@
+//! Conversion of the dates and times reported by a UPS, such as `battery.date`, or `ups.date`
+//! together with `ups.time`, to UNIX timestamps, so they are exported as metrics instead of being
+//! dropped for not being numbers. The clock of the UPS is also compared to the clock of the
+//! exporter, since a UPS whose clock drifts far off often has a management card with a dying
+//! battery, or one that was never configured.
+//!
+//! Dates are read in the formats drivers usually report them in, `YYYY/MM/DD`, `YYYY-MM-DD`,
+//! `MM/DD/YYYY` and `MM/DD/YY`, and times as `HH:MM:SS` or `HH:MM`. Other formats, or ambiguous
+//! ones such as the `DD/MM/YYYY` of some management cards, can be given as a hint written
+//! `VAR=FORMAT`, such as `battery.date=%d/%m/%Y`, using `%Y`, `%y`, `%m`, `%b` for a month name,
+//! `%d`, `%H`, `%M`, `%S`, and `%%` for a percent sign. Dates and times are taken to be in a fixed
+//! time zone, UTC by default. Time zones with daylight saving time are not supported, so a UPS
+//! clock that follows one is converted an hour off for part of the year.
+
+use crate::sink::Sink;
+use crate::time::{days_from_civil, parse_civil_date};
+use crate::{Error, Variable};
+use log::{debug, warn};
+use prometheus::{register_gauge_with_registry, register_gauge_vec_with_registry, Gauge, GaugeVec, Registry};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of seconds in a day.
+const DAY: i64 = 86400;
+
+/// Names of the months, as matched by `%b`.
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// A fixed time zone, written as `UTC` or as an offset from it such as `+02:00` or `-0500`. Named
+/// zones such as `Europe/Berlin` are not supported, since their offset changes with daylight
+/// saving time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeZone {
+    offset: i32,
+}
+
+impl TimeZone {
+    /// Coordinated Universal Time.
+    pub const UTC: TimeZone = TimeZone { offset: 0 };
+
+    /// Returns the offset of the time zone from UTC, in seconds east of it.
+    #[must_use]
+    pub fn offset(self) -> i32 {
+        self.offset
+    }
+}
+
+impl FromStr for TimeZone {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<TimeZone, Error> {
+        let invalid = || Error::Parse(format!("expected UTC or an offset such as +02:00, got `{input}`"));
+        let input = input.trim();
+        if input.eq_ignore_ascii_case("utc") || input.eq_ignore_ascii_case("z") {
+            return Ok(TimeZone::UTC);
+        }
+        let input = input.strip_prefix("UTC").unwrap_or(input);
+        let (sign, offset) = match input.split_at_checked(1).ok_or_else(invalid)? {
+            ("+", offset) => (1, offset),
+            ("-", offset) => (-1, offset),
+            _ => return Err(invalid()),
+        };
+        let (hours, minutes) = match offset.split_once(':') {
+            Some((hours, minutes)) => (hours, minutes),
+            None if offset.len() == 4 => offset.split_at(2),
+            None => (offset, "0"),
+        };
+        let hours: i32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+        if !(0..=14).contains(&hours) || !(0..60).contains(&minutes) {
+            return Err(invalid());
+        }
+        Ok(TimeZone {
+            offset: sign * (hours * 3600 + minutes * 60),
+        })
+    }
+}
+
+impl TryFrom<String> for TimeZone {
+    type Error = Error;
+
+    fn try_from(input: String) -> Result<TimeZone, Error> {
+        input.parse()
+    }
+}
+
+impl From<TimeZone> for String {
+    fn from(timezone: TimeZone) -> String {
+        timezone.to_string()
+    }
+}
+
+impl fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.offset == 0 {
+            return f.write_str("UTC");
+        }
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.unsigned_abs();
+        write!(f, "{sign}{:02}:{:02}", offset / 3600, offset % 3600 / 60)
+    }
+}
+
+/// The format a variable is reported in, overriding the formats that are otherwise detected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DateFormat {
+    /// Name of the variable, such as `battery.date`.
+    pub variable: String,
+    /// The format, such as `%d/%m/%Y`.
+    pub format: String,
+}
+
+impl FromStr for DateFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<DateFormat, Error> {
+        let invalid = |reason: &str| Error::Parse(format!("expected VAR=FORMAT such as battery.date=%d/%m/%Y, got `{input}`: {reason}"));
+        let (variable, format) = input.split_once('=').ok_or_else(|| invalid("missing `=`"))?;
+        let (variable, format) = (variable.trim(), format.trim());
+        if variable.is_empty() {
+            return Err(invalid("missing variable"));
+        }
+        let mut chars = format.chars();
+        let mut fields = 0;
+        while let Some(c) = chars.next() {
+            if c == '%' {
+                match chars.next() {
+                    Some('Y' | 'y' | 'm' | 'b' | 'd' | 'H' | 'M' | 'S') => fields += 1,
+                    Some('%') => {}
+                    _ => return Err(invalid("unknown field")),
+                }
+            }
+        }
+        if fields == 0 {
+            return Err(invalid("no fields"));
+        }
+        Ok(DateFormat {
+            variable: variable.to_string(),
+            format: format.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for DateFormat {
+    type Error = Error;
+
+    fn try_from(input: String) -> Result<DateFormat, Error> {
+        input.parse()
+    }
+}
+
+impl From<DateFormat> for String {
+    fn from(format: DateFormat) -> String {
+        format!("{}={}", format.variable, format.format)
+    }
+}
+
+/// A date, a time of day, or both, read from a value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct DateTime {
+    /// Year, month, and day.
+    date: Option<(i64, i64, i64)>,
+    /// Seconds since midnight.
+    time: Option<i64>,
+}
+
+impl DateTime {
+    /// Reads a value in the given format.
+    fn parse_format(format: &str, value: &str) -> Option<DateTime> {
+        let mut value = value.trim();
+        let (mut year, mut month, mut day) = (None, None, None);
+        let (mut hour, mut minute, mut second) = (None, None, None);
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            let field = match c {
+                '%' => chars.next()?,
+                c => {
+                    value = value.strip_prefix(c)?;
+                    continue;
+                }
+            };
+            match field {
+                '%' => value = value.strip_prefix('%')?,
+                'b' => {
+                    let letters = value.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(value.len());
+                    let name = value.get(..3.min(letters))?.to_ascii_lowercase();
+                    month = Some(MONTHS.iter().position(|month| *month == name)? as i64 + 1);
+                    value = &value[letters..];
+                }
+                field => {
+                    let width = if field == 'Y' { 4 } else { 2 };
+                    let digits = value.bytes().take(width).take_while(u8::is_ascii_digit).count();
+                    let number: i64 = value.get(..digits)?.parse().ok()?;
+                    value = &value[digits..];
+                    match field {
+                        'Y' => year = Some(number),
+                        'y' => year = Some(2000 + number),
+                        'm' => month = Some(number),
+                        'd' => day = Some(number),
+                        'H' => hour = Some(number),
+                        'M' => minute = Some(number),
+                        'S' => second = Some(number),
+                        _ => return None,
+                    }
+                }
+            }
+        }
+        if !value.is_empty() {
+            return None;
+        }
+        let date = match (year, month, day) {
+            (Some(year), Some(month), Some(day)) if (1..=12).contains(&month) && (1..=31).contains(&day) => Some((year, month, day)),
+            (None, None, None) => None,
+            _ => return None,
+        };
+        let time = match (hour, minute, second) {
+            (Some(hour), Some(minute), second) if hour < 24 && minute < 60 && second.unwrap_or(0) <= 60 => {
+                Some(hour * 3600 + minute * 60 + second.unwrap_or(0))
+            }
+            (None, None, None) => None,
+            _ => return None,
+        };
+        Some(DateTime { date, time })
+    }
+
+    /// Reads a value in any of the detected formats.
+    fn detect(value: &str) -> Option<DateTime> {
+        if let Some(date) = parse_civil_date(value) {
+            return Some(DateTime { date: Some(date), time: None });
+        }
+        ["%H:%M:%S", "%H:%M"].iter().find_map(|format| DateTime::parse_format(format, value))
+    }
+}
+
+/// Returns whether dates and times are read from a variable without a format hint.
+fn is_date(name: &str) -> bool {
+    name.ends_with(".date")
+}
+
+/// A timestamp read from a variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Timestamp {
+    /// Seconds since the UNIX epoch.
+    seconds: i64,
+    /// Whether the time of day was known, rather than taken to be midnight.
+    with_time: bool,
+}
+
+/// A sink that exports the dates and times reported by the UPS as
+/// `ups_date_timestamp_seconds`, labelled by variable. `ups.date` is combined with `ups.time`
+/// when both are reported, into the time of the clock of the UPS, which is compared to the time
+/// of the poll in `ups_clock_skew_seconds`. For UPSes that only report `ups.time`, the skew is
+/// measured within a day, as the smallest difference between the times of day.
+#[derive(Debug)]
+pub struct DateMetrics {
+    timezone: TimeZone,
+    formats: HashMap<String, String>,
+    unreadable: HashMap<String, String>,
+    exported: Vec<String>,
+    gauge: GaugeVec,
+    skew_gauge: Option<Gauge>,
+    registry: Registry,
+}
+
+impl DateMetrics {
+    /// Registers the timestamp gauge, reading dates and times in the given time zone, and in the
+    /// given formats for the variables they are given for. The clock skew is registered in the same
+    /// registry once it is first measured.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the gauge cannot be registered with Prometheus.
+    pub fn new(timezone: TimeZone, formats: Vec<DateFormat>, registry: &Registry) -> crate::Result<DateMetrics> {
+        let gauge = register_gauge_vec_with_registry!(
+            "ups_date_timestamp_seconds",
+            "Dates and times reported by the UPS, such as battery.date, as UNIX timestamps",
+            &["variable"],
+            registry
+        )?;
+        Ok(DateMetrics {
+            timezone,
+            formats: formats.into_iter().map(|format| (format.variable, format.format)).collect(),
+            unreadable: HashMap::new(),
+            exported: Vec::new(),
+            gauge,
+            skew_gauge: None,
+            registry: registry.clone(),
+        })
+    }
+
+    /// Reads a variable in its hinted format, or in any of the detected formats.
+    fn read(&self, name: &str, value: &str) -> Option<DateTime> {
+        match self.formats.get(name) {
+            Some(format) => DateTime::parse_format(format, value),
+            None => DateTime::detect(value),
+        }
+    }
+
+    /// Returns the UNIX timestamp of every date variable of a poll that could be read, logging
+    /// those that could not once for every value.
+    fn timestamps(&mut self, values: &BTreeMap<&str, &str>) -> BTreeMap<String, Timestamp> {
+        let mut timestamps = BTreeMap::new();
+        for (&name, &value) in values.iter().filter(|(name, _)| is_date(name) || self.formats.contains_key(**name)) {
+            let read = self.read(name, value);
+            let Some((year, month, day)) = read.and_then(|read| read.date) else {
+                // Times of day alone, such as ups.time, are only read along with their date
+                if read.is_none() && self.unreadable.get(name).is_none_or(|previous| previous != value) {
+                    debug!("Could not read {name} value {value:?} as a date");
+                    self.unreadable.insert(name.to_string(), value.to_string());
+                }
+                continue;
+            };
+            let time = read.and_then(|read| read.time).or_else(|| {
+                let time = values.get("ups.time").filter(|_| name == "ups.date")?;
+                self.read("ups.time", time)?.time
+            });
+            let seconds = days_from_civil(year, month, day) * DAY + time.unwrap_or(0) - i64::from(self.timezone.offset());
+            timestamps.insert(
+                name.to_string(),
+                Timestamp {
+                    seconds,
+                    with_time: time.is_some(),
+                },
+            );
+        }
+        timestamps
+    }
+
+    /// Returns how many seconds the clock of the UPS is ahead of `now`, or behind it if negative,
+    /// or `None` if the UPS does not report the time.
+    fn clock_skew(&self, values: &BTreeMap<&str, &str>, timestamps: &BTreeMap<String, Timestamp>, now: SystemTime) -> Option<i64> {
+        let now = i64::try_from(now.duration_since(UNIX_EPOCH).ok()?.as_secs()).ok()?;
+        if let Some(clock) = timestamps.get("ups.date").filter(|clock| clock.with_time) {
+            return Some(clock.seconds - now);
+        }
+        let time = self.read("ups.time", values.get("ups.time")?)?;
+        let time = time.time.filter(|_| time.date.is_none())?;
+        let local = (now + i64::from(self.timezone.offset())).rem_euclid(DAY);
+        Some((time - local + DAY / 2).rem_euclid(DAY) - DAY / 2)
+    }
+
+    /// Exports the clock skew, registering its gauge once it is first measured, so it is never
+    /// exported as zero for a UPS that does not report the time.
+    fn set_clock_skew(&mut self, skew: Option<i64>) {
+        if self.skew_gauge.is_none() && skew.is_some() {
+            match register_gauge_with_registry!(
+                "ups_clock_skew_seconds",
+                "Time the clock of the UPS is ahead of the exporter, or behind it if negative",
+                self.registry
+            ) {
+                Ok(gauge) => self.skew_gauge = Some(gauge),
+                Err(err) => warn!("Failed to register the clock skew: {err}"),
+            }
+        }
+        if let Some(gauge) = &self.skew_gauge {
+            gauge.set(skew.map_or(f64::NAN, |skew| skew as f64));
+        }
+    }
+}
+
+impl Sink for DateMetrics {
+    fn name(&self) -> &str {
+        "dates"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn StdError>> {
+        let values = vars.iter().map(|var| (var.name(), var.value())).collect();
+        let timestamps = self.timestamps(&values);
+        for name in self.exported.iter().filter(|name| !timestamps.contains_key(*name)) {
+            let _ = self.gauge.remove_label_values(&[name]);
+        }
+        for (name, timestamp) in &timestamps {
+            self.gauge.with_label_values(&[name]).set(timestamp.seconds as f64);
+        }
+        let skew = self.clock_skew(&values, &timestamps, SystemTime::now());
+        self.set_clock_skew(skew);
+        self.exported = timestamps.into_keys().collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_time_zones() {
+        assert_eq!("UTC".parse::<TimeZone>().unwrap(), TimeZone::UTC);
+        assert_eq!("+02:00".parse::<TimeZone>().unwrap().offset(), 7200);
+        assert_eq!("-0530".parse::<TimeZone>().unwrap().offset(), -19800);
+        assert_eq!("UTC+1".parse::<TimeZone>().unwrap().offset(), 3600);
+        assert_eq!("-05:00".parse::<TimeZone>().unwrap().to_string(), "-05:00");
+        assert!("Europe/Berlin".parse::<TimeZone>().is_err());
+        assert!("+15:00".parse::<TimeZone>().is_err());
+    }
+
+    #[test]
+    fn parse_formats() {
+        let format: DateFormat = "battery.date=%d/%m/%Y".parse().unwrap();
+        assert_eq!(String::from(format.clone()), "battery.date=%d/%m/%Y");
+        assert!("battery.date=%d/%m/%Q".parse::<DateFormat>().is_err());
+        assert!("battery.date=today".parse::<DateFormat>().is_err());
+
+        let read = |format: &str, value: &str| DateTime::parse_format(format, value);
+        assert_eq!(read(&format.format, "28/02/2022").unwrap().date, Some((2022, 2, 28)));
+        assert_eq!(read(&format.format, "02/28/2022"), None);
+        assert_eq!(read("%b %d %Y", "February 28 2022").unwrap().date, Some((2022, 2, 28)));
+        assert_eq!(read("%Y%m%d %H%M", "20220228 1330").unwrap().time, Some(48600));
+        assert_eq!(DateTime::detect("13:30:05").unwrap().time, Some(48605));
+        assert_eq!(DateTime::detect("not set"), None);
+    }
+
+    #[test]
+    fn export_timestamps_and_clock_skew() {
+        let formats = vec!["ups.mfr.date=%d.%m.%Y".parse().unwrap()];
+        let mut dates = DateMetrics::new("+01:00".parse().unwrap(), formats, &Registry::new()).unwrap();
+        let values = BTreeMap::from([
+            ("battery.date", "2024/02/29"),
+            ("ups.date", "02/29/2024"),
+            ("ups.time", "01:00:30"),
+            ("ups.mfr.date", "15.01.2020"),
+            ("ups.test.date", "unknown"),
+            ("ups.load", "20"),
+        ]);
+        let timestamps = dates.timestamps(&values);
+        assert_eq!(timestamps.keys().collect::<Vec<_>>(), ["battery.date", "ups.date", "ups.mfr.date"]);
+        assert_eq!(timestamps["battery.date"].seconds, 1_709_164_800 - 3600);
+        assert!(!timestamps["battery.date"].with_time);
+        assert_eq!(timestamps["ups.date"].seconds, 1_709_164_830);
+        assert_eq!(timestamps["ups.mfr.date"].seconds, 1_579_042_800);
+        assert_eq!(dates.unreadable["ups.test.date"], "unknown");
+
+        let now = UNIX_EPOCH + std::time::Duration::from_secs(1_709_164_800);
+        let mut skew = |values: &[(&str, &str)]| {
+            let values = values.iter().copied().collect();
+            let timestamps = dates.timestamps(&values);
+            dates.clock_skew(&values, &timestamps, now)
+        };
+        assert_eq!(skew(&[("ups.date", "2024/02/29"), ("ups.time", "01:02:00")]), Some(120));
+        assert_eq!(skew(&[("ups.date", "2024/02/28"), ("ups.time", "01:00:00")]), Some(-DAY));
+        // Without the date, the skew is measured across midnight
+        assert_eq!(skew(&[("ups.time", "00:59:30")]), Some(-30));
+        assert_eq!(skew(&[("ups.time", "01:00:10")]), Some(10));
+        assert_eq!(skew(&[("ups.date", "2024/02/29")]), None);
+        assert_eq!(skew(&[("ups.time", "unknown")]), None);
+    }
+}
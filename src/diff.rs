@@ -0,0 +1,181 @@
+//! Comparison of the variables of a UPS at two points, such as before and after a driver upgrade
+//! or a firmware update, to show which variables were added, removed, or changed.
+//!
+//! Each side is read from a file or polled from a live NUT server. Files can be the output of
+//! `--dump-metadata`, the response of `/api/v1/variables`, or a recording, of which the last
+//! poll is compared.
+
+use crate::client::{Connection, UpsClient};
+use crate::{Error, Result, UpsTarget};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// The values of the variables of a UPS, keyed by name.
+pub type Vars = BTreeMap<String, String>;
+
+/// The differences between the variables of a UPS at two points.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VarDiff {
+    /// Variables only in the second set, along with their values.
+    pub added: Vec<(String, String)>,
+    /// Variables only in the first set, along with their values.
+    pub removed: Vec<(String, String)>,
+    /// Variables in both sets with different values, along with the first and second value.
+    pub changed: Vec<(String, String, String)>,
+}
+
+impl VarDiff {
+    /// Compares two sets of variables. Values are compared as numbers when both are numeric, so
+    /// `230` and `230.0` are the same value.
+    #[must_use]
+    pub fn new(before: &Vars, after: &Vars) -> VarDiff {
+        let mut diff = VarDiff::default();
+        for (name, value) in before {
+            match after.get(name) {
+                None => diff.removed.push((name.clone(), value.clone())),
+                Some(other) if !same_value(value, other) => diff.changed.push((name.clone(), value.clone(), other.clone())),
+                Some(_) => {}
+            }
+        }
+        for (name, value) in after {
+            if !before.contains_key(name) {
+                diff.added.push((name.clone(), value.clone()));
+            }
+        }
+        diff
+    }
+
+    /// Returns true if neither set has a variable the other does not, and every value is the same.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Leaves out the variables whose values changed, to only show added and removed variables.
+    #[must_use]
+    pub fn without_changes(mut self) -> VarDiff {
+        self.changed.clear();
+        self
+    }
+}
+
+/// Shows one line per difference, prefixed by `+` for added, `-` for removed, and `~` for changed
+/// variables, followed by a summary.
+impl fmt::Display for VarDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.added {
+            writeln!(f, "+ {name}: {value}")?;
+        }
+        for (name, value) in &self.removed {
+            writeln!(f, "- {name}: {value}")?;
+        }
+        for (name, before, after) in &self.changed {
+            writeln!(f, "~ {name}: {before} -> {after}")?;
+        }
+        write!(f, "{} added, {} removed, {} changed", self.added.len(), self.removed.len(), self.changed.len())
+    }
+}
+
+/// Returns true if two values are the same, as numbers if both are numeric.
+fn same_value(a: &str, b: &str) -> bool {
+    match (crate::parse_number(a), crate::parse_number(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Reads the variables of one side of a comparison, from a file if `source` is the path of one,
+/// or else polled from the UPS target it names, such as `ups@nut.local:3493`.
+///
+/// # Errors
+///
+/// An error will be returned if the file cannot be read or parsed, or if the UPS cannot be
+/// polled.
+pub fn load(source: &str) -> Result<Vars> {
+    let path = Path::new(source);
+    if path.is_file() {
+        return load_file(path);
+    }
+    let target: UpsTarget = source.parse()?;
+    let mut client = Connection::open(&target.host, target.port)?;
+    let vars = client.list_vars(&target.name)?;
+    client.close()?;
+    Ok(vars.into_iter().map(|var| (var.name().to_string(), var.value().to_string())).collect())
+}
+
+/// Reads the variables of a file, which is read as a JSON document listing variables if it ends
+/// in `.json`, and otherwise as a recording.
+fn load_file(path: &Path) -> Result<Vars> {
+    if path.extension().is_none_or(|ext| ext != "json") {
+        let polls = crate::replay::load(path)?;
+        let last = polls.into_iter().next_back().expect("recordings always have a poll");
+        return Ok(last.vars.into_iter().map(|var| (var.name().to_string(), var.value().to_string())).collect());
+    }
+    let contents = fs::read_to_string(path).map_err(|source| Error::Io {
+        context: format!("failed to read {}", path.display()),
+        source,
+    })?;
+    let document: Value =
+        serde_json::from_str(&contents).map_err(|err| Error::Parse(format!("{} is not valid JSON: {err}", path.display())))?;
+    parse_document(&document).ok_or_else(|| Error::Parse(format!("{} does not list the variables of a UPS", path.display())))
+}
+
+/// Reads the variables of a JSON document, which is either a list of variables each with a
+/// `name` and `value`, as written by `--dump-metadata`, an object with such a list under
+/// `variables`, as served by `/api/v1/variables`, or an object with the values of variables by
+/// name under `vars`, as recorded for a poll.
+fn parse_document(document: &Value) -> Option<Vars> {
+    let list = match document {
+        Value::Array(list) => list,
+        Value::Object(object) => match (object.get("variables"), object.get("vars")) {
+            (Some(Value::Array(list)), _) => list,
+            (_, Some(Value::Object(vars))) => {
+                return vars.iter().map(|(name, value)| Some((name.clone(), value.as_str()?.to_string()))).collect();
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    list.iter()
+        .map(|var| Some((var["name"].as_str()?.to_string(), var["value"].as_str()?.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vars {
+        pairs.iter().map(|(name, value)| ((*name).to_string(), (*value).to_string())).collect()
+    }
+
+    #[test]
+    fn diff_variables() {
+        let before = vars(&[("battery.charge", "100"), ("input.voltage", "230"), ("ups.beeper.status", "enabled")]);
+        let after = vars(&[("battery.charge", "87"), ("input.voltage", "230.0"), ("ups.temperature", "31")]);
+        let diff = VarDiff::new(&before, &after);
+        assert_eq!(
+            diff.to_string(),
+            "+ ups.temperature: 31\n- ups.beeper.status: enabled\n~ battery.charge: 100 -> 87\n1 added, 1 removed, 1 changed"
+        );
+        assert!(VarDiff::new(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn parse_documents() {
+        let expected = Some(vars(&[("battery.charge", "100"), ("ups.status", "OL")]));
+        let dump = json!([
+            { "name": "battery.charge", "value": "100", "description": "Battery charge", "writable": false, "type": "number" },
+            { "name": "ups.status", "value": "OL", "description": "UPS status", "writable": false, "type": "string" },
+        ]);
+        assert_eq!(parse_document(&dump), expected);
+        assert_eq!(parse_document(&json!({ "ups": "ups", "variables": dump })), expected);
+        let poll = json!({ "timestamp": "2024-02-29T12:34:56Z", "vars": { "battery.charge": "100", "ups.status": "OL" } });
+        assert_eq!(parse_document(&poll), expected);
+        assert_eq!(parse_document(&json!({ "ups": "ups" })), None);
+    }
+}
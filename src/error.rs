@@ -0,0 +1,157 @@
+//! The error type shared by all of pistachio's library functions.
+
+use std::io;
+use thiserror::Error as ThisError;
+
+/// An error that occurred while monitoring a UPS.
+#[derive(Debug, ThisError)]
+pub enum Error {
+    /// The NUT server could not be reached, or the connection to it was lost.
+    #[error("connection error: {0}")]
+    Connection(#[source] io::Error),
+    /// The NUT server returned an error or a response that could not be understood.
+    #[error("protocol error: {0}")]
+    Protocol(#[source] rups::NutError),
+    /// The provided configuration is invalid.
+    #[error("configuration error: {0}")]
+    Config(String),
+    /// A value could not be parsed.
+    #[error("parse error: {0}")]
+    Parse(String),
+    /// A Prometheus metric could not be created, registered, or updated.
+    #[error("metrics error: {0}")]
+    Metrics(#[from] prometheus::Error),
+    /// A file or socket needed by the exporter could not be opened.
+    #[error("{context}: {source}")]
+    Io {
+        /// What was being opened.
+        context: String,
+        /// The underlying error.
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl From<rups::ClientError> for Error {
+    fn from(err: rups::ClientError) -> Error {
+        match err {
+            rups::ClientError::Io(err) => Error::Connection(err),
+            rups::ClientError::Nut(err) => Error::Protocol(err),
+        }
+    }
+}
+
+/// The class of an [Error], which decides whether the connection it happened on is replaced and
+/// is exported as the `error_type` label of `pistachio_nut_errors_total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorType {
+    /// The NUT server could not be reached, or the connection to it was lost.
+    Network,
+    /// The NUT server sent a response that could not be understood, so the connection can no
+    /// longer be trusted.
+    Protocol,
+    /// The NUT server rejected the credentials, or requires some that were not given.
+    Auth,
+    /// The UPS is unknown to the NUT server, or its driver is not connected or not updating.
+    Ups,
+    /// The NUT server rejected a request, such as for a variable the UPS does not have.
+    Request,
+    /// TLS could not be used on the connection.
+    Tls,
+    /// The NUT server does not support or allow a command or feature.
+    Unsupported,
+    /// The error did not come from the NUT server, such as an invalid configuration.
+    Other,
+}
+
+impl ErrorType {
+    /// Returns the name of the class, as used in the `error_type` label.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorType::Network => "network",
+            ErrorType::Protocol => "protocol",
+            ErrorType::Auth => "auth",
+            ErrorType::Ups => "ups",
+            ErrorType::Request => "request",
+            ErrorType::Tls => "tls",
+            ErrorType::Unsupported => "unsupported",
+            ErrorType::Other => "other",
+        }
+    }
+
+    /// Returns true if errors of the class mean the connection they happened on can no longer be
+    /// used and must be replaced, as opposed to errors reported by a server that still works.
+    #[must_use]
+    pub fn breaks_connection(self) -> bool {
+        matches!(self, ErrorType::Network | ErrorType::Protocol)
+    }
+}
+
+impl Error {
+    /// Returns the class of the error.
+    #[must_use]
+    pub fn error_type(&self) -> ErrorType {
+        use rups::NutError;
+        match self {
+            Error::Connection(_) => ErrorType::Network,
+            Error::Protocol(err) => match err {
+                NutError::Generic(_) | NutError::UnexpectedResponse | NutError::UnknownResponseType(_) => ErrorType::Protocol,
+                NutError::AccessDenied
+                | NutError::AlreadyLoggedIn
+                | NutError::InvalidPassword
+                | NutError::AlreadySetPassword
+                | NutError::InvalidUsername
+                | NutError::AlreadySetUsername
+                | NutError::UsernameRequired
+                | NutError::PasswordRequired => ErrorType::Auth,
+                NutError::UnknownUps | NutError::DriverNotConnected | NutError::DataStale => ErrorType::Ups,
+                NutError::VarNotSupported
+                | NutError::CmdNotSupported
+                | NutError::InvalidArgument
+                | NutError::InstCmdFailed
+                | NutError::SetFailed
+                | NutError::ReadOnly
+                | NutError::TooLong
+                | NutError::InvalidValue => ErrorType::Request,
+                NutError::AlreadySslMode | NutError::SslNotSupported | NutError::SslInvalidHostname => ErrorType::Tls,
+                NutError::FeatureNotSupported | NutError::FeatureNotConfigured | NutError::UnknownCommand => ErrorType::Unsupported,
+            },
+            Error::Config(_) | Error::Parse(_) | Error::Metrics(_) | Error::Io { .. } => ErrorType::Other,
+        }
+    }
+}
+
+/// A result with pistachio's [Error] type.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_client_errors() {
+        let io_err = rups::ClientError::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        assert!(matches!(Error::from(io_err), Error::Connection(_)));
+        let nut_err = rups::ClientError::Nut(rups::NutError::UnknownUps);
+        let err = Error::from(nut_err);
+        assert!(matches!(err, Error::Protocol(rups::NutError::UnknownUps)));
+        assert_eq!(err.to_string(), "protocol error: Unknown UPS device");
+    }
+
+    #[test]
+    fn classify_errors() {
+        let network = Error::Connection(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        assert_eq!(network.error_type(), ErrorType::Network);
+        assert!(network.error_type().breaks_connection());
+        let garbled = Error::Protocol(rups::NutError::UnknownResponseType(String::from("BOGUS")));
+        assert_eq!(garbled.error_type().as_str(), "protocol");
+        assert!(garbled.error_type().breaks_connection());
+        let denied = Error::Protocol(rups::NutError::AccessDenied);
+        assert_eq!(denied.error_type(), ErrorType::Auth);
+        assert!(!denied.error_type().breaks_connection());
+        assert_eq!(Error::Protocol(rups::NutError::DataStale).error_type(), ErrorType::Ups);
+        assert_eq!(Error::Protocol(rups::NutError::VarNotSupported).error_type(), ErrorType::Request);
+        assert_eq!(Error::Config(String::from("invalid")).error_type(), ErrorType::Other);
+    }
+}
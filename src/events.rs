@@ -0,0 +1,427 @@
+//! Structured events describing changes in the state of the UPS and its connection.
+
+use crate::sink::Sink;
+use crate::status::UpsStatus;
+use crate::time::format_rfc3339;
+use crate::Variable;
+use log::debug;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// A change in the state of the UPS or of the connection to the NUT server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The value of `ups.status` changed. `previous` is `None` on the first poll.
+    StatusChanged {
+        /// Status before the change.
+        previous: Option<String>,
+        /// Status after the change.
+        current: String,
+    },
+    /// The UPS started a forced shutdown, adding `FSD` to `ups.status`. The NUT server sets this
+    /// when the primary monitor decides every system powered by the UPS must shut down.
+    ForcedShutdown {
+        /// Status with the `FSD` flag.
+        status: String,
+    },
+    /// The UPS raised a new alarm, or the text of `ups.alarm` changed.
+    AlarmRaised {
+        /// Text of the alarm.
+        alarm: String,
+    },
+    /// The UPS cleared all alarms.
+    AlarmCleared,
+    /// Polling the NUT server failed after previously succeeding.
+    ConnectionLost {
+        /// Description of the error that caused the failure.
+        error: String,
+    },
+    /// Polling the NUT server succeeded after previously failing.
+    ConnectionRestored,
+    /// The value of a variable other than `ups.status` or `ups.alarm` changed between polls.
+    VariableChanged {
+        /// Name of the variable.
+        name: String,
+        /// Value before the change, or `None` if the variable was not reported before.
+        previous: Option<String>,
+        /// Value after the change.
+        current: String,
+    },
+    /// An instant command tracked by the NUT server finished running.
+    CommandFinished {
+        /// Name of the command.
+        command: String,
+        /// Why the command failed, or `None` if it succeeded.
+        error: Option<String>,
+    },
+    /// The condition of an alert rule started holding for long enough.
+    AlertRaised {
+        /// Name of the alert.
+        alert: String,
+        /// The rule of the alert, as it was configured.
+        rule: String,
+    },
+    /// The condition of an active alert rule stopped holding.
+    AlertResolved {
+        /// Name of the alert.
+        alert: String,
+    },
+    /// The load-shedding tier recommended during an outage changed.
+    ShedTierChanged {
+        /// Tier recommended before the change, or 0 if none was.
+        previous: usize,
+        /// Tier recommended after the change, or 0 if none is.
+        tier: usize,
+        /// Name of the tier recommended after the change, or `None` if none is.
+        name: Option<String>,
+    },
+    /// Pistachio panicked and is about to exit.
+    ExporterCrashed {
+        /// Message of the panic, with the thread and location it happened at.
+        message: String,
+    },
+}
+
+impl Event {
+    /// Returns a short, stable identifier for the kind of event, such as `status_changed`.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::StatusChanged { .. } => "status_changed",
+            Event::ForcedShutdown { .. } => "forced_shutdown",
+            Event::AlarmRaised { .. } => "alarm_raised",
+            Event::AlarmCleared => "alarm_cleared",
+            Event::ConnectionLost { .. } => "connection_lost",
+            Event::ConnectionRestored => "connection_restored",
+            Event::VariableChanged { .. } => "variable_changed",
+            Event::CommandFinished { .. } => "command_finished",
+            Event::AlertRaised { .. } => "alert_raised",
+            Event::AlertResolved { .. } => "alert_resolved",
+            Event::ShedTierChanged { .. } => "shed_tier_changed",
+            Event::ExporterCrashed { .. } => "exporter_crashed",
+        }
+    }
+
+    /// Returns the level the event is logged at: errors for forced shutdowns and crashes, debug
+    /// messages for changes of other variables, and information for everything else.
+    #[must_use]
+    pub fn level(&self) -> log::Level {
+        match self {
+            Event::VariableChanged { .. } => log::Level::Debug,
+            Event::ForcedShutdown { .. } | Event::ExporterCrashed { .. } => log::Level::Error,
+            _ => log::Level::Info,
+        }
+    }
+
+    /// Serializes the event as a JSON object for the given UPS, stamped with the given time.
+    #[must_use]
+    pub fn to_json(&self, ups_name: &str, time: SystemTime) -> Value {
+        let mut value = json!({
+            "type": self.kind(),
+            "ups": ups_name,
+            "timestamp": format_rfc3339(time),
+        });
+        match self {
+            Event::StatusChanged { previous, current } => {
+                value["previous"] = json!(previous);
+                value["current"] = json!(current);
+            }
+            Event::ForcedShutdown { status } => value["status"] = json!(status),
+            Event::AlarmRaised { alarm } => value["alarm"] = json!(alarm),
+            Event::ConnectionLost { error } => value["error"] = json!(error),
+            Event::VariableChanged { name, previous, current } => {
+                value["name"] = json!(name);
+                value["previous"] = json!(previous);
+                value["current"] = json!(current);
+            }
+            Event::CommandFinished { command, error } => {
+                value["command"] = json!(command);
+                value["succeeded"] = json!(error.is_none());
+                value["error"] = json!(error);
+            }
+            Event::AlertRaised { alert, rule } => {
+                value["alert"] = json!(alert);
+                value["rule"] = json!(rule);
+            }
+            Event::AlertResolved { alert } => value["alert"] = json!(alert),
+            Event::ShedTierChanged { previous, tier, name } => {
+                value["previous"] = json!(previous);
+                value["tier"] = json!(tier);
+                value["name"] = json!(name);
+            }
+            Event::ExporterCrashed { message } => value["message"] = json!(message),
+            Event::AlarmCleared | Event::ConnectionRestored => {}
+        }
+        value
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::StatusChanged { previous: Some(previous), current } => {
+                write!(f, "UPS status changed from {previous} to {current}")
+            }
+            Event::StatusChanged { previous: None, current } => write!(f, "UPS status is {current}"),
+            Event::ForcedShutdown { status } => write!(f, "UPS started a forced shutdown, status is {status}"),
+            Event::AlarmRaised { alarm } => write!(f, "UPS alarm raised: {alarm}"),
+            Event::AlarmCleared => write!(f, "UPS alarms cleared"),
+            Event::ConnectionLost { error } => write!(f, "Connection with the UPS was lost: {error}"),
+            Event::ConnectionRestored => write!(f, "Connection with the UPS has been reestablished"),
+            Event::VariableChanged { name, previous: Some(previous), current } => {
+                write!(f, "{name} changed from {previous} to {current}")
+            }
+            Event::VariableChanged { name, previous: None, current } => write!(f, "{name} is {current}"),
+            Event::CommandFinished { command, error: None } => write!(f, "Command {command} succeeded"),
+            Event::CommandFinished { command, error: Some(error) } => write!(f, "Command {command} failed: {error}"),
+            Event::AlertRaised { alert, rule } => write!(f, "Alert {alert} raised: {rule}"),
+            Event::AlertResolved { alert } => write!(f, "Alert {alert} resolved"),
+            Event::ShedTierChanged { tier: 0, .. } => write!(f, "No loads need to be shed anymore"),
+            Event::ShedTierChanged { tier, name: Some(name), .. } => write!(f, "Loads up to tier {tier} ({name}) should be shed"),
+            Event::ShedTierChanged { tier, name: None, .. } => write!(f, "Loads up to tier {tier} should be shed"),
+            Event::ExporterCrashed { message } => write!(f, "Pistachio crashed: {message}"),
+        }
+    }
+}
+
+/// Number of events a subscriber of an [`EventBus`] can fall behind by, after which newer events
+/// are dropped for it until it catches up.
+pub const SUBSCRIBER_BUFFER: usize = 256;
+
+/// Compares the variables from consecutive polls to find status and alarm changes.
+#[derive(Debug, Default)]
+pub struct EventDetector {
+    last_values: HashMap<String, String>,
+}
+
+impl EventDetector {
+    /// Creates a detector that has not seen any polls yet.
+    #[must_use]
+    pub fn new() -> EventDetector {
+        EventDetector::default()
+    }
+
+    /// Returns all events implied by the variables from a new poll.
+    pub fn detect(&mut self, vars: &[Variable]) -> Vec<Event> {
+        let mut events = Vec::new();
+        let values: HashMap<String, String> = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+
+        if let Some(current) = values.get("ups.status") {
+            let previous = self.last_values.get("ups.status");
+            if previous != Some(current) {
+                events.push(Event::StatusChanged {
+                    previous: previous.cloned(),
+                    current: current.clone(),
+                });
+                let was_forced = previous.is_some_and(|status| UpsStatus::parse(status).contains(UpsStatus::FORCED_SHUTDOWN));
+                if UpsStatus::parse(current).contains(UpsStatus::FORCED_SHUTDOWN) && !was_forced {
+                    events.push(Event::ForcedShutdown { status: current.clone() });
+                }
+            }
+        }
+
+        let previous_alarm = self.last_values.get("ups.alarm").filter(|alarm| !alarm.is_empty());
+        match values.get("ups.alarm").filter(|alarm| !alarm.is_empty()) {
+            Some(alarm) if previous_alarm != Some(alarm) => events.push(Event::AlarmRaised { alarm: alarm.clone() }),
+            None if previous_alarm.is_some() => events.push(Event::AlarmCleared),
+            _ => {}
+        }
+
+        // Only report changes between polls, not every variable on the first one
+        if !self.last_values.is_empty() {
+            let mut changed: Vec<_> = values
+                .iter()
+                .filter(|(name, _)| !matches!(name.as_str(), "ups.status" | "ups.alarm"))
+                .filter(|(name, value)| self.last_values.get(*name) != Some(*value))
+                .collect();
+            changed.sort();
+            events.extend(changed.into_iter().map(|(name, value)| Event::VariableChanged {
+                name: name.clone(),
+                previous: self.last_values.get(name).cloned(),
+                current: value.clone(),
+            }));
+        }
+
+        self.last_values = values;
+        events
+    }
+}
+
+/// Distributes events to any number of subscribers, each receiving every event on its own
+/// channel. Subscribers that have been dropped are removed on the next event, and those that fall
+/// [`SUBSCRIBER_BUFFER`] events behind miss events until they catch up, so a subscriber that
+/// stops reading never holds up the polling loop or grows without bound.
+///
+/// The bus is a [Sink], so adding it to the sinks of [`crate::monitor`] delivers every event of the
+/// polling loop to its subscribers, and [`crate::run_in`] delivers every event of the exporter to
+/// the bus it is given. The sinks of the exporter itself, such as the journal and the publishers of
+/// events to NATS or OpenTelemetry, are not subscribers of a bus: the polling loop hands events to
+/// them directly, so they receive events after the poll that caused them and never miss one for
+/// falling behind.
+#[derive(Debug, Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<SyncSender<Event>>>>,
+}
+
+impl EventBus {
+    /// Creates a bus without subscribers.
+    #[must_use]
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    /// Returns a receiver for all events sent after this call, up to [`SUBSCRIBER_BUFFER`] of
+    /// which are kept until they are received.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_BUFFER);
+        self.subscribers.lock().unwrap_or_else(PoisonError::into_inner).push(sender);
+        receiver
+    }
+
+    /// Sends an event to every subscriber, without waiting for those that are behind.
+    pub fn send(&self, event: &Event) {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    debug!("Dropped {} event for a subscriber that fell behind", event.kind());
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
+
+impl Sink for EventBus {
+    fn name(&self) -> &str {
+        "events"
+    }
+
+    fn event(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        self.send(event);
+        Ok(())
+    }
+
+    fn variable_changes(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str, value: &str) -> Variable {
+        Variable::new(name, value.to_string())
+    }
+
+    #[test]
+    fn detect_status_and_alarm_changes() {
+        let mut detector = EventDetector::new();
+        assert_eq!(
+            detector.detect(&[var("ups.status", "OL")]),
+            vec![Event::StatusChanged {
+                previous: None,
+                current: String::from("OL")
+            }]
+        );
+        assert!(detector.detect(&[var("ups.status", "OL")]).is_empty());
+        assert_eq!(
+            detector.detect(&[var("ups.status", "OB"), var("ups.alarm", "Replace battery!")]),
+            vec![
+                Event::StatusChanged {
+                    previous: Some(String::from("OL")),
+                    current: String::from("OB")
+                },
+                Event::AlarmRaised {
+                    alarm: String::from("Replace battery!")
+                },
+            ]
+        );
+        assert_eq!(detector.detect(&[var("ups.status", "OB")]), vec![Event::AlarmCleared]);
+    }
+
+    #[test]
+    fn detect_forced_shutdown() {
+        let mut detector = EventDetector::new();
+        detector.detect(&[var("ups.status", "OB LB")]);
+        let events = detector.detect(&[var("ups.status", "FSD OB LB")]);
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[1],
+            Event::ForcedShutdown {
+                status: String::from("FSD OB LB")
+            }
+        );
+        // Only the start of the shutdown is reported
+        assert_eq!(detector.detect(&[var("ups.status", "FSD OB")]).len(), 1);
+    }
+
+    #[test]
+    fn detect_variable_changes() {
+        let mut detector = EventDetector::new();
+        assert!(detector.detect(&[var("battery.charge", "100")]).is_empty());
+        assert!(detector.detect(&[var("battery.charge", "100")]).is_empty());
+        assert_eq!(
+            detector.detect(&[var("battery.charge", "95"), var("ups.load", "20")]),
+            vec![
+                Event::VariableChanged {
+                    name: String::from("battery.charge"),
+                    previous: Some(String::from("100")),
+                    current: String::from("95")
+                },
+                Event::VariableChanged {
+                    name: String::from("ups.load"),
+                    previous: None,
+                    current: String::from("20")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn deliver_events_to_subscribers() {
+        let bus = EventBus::new();
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        bus.send(&Event::ConnectionRestored);
+        drop(second);
+        bus.send(&Event::AlarmCleared);
+        assert_eq!(first.try_iter().collect::<Vec<_>>(), vec![Event::ConnectionRestored, Event::AlarmCleared]);
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn drop_events_for_full_subscribers() {
+        let bus = EventBus::new();
+        let slow = bus.subscribe();
+        for _ in 0..=SUBSCRIBER_BUFFER {
+            bus.send(&Event::ConnectionRestored);
+        }
+        bus.send(&Event::AlarmCleared);
+        assert_eq!(slow.try_iter().count(), SUBSCRIBER_BUFFER);
+
+        // Once it has caught up, the subscriber receives events again
+        bus.send(&Event::AlarmCleared);
+        assert_eq!(slow.try_recv().unwrap(), Event::AlarmCleared);
+    }
+
+    #[test]
+    fn serialize_event() {
+        let event = Event::ConnectionLost {
+            error: String::from("Connection refused"),
+        };
+        let value = event.to_json("ups", std::time::UNIX_EPOCH);
+        assert_eq!(value["type"], "connection_lost");
+        assert_eq!(value["ups"], "ups");
+        assert_eq!(value["timestamp"], "1970-01-01T00:00:00Z");
+        assert_eq!(value["error"], "Connection refused");
+    }
+}
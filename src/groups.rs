@@ -0,0 +1,290 @@
+//! Groups of variables polled at intervals of their own, so variables that change quickly, such
+//! as `battery.charge`, can be polled more often than the poll rate, and variables that hardly
+//! ever change, such as `ups.*.nominal`, less often.
+//!
+//! [`GroupedClient`] wraps the client of the UPS and is polled at the shortest interval of all
+//! groups. Every poll returns the latest value of every variable, but only asks the UPS for the
+//! variables of groups that are due, and lists all variables once the poll rate has passed.
+
+use crate::client::UpsClient;
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// A group of variables polled at an interval of its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PollGroup {
+    /// Names of the variables in the group, in which `*` matches any sequence of characters.
+    pub patterns: Vec<String>,
+    /// Time between polls of the variables in the group.
+    pub interval: Duration,
+}
+
+impl PollGroup {
+    /// Returns whether a variable belongs to the group.
+    #[must_use]
+    pub fn contains(&self, var_name: &str) -> bool {
+        self.patterns.iter().any(|pattern| matches_pattern(pattern, var_name))
+    }
+}
+
+impl FromStr for PollGroup {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<PollGroup> {
+        let invalid = || Error::Parse(format!("expected PATTERN [PATTERN...]=SECONDS, got `{input}`"));
+        let (patterns, seconds) = input.rsplit_once('=').ok_or_else(invalid)?;
+        let seconds: u64 = seconds.trim().trim_end_matches('s').parse().map_err(|_| invalid())?;
+        let patterns: Vec<String> = patterns.split_whitespace().map(String::from).collect();
+        if patterns.is_empty() || seconds == 0 {
+            return Err(invalid());
+        }
+        Ok(PollGroup {
+            patterns,
+            interval: Duration::from_secs(seconds),
+        })
+    }
+}
+
+impl TryFrom<String> for PollGroup {
+    type Error = Error;
+
+    fn try_from(input: String) -> Result<PollGroup> {
+        input.parse()
+    }
+}
+
+impl From<PollGroup> for String {
+    fn from(group: PollGroup) -> String {
+        group.to_string()
+    }
+}
+
+impl fmt::Display for PollGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.patterns.join(" "), self.interval.as_secs())
+    }
+}
+
+/// Returns whether a variable name matches a pattern, in which `*` matches any sequence of
+/// characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard, so the name must match exactly
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// A client that polls every group of variables at its own interval, and all other variables at
+/// the poll rate, returning the latest value of every variable on each poll.
+#[derive(Debug)]
+pub struct GroupedClient<'a, C> {
+    client: &'a mut C,
+    groups: Vec<PollGroup>,
+    interval: Duration,
+    /// When all variables were last listed.
+    listed: Option<Instant>,
+    /// When the variables of each group were last polled.
+    polled: Vec<Option<Instant>>,
+    /// Latest value of every variable, and the index of the group it belongs to.
+    vars: BTreeMap<String, (String, Option<usize>)>,
+}
+
+impl<'a, C: UpsClient> GroupedClient<'a, C> {
+    /// Wraps a client to poll the given groups at their intervals, and all variables at
+    /// `interval`.
+    pub fn new(client: &'a mut C, groups: &[PollGroup], interval: Duration) -> GroupedClient<'a, C> {
+        GroupedClient {
+            client,
+            groups: groups.to_vec(),
+            interval,
+            listed: None,
+            polled: vec![None; groups.len()],
+            vars: BTreeMap::new(),
+        }
+    }
+
+    /// Returns how often the client must be polled for every group to be polled on time.
+    #[must_use]
+    pub fn tick(&self) -> Duration {
+        self.groups.iter().map(|group| group.interval).fold(self.interval, Duration::min)
+    }
+
+    /// Polls the variables that are due at `now`, returning the latest value of every variable.
+    fn poll_at(&mut self, ups_name: &str, now: Instant) -> Result<Vec<Variable>> {
+        let is_due = |last: Option<Instant>, interval: Duration| last.is_none_or(|last| now.duration_since(last) >= interval);
+        let due: Vec<bool> = self.polled.iter().zip(&self.groups).map(|(last, group)| is_due(*last, group.interval)).collect();
+        if is_due(self.listed, self.interval) {
+            let listed = self.client.list_vars(ups_name)?;
+            let mut vars = BTreeMap::new();
+            for var in listed {
+                let name = var.name().to_string();
+                let group = self.groups.iter().position(|group| group.contains(&name));
+                // Variables of groups that are not due keep the value they were last polled with
+                let value = match (group, self.vars.remove(&name)) {
+                    (Some(group), Some((value, _))) if !due[group] => value,
+                    _ => var.value().to_string(),
+                };
+                vars.insert(name, (value, group));
+            }
+            self.vars = vars;
+            self.listed = Some(now);
+        } else {
+            let names: Vec<String> = self
+                .vars
+                .iter()
+                .filter(|(_, (_, group))| group.is_some_and(|group| due[group]))
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in names {
+                match self.client.get_var(ups_name, &name) {
+                    Ok(var) => {
+                        if let Some((value, _)) = self.vars.get_mut(&name) {
+                            *value = var.value().to_string();
+                        }
+                    }
+                    // The variable is gone until the next listing finds it again
+                    Err(Error::Protocol(rups::NutError::VarNotSupported)) => {
+                        self.vars.remove(&name);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        for (last, due) in self.polled.iter_mut().zip(due) {
+            if due {
+                *last = Some(now);
+            }
+        }
+        Ok(self
+            .vars
+            .iter()
+            .map(|(name, (value, _))| Variable::new(name, value.clone()))
+            .collect())
+    }
+}
+
+impl<C: UpsClient> UpsClient for GroupedClient<'_, C> {
+    fn list_vars(&mut self, ups_name: &str) -> Result<Vec<Variable>> {
+        if self.groups.is_empty() {
+            return self.client.list_vars(ups_name);
+        }
+        self.poll_at(ups_name, Instant::now())
+    }
+
+    fn get_var(&mut self, ups_name: &str, var_name: &str) -> Result<Variable> {
+        self.client.get_var(ups_name, var_name)
+    }
+
+    fn get_var_description(&mut self, ups_name: &str, var_name: &str) -> Result<String> {
+        self.client.get_var_description(ups_name, var_name)
+    }
+
+    fn get_var_type(&mut self, ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        self.client.get_var_type(ups_name, var_name)
+    }
+
+    fn list_var_enum(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<String>> {
+        self.client.list_var_enum(ups_name, var_name)
+    }
+
+    fn list_var_range(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<VariableRange>> {
+        self.client.list_var_range(ups_name, var_name)
+    }
+
+    fn list_clients(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        self.client.list_clients(ups_name)
+    }
+
+    fn list_commands(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        self.client.list_commands(ups_name)
+    }
+
+    fn get_command_description(&mut self, ups_name: &str, command: &str) -> Result<String> {
+        self.client.get_command_description(ups_name, command)
+    }
+
+    /// Does nothing, since the wrapped client is only borrowed and is closed by its owner.
+    fn close(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockUpsClient;
+    use std::collections::HashMap;
+
+    fn values(vars: &[Variable]) -> HashMap<String, String> {
+        vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect()
+    }
+
+    #[test]
+    fn parse_poll_groups() {
+        let group: PollGroup = "battery.* ups.status=2".parse().unwrap();
+        assert_eq!(group.patterns, vec!["battery.*", "ups.status"]);
+        assert_eq!(group.interval, Duration::from_secs(2));
+        assert_eq!(group.to_string(), "battery.* ups.status=2");
+        assert!("battery.*".parse::<PollGroup>().is_err());
+        assert!("=5".parse::<PollGroup>().is_err());
+        assert!("battery.*=0".parse::<PollGroup>().is_err());
+
+        let nominal: PollGroup = "ups.*.nominal driver.*=300".parse().unwrap();
+        assert!(nominal.contains("ups.power.nominal"));
+        assert!(nominal.contains("driver.version"));
+        assert!(!nominal.contains("ups.power"));
+        assert!(group.contains("ups.status"));
+        assert!(!group.contains("ups.status.extra"));
+    }
+
+    #[test]
+    fn poll_groups_at_intervals() {
+        let mut client = MockUpsClient::new()
+            .with_var("battery.charge", "100")
+            .with_var("ups.load", "20")
+            .with_var("ups.power.nominal", "1000")
+            .then_poll()
+            .then_set("ups.power.nominal", "1500")
+            .then_set("ups.load", "30");
+        let groups = ["battery.*=2".parse().unwrap(), "ups.*.nominal=300".parse().unwrap()];
+        let mut grouped = GroupedClient::new(&mut client, &groups, Duration::from_secs(10));
+        assert_eq!(grouped.tick(), Duration::from_secs(2));
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        let vars = values(&grouped.poll_at("ups", at(0)).unwrap());
+        assert_eq!(vars["ups.power.nominal"], "1000");
+        assert_eq!(grouped.client.polls(), 1);
+
+        // Only the fast group is due, which is read variable by variable
+        let vars = values(&grouped.poll_at("ups", at(2)).unwrap());
+        assert_eq!(vars.len(), 3);
+        assert_eq!(grouped.client.polls(), 1);
+
+        // Every variable is listed, but the slow group keeps its value until it is due
+        let vars = values(&grouped.poll_at("ups", at(10)).unwrap());
+        assert_eq!(grouped.client.polls(), 2);
+        assert_eq!(vars["ups.power.nominal"], "1000");
+        let vars = values(&grouped.poll_at("ups", at(300)).unwrap());
+        assert_eq!(vars["ups.power.nominal"], "1500");
+        assert_eq!(vars["ups.load"], "30");
+    }
+}
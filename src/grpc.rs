@@ -0,0 +1,292 @@
+//! A gRPC service for reading the state and events of the UPS, so other infrastructure services
+//! can consume them with typed clients instead of scraping Prometheus text.
+//!
+//! The service is defined in `proto/pistachio.proto`, and is served with `tonic` on a runtime of
+//! its own, so the rest of the exporter stays synchronous.
+
+use crate::events::Event;
+use crate::metadata::VarMetadata;
+use crate::sink::Sink;
+use crate::time::format_rfc3339;
+use crate::Variable;
+use log::{debug, error};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+use tokio::sync::{broadcast, oneshot};
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+/// Messages and services generated from `proto/pistachio.proto`.
+#[allow(missing_docs)]
+pub mod proto {
+    tonic::include_proto!("pistachio.v1");
+}
+
+use proto::ups_server::{Ups, UpsServer};
+
+/// Number of events kept for streams that fall behind, after which they skip the oldest.
+const EVENT_BUFFER: usize = 64;
+
+/// Connections accepted from every listener of the service.
+type Incoming = Pin<Box<dyn Stream<Item = io::Result<tokio::net::TcpStream>> + Send>>;
+
+/// Variables of the UPS from the latest poll.
+#[derive(Debug, Default)]
+struct Latest {
+    vars: BTreeMap<String, String>,
+    updated: Option<SystemTime>,
+}
+
+/// A sink that serves the latest variables of the UPS and streams its events over gRPC. The
+/// service stops, closing every connection, when the sink is dropped.
+#[derive(Debug)]
+pub struct Grpc {
+    ups_name: String,
+    latest: Arc<Mutex<Latest>>,
+    events: broadcast::Sender<proto::Event>,
+    local_addr: SocketAddr,
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Grpc {
+    /// Binds to every given address and serves the gRPC service on a background thread, with the
+    /// descriptions of variables taken from their metadata.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the server cannot bind to any of the addresses.
+    pub fn start(addrs: &[SocketAddr], ups_name: &str, metadata: &[VarMetadata]) -> io::Result<Grpc> {
+        let listeners = addrs.iter().map(|addr| crate::http::bind(*addr)).collect::<io::Result<Vec<_>>>()?;
+        let Some(local_addr) = listeners.first().map(std::net::TcpListener::local_addr).transpose()? else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to bind to"));
+        };
+        for listener in &listeners {
+            listener.set_nonblocking(true)?;
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build()?;
+        let latest = Arc::default();
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let service = Service {
+            ups_name: ups_name.to_string(),
+            descriptions: metadata.iter().map(|var| (var.name.clone(), var.description.clone())).collect(),
+            latest: Arc::clone(&latest),
+            events: events.clone(),
+        };
+        let (stop, stopped) = oneshot::channel();
+        let thread = thread::spawn(move || {
+            runtime.spawn(async move {
+                let mut incoming: Incoming = Box::pin(tokio_stream::empty());
+                for listener in listeners {
+                    match tokio::net::TcpListener::from_std(listener) {
+                        Ok(listener) => incoming = Box::pin(incoming.merge(TcpListenerStream::new(listener))),
+                        Err(err) => return error!("Failed to start gRPC server: {err}"),
+                    }
+                }
+                let server = tonic::transport::Server::builder().add_service(UpsServer::new(service));
+                if let Err(err) = server.serve_with_incoming(incoming).await {
+                    error!("gRPC server stopped: {err}");
+                }
+            });
+            // Dropping the runtime once the sink is gone cancels the server with every connection,
+            // which a graceful shutdown would wait for while event streams are open
+            let _ = runtime.block_on(stopped);
+        });
+        Ok(Grpc {
+            ups_name: ups_name.to_string(),
+            latest,
+            events,
+            local_addr,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the address the service is served on.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Sink for Grpc {
+    fn name(&self) -> &str {
+        "gRPC"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let mut latest = self.latest.lock().unwrap_or_else(PoisonError::into_inner);
+        latest.vars = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        latest.updated = Some(SystemTime::now());
+        Ok(())
+    }
+
+    fn event(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        // Sending only fails when no stream is open, which is not an error
+        if self.events.send(to_message(event, &self.ups_name, SystemTime::now())).is_err() {
+            debug!("No gRPC stream to send {} to", event.kind());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Grpc {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Converts an event to its message, with the fields of its JSON other than the type, UPS, and
+/// timestamp as strings.
+fn to_message(event: &Event, ups_name: &str, time: SystemTime) -> proto::Event {
+    let Value::Object(json) = event.to_json(ups_name, time) else {
+        unreachable!("events are always serialized as objects");
+    };
+    let fields = json
+        .into_iter()
+        .filter(|(name, _)| !matches!(name.as_str(), "type" | "ups" | "timestamp"))
+        .filter_map(|(name, value)| match value {
+            Value::Null => None,
+            Value::String(value) => Some((name, value)),
+            value => Some((name, value.to_string())),
+        })
+        .collect();
+    proto::Event {
+        r#type: event.kind().to_string(),
+        ups: ups_name.to_string(),
+        timestamp: format_rfc3339(time),
+        fields,
+    }
+}
+
+/// The implementation of the gRPC service, shared by every connection.
+struct Service {
+    ups_name: String,
+    descriptions: HashMap<String, String>,
+    latest: Arc<Mutex<Latest>>,
+    events: broadcast::Sender<proto::Event>,
+}
+
+impl Service {
+    /// Returns the variables of the latest poll, or an error if the UPS has not been polled yet.
+    fn latest(&self) -> Result<(BTreeMap<String, String>, SystemTime), Status> {
+        let latest = self.latest.lock().unwrap_or_else(PoisonError::into_inner);
+        match latest.updated {
+            Some(updated) => Ok((latest.vars.clone(), updated)),
+            None => Err(Status::unavailable("the UPS has not been polled yet")),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Ups for Service {
+    async fn get_status(&self, _request: Request<proto::GetStatusRequest>) -> Result<Response<proto::GetStatusResponse>, Status> {
+        let (vars, updated) = self.latest()?;
+        let number = |name: &str| vars.get(name).and_then(|value| value.parse::<f64>().ok());
+        let status = vars.get("ups.status").cloned().unwrap_or_default();
+        Ok(Response::new(proto::GetStatusResponse {
+            ups: self.ups_name.clone(),
+            flags: status.split_whitespace().map(String::from).collect(),
+            status,
+            battery_charge: number("battery.charge"),
+            battery_runtime: number("battery.runtime"),
+            ups_load: number("ups.load"),
+            updated: format_rfc3339(updated),
+        }))
+    }
+
+    async fn list_variables(
+        &self,
+        _request: Request<proto::ListVariablesRequest>,
+    ) -> Result<Response<proto::ListVariablesResponse>, Status> {
+        let (vars, _) = self.latest()?;
+        let variables = vars
+            .into_iter()
+            .map(|(name, value)| proto::Variable {
+                description: self.descriptions.get(&name).cloned().unwrap_or_default(),
+                name,
+                value,
+            })
+            .collect();
+        Ok(Response::new(proto::ListVariablesResponse {
+            ups: self.ups_name.clone(),
+            variables,
+        }))
+    }
+
+    type StreamEventsStream = Pin<Box<dyn Stream<Item = Result<proto::Event, Status>> + Send>>;
+
+    async fn stream_events(&self, _request: Request<proto::StreamEventsRequest>) -> Result<Response<Self::StreamEventsStream>, Status> {
+        // Events missed by a stream that fell behind are skipped
+        let stream = BroadcastStream::new(self.events.subscribe()).filter_map(|event| event.ok().map(Ok));
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::VarKind;
+    use proto::ups_client::UpsClient;
+
+    #[test]
+    fn serve_grpc() {
+        let metadata = vec![VarMetadata {
+            name: String::from("battery.charge"),
+            value: String::from("100"),
+            description: String::from("Battery charge (percent of full)"),
+            writable: false,
+            kind: VarKind::Number,
+        }];
+        let mut grpc = Grpc::start(&[SocketAddr::from(([127, 0, 0, 1], 0))], "ups", &metadata).unwrap();
+        let url = format!("http://{}", grpc.local_addr());
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let mut client = runtime.block_on(UpsClient::connect(url)).unwrap();
+
+        let err = runtime.block_on(client.get_status(proto::GetStatusRequest {})).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+
+        let vars = [("battery.charge", "87"), ("ups.status", "OB DISCHRG"), ("ups.model", "Smart-UPS")];
+        grpc.publish(&vars.map(|(name, value)| Variable::new(name, value.to_string()))).unwrap();
+        let status = runtime.block_on(client.get_status(proto::GetStatusRequest {})).unwrap().into_inner();
+        assert_eq!(status.ups, "ups");
+        assert_eq!(status.status, "OB DISCHRG");
+        assert_eq!(status.flags, vec!["OB", "DISCHRG"]);
+        assert_eq!(status.battery_charge, Some(87.0));
+        assert_eq!(status.ups_load, None);
+
+        let variables = runtime.block_on(client.list_variables(proto::ListVariablesRequest {})).unwrap().into_inner().variables;
+        let names: Vec<&str> = variables.iter().map(|var| var.name.as_str()).collect();
+        assert_eq!(names, vec!["battery.charge", "ups.model", "ups.status"]);
+        assert_eq!(variables[0].description, "Battery charge (percent of full)");
+        assert_eq!(variables[1].description, "");
+
+        let mut stream = runtime.block_on(client.stream_events(proto::StreamEventsRequest {})).unwrap().into_inner();
+        let event = Event::StatusChanged {
+            previous: Some(String::from("OL")),
+            current: String::from("OB DISCHRG"),
+        };
+        grpc.event(&event).unwrap();
+        let message = runtime.block_on(stream.message()).unwrap().unwrap();
+        assert_eq!(message.r#type, "status_changed");
+        assert_eq!(message.fields["previous"], "OL");
+        assert_eq!(message.fields["current"], "OB DISCHRG");
+
+        // Dropping the sink ends open streams and releases the address
+        let addr = grpc.local_addr();
+        drop(grpc);
+        assert!(!matches!(runtime.block_on(stream.message()), Ok(Some(_))));
+        assert!(crate::http::bind(addr).is_ok());
+    }
+}
@@ -0,0 +1,356 @@
+//! Active/standby high availability, for running several replicas against the same NUT server
+//! without duplicating what they push to external services.
+//!
+//! Replicas share a lease file, which holds the identity of the leader and when it last renewed
+//! the lease. Every replica serves metrics, but only the leader publishes to the sinks wrapped in
+//! [`Gated`]. A standby takes over once the leader stops renewing for the duration of the lease.
+//! The lease is only renewed after successful polls, so a leader that cannot reach the UPS while a
+//! standby can is replaced, and steps down after its next failed poll once it sees the new holder.
+//!
+//! Replicas hold an exclusive lock on a file next to the lease, named after it with `.lock`
+//! appended, while they read and replace the lease, so two standbys cannot both take it over.
+
+use crate::events::Event;
+use crate::sink::Sink;
+use crate::Variable;
+use log::info;
+use prometheus::{register_gauge_with_registry, Gauge, Registry};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A sink that renews or takes over the lease after every poll, and exports whether this replica
+/// is the leader as `pistachio_ha_leader`.
+#[derive(Debug)]
+pub struct Lease {
+    path: PathBuf,
+    id: String,
+    duration: Duration,
+    leader: Arc<AtomicBool>,
+    gauge: Gauge,
+}
+
+impl Lease {
+    /// Creates a lease held in the file at `path` for `duration` after every renewal. This
+    /// replica starts as a standby until its first poll.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the gauge cannot be registered with Prometheus.
+    pub fn new(path: &Path, id: &str, duration: Duration, registry: &Registry) -> crate::Result<Lease> {
+        let gauge = register_gauge_with_registry!(
+            "pistachio_ha_leader",
+            "Whether this replica is the leader that publishes to external services",
+            registry
+        )?;
+        Ok(Lease {
+            path: path.to_path_buf(),
+            id: id.to_string(),
+            duration,
+            leader: Arc::default(),
+            gauge,
+        })
+    }
+
+    /// Returns a flag that is set while this replica is the leader, for gating sinks with.
+    #[must_use]
+    pub fn leader(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.leader)
+    }
+
+    /// Takes the exclusive lock on the lease, waiting for other replicas to release it. The lock
+    /// is released when the returned file is dropped.
+    fn lock(&self) -> io::Result<File> {
+        let mut lock_path = self.path.as_os_str().to_os_string();
+        lock_path.push(".lock");
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(lock_path)?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// Reads the holder of the lease and when it was last renewed, if the file exists.
+    fn read(&self) -> io::Result<Option<(String, SystemTime)>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let holder = contents
+            .trim()
+            .rsplit_once(' ')
+            .and_then(|(id, secs)| Some((id.to_string(), UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?))));
+        // A lease that cannot be read is as good as expired
+        Ok(holder.or(Some((String::new(), UNIX_EPOCH))))
+    }
+
+    /// Renews the lease if this replica holds it, or takes it over if it is free or expired, and
+    /// returns whether this replica is the leader.
+    fn renew(&self, now: SystemTime) -> io::Result<bool> {
+        let _lock = self.lock()?;
+        let takeover = match self.read()? {
+            None => true,
+            Some((holder, _)) if holder == self.id => true,
+            Some((_, renewed)) => now.duration_since(renewed).is_ok_and(|elapsed| elapsed >= self.duration),
+        };
+        if !takeover {
+            return Ok(false);
+        }
+        // Replace the file atomically, so replicas reading it without the lock never see it half
+        // written
+        let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut temp_path = self.path.as_os_str().to_os_string();
+        temp_path.push(format!(".{}.tmp", std::process::id()));
+        fs::write(&temp_path, format!("{} {secs}\n", self.id))?;
+        fs::rename(&temp_path, &self.path)?;
+        Ok(true)
+    }
+
+    /// Steps down if another replica has taken over the lease, which is checked after failed polls
+    /// since the lease is not renewed then.
+    fn check_holder(&self) -> io::Result<()> {
+        if self.leader.load(Ordering::Relaxed) && self.read()?.is_none_or(|(holder, _)| holder != self.id) {
+            self.set_leader(false);
+        }
+        Ok(())
+    }
+
+    /// Updates the leader flag and gauge, logging changes of role.
+    fn set_leader(&self, leader: bool) {
+        if self.leader.swap(leader, Ordering::Relaxed) != leader {
+            if leader {
+                info!("This replica is now the leader and will publish to external services");
+            } else {
+                info!("This replica is now a standby and will not publish to external services");
+            }
+        }
+        self.gauge.set(if leader { 1.0 } else { 0.0 });
+    }
+}
+
+impl Sink for Lease {
+    fn name(&self) -> &str {
+        "lease"
+    }
+
+    fn publish(&mut self, _vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        match self.renew(SystemTime::now()) {
+            Ok(leader) => self.set_leader(leader),
+            Err(err) => {
+                // Step down, since another replica may well be able to reach the file
+                self.set_leader(false);
+                return Err(format!("could not renew lease {}: {err}", self.path.display()).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn polled(&mut self, _started: SystemTime, _duration: Duration, error: Option<&str>) -> Result<(), Box<dyn Error>> {
+        // Successful polls already renewed the lease in publish
+        if error.is_none() {
+            return Ok(());
+        }
+        if let Err(err) = self.check_holder() {
+            self.set_leader(false);
+            return Err(format!("could not read lease {}: {err}", self.path.display()).into());
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Box<dyn Error>> {
+        // Let a standby take over right away instead of after the lease expires
+        if self.leader.load(Ordering::Relaxed) {
+            let _lock = self.lock()?;
+            if self.read()?.is_some_and(|(holder, _)| holder == self.id) {
+                fs::remove_file(&self.path)?;
+                self.set_leader(false);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A sink that only passes variables and events on to another sink while this replica is the
+/// leader.
+pub struct Gated {
+    inner: Box<dyn Sink>,
+    leader: Arc<AtomicBool>,
+}
+
+impl Gated {
+    /// Wraps a sink so it is only published to while `leader` is set.
+    #[must_use]
+    pub fn new(inner: Box<dyn Sink>, leader: Arc<AtomicBool>) -> Gated {
+        Gated { inner, leader }
+    }
+}
+
+impl Sink for Gated {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        if self.leader.load(Ordering::Relaxed) {
+            self.inner.publish(vars)?;
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        if self.leader.load(Ordering::Relaxed) {
+            self.inner.event(event)?;
+        }
+        Ok(())
+    }
+
+    fn polled(&mut self, started: SystemTime, duration: Duration, error: Option<&str>) -> Result<(), Box<dyn Error>> {
+        if self.leader.load(Ordering::Relaxed) {
+            self.inner.polled(started, duration, error)?;
+        }
+        Ok(())
+    }
+
+    fn variable_changes(&self) -> bool {
+        self.inner.variable_changes()
+    }
+
+    fn shutdown(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inner.shutdown()
+    }
+}
+
+/// Returns the default identity of this replica in the lease, made of the host name and process ID.
+#[must_use]
+pub fn default_id() -> String {
+    let host = crate::hostname().unwrap_or_else(|| String::from("pistachio"));
+    format!("{host}-{}", std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// A sink that counts what it receives.
+    struct Counting(Arc<Mutex<usize>>);
+
+    impl Sink for Counting {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn publish(&mut self, _vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+            *self.0.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    fn lease(path: &Path, id: &str, leader: bool) -> Lease {
+        Lease {
+            path: path.to_path_buf(),
+            id: id.to_string(),
+            duration: Duration::from_secs(30),
+            leader: Arc::new(AtomicBool::new(leader)),
+            gauge: Gauge::new("pistachio_ha_leader", "Leader").unwrap(),
+        }
+    }
+
+    #[test]
+    fn lease_takeover() {
+        let path = std::env::temp_dir().join(format!("pistachio-lease-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(secs);
+        let first = lease(&path, "first", false);
+        let second = lease(&path, "second", false);
+
+        assert!(first.renew(at(1000)).unwrap());
+        assert!(!second.renew(at(1010)).unwrap());
+        assert!(first.renew(at(1020)).unwrap());
+        assert!(!second.renew(at(1049)).unwrap());
+        // The first replica stopped renewing, so the second takes over
+        assert!(second.renew(at(1050)).unwrap());
+        assert!(!first.renew(at(1060)).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second 1050\n");
+
+        // A lease that cannot be read is taken over
+        fs::write(&path, "garbage").unwrap();
+        assert!(first.renew(at(1070)).unwrap());
+
+        // Releasing the lease lets the other replica take over right away
+        let mut first = lease(&path, "first", true);
+        first.shutdown().unwrap();
+        assert!(!path.exists());
+        assert!(second.renew(at(1071)).unwrap());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.lock", path.display())).unwrap();
+    }
+
+    #[test]
+    fn step_down_while_polls_fail() {
+        let path = std::env::temp_dir().join(format!("pistachio-failing-lease-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut first = lease(&path, "first", false);
+        let mut second = lease(&path, "second", false);
+        first.set_leader(first.renew(at(1000)).unwrap());
+        assert!(first.leader.load(Ordering::Relaxed));
+
+        // The first replica cannot reach the UPS, but stays the leader while nobody takes over
+        first.polled(at(1010), Duration::ZERO, Some("connection refused")).unwrap();
+        assert!(first.leader.load(Ordering::Relaxed));
+        // The second replica can, and takes over once the lease expires
+        second.set_leader(second.renew(at(1030)).unwrap());
+        assert!(second.leader.load(Ordering::Relaxed));
+        second.polled(at(1030), Duration::ZERO, None).unwrap();
+        first.polled(at(1030), Duration::ZERO, Some("connection refused")).unwrap();
+        assert!(!first.leader.load(Ordering::Relaxed));
+        assert!(second.leader.load(Ordering::Relaxed));
+        assert_eq!(first.gauge.get(), 0.0);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.lock", path.display())).unwrap();
+    }
+
+    #[test]
+    fn racing_takeovers() {
+        let path = std::env::temp_dir().join(format!("pistachio-racing-lease-{}", std::process::id()));
+        let replicas: Vec<Lease> = (0..8).map(|index| lease(&path, &format!("standby-{index}"), false)).collect();
+        for round in 0..100 {
+            let _ = fs::remove_file(&path);
+            let barrier = std::sync::Barrier::new(replicas.len());
+            let now = UNIX_EPOCH + Duration::from_secs(1000 + round);
+            let leaders = std::thread::scope(|scope| {
+                let handles: Vec<_> = replicas
+                    .iter()
+                    .map(|replica| {
+                        let barrier = &barrier;
+                        scope.spawn(move || {
+                            barrier.wait();
+                            replica.renew(now).unwrap()
+                        })
+                    })
+                    .collect();
+                handles.into_iter().map(|handle| handle.join().unwrap()).filter(|leader| *leader).count()
+            });
+            assert_eq!(leaders, 1);
+        }
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.lock", path.display())).unwrap();
+    }
+
+    #[test]
+    fn gated_sink() {
+        let count = Arc::new(Mutex::new(0));
+        let leader = Arc::new(AtomicBool::new(false));
+        let mut gated = Gated::new(Box::new(Counting(Arc::clone(&count))), Arc::clone(&leader));
+        gated.publish(&[]).unwrap();
+        assert_eq!(*count.lock().unwrap(), 0);
+        leader.store(true, Ordering::Relaxed);
+        gated.publish(&[]).unwrap();
+        assert_eq!(*count.lock().unwrap(), 1);
+        assert_eq!(gated.name(), "counting");
+    }
+}
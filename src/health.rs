@@ -0,0 +1,270 @@
+//! Scoring of battery health from how the battery behaves over time, giving early warning of a
+//! dying battery before the UPS reports it with the `RB` flag.
+
+use crate::sink::Sink;
+use crate::status::UpsStatus;
+use crate::time::parse_date;
+use crate::Variable;
+use log::{debug, info, warn};
+use prometheus::{
+    register_gauge_with_registry, register_gauge_vec_with_registry, register_int_counter_with_registry, Gauge, GaugeVec, IntCounter, Registry,
+};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Relative drop of the battery voltage below nominal at which a battery under load is
+/// considered exhausted, which is where most lead-acid batteries are cut off.
+const MAX_VOLTAGE_SAG: f64 = 0.125;
+
+/// Charge above which the battery is considered full.
+const FULL_CHARGE: f64 = 95.0;
+
+/// A sink that scores the health of the battery from a few factors, each between 0 and 1:
+///
+/// - `charge`: the charge the battery holds once the UPS stops charging it on line power.
+/// - `runtime`: the runtime estimated at full charge, relative to the rated runtime.
+/// - `voltage_sag`: how far the battery voltage drops below nominal at the start of an outage.
+/// - `age`: the time since `battery.date`, relative to the expected life of the battery.
+///
+/// Each factor is exported once it has been measured, and the score is their average, from 0 to
+/// 100. Factors that cannot be measured, such as the age of a battery without a date, are left
+/// out.
+#[derive(Debug)]
+pub struct BatteryHealth {
+    rated_runtime: Option<Duration>,
+    expected_life: Duration,
+    factors: BTreeMap<&'static str, f64>,
+    was_on_battery: bool,
+    factor_gauge: GaugeVec,
+    score_gauge: Option<Gauge>,
+    registry: Registry,
+}
+
+impl BatteryHealth {
+    /// Registers the gauge of the health factors. The score is only registered once a factor has
+    /// been measured, so it is never exported as zero for a battery that has not been assessed.
+    /// The runtime factor is only measured if the rated runtime of the UPS at its usual load is
+    /// given.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the gauges cannot be registered with Prometheus.
+    pub fn new(rated_runtime: Option<Duration>, expected_life: Duration, registry: &Registry) -> crate::Result<BatteryHealth> {
+        let factor_gauge = register_gauge_vec_with_registry!(
+            "ups_battery_health_factor",
+            "Factors contributing to the battery health score, from 0 to 1",
+            &["factor"],
+            registry
+        )?;
+        Ok(BatteryHealth {
+            rated_runtime,
+            expected_life,
+            factors: BTreeMap::new(),
+            was_on_battery: false,
+            factor_gauge,
+            score_gauge: None,
+            registry: registry.clone(),
+        })
+    }
+
+    /// Returns the health score, or `None` if no factor has been measured yet.
+    #[must_use]
+    pub fn score(&self) -> Option<f64> {
+        if self.factors.is_empty() {
+            return None;
+        }
+        Some(100.0 * self.factors.values().sum::<f64>() / self.factors.len() as f64)
+    }
+
+    /// Measures every factor that can be measured from a poll made at `now`.
+    fn assess(&mut self, values: &BTreeMap<String, String>, now: SystemTime) {
+        let get = |name: &str| values.get(name).and_then(|value| value.trim().parse::<f64>().ok());
+        let status = values.get("ups.status").map_or(UpsStatus::empty(), |status| UpsStatus::parse(status));
+        let charge = get("battery.charge");
+
+        if status.is_online() && !status.intersects(UpsStatus::CHARGING | UpsStatus::ON_BATTERY) {
+            if let Some(charge) = charge {
+                self.set("charge", charge / 100.0);
+            }
+        }
+        if let (Some(rated), Some(runtime)) = (self.rated_runtime, get("battery.runtime")) {
+            if status.is_online() && charge.is_some_and(|charge| charge >= FULL_CHARGE) {
+                self.set("runtime", runtime / rated.as_secs_f64());
+            }
+        }
+        // Only the start of an outage is measured, since the voltage keeps dropping as the battery
+        // discharges
+        if status.is_on_battery() && !self.was_on_battery && charge.is_some_and(|charge| charge >= FULL_CHARGE) {
+            if let (Some(voltage), Some(nominal)) = (get("battery.voltage"), get("battery.voltage.nominal")) {
+                let sag = (nominal - voltage) / nominal;
+                self.set("voltage_sag", 1.0 - sag / MAX_VOLTAGE_SAG);
+            }
+        }
+        self.was_on_battery = status.is_on_battery();
+        if let Some(installed) = values.get("battery.date").and_then(|date| parse_date(date)) {
+            let age = now.duration_since(installed).unwrap_or_default();
+            self.set("age", 1.0 - age.as_secs_f64() / self.expected_life.as_secs_f64());
+        }
+    }
+
+    /// Sets a factor, limited to between 0 and 1.
+    fn set(&mut self, factor: &'static str, value: f64) {
+        let value = value.clamp(0.0, 1.0);
+        if self.factors.insert(factor, value) != Some(value) {
+            debug!("Battery health factor {factor} is now {value:.2}");
+        }
+        self.factor_gauge.with_label_values(&[factor]).set(value);
+        if self.score_gauge.is_none() {
+            match register_gauge_with_registry!("ups_battery_health_score", "Battery health score, from 0 to 100", self.registry) {
+                Ok(gauge) => self.score_gauge = Some(gauge),
+                Err(err) => warn!("Failed to register the battery health score: {err}"),
+            }
+        }
+        if let (Some(gauge), Some(score)) = (&self.score_gauge, self.score()) {
+            gauge.set(score);
+        }
+    }
+}
+
+impl Sink for BatteryHealth {
+    fn name(&self) -> &str {
+        "health"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let values = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        self.assess(&values, SystemTime::now());
+        Ok(())
+    }
+}
+
+/// A sink that tracks the runtime calibrations of the UPS, during which `ups.status` has the `CAL`
+/// flag and the UPS runs on battery until it is nearly empty.
+///
+/// How long a calibration lasts is the runtime the battery actually delivered at the load at the
+/// time, which makes it a better measure of battery health than the estimate of the UPS.
+#[derive(Debug)]
+pub struct Calibrations {
+    started: Option<SystemTime>,
+    count: IntCounter,
+    last: Gauge,
+    runtime: Gauge,
+}
+
+impl Calibrations {
+    /// Registers the calibration counter and gauges.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the metrics cannot be registered with Prometheus.
+    pub fn new(registry: &Registry) -> crate::Result<Calibrations> {
+        Ok(Calibrations {
+            started: None,
+            count: register_int_counter_with_registry!("ups_calibrations_total", "Number of runtime calibrations started", registry)?,
+            last: register_gauge_with_registry!(
+                "ups_last_calibration_timestamp_seconds",
+                "Time the latest runtime calibration started",
+                registry
+            )?,
+            runtime: register_gauge_with_registry!(
+                "ups_calibrated_runtime_seconds",
+                "Runtime measured by the latest completed runtime calibration",
+                registry
+            )?,
+        })
+    }
+
+    /// Follows the calibration status of a poll made at `now`, returning the runtime measured if
+    /// a calibration ended with it.
+    fn track(&mut self, values: &BTreeMap<String, String>, now: SystemTime) -> Option<Duration> {
+        let status = values.get("ups.status").map_or(UpsStatus::empty(), |status| UpsStatus::parse(status));
+        match (self.started, status.contains(UpsStatus::CALIBRATING)) {
+            (None, true) => {
+                info!("The UPS started a runtime calibration");
+                self.started = Some(now);
+                self.count.inc();
+                self.last.set(now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64());
+                None
+            }
+            (Some(started), false) => {
+                self.started = None;
+                let runtime = now.duration_since(started).unwrap_or_default();
+                info!("The UPS finished a runtime calibration, which measured a runtime of {}s", runtime.as_secs());
+                self.runtime.set(runtime.as_secs_f64());
+                Some(runtime)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Sink for Calibrations {
+    fn name(&self) -> &str {
+        "calibrations"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let values = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        self.track(&values, SystemTime::now());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn values(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn score_battery_health() {
+        let year = Duration::from_secs(365 * 86400);
+        let mut health = BatteryHealth::new(Some(Duration::from_secs(1200)), year * 4, &Registry::new()).unwrap();
+        assert_eq!(health.score(), None);
+
+        // Charging does not count, and neither does a date that cannot be parsed
+        let now = UNIX_EPOCH + Duration::from_secs(1_709_164_800);
+        health.assess(&values(&[("ups.status", "OL CHRG"), ("battery.charge", "60"), ("battery.date", "unknown")]), now);
+        assert_eq!(health.score(), None);
+
+        let fully_charged = [
+            ("ups.status", "OL"),
+            ("battery.charge", "100"),
+            ("battery.runtime", "900"),
+            ("battery.voltage", "13.5"),
+            ("battery.voltage.nominal", "12.0"),
+            ("battery.date", "2022/02/28"),
+        ];
+        health.assess(&values(&fully_charged), now);
+        assert_eq!(health.factors["charge"], 1.0);
+        assert_eq!(health.factors["runtime"], 0.75);
+        assert!(!health.factors.contains_key("voltage_sag"));
+        assert!((health.factors["age"] - 0.5).abs() < 0.01);
+
+        // The voltage is measured once at the start of an outage
+        health.assess(&values(&[("ups.status", "OB"), ("battery.charge", "99"), ("battery.voltage", "11.4"), ("battery.voltage.nominal", "12.0")]), now);
+        assert!((health.factors["voltage_sag"] - 0.6).abs() < 1e-9);
+        health.assess(&values(&[("ups.status", "OB"), ("battery.charge", "98"), ("battery.voltage", "10.5"), ("battery.voltage.nominal", "12.0")]), now);
+        assert!((health.factors["voltage_sag"] - 0.6).abs() < 1e-9);
+        let score = health.score().unwrap();
+        assert!((score - 71.2).abs() < 0.5, "{score}");
+    }
+
+    #[test]
+    fn track_calibrations() {
+        let mut calibrations = Calibrations::new(&Registry::new()).unwrap();
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(1_709_164_800 + secs);
+        assert_eq!(calibrations.track(&values(&[("ups.status", "OL")]), at(0)), None);
+        assert_eq!(calibrations.track(&values(&[("ups.status", "OB DISCHRG CAL")]), at(10)), None);
+        assert_eq!(calibrations.count.get(), 1);
+        assert_eq!(calibrations.last.get(), 1_709_164_810.0);
+        assert_eq!(calibrations.track(&values(&[("ups.status", "OB DISCHRG CAL")]), at(600)), None);
+        assert_eq!(calibrations.track(&values(&[("ups.status", "OL CHRG")]), at(1210)), Some(Duration::from_secs(1200)));
+        assert_eq!(calibrations.runtime.get(), 1200.0);
+        assert_eq!(calibrations.count.get(), 1);
+    }
+}
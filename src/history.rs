@@ -0,0 +1,298 @@
+//! Local history storage in an embedded SQLite database.
+//!
+//! Records the values of selected variables on every poll, along with every change of the UPS
+//! and beeper status, so recent events can be investigated without a full time series database.
+
+use crate::http::{Request, Response};
+use crate::sink::Sink;
+use crate::Variable;
+use log::warn;
+use rusqlite::{params, Connection};
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Variables whose changes are always recorded as transitions.
+const TRANSITION_VARS: &[&str] = &["ups.status", "ups.beeper.status"];
+
+/// Maximum number of rows returned by a single history query.
+const MAX_QUERY_LIMIT: usize = 10_000;
+
+/// Default number of rows returned by a history query.
+const DEFAULT_QUERY_LIMIT: usize = 1000;
+
+/// A recorded value of a variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// UNIX timestamp of the poll, in seconds.
+    pub timestamp: i64,
+    /// Name of the UPS variable.
+    pub variable: String,
+    /// Value of the variable.
+    pub value: String,
+}
+
+/// A recorded change in the value of a status variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    /// UNIX timestamp of the poll where the change was seen, in seconds.
+    pub timestamp: i64,
+    /// Name of the UPS variable.
+    pub variable: String,
+    /// Value before the change, or `None` if this is the first value seen.
+    pub previous: Option<String>,
+    /// Value after the change.
+    pub current: String,
+}
+
+/// A handle to the history database, which can be shared between the polling loop and the HTTP
+/// server.
+#[derive(Debug, Clone)]
+pub struct History {
+    conn: Arc<Mutex<Connection>>,
+    retention: Duration,
+}
+
+impl History {
+    /// Opens or creates the history database at the given path. Records older than the retention
+    /// period are removed as new ones are added.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the database cannot be opened or its tables cannot be created.
+    pub fn open(path: &Path, retention: Duration) -> Result<History, rusqlite::Error> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS samples (
+                timestamp INTEGER NOT NULL,
+                variable TEXT NOT NULL,
+                value TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS samples_timestamp ON samples (timestamp);
+            CREATE TABLE IF NOT EXISTS transitions (
+                timestamp INTEGER NOT NULL,
+                variable TEXT NOT NULL,
+                previous TEXT,
+                current TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS transitions_timestamp ON transitions (timestamp);",
+        )?;
+        Ok(History {
+            conn: Arc::new(Mutex::new(conn)),
+            retention,
+        })
+    }
+
+    /// Creates a sink that records the given variables from every poll into this database.
+    #[must_use]
+    pub fn recorder(&self, vars: &[String]) -> HistoryRecorder {
+        HistoryRecorder {
+            history: self.clone(),
+            vars: vars.to_vec(),
+            last_values: HashMap::new(),
+        }
+    }
+
+    /// Returns the most recent samples recorded at or after `since`, optionally for a single
+    /// variable, in chronological order.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the database cannot be queried.
+    pub fn samples(&self, since: i64, variable: Option<&str>, limit: usize) -> Result<Vec<Sample>, rusqlite::Error> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(
+            "SELECT timestamp, variable, value FROM samples
+            WHERE timestamp >= ?1 AND (?2 IS NULL OR variable = ?2)
+            ORDER BY timestamp DESC, rowid DESC LIMIT ?3",
+        )?;
+        let mut samples = stmt
+            .query_map(params![since, variable, limit], |row| {
+                Ok(Sample {
+                    timestamp: row.get(0)?,
+                    variable: row.get(1)?,
+                    value: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        samples.reverse();
+        Ok(samples)
+    }
+
+    /// Returns the most recent status transitions recorded at or after `since`, in chronological
+    /// order.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the database cannot be queried.
+    pub fn transitions(&self, since: i64, limit: usize) -> Result<Vec<Transition>, rusqlite::Error> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(
+            "SELECT timestamp, variable, previous, current FROM transitions
+            WHERE timestamp >= ?1 ORDER BY timestamp DESC, rowid DESC LIMIT ?2",
+        )?;
+        let mut transitions = stmt
+            .query_map(params![since, limit], |row| {
+                Ok(Transition {
+                    timestamp: row.get(0)?,
+                    variable: row.get(1)?,
+                    previous: row.get(2)?,
+                    current: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        transitions.reverse();
+        Ok(transitions)
+    }
+
+    /// Serves recent history as JSON. Supports the `since` (UNIX timestamp), `var`, and `limit`
+    /// query parameters.
+    #[must_use]
+    pub fn handle(&self, request: &Request) -> Response {
+        let since = match request.query_param("since").map(str::parse::<i64>) {
+            Some(Ok(since)) => since,
+            Some(Err(_)) => return Response::text(400, "Invalid value for since\n"),
+            None => 0,
+        };
+        let limit = match request.query_param("limit").map(str::parse::<usize>) {
+            Some(Ok(limit)) => limit.min(MAX_QUERY_LIMIT),
+            Some(Err(_)) => return Response::text(400, "Invalid value for limit\n"),
+            None => DEFAULT_QUERY_LIMIT,
+        };
+        let variable = request.query_param("var");
+        let result = self
+            .samples(since, variable, limit)
+            .and_then(|samples| Ok((samples, self.transitions(since, limit)?)));
+        match result {
+            Ok((samples, transitions)) => {
+                let samples: Vec<_> = samples
+                    .iter()
+                    .map(|s| json!({"timestamp": s.timestamp, "variable": s.variable, "value": s.value}))
+                    .collect();
+                let transitions: Vec<_> = transitions
+                    .iter()
+                    .map(|t| {
+                        json!({
+                            "timestamp": t.timestamp,
+                            "variable": t.variable,
+                            "previous": t.previous,
+                            "current": t.current,
+                        })
+                    })
+                    .collect();
+                Response::json(200, &json!({"samples": samples, "transitions": transitions}))
+            }
+            Err(err) => {
+                warn!("Failed to query history: {err}");
+                Response::text(500, "Failed to query history\n")
+            }
+        }
+    }
+
+    /// Locks the database connection, recovering it if another thread panicked while holding it.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// A sink that records polled variables into a [History] database.
+#[derive(Debug)]
+pub struct HistoryRecorder {
+    history: History,
+    vars: Vec<String>,
+    last_values: HashMap<String, String>,
+}
+
+impl HistoryRecorder {
+    /// Records a poll that happened at the given UNIX timestamp.
+    fn record(&mut self, timestamp: i64, vars: &[Variable]) -> Result<(), rusqlite::Error> {
+        let mut conn = self.history.lock();
+        let tx = conn.transaction()?;
+        for var in vars {
+            let name = var.name();
+            if self.vars.iter().any(|v| v == name) {
+                tx.execute(
+                    "INSERT INTO samples (timestamp, variable, value) VALUES (?1, ?2, ?3)",
+                    params![timestamp, name, var.value()],
+                )?;
+            }
+            if TRANSITION_VARS.contains(&name) {
+                let value = var.value().to_string();
+                let previous = self.last_values.get(name);
+                if previous != Some(&value) {
+                    tx.execute(
+                        "INSERT INTO transitions (timestamp, variable, previous, current) VALUES (?1, ?2, ?3, ?4)",
+                        params![timestamp, name, previous, value],
+                    )?;
+                    self.last_values.insert(name.to_string(), value);
+                }
+            }
+        }
+        let cutoff = timestamp.saturating_sub(i64::try_from(self.history.retention.as_secs()).unwrap_or(i64::MAX));
+        tx.execute("DELETE FROM samples WHERE timestamp < ?1", params![cutoff])?;
+        tx.execute("DELETE FROM transitions WHERE timestamp < ?1", params![cutoff])?;
+        tx.commit()
+    }
+}
+
+impl Sink for HistoryRecorder {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.record(i64::try_from(timestamp)?, vars)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str, value: &str) -> Variable {
+        Variable::new(name, value.to_string())
+    }
+
+    #[test]
+    fn record_samples_and_transitions() {
+        let history = History::open(Path::new(":memory:"), Duration::from_secs(3600)).unwrap();
+        let mut recorder = history.recorder(&[String::from("battery.charge")]);
+
+        recorder.record(100, &[var("battery.charge", "100"), var("ups.status", "OL"), var("ups.load", "20")]).unwrap();
+        recorder.record(110, &[var("battery.charge", "99"), var("ups.status", "OL")]).unwrap();
+        recorder.record(120, &[var("battery.charge", "98"), var("ups.status", "OB DISCHRG")]).unwrap();
+
+        let samples = history.samples(0, None, 10).unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].value, "100");
+        assert_eq!(samples[2].value, "98");
+        assert_eq!(history.samples(0, Some("ups.load"), 10).unwrap().len(), 0);
+        assert_eq!(history.samples(110, None, 10).unwrap().len(), 2);
+        assert_eq!(history.samples(0, None, 1).unwrap()[0].timestamp, 120);
+
+        let transitions = history.transitions(0, 10).unwrap();
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(transitions[0].previous, None);
+        assert_eq!(transitions[1].previous.as_deref(), Some("OL"));
+        assert_eq!(transitions[1].current, "OB DISCHRG");
+    }
+
+    #[test]
+    fn prune_expired_records() {
+        let history = History::open(Path::new(":memory:"), Duration::from_secs(60)).unwrap();
+        let mut recorder = history.recorder(&[String::from("battery.charge")]);
+
+        recorder.record(100, &[var("battery.charge", "100"), var("ups.status", "OL")]).unwrap();
+        recorder.record(200, &[var("battery.charge", "99")]).unwrap();
+
+        let samples = history.samples(0, None, 10).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].timestamp, 200);
+        assert!(history.transitions(0, 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1150 @@
+//! A minimal HTTP server for exposing Prometheus metrics and the JSON API.
+//!
+//! Each connection is handled on its own thread, which is plenty for the handful of scrapers and
+//! API clients an exporter like this serves. With the `tls` feature, connections can be served
+//! over HTTPS instead.
+
+use crate::auth::{ApiToken, ClientScopes};
+use log::{debug, warn};
+use prometheus::proto::{LabelPair, MetricFamily};
+use prometheus::{Encoder, IntCounterVec, Registry, TextEncoder};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Maximum time to wait for a client to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum time to wait for a client to accept a response.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum time spent connecting to a listener to wake it up when the server stops.
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum time spent telling a client its connection was rejected, since it blocks accepting
+/// other connections.
+const REJECT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Time waited before retrying to bind to an address in use, which doubles with every retry.
+const BIND_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest time waited before retrying to bind to an address in use.
+const MAX_BIND_RETRY_DELAY: Duration = Duration::from_secs(8);
+
+/// Maximum combined size of the request line and headers.
+const MAX_HEADER_BYTES: usize = 8 * 1024;
+
+/// Maximum size of a request body.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Prefix of the paths of the JSON API, the only paths served with CORS headers.
+const API_PREFIX: &str = "/api/v1/";
+
+/// How long browsers may cache the answer to a CORS preflight request, in seconds.
+const CORS_MAX_AGE: u32 = 600;
+
+/// A function that produces a response for a request.
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// An HTTP request received by the server.
+#[derive(Debug)]
+pub struct Request {
+    /// Request method, such as `GET`.
+    pub method: String,
+    /// Request path, without the query string.
+    pub path: String,
+    /// Decoded query string parameters.
+    pub query: HashMap<String, String>,
+    /// Request headers, with lowercase names.
+    pub headers: HashMap<String, String>,
+    /// Request body.
+    pub body: Vec<u8>,
+    /// Address of the client that sent the request.
+    pub remote_addr: SocketAddr,
+    /// Common name of the certificate the client presented over HTTPS, if any.
+    pub client_cn: Option<String>,
+}
+
+impl Request {
+    /// Returns the value of a query string parameter, if present.
+    #[must_use]
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(String::as_str)
+    }
+
+    /// Returns the value of a header, if present. Header names are matched case-insensitively.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+
+    /// Returns the user name and password sent with HTTP Basic authentication, if any.
+    #[must_use]
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        let (scheme, encoded) = self.header("authorization")?.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(decode_base64(encoded.trim())?).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        Some((username.to_string(), password.to_string()))
+    }
+}
+
+/// An HTTP response to be sent by the server.
+#[derive(Debug)]
+pub struct Response {
+    /// Status code of the response.
+    pub status: u16,
+    /// Additional response headers.
+    pub headers: Vec<(String, String)>,
+    /// Response body.
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Creates a response with the given status, content type, and body.
+    #[must_use]
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status,
+            headers: vec![(String::from("Content-Type"), content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    /// Creates a plain text response.
+    #[must_use]
+    pub fn text(status: u16, body: &str) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", body)
+    }
+
+    /// Creates a JSON response.
+    #[must_use]
+    pub fn json(status: u16, body: &serde_json::Value) -> Response {
+        Response::new(status, "application/json", body.to_string())
+    }
+
+    /// Creates a `401 Unauthorized` response asking for HTTP Basic authentication.
+    #[must_use]
+    pub fn unauthorized() -> Response {
+        Response::text(401, "Unauthorized\n").with_header("WWW-Authenticate", "Basic realm=\"pistachio\"")
+    }
+
+    /// Creates a `404 Not Found` response.
+    #[must_use]
+    pub fn not_found() -> Response {
+        Response::text(404, "Not Found\n")
+    }
+
+    /// Adds a header to the response.
+    #[must_use]
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// A route, matched by method and exact path.
+struct Route {
+    method: String,
+    path: String,
+    handler: Handler,
+}
+
+/// Limits the rate of requests from each IP address with a token bucket, which holds up to a
+/// minute of requests and refills continuously.
+#[derive(Debug)]
+struct RateLimiter {
+    per_minute: f64,
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    /// Maximum number of addresses tracked before those with full buckets are forgotten.
+    const MAX_TRACKED: usize = 4096;
+
+    fn new(per_minute: u32) -> RateLimiter {
+        RateLimiter {
+            per_minute: f64::from(per_minute),
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Takes a token for a request from `ip` at `now`, returning whether the request is allowed.
+    fn allow(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let refill = |(tokens, last): (f64, Instant)| {
+            (tokens + now.duration_since(last).as_secs_f64() * self.per_minute / 60.0).min(self.per_minute)
+        };
+        if buckets.len() >= RateLimiter::MAX_TRACKED {
+            buckets.retain(|_, bucket| refill(*bucket) < self.per_minute);
+        }
+        let tokens = buckets.get(&ip).map_or(self.per_minute, |bucket| refill(*bucket));
+        let allowed = tokens >= 1.0;
+        buckets.insert(ip, (if allowed { tokens - 1.0 } else { tokens }, now));
+        allowed
+    }
+}
+
+/// An HTTP server with a fixed set of routes.
+#[derive(Default)]
+pub struct Server {
+    routes: Vec<Route>,
+    max_connections: Option<usize>,
+    active_connections: AtomicUsize,
+    rate_limiter: Option<RateLimiter>,
+    access_log: Option<log::Level>,
+    requests: Option<IntCounterVec>,
+    bind_retries: u32,
+    cors_origins: Vec<String>,
+    api_tokens: Vec<ApiToken>,
+    client_scopes: Vec<ClientScopes>,
+    #[cfg(feature = "tls")]
+    tls: Option<crate::tls::ReloadableConfig>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    max_header_bytes: Option<usize>,
+}
+
+impl Server {
+    /// Creates a server with no routes.
+    #[must_use]
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /// Adds a route to the server, which will be served by the given handler.
+    #[must_use]
+    pub fn route<F>(mut self, method: &str, path: &str, handler: F) -> Server
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Limits the number of connections handled at once. Connections beyond the limit are
+    /// answered with `503 Service Unavailable` and closed.
+    #[must_use]
+    pub fn max_connections(mut self, max: usize) -> Server {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Limits the number of requests per minute from each IP address. Requests beyond the limit
+    /// are answered with `429 Too Many Requests`.
+    #[must_use]
+    pub fn rate_limit(mut self, per_minute: u32) -> Server {
+        self.rate_limiter = Some(RateLimiter::new(per_minute));
+        self
+    }
+
+    /// Logs every request with its method, path, status, duration, and remote address at the
+    /// given level.
+    #[must_use]
+    pub fn access_log(mut self, level: log::Level) -> Server {
+        self.access_log = Some(level);
+        self
+    }
+
+    /// Retries binding to an address that is in use up to `retries` times, waiting longer before
+    /// every retry, such as while a previous instance is still shutting down after a restart.
+    #[must_use]
+    pub fn bind_retries(mut self, retries: u32) -> Server {
+        self.bind_retries = retries;
+        self
+    }
+
+    /// Sets how long to wait for a client to send its request, and to accept the response. Both
+    /// are 10 seconds by default.
+    #[must_use]
+    pub fn timeouts(mut self, read: Duration, write: Duration) -> Server {
+        self.read_timeout = Some(read);
+        self.write_timeout = Some(write);
+        self
+    }
+
+    /// Keeps connections open for further requests until they have been idle for the given time.
+    /// Connections are closed after every response by default.
+    #[must_use]
+    pub fn keep_alive(mut self, idle_timeout: Duration) -> Server {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Limits the combined size of the request line and headers, beyond which requests are
+    /// answered with `400 Bad Request`. The limit is 8 KiB by default.
+    #[must_use]
+    pub fn max_header_bytes(mut self, max: usize) -> Server {
+        self.max_header_bytes = Some(max);
+        self
+    }
+
+    /// Allows web pages from the given origins, or from any origin with `*`, to read the JSON
+    /// API, answering its CORS preflight requests and adding `Access-Control-Allow-Origin` to its
+    /// responses.
+    #[must_use]
+    pub fn cors(mut self, origins: Vec<String>) -> Server {
+        self.cors_origins = origins;
+        self
+    }
+
+    /// Requires every request, except to `/ready`, to be sent with one of the tokens, granting
+    /// the scope needed for the request. Requests are not authenticated by default.
+    #[must_use]
+    pub fn api_tokens(mut self, tokens: Vec<ApiToken>) -> Server {
+        self.api_tokens = tokens;
+        self
+    }
+
+    /// Grants scopes to clients by the common name of the certificate they present over HTTPS,
+    /// requiring every other request, except to `/ready`, to be sent with an API token.
+    #[must_use]
+    pub fn client_scopes(mut self, clients: Vec<ClientScopes>) -> Server {
+        self.client_scopes = clients;
+        self
+    }
+
+    /// Serves HTTPS with the given configuration, such as one built by
+    /// [`crate::tls::server_config`], instead of plain HTTP. Connections are accepted with the
+    /// current configuration of a [`crate::tls::ReloadableConfig`], so it can be replaced while
+    /// the server is running.
+    #[cfg(feature = "tls")]
+    #[must_use]
+    pub fn tls(mut self, config: impl Into<crate::tls::ReloadableConfig>) -> Server {
+        self.tls = Some(config.into());
+        self
+    }
+
+    /// Returns whether connections are served over HTTPS.
+    fn is_tls(&self) -> bool {
+        #[cfg(feature = "tls")]
+        {
+            self.tls.is_some()
+        }
+        #[cfg(not(feature = "tls"))]
+        {
+            false
+        }
+    }
+
+    /// Returns the method and path of every route, in the order they were added.
+    pub fn routes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.routes.iter().map(|route| (route.method.as_str(), route.path.as_str()))
+    }
+
+    /// Counts every response in a counter with `path` and `code` labels. Requests to paths
+    /// without a route are counted under the path `other`, to keep the number of series bounded.
+    #[must_use]
+    pub fn count_requests(mut self, counter: IntCounterVec) -> Server {
+        self.requests = Some(counter);
+        self
+    }
+
+    /// Binds to every given address and serves requests on background threads, until the
+    /// returned listeners are dropped.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the server cannot bind to any of the addresses, once every
+    /// retry has failed for an address in use.
+    pub fn start(self, addrs: &[SocketAddr]) -> io::Result<Listeners> {
+        let listeners = addrs
+            .iter()
+            .map(|addr| bind_retrying(*addr, self.bind_retries))
+            .collect::<io::Result<Vec<_>>>()?;
+        let addrs = listeners.iter().map(TcpListener::local_addr).collect::<io::Result<Vec<_>>>()?;
+        let server = Arc::new(self);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let threads = listeners
+            .into_iter()
+            .map(|listener| {
+                let (server, stopped) = (Arc::clone(&server), Arc::clone(&stopped));
+                thread::spawn(move || server.accept(&listener, &stopped))
+            })
+            .collect();
+        Ok(Listeners { addrs, stopped, threads })
+    }
+
+    /// Accepts connections from the listener, handling each on a thread of its own, until
+    /// `stopped` is set.
+    fn accept(self: &Arc<Server>, listener: &TcpListener, stopped: &AtomicBool) {
+        for stream in listener.incoming() {
+            if stopped.load(Ordering::Relaxed) {
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    let active = self.active_connections.fetch_add(1, Ordering::Relaxed);
+                    if self.max_connections.is_some_and(|max| active >= max) {
+                        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+                        debug!("Rejected HTTP connection because {active} are already open");
+                        // Clients expecting a TLS handshake could not read a plain response
+                        if !self.is_tls() {
+                            let _ = stream.set_write_timeout(Some(REJECT_TIMEOUT));
+                            let _ = write_response(&stream, &Response::text(503, "Too many connections\n"), None);
+                        }
+                        continue;
+                    }
+                    let server = Arc::clone(self);
+                    thread::spawn(move || {
+                        server.handle_connection(stream);
+                        server.active_connections.fetch_sub(1, Ordering::Relaxed);
+                    });
+                }
+                Err(err) => warn!("Failed to accept HTTP connection: {err}"),
+            }
+        }
+    }
+
+    /// Dispatches a request to the matching route, adding CORS headers to responses of the JSON
+    /// API for allowed origins.
+    fn dispatch(&self, request: &Request) -> Response {
+        let Some(origin) = self.allowed_origin(request) else {
+            return self.route_request(request);
+        };
+        let response = if request.method == "OPTIONS" && request.header("Access-Control-Request-Method").is_some() {
+            self.preflight(request)
+        } else {
+            self.route_request(request)
+        };
+        response.with_header("Access-Control-Allow-Origin", origin).with_header("Vary", "Origin")
+    }
+
+    /// Dispatches a request to the route matching its method and path, once it is authorized.
+    fn route_request(&self, request: &Request) -> Response {
+        if !self.api_tokens.is_empty() || !self.client_scopes.is_empty() {
+            if let Err(response) = crate::auth::authorize(&self.api_tokens, &self.client_scopes, request) {
+                debug!("Rejected HTTP request from {} for {} with {}", request.remote_addr, request.path, response.status);
+                return response;
+            }
+        }
+        let mut path_matched = false;
+        for route in &self.routes {
+            if route.path == request.path {
+                if route.method == request.method {
+                    return (route.handler)(request);
+                }
+                path_matched = true;
+            }
+        }
+        if path_matched {
+            Response::text(405, "Method Not Allowed\n")
+        } else {
+            Response::not_found()
+        }
+    }
+
+    /// Returns the value of `Access-Control-Allow-Origin` for a request to the JSON API from an
+    /// allowed origin, or `None` if no CORS headers should be added.
+    fn allowed_origin<'a>(&'a self, request: &'a Request) -> Option<&'a str> {
+        if !request.path.starts_with(API_PREFIX) {
+            return None;
+        }
+        let origin = request.header("Origin")?;
+        self.cors_origins
+            .iter()
+            .find(|allowed| *allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+            .map(|allowed| if allowed == "*" { "*" } else { origin })
+    }
+
+    /// Answers a CORS preflight request with the methods served at its path.
+    fn preflight(&self, request: &Request) -> Response {
+        let methods: Vec<&str> = self.routes().filter(|(_, path)| *path == request.path).map(|(method, _)| method).collect();
+        if methods.is_empty() {
+            return Response::not_found();
+        }
+        Response {
+            status: 204,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+        .with_header("Access-Control-Allow-Methods", &[methods.as_slice(), &["OPTIONS"]].concat().join(", "))
+        .with_header("Access-Control-Allow-Headers", "Authorization, Content-Type, X-Api-Token")
+        .with_header("Access-Control-Max-Age", &CORS_MAX_AGE.to_string())
+    }
+
+    /// Serves a connection, once the TLS handshake is completed when serving HTTPS.
+    fn handle_connection(&self, stream: TcpStream) {
+        let Ok(remote_addr) = stream.peer_addr() else {
+            return;
+        };
+        if let Err(err) = stream.set_write_timeout(Some(self.write_timeout.unwrap_or(WRITE_TIMEOUT))) {
+            warn!("Failed to set HTTP write timeout: {err}");
+        }
+        #[cfg(feature = "tls")]
+        if let Some(config) = &self.tls {
+            let _ = stream.set_read_timeout(Some(self.read_timeout.unwrap_or(READ_TIMEOUT)));
+            let mut tls = match crate::tls::accept(config.current(), &stream) {
+                Ok(tls) => tls,
+                Err(err) => {
+                    debug!("TLS handshake with {remote_addr} failed: {err}");
+                    return;
+                }
+            };
+            let client_cn = crate::tls::client_common_name(&tls.conn);
+            if let Some(cn) = &client_cn {
+                debug!("HTTPS client {remote_addr} presented a certificate for {cn}");
+            }
+            self.serve(&stream, &mut tls, remote_addr, client_cn.as_deref());
+            tls.conn.send_close_notify();
+            let _ = tls.flush();
+            return;
+        }
+        self.serve(&stream, &stream, remote_addr, None);
+    }
+
+    /// Reads requests from the connection and writes their responses, until the client or the
+    /// server closes it, or it stays idle for longer than the idle timeout. Timeouts are set on
+    /// `socket`, while requests are read from and responses written to `stream`.
+    fn serve(&self, socket: &TcpStream, stream: impl Read + Write, remote_addr: SocketAddr, client_cn: Option<&str>) {
+        let read_timeout = self.read_timeout.unwrap_or(READ_TIMEOUT);
+        let mut reader = BufReader::new(stream);
+        let mut idle = false;
+        loop {
+            if idle {
+                // Wait for the next request for up to the idle timeout, closing the connection if
+                // none arrives
+                let _ = socket.set_read_timeout(self.idle_timeout);
+                if !reader.fill_buf().is_ok_and(|buffered| !buffered.is_empty()) {
+                    return;
+                }
+            }
+            if self.rate_limiter.as_ref().is_some_and(|limiter| !limiter.allow(remote_addr.ip(), Instant::now())) {
+                debug!("Rate limited HTTP request from {remote_addr}");
+                let response = Response::text(429, "Too Many Requests\n").with_header("Retry-After", "60");
+                self.count(None, response.status);
+                let _ = write_response(reader.get_mut(), &response, None);
+                return;
+            }
+            if let Err(err) = socket.set_read_timeout(Some(read_timeout)) {
+                warn!("Failed to set HTTP read timeout: {err}");
+            }
+            let max_header_bytes = self.max_header_bytes.unwrap_or(MAX_HEADER_BYTES);
+            let mut request = match read_request(&mut reader, remote_addr, max_header_bytes) {
+                Ok(request) => request,
+                Err(err) => {
+                    debug!("Invalid HTTP request from {remote_addr}: {err}");
+                    let response = Response::text(400, "Bad Request\n");
+                    self.count(None, response.status);
+                    let _ = write_response(reader.get_mut(), &response, None);
+                    return;
+                }
+            };
+            request.client_cn = client_cn.map(String::from);
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("request", method = %request.method, path = %request.path, %remote_addr).entered();
+            let start = Instant::now();
+            let response = self.dispatch(&request);
+            let closing = request.header("Connection").is_some_and(|value| value.eq_ignore_ascii_case("close"));
+            let keep_alive = self.idle_timeout.filter(|_| !closing);
+            if let Err(err) = write_response(reader.get_mut(), &response, keep_alive) {
+                debug!("Failed to write HTTP response to {remote_addr}: {err}");
+                return;
+            }
+            self.count(Some(&request.path), response.status);
+            if let Some(level) = self.access_log {
+                let millis = start.elapsed().as_secs_f64() * 1000.0;
+                let client = client_cn.map(|cn| format!(" CN={cn}")).unwrap_or_default();
+                log::log!(level, "{remote_addr}{client} \"{} {}\" {} {millis:.1}ms", request.method, request.path, response.status);
+            }
+            if keep_alive.is_none() {
+                return;
+            }
+            idle = true;
+        }
+    }
+
+    /// Counts a response to a request for `path`, or to a request that could not be read.
+    fn count(&self, path: Option<&str>, status: u16) {
+        let Some(requests) = &self.requests else {
+            return;
+        };
+        let path = path.filter(|path| self.routes.iter().any(|route| route.path == *path)).unwrap_or("other");
+        requests.with_label_values(&[path, &status.to_string()]).inc();
+    }
+}
+
+/// The listeners of a started [`Server`], which stop accepting connections and release their
+/// addresses when dropped. Connections already accepted are served until they are closed.
+#[derive(Debug)]
+#[must_use = "the server stops listening when its listeners are dropped"]
+pub struct Listeners {
+    addrs: Vec<SocketAddr>,
+    stopped: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Listeners {
+    /// Returns the addresses the server is listening on, with the port picked by the system for
+    /// addresses bound to port 0.
+    #[must_use]
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+}
+
+impl Drop for Listeners {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // Accepting blocks until a connection comes in, so connect to every listener to wake it
+        for (addr, thread) in self.addrs.iter().zip(self.threads.drain(..)) {
+            let ip = match addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
+                IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(std::net::Ipv6Addr::LOCALHOST),
+                ip => ip,
+            };
+            match TcpStream::connect_timeout(&SocketAddr::new(ip, addr.port()), WAKE_TIMEOUT) {
+                Ok(_) => {
+                    let _ = thread.join();
+                }
+                Err(err) => warn!("Failed to stop the HTTP listener on {addr}: {err}"),
+            }
+        }
+    }
+}
+
+/// Resolves the IP address or host name to bind to, which may be an IPv6 address in brackets, to
+/// the distinct addresses it stands for.
+///
+/// # Errors
+///
+/// An error will be returned if the host name cannot be resolved.
+pub fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    let host = host.trim();
+    let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for addr in (host, port).to_socket_addrs()? {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{host} did not resolve to any address")));
+    }
+    Ok(addrs)
+}
+
+/// Binds a listener to the given address. Listeners on IPv6 addresses also accept IPv4
+/// connections, whatever the default of the system, so `::` serves both families.
+///
+/// # Errors
+///
+/// An error will be returned if the listener cannot bind to the address.
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    // Like the standard library, allow restarting while old connections linger in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+/// Binds a listener like [`bind`], retrying up to `retries` times with a growing delay while the
+/// address is in use. Other errors, such as a missing permission, are returned right away.
+fn bind_retrying(addr: SocketAddr, retries: u32) -> io::Result<TcpListener> {
+    let mut delay = BIND_RETRY_DELAY;
+    for retry in 1..=retries {
+        match bind(addr) {
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+                warn!("Address {addr} is already in use, retrying in {delay:?} ({retry}/{retries})");
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_BIND_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+    bind(addr)
+}
+
+/// Serves all metrics from the default Prometheus registry, in the OpenMetrics text format if the
+/// scraper asks for it in its `Accept` header, and in the Prometheus text format otherwise.
+#[must_use]
+pub fn metrics(request: &Request) -> Response {
+    serve_metrics(request, prometheus::default_registry(), None, &[], &crate::naming::Renamer::default())
+}
+
+/// Like [`metrics`], but serves the metrics of the given registry, stamps the samples of the UPS with the time of the poll they were read in
+/// when serving the OpenMetrics text format, renames the gauges of UPS variables with `renamer`,
+/// and adds the given labels to every metric.
+#[must_use]
+pub fn serve_metrics(
+    request: &Request,
+    registry: &Registry,
+    poll_time: Option<SystemTime>,
+    labels: &[(String, String)],
+    renamer: &crate::naming::Renamer,
+) -> Response {
+    let mut families = registry.gather();
+    renamer.rename(&mut families);
+    add_labels(&mut families, labels);
+    if request.header("accept").is_some_and(crate::openmetrics::accepts) {
+        let encoded = crate::openmetrics::encode(&families, poll_time);
+        return Response::new(200, crate::openmetrics::CONTENT_TYPE, encoded);
+    }
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(err) = encoder.encode(&families, &mut buffer) {
+        warn!("Failed to encode metrics: {err}");
+        return Response::text(500, "Failed to encode metrics\n");
+    }
+    Response::new(200, encoder.format_type(), buffer)
+}
+
+/// Answers a readiness check, which fails once no poll has succeeded for longer than
+/// `max_staleness`, if set. Until the first poll, the time since `started` counts as staleness.
+#[must_use]
+pub fn serve_ready(poll_time: Option<SystemTime>, started: SystemTime, max_staleness: Option<Duration>, now: SystemTime) -> Response {
+    let Some(max_staleness) = max_staleness else {
+        return Response::text(200, "ready\n");
+    };
+    let staleness = now.duration_since(poll_time.unwrap_or(started)).unwrap_or_default();
+    if staleness > max_staleness {
+        let reason = match poll_time {
+            Some(_) => format!("no successful poll for {}s\n", staleness.as_secs()),
+            None => String::from("no successful poll yet\n"),
+        };
+        return Response::text(503, &reason);
+    }
+    Response::text(200, "ready\n")
+}
+
+/// Adds labels to every metric of the families that does not have a label of the same name.
+fn add_labels(families: &mut [MetricFamily], labels: &[(String, String)]) {
+    for metric in families.iter_mut().flat_map(|family| family.mut_metric().iter_mut()) {
+        for (name, value) in labels {
+            if metric.get_label().iter().any(|label| label.get_name() == name) {
+                continue;
+            }
+            let mut label = LabelPair::default();
+            label.set_name(name.clone());
+            label.set_value(value.clone());
+            metric.mut_label().push(label);
+        }
+        metric.mut_label().sort_by(|a, b| a.get_name().cmp(b.get_name()));
+    }
+}
+
+/// Parses an HTTP/1.x request from a stream, failing if the request line and headers are larger
+/// than `max_header_bytes`. HTTP/1.0 requests without a `Connection` header are given
+/// `Connection: close`, since those clients do not expect the connection to be kept open.
+fn read_request(reader: &mut impl BufRead, remote_addr: SocketAddr, max_header_bytes: usize) -> io::Result<Request> {
+    // Limit how much of the stream is read while looking for the end of the headers
+    let mut head = reader.take(max_header_bytes as u64);
+    let request_line = read_header_line(&mut head)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid_data("malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(invalid_data("unsupported HTTP version"));
+    }
+
+    let mut headers = HashMap::new();
+    loop {
+        let line = read_header_line(&mut head)?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(invalid_data("malformed header"));
+        };
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+    if version == "HTTP/1.0" {
+        headers.entry(String::from("connection")).or_insert_with(|| String::from("close"));
+    }
+    let reader = head.into_inner();
+
+    let content_length = match headers.get("content-length") {
+        Some(length) => length.parse::<usize>().map_err(|_| invalid_data("invalid content length"))?,
+        None => 0,
+    };
+    if content_length > MAX_BODY_BYTES {
+        return Err(invalid_data("body too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Ok(Request {
+        method: method.to_string(),
+        path: percent_decode(path),
+        query: parse_query(query),
+        headers,
+        body,
+        remote_addr,
+        client_cn: None,
+    })
+}
+
+/// Reads a single CRLF or LF terminated line, failing if the line is incomplete.
+fn read_header_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(invalid_data("headers too large or incomplete"));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+/// Writes a response to a stream, telling the client the connection will be kept open for the
+/// given idle timeout, or otherwise closed afterwards.
+fn write_response(mut stream: impl Write, response: &Response, keep_alive: Option<Duration>) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason_phrase(response.status));
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    match keep_alive {
+        Some(idle_timeout) => {
+            head.push_str(&format!("Connection: keep-alive\r\nKeep-Alive: timeout={}\r\n\r\n", idle_timeout.as_secs()));
+        }
+        None => head.push_str("Connection: close\r\n\r\n"),
+    }
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// Parses a query string into a map of decoded keys and values.
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Decodes a percent-encoded URL component, treating `+` as a space.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns the standard reason phrase for the status codes used by pistachio.
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Decodes standard base64 with optional padding, returning `None` if the input is invalid.
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in input.trim_end_matches('=').bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+/// Creates an error for a malformed request.
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_request_with_query() {
+        let raw = b"GET /api/v1/history?var=battery.charge&since=10 HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let request = read_request(&mut &raw[..], addr, MAX_HEADER_BYTES).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/api/v1/history");
+        assert_eq!(request.query_param("var"), Some("battery.charge"));
+        assert_eq!(request.query_param("since"), Some("10"));
+        assert_eq!(request.header("HOST"), Some("localhost"));
+    }
+
+    #[test]
+    fn decode_percent_encoding() {
+        assert_eq!(percent_decode("ups%2Estatus"), "ups.status");
+        assert_eq!(percent_decode("a+b"), "a b");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn dispatch_unknown_routes() {
+        let server = Server::new().route("GET", "/metrics", metrics);
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let raw = b"POST /metrics HTTP/1.1\r\n\r\n";
+        let request = read_request(&mut &raw[..], addr, MAX_HEADER_BYTES).unwrap();
+        assert_eq!(server.dispatch(&request).status, 405);
+        let raw = b"GET /missing HTTP/1.1\r\n\r\n";
+        let request = read_request(&mut &raw[..], addr, MAX_HEADER_BYTES).unwrap();
+        assert_eq!(server.dispatch(&request).status, 404);
+    }
+
+    #[test]
+    fn limit_header_size() {
+        let raw = format!("GET /metrics HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(100));
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        assert!(read_request(&mut raw.as_bytes(), addr, 256).is_ok());
+        assert_eq!(read_request(&mut raw.as_bytes(), addr, 64).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let request = read_request(&mut &b"GET /metrics HTTP/1.0\r\n\r\n"[..], addr, 64).unwrap();
+        assert_eq!(request.header("Connection"), Some("close"));
+    }
+
+    #[test]
+    fn keep_connections_alive() {
+        let server = Server::new().route("GET", "/metrics", metrics).keep_alive(Duration::from_secs(1));
+        let listeners = server.start(&[SocketAddr::from(([127, 0, 0, 1], 0))]).unwrap();
+        let addr = listeners.local_addrs()[0];
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        for connection in ["keep-alive", "close"] {
+            write!(stream, "GET /metrics HTTP/1.1\r\nConnection: {connection}\r\n\r\n").unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "HTTP/1.1 200 OK\r\n");
+            let mut length = 0;
+            let mut headers = Vec::new();
+            loop {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+                headers.push(line.trim_end().to_string());
+            }
+            assert!(headers.contains(&format!("Connection: {connection}")));
+            reader.read_exact(&mut vec![0; length]).unwrap();
+        }
+        assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn stop_listening() {
+        let listeners = Server::new().route("GET", "/metrics", metrics).start(&[SocketAddr::from(([127, 0, 0, 1], 0))]).unwrap();
+        let addr = listeners.local_addrs()[0];
+        TcpStream::connect(addr).unwrap();
+        drop(listeners);
+        assert!(TcpStream::connect(addr).is_err());
+        assert!(bind(addr).is_ok());
+    }
+
+    #[test]
+    fn dispatch_cors_requests() {
+        let ok = |_: &Request| Response::json(200, &serde_json::json!({}));
+        let server = Server::new()
+            .route("GET", "/metrics", metrics)
+            .route("GET", "/api/v1/variables", ok)
+            .route("POST", "/api/v1/variable", ok)
+            .cors(vec![String::from("https://ups.example.com")]);
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let dispatch = |raw: &[u8]| server.dispatch(&read_request(&mut &raw[..], addr, MAX_HEADER_BYTES).unwrap());
+        let header = |response: &Response, name: &str| {
+            response.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.clone())
+        };
+        let response = dispatch(b"GET /api/v1/variables HTTP/1.1\r\nOrigin: https://ups.example.com\r\n\r\n");
+        assert_eq!(response.status, 200);
+        assert_eq!(header(&response, "Access-Control-Allow-Origin").as_deref(), Some("https://ups.example.com"));
+        let response = dispatch(
+            b"OPTIONS /api/v1/variable HTTP/1.1\r\nOrigin: https://ups.example.com\r\nAccess-Control-Request-Method: POST\r\n\r\n",
+        );
+        assert_eq!(response.status, 204);
+        assert_eq!(header(&response, "Access-Control-Allow-Methods").as_deref(), Some("POST, OPTIONS"));
+        let response = dispatch(b"GET /api/v1/variables HTTP/1.1\r\nOrigin: https://evil.example.com\r\n\r\n");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+        let response = dispatch(b"GET /metrics HTTP/1.1\r\nOrigin: https://ups.example.com\r\n\r\n");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+    }
+
+    #[test]
+    fn dispatch_authorized_requests() {
+        let ok = |_: &Request| Response::json(200, &serde_json::json!({}));
+        let server = Server::new()
+            .route("GET", "/metrics", metrics)
+            .route("POST", "/api/v1/variable", ok)
+            .cors(vec![String::from("*")])
+            .api_tokens(vec!["scraper-0123456789=metrics:read".parse().unwrap()]);
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let dispatch = |raw: &[u8]| server.dispatch(&read_request(&mut &raw[..], addr, MAX_HEADER_BYTES).unwrap()).status;
+        assert_eq!(dispatch(b"GET /metrics HTTP/1.1\r\n\r\n"), 401);
+        assert_eq!(dispatch(b"GET /metrics HTTP/1.1\r\nAuthorization: Bearer scraper-0123456789\r\n\r\n"), 200);
+        assert_eq!(dispatch(b"POST /api/v1/variable HTTP/1.1\r\nX-Api-Token: scraper-0123456789\r\n\r\n"), 403);
+        // Browsers send preflight requests without credentials
+        let preflight = b"OPTIONS /api/v1/variable HTTP/1.1\r\nOrigin: https://ups.example.com\r\nAccess-Control-Request-Method: POST\r\n\r\n";
+        assert_eq!(dispatch(preflight), 204);
+    }
+
+    #[test]
+    fn bind_dual_stack() {
+        assert_eq!(resolve("[::1]", 9120).unwrap(), vec![SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 9120))]);
+        assert_eq!(resolve("0.0.0.0", 9120).unwrap(), vec![SocketAddr::from(([0, 0, 0, 0], 9120))]);
+        assert!(!resolve("localhost", 9120).unwrap().is_empty());
+
+        // Skip where IPv6 is disabled
+        let Ok(listener) = bind(SocketAddr::from(([0; 8], 0))) else {
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        assert!(TcpStream::connect((IpAddr::from([127, 0, 0, 1]), port)).is_ok());
+    }
+
+    #[test]
+    fn retry_binding_address_in_use() {
+        let previous = bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = previous.local_addr().unwrap();
+        assert_eq!(bind_retrying(addr, 0).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        let releasing = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            drop(previous);
+        });
+        assert_eq!(bind_retrying(addr, 2).unwrap().local_addr().unwrap(), addr);
+        releasing.join().unwrap();
+    }
+
+    #[test]
+    fn ready_while_fresh() {
+        let started = SystemTime::UNIX_EPOCH;
+        let at = |secs| started + Duration::from_secs(secs);
+        let max = Some(Duration::from_secs(30));
+        assert_eq!(serve_ready(None, started, None, at(600)).status, 200);
+        assert_eq!(serve_ready(None, started, max, at(10)).status, 200);
+        assert_eq!(serve_ready(None, started, max, at(31)).status, 503);
+        assert_eq!(serve_ready(Some(at(20)), started, max, at(50)).status, 200);
+        let stale = serve_ready(Some(at(20)), started, max, at(51));
+        assert_eq!((stale.status, stale.body.as_slice()), (503, &b"no successful poll for 31s\n"[..]));
+    }
+
+    #[test]
+    fn serve_metrics_of_registry() {
+        let registry = Registry::new();
+        let gauge = prometheus::Gauge::new("ups_embedded_only", "Only in the given registry").unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        let raw = b"GET /metrics HTTP/1.1\r\n\r\n";
+        let request = read_request(&mut &raw[..], SocketAddr::from(([127, 0, 0, 1], 1234)), MAX_HEADER_BYTES).unwrap();
+        let response = serve_metrics(&request, &registry, None, &[], &crate::naming::Renamer::default());
+        assert!(String::from_utf8(response.body).unwrap().contains("ups_embedded_only 0"));
+        assert!(!String::from_utf8(metrics(&request).body).unwrap().contains("ups_embedded_only"));
+    }
+
+    #[test]
+    fn add_host_label() {
+        let registry = prometheus::Registry::new();
+        let gauges = prometheus::GaugeVec::new(prometheus::Opts::new("ups_status", "Status"), &["status"]).unwrap();
+        registry.register(Box::new(gauges.clone())).unwrap();
+        gauges.with_label_values(&["OL"]).set(1.0);
+        let mut families = registry.gather();
+        add_labels(&mut families, &[(String::from("host"), String::from("rack1")), (String::from("status"), String::from("x"))]);
+        let labels: Vec<(&str, &str)> = families[0].get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|label| (label.get_name(), label.get_value()))
+            .collect();
+        assert_eq!(labels, vec![("host", "rack1"), ("status", "OL")]);
+    }
+
+    #[test]
+    fn count_requests() {
+        let counter = IntCounterVec::new(prometheus::Opts::new("requests_total", "Requests"), &["path", "code"]).unwrap();
+        let server = Server::new().route("GET", "/metrics", metrics).count_requests(counter.clone());
+        server.count(Some("/metrics"), 200);
+        server.count(Some("/metrics"), 200);
+        server.count(Some("/missing"), 404);
+        server.count(None, 400);
+        assert_eq!(counter.with_label_values(&["/metrics", "200"]).get(), 2);
+        assert_eq!(counter.with_label_values(&["other", "404"]).get(), 1);
+        assert_eq!(counter.with_label_values(&["other", "400"]).get(), 1);
+    }
+
+    #[test]
+    fn limit_request_rate() {
+        let limiter = RateLimiter::new(2);
+        let first = IpAddr::from([10, 0, 0, 1]);
+        let second = IpAddr::from([10, 0, 0, 2]);
+        let start = Instant::now();
+        assert!(limiter.allow(first, start));
+        assert!(limiter.allow(first, start));
+        assert!(!limiter.allow(first, start));
+        assert!(limiter.allow(second, start));
+        // A token is refilled every 30 seconds
+        assert!(!limiter.allow(first, start + Duration::from_secs(29)));
+        assert!(limiter.allow(first, start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn parse_basic_auth() {
+        let raw = b"POST /api/v1/command HTTP/1.1\r\nAuthorization: Basic YWRtaW46c2VjcmV0\r\n\r\n";
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let request = read_request(&mut &raw[..], addr, MAX_HEADER_BYTES).unwrap();
+        assert_eq!(request.basic_auth(), Some((String::from("admin"), String::from("secret"))));
+        assert_eq!(decode_base64("YQ=="), Some(b"a".to_vec()));
+        assert_eq!(decode_base64("not base64!"), None);
+    }
+}
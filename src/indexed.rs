@@ -0,0 +1,145 @@
+//! Detection of variables that only differ in an index, such as the phase in `input.L1.voltage`
+//! or the battery pack in `battery.1.voltage`, so they can be exported as one metric with the index as a
+//! label.
+
+/// A variable name split into the name it shares with the other variables of its kind and the
+/// index that tells them apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexedName {
+    /// The variable name without the index, such as `input.voltage`.
+    pub name: String,
+    /// The name of the label holding the index, such as `phase`.
+    pub label: &'static str,
+    /// The index, such as `L1`.
+    pub index: String,
+}
+
+/// Where the index of a variable appears in its name.
+#[derive(Debug, Clone, Copy)]
+enum Position {
+    /// Right after the given segments, such as the `1` in `outlet.1.load`.
+    After(&'static [&'static str]),
+    /// In any segment but the first and last, such as the `L1` in `input.L1.voltage`.
+    Anywhere,
+}
+
+/// A kind of index found in variable names, and the label it is exported as.
+#[derive(Debug, Clone, Copy)]
+struct IndexPattern {
+    label: &'static str,
+    position: Position,
+    is_index: fn(&str) -> bool,
+}
+
+/// Every kind of index, in the order they are tried. Only the first match is split out of a name.
+const PATTERNS: &[IndexPattern] = &[
+    IndexPattern {
+        label: "group",
+        position: Position::After(&["outlet", "group"]),
+        is_index: is_number,
+    },
+    IndexPattern {
+        label: "outlet",
+        position: Position::After(&["outlet"]),
+        is_index: is_number,
+    },
+    IndexPattern {
+        label: "pack",
+        position: Position::After(&["battery"]),
+        is_index: is_number,
+    },
+    IndexPattern {
+        label: "phase",
+        position: Position::Anywhere,
+        is_index: is_phase,
+    },
+];
+
+/// Splits the index out of the name of a variable, such as the phase of `input.L1.voltage` or
+/// `output.L2-N.voltage`, the outlet of `outlet.1.status`, the outlet group of
+/// `outlet.group.1.load` or the battery pack of `battery.1.voltage`. Returns `None` if the
+/// variable has no index.
+pub(crate) fn split(var_name: &str) -> Option<IndexedName> {
+    let segments: Vec<&str> = var_name.split('.').collect();
+    let last = segments.len().saturating_sub(1);
+    PATTERNS.iter().find_map(|pattern| {
+        let position = match pattern.position {
+            Position::After(prefix) => Some(prefix.len()).filter(|&i| i < last && segments.starts_with(prefix)),
+            Position::Anywhere => (1..last).find(|&i| (pattern.is_index)(segments[i])),
+        }
+        .filter(|&i| (pattern.is_index)(segments[i]))?;
+        let name = segments
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != position)
+            .map(|(_, segment)| *segment)
+            .collect::<Vec<_>>()
+            .join(".");
+        Some(IndexedName {
+            name,
+            label: pattern.label,
+            index: segments[position].to_string(),
+        })
+    })
+}
+
+/// Returns true if a segment of a variable name is a number, such as the index of an outlet.
+fn is_number(segment: &str) -> bool {
+    !segment.is_empty() && segment.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// Returns true if a segment of a variable name is a phase such as `L1`, or a pair of phases
+/// such as `L1-L2` or `L1-N` between which a voltage is measured.
+fn is_phase(segment: &str) -> bool {
+    let is_line = |part: &str| matches!(part, "L1" | "L2" | "L3");
+    match segment.split_once('-') {
+        Some((first, second)) => is_line(first) && (is_line(second) || second == "N") && first != second,
+        None => is_line(segment),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_phases() {
+        let indexed = split("input.L1.voltage").unwrap();
+        assert_eq!(indexed.name, "input.voltage");
+        assert_eq!(indexed.label, "phase");
+        assert_eq!(indexed.index, "L1");
+        assert_eq!(split("output.L2-N.voltage").unwrap().index, "L2-N");
+        assert_eq!(split("input.bypass.L3-L1.voltage").unwrap().name, "input.bypass.voltage");
+        assert_eq!(split("input.voltage"), None);
+        assert_eq!(split("L1.voltage"), None);
+        assert_eq!(split("input.L1"), None);
+        assert_eq!(split("input.L1-L1.voltage"), None);
+        assert_eq!(split("input.L4.voltage"), None);
+    }
+
+    #[test]
+    fn split_outlets() {
+        let indexed = split("outlet.1.status").unwrap();
+        assert_eq!(indexed.name, "outlet.status");
+        assert_eq!(indexed.label, "outlet");
+        assert_eq!(indexed.index, "1");
+        let indexed = split("outlet.group.2.load").unwrap();
+        assert_eq!(indexed.name, "outlet.group.load");
+        assert_eq!(indexed.label, "group");
+        assert_eq!(indexed.index, "2");
+        assert_eq!(split("outlet.1.delay.shutdown").unwrap().name, "outlet.delay.shutdown");
+        assert_eq!(split("outlet.desc"), None);
+        assert_eq!(split("outlet.1"), None);
+        assert_eq!(split("outlet.group.count"), None);
+    }
+
+    #[test]
+    fn split_battery_packs() {
+        let indexed = split("battery.2.voltage").unwrap();
+        assert_eq!(indexed.name, "battery.voltage");
+        assert_eq!(indexed.label, "pack");
+        assert_eq!(indexed.index, "2");
+        assert_eq!(split("battery.packs"), None);
+        assert_eq!(split("battery.charge.low"), None);
+    }
+}
@@ -0,0 +1,153 @@
+//! Writing of UPS variables to standard output in InfluxDB line protocol, for running pistachio
+//! as a Telegraf `execd` plugin without serving metrics over HTTP.
+//!
+//! A line is written after every poll, which suits the `signal = "none"` setting of the plugin.
+//! Once Telegraf sends a newline on standard input, as it does with `signal = "STDIN"`, lines are
+//! only written in reply to a newline, with the variables of the latest poll.
+
+use crate::sink::Sink;
+use crate::Variable;
+use log::{debug, warn};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the measurement that every line is written with.
+const MEASUREMENT: &str = "ups";
+
+/// The latest line written, and whether Telegraf asks for lines on standard input.
+#[derive(Debug, Default)]
+struct Latest {
+    line: Option<String>,
+    signaled: bool,
+    /// Whether a line was asked for before the first poll, so the first poll writes it.
+    pending: bool,
+}
+
+/// A sink that writes the variables of every poll to standard output as a line of InfluxDB line
+/// protocol, with each variable as a field and the name of the UPS as the `ups` tag.
+#[derive(Debug)]
+pub struct InfluxStdout {
+    ups_name: String,
+    latest: Arc<Mutex<Latest>>,
+}
+
+impl InfluxStdout {
+    /// Creates a sink for the given UPS and starts listening for newlines on standard input.
+    #[must_use]
+    pub fn new(ups_name: &str) -> InfluxStdout {
+        let latest = Arc::new(Mutex::new(Latest::default()));
+        let listener = Arc::clone(&latest);
+        thread::spawn(move || listen(&listener));
+        InfluxStdout {
+            ups_name: ups_name.to_string(),
+            latest,
+        }
+    }
+}
+
+/// Writes the latest line for every newline read from standard input, until it is closed.
+fn listen(latest: &Mutex<Latest>) {
+    for line in io::stdin().lock().lines() {
+        if line.is_err() {
+            break;
+        }
+        let mut latest = latest.lock().unwrap_or_else(PoisonError::into_inner);
+        if !latest.signaled {
+            debug!("Lines will be written to standard output when asked for on standard input");
+            latest.signaled = true;
+        }
+        match &latest.line {
+            Some(line) => {
+                if let Err(err) = write_stdout(line) {
+                    warn!("Failed to write line protocol to standard output: {err}");
+                }
+            }
+            None => latest.pending = true,
+        }
+    }
+}
+
+/// Writes a line to standard output and flushes it, so Telegraf reads it right away.
+fn write_stdout(line: &str) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(line.as_bytes())?;
+    stdout.flush()
+}
+
+/// Encodes the variables of a poll as a line of line protocol, with a timestamp in nanoseconds.
+/// Numeric values are written as float fields and all others as string fields. Returns `None`
+/// if there are no variables, since a line must have at least one field.
+fn encode(ups_name: &str, vars: &[Variable], time: SystemTime) -> Option<String> {
+    if vars.is_empty() {
+        return None;
+    }
+    let fields: Vec<String> = vars
+        .iter()
+        .map(|var| {
+            let value = var.value();
+            let value = match crate::parse_number(value) {
+                Some(number) if number.is_finite() => number.to_string(),
+                _ => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+            };
+            format!("{}={value}", escape(var.name()))
+        })
+        .collect();
+    let nanos = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    Some(format!("{MEASUREMENT},ups={} {} {nanos}\n", escape(ups_name), fields.join(",")))
+}
+
+/// Escapes the commas, equals signs, and spaces of a tag value or field key.
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, ',' | '=' | ' ' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Sink for InfluxStdout {
+    fn name(&self) -> &str {
+        "influx-stdout"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let Some(line) = encode(&self.ups_name, vars, SystemTime::now()) else {
+            return Ok(());
+        };
+        let mut latest = self.latest.lock().unwrap_or_else(PoisonError::into_inner);
+        let write = !latest.signaled || latest.pending;
+        latest.pending = false;
+        let line = latest.line.insert(line);
+        if write {
+            write_stdout(line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn encode_line_protocol() {
+        let vars = [
+            Variable::new("battery.charge", String::from("87")),
+            Variable::new("ups.status", String::from("OL CHRG")),
+            Variable::new("ups.mfr", String::from(r#"Say "hi""#)),
+        ];
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            encode("rack ups", &vars, time).unwrap(),
+            "ups,ups=rack\\ ups battery.charge=87,ups.status=\"OL CHRG\",ups.mfr=\"Say \\\"hi\\\"\" 1700000000000000000\n"
+        );
+        assert_eq!(encode("ups", &[], time), None);
+    }
+}
@@ -0,0 +1,246 @@
+//! A journal of recent events, such as the UPS going on battery and coming back, served at
+//! `GET /api/v1/events` and optionally saved to a file so it survives restarts.
+
+use crate::events::Event;
+use crate::http::{Request, Response};
+use crate::sink::Sink;
+use crate::status::UpsStatus;
+use log::{debug, warn};
+use prometheus::core::{AtomicF64, GenericCounter};
+use prometheus::{register_counter_with_registry, register_gauge_with_registry, Gauge, Registry};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// A sink that keeps the most recent events in a ring buffer, and summarizes outages in metrics.
+///
+/// Every event except changes of ordinary variables is journaled, as the JSON object described
+/// in [`Event::to_json`]. The event that ends an outage also has an `outage_seconds` field with
+/// the duration of the outage.
+#[derive(Debug)]
+pub struct Journal {
+    ups_name: String,
+    capacity: usize,
+    path: Option<PathBuf>,
+    entries: Arc<Mutex<VecDeque<Value>>>,
+    outage_start: Option<SystemTime>,
+    outages: GenericCounter<AtomicF64>,
+    last_outage_duration: Gauge,
+}
+
+impl Journal {
+    /// Creates a journal holding up to `capacity` events. If a path is given, the journal is
+    /// restored from it and saved to it after every event.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the metrics cannot be registered with Prometheus.
+    pub fn new(ups_name: &str, capacity: usize, path: Option<&Path>, registry: &Registry) -> crate::Result<Journal> {
+        let outages = register_counter_with_registry!("ups_outages_total", "Number of times the UPS has gone on battery", registry)?;
+        let last_outage_duration = register_gauge_with_registry!(
+            "ups_last_outage_duration_seconds",
+            "Duration of the last outage that has ended",
+            registry
+        )?;
+        let mut entries = VecDeque::with_capacity(capacity);
+        if let Some(path) = path.filter(|path| path.exists()) {
+            match load(path) {
+                Ok(loaded) => {
+                    entries.extend(loaded.into_iter().rev().take(capacity).rev());
+                    debug!("Restored {} events from {}", entries.len(), path.display());
+                }
+                Err(err) => warn!("Could not load journal from {}, starting fresh: {err}", path.display()),
+            }
+        }
+        Ok(Journal {
+            ups_name: ups_name.to_string(),
+            capacity,
+            path: path.map(Path::to_path_buf),
+            entries: Arc::new(Mutex::new(entries)),
+            outage_start: None,
+            outages,
+            last_outage_duration,
+        })
+    }
+
+    /// Returns a handle for serving the journal over HTTP.
+    #[must_use]
+    pub fn reader(&self) -> JournalReader {
+        JournalReader {
+            ups_name: self.ups_name.clone(),
+            entries: Arc::clone(&self.entries),
+        }
+    }
+
+    /// Returns the counter of outages, so it can be saved with the other counters.
+    #[must_use]
+    pub fn outages(&self) -> GenericCounter<AtomicF64> {
+        self.outages.clone()
+    }
+
+    /// Journals an event that happened at `time`.
+    fn record(&mut self, event: &Event, time: SystemTime) {
+        if matches!(event, Event::VariableChanged { .. }) {
+            return;
+        }
+        let mut entry = event.to_json(&self.ups_name, time);
+        if let Event::StatusChanged { previous, current } = event {
+            let was_on_battery = previous.as_deref().is_some_and(|status| UpsStatus::parse(status).is_on_battery());
+            let is_on_battery = UpsStatus::parse(current).is_on_battery();
+            if is_on_battery && !was_on_battery {
+                self.outage_start = Some(time);
+                self.outages.inc();
+            } else if was_on_battery && !is_on_battery {
+                if let Some(start) = self.outage_start.take() {
+                    let duration = time.duration_since(start).unwrap_or_default().as_secs_f64();
+                    self.last_outage_duration.set(duration);
+                    entry["outage_seconds"] = json!(duration);
+                }
+            }
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+        if let Some(path) = &self.path {
+            if let Err(err) = save(path, &entries) {
+                warn!("Failed to save journal to {}: {err}", path.display());
+            }
+        }
+    }
+}
+
+impl Sink for Journal {
+    fn name(&self) -> &str {
+        "journal"
+    }
+
+    fn event(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        self.record(event, SystemTime::now());
+        Ok(())
+    }
+}
+
+/// A handle for reading the journal, which can be shared with the HTTP server.
+#[derive(Debug, Clone)]
+pub struct JournalReader {
+    ups_name: String,
+    entries: Arc<Mutex<VecDeque<Value>>>,
+}
+
+impl JournalReader {
+    /// Handles a request for the journal, oldest event first. The `type` query parameter keeps
+    /// only events of one kind, such as `status_changed`, and `limit` keeps only the most recent
+    /// events.
+    #[must_use]
+    pub fn handle(&self, request: &Request) -> Response {
+        let limit = match request.query_param("limit").map(str::parse::<usize>) {
+            Some(Ok(limit)) => limit,
+            Some(Err(_)) => return Response::text(400, "Invalid value for limit\n"),
+            None => usize::MAX,
+        };
+        let kind = request.query_param("type");
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut events: Vec<&Value> = entries
+            .iter()
+            .rev()
+            .filter(|entry| kind.is_none_or(|kind| entry["type"] == kind))
+            .take(limit)
+            .collect();
+        events.reverse();
+        Response::json(200, &json!({ "ups": self.ups_name, "events": events }))
+    }
+}
+
+/// Reads a journal file of one JSON object per line.
+fn load(path: &Path) -> io::Result<Vec<Value>> {
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)))
+        .collect()
+}
+
+/// Writes a journal file, replacing it atomically so a crash can never leave it half written.
+fn save(path: &Path, entries: &VecDeque<Value>) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_os_string();
+    temp_path.push(".tmp");
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&entry.to_string());
+        contents.push('\n');
+    }
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn request(query: &[(&str, &str)]) -> Request {
+        Request {
+            method: String::from("GET"),
+            path: String::from("/api/v1/events"),
+            query: query.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            headers: std::collections::HashMap::new(),
+            body: Vec::new(),
+            remote_addr: std::net::SocketAddr::from(([127, 0, 0, 1], 1234)),
+            client_cn: None,
+        }
+    }
+
+    fn status(previous: Option<&str>, current: &str) -> Event {
+        Event::StatusChanged {
+            previous: previous.map(String::from),
+            current: current.to_string(),
+        }
+    }
+
+    #[test]
+    fn journal_outages() {
+        let path = std::env::temp_dir().join(format!("pistachio-journal-{}.jsonl", std::process::id()));
+        let mut journal = Journal::new("ups", 3, Some(&path), &Registry::new()).unwrap();
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(secs);
+        journal.record(&status(None, "OL"), at(0));
+        journal.record(&status(Some("OL"), "OB DISCHRG"), at(100));
+        journal.record(
+            &Event::VariableChanged {
+                name: String::from("battery.charge"),
+                previous: None,
+                current: String::from("90"),
+            },
+            at(110),
+        );
+        journal.record(&Event::ConnectionRestored, at(120));
+        journal.record(&status(Some("OB DISCHRG"), "OL CHRG"), at(190));
+        assert_eq!(journal.outages.get(), 1.0);
+        assert_eq!(journal.last_outage_duration.get(), 90.0);
+
+        // Only the most recent events are kept
+        let reader = journal.reader();
+        let response = reader.handle(&request(&[]));
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        let events = body["events"].as_array().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["current"], "OB DISCHRG");
+        assert_eq!(events[2]["outage_seconds"], 90.0);
+
+        let response = reader.handle(&request(&[("type", "status_changed"), ("limit", "1")]));
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["events"].as_array().unwrap().len(), 1);
+        assert_eq!(body["events"][0]["current"], "OL CHRG");
+
+        // The journal survives a restart
+        assert_eq!(load(&path).unwrap().len(), 3);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,24 +4,140 @@
 //!
 //! Pistachio is a Prometheus exporter written in Rust, designed for monitoring UPS devices using Network UPS Tools (NUT).
 
-use clap::Parser;
-use log::{debug, info, warn};
-use prometheus_exporter::prometheus;
-use prometheus_exporter::prometheus::core::{AtomicF64, GenericGauge, GenericGaugeVec};
-use prometheus_exporter::prometheus::{register_gauge, register_gauge_vec};
-use rups::blocking::Connection;
-use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr};
-use std::thread;
-use std::time::Duration;
+use log::{debug, error, info, warn};
+use prometheus::core::{AtomicF64, Collector, GenericGauge, GenericGaugeVec};
+use prometheus::{Gauge, GaugeVec, IntCounter, Opts, Registry};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "history")]
+pub mod history;
+pub mod alerts;
+pub mod apcupsd;
+pub mod audit;
+pub mod auth;
+mod app;
+#[cfg(feature = "cli")]
+mod cli;
+pub mod client;
+#[cfg(feature = "cloudwatch")]
+pub mod cloudwatch;
+pub mod collectd;
+mod config;
+pub mod connection;
+pub mod control;
+pub mod cost;
+pub mod crash;
+pub mod dates;
+pub mod diff;
+mod error;
+pub mod events;
+pub mod groups;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod ha;
+pub mod health;
+pub mod http;
+mod indexed;
+pub mod influx;
+pub mod journal;
+pub mod logging;
+pub mod metadata;
+pub mod modbus;
+pub mod naming;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod openapi;
+pub mod openmetrics;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod ping;
+pub mod power;
+pub mod predict;
+pub mod record;
+pub mod replay;
+pub mod sampling;
+pub mod shed;
+pub mod shutdown;
+pub mod simulate;
+pub mod sink;
+pub mod snapshot;
+pub mod snmp;
+pub mod state;
+mod status;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+mod time;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod top;
+#[cfg(feature = "ui")]
+pub mod ui;
+#[cfg(feature = "usbhid")]
+pub mod usbhid;
+mod variable;
+pub mod vars;
+pub mod version;
+pub mod watchdog;
+pub mod zabbix;
+
+use events::EventDetector;
+pub use app::{record, replay, run, run_in};
+#[cfg(feature = "cli")]
+pub use cli::{Args, Command, DiffArgs, RecordArgs, ReplayArgs, SimulateArgs, TopArgs};
+pub use client::{Connection, UpsClient};
+pub use config::{AccessLogLevel, Backend, Config, ConfigBuilder, MetricIdleAction, Output, UpsTarget};
+pub use error::{Error, ErrorType, Result};
+pub use events::{Event, EventBus};
+pub use sink::Sink;
+pub use snapshot::{poll, snapshots, UpsSnapshot};
+pub use status::UpsStatus;
+pub use variable::{Variable, VariableDefinition, VariableRange};
 
 /// Default configuration options
 const DEFAULT_UPS_NAME: &str = "ups";
 const DEFAULT_UPS_HOST: &str = "127.0.0.1";
 const DEFAULT_UPS_PORT: u16 = 3493;
-const DEFAULT_BIND_IP: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_BIND_IP: &str = "0.0.0.0";
 const DEFAULT_BIND_PORT: u16 = 9120;
+const DEFAULT_BIND_RETRIES: u32 = 5;
+const DEFAULT_HTTP_READ_TIMEOUT: u64 = 10;
+const DEFAULT_HTTP_WRITE_TIMEOUT: u64 = 10;
+const DEFAULT_HTTP_MAX_HEADER_BYTES: usize = 8 * 1024;
 const DEFAULT_POLL_RATE: u64 = 10;
+const DEFAULT_SAMPLE_INTERVAL: u64 = 1;
+const DEFAULT_METADATA_CONNECTIONS: usize = 4;
+const DEFAULT_POLL_STALL_THRESHOLD: u32 = 3;
+const DEFAULT_FAILURE_GRACE_POLLS: u32 = 0;
+const DEFAULT_SNMP_COMMUNITY: &str = "public";
+const DEFAULT_RECORD_KEEP: usize = 5;
+const DEFAULT_STATE_SAVE_INTERVAL: u64 = 60;
+const DEFAULT_JOURNAL_SIZE: usize = 1000;
+const DEFAULT_BATTERY_EXPECTED_LIFE: u64 = 4;
+const DEFAULT_VOLTAGE_SAG_PERCENT: f64 = 10.0;
+const DEFAULT_VOLTAGE_SWELL_PERCENT: f64 = 10.0;
+const DEFAULT_FREQUENCY_TOLERANCE: f64 = 1.0;
+const DEFAULT_ZABBIX_KEY_TEMPLATE: &str = "pistachio[{var}]";
+#[cfg(feature = "cli")]
+const DEFAULT_SIMULATE_DELAY: u64 = 30;
+#[cfg(feature = "cli")]
+const DEFAULT_TOP_INTERVAL: u64 = 2;
+#[cfg(feature = "nats")]
+const DEFAULT_NATS_SUBJECT: &str = "pistachio.events";
+#[cfg(feature = "otlp")]
+const DEFAULT_OTLP_SERVICE_NAME: &str = "pistachio";
+#[cfg(any(feature = "history", feature = "cloudwatch"))]
+const DEFAULT_KEY_VARS: &[&str] = &["battery.charge", "battery.runtime", "ups.load", "input.voltage", "output.voltage"];
+#[cfg(feature = "history")]
+const DEFAULT_HISTORY_RETENTION: u64 = 168;
+#[cfg(feature = "cloudwatch")]
+const DEFAULT_CLOUDWATCH_NAMESPACE: &str = "Pistachio";
+#[cfg(feature = "cloudwatch")]
+const DEFAULT_CLOUDWATCH_INTERVAL: u64 = 60;
 
 /// An array of possible UPS system states
 const STATUSES: &[&str] = &["OL", "OB", "LB", "RB", "CHRG", "DISCHRG", "ALARM", "OVER", "TRIM", "BOOST", "BYPASS", "OFF", "CAL", "TEST", "FSD"];
@@ -29,35 +145,172 @@ const STATUSES: &[&str] = &["OL", "OB", "LB", "RB", "CHRG", "DISCHRG", "ALARM",
 /// An array of possible UPS beeper states
 const BEEPER_STATUSES: &[&str] = &["enabled", "disabled", "muted"];
 
-/// A collection of arguments to be parsed from the command line or environment.
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-pub struct Args {
-    /// Name of the UPS to monitor. Default is `ups`.
-    #[arg(long, env, default_value_t = String::from(DEFAULT_UPS_NAME))]
-    pub ups_name: String,
-    /// Hostname of the NUT server to monitor. Default is `127.0.0.1`.
-    #[arg(long, env, default_value_t = String::from(DEFAULT_UPS_HOST))]
-    pub ups_host: String,
-    /// Port of the NUT server to monitor. Default is `3493`.
-    #[arg(long, env, default_value_t = DEFAULT_UPS_PORT)]
-    pub ups_port: u16,
-    /// IP address on which the exporter will serve metrics. Default is `0.0.0.0`.
-    #[arg(long, env, default_value_t = DEFAULT_BIND_IP)]
-    pub bind_ip: IpAddr,
-    /// Port on which the exporter will serve metrics. Default is `9120`.
-    #[arg(long, env, default_value_t = DEFAULT_BIND_PORT)]
-    pub bind_port: u16,
-    /// Time in seconds between requests to the NUT server. Must be at least 1 second. Default is `10`.
-    #[arg(long, env, default_value_t = DEFAULT_POLL_RATE, value_parser = clap::value_parser!(u64).range(1..))]
-    pub poll_rate: u64,
+/// An array of possible states of an outlet or outlet group
+const OUTLET_STATUSES: &[&str] = &["on", "off"];
+
+/// A map of basic gauges, keyed by UPS variable name.
+type BasicGauges = HashMap<Arc<str>, GenericGauge<AtomicF64>>;
+
+/// A map of label gauges, keyed by UPS variable name.
+type LabelGauges = HashMap<Arc<str>, LabelGauge>;
+
+/// A map of gauges shared by variables that only differ in an index, keyed by metric name.
+type IndexedFamilies = HashMap<String, IndexedFamily>;
+
+/// The names of the metrics created for variables, mapped to the variable each was created for,
+/// so two variables whose names convert to the same metric name never collide.
+#[derive(Debug, Default)]
+struct MetricNames(HashMap<String, String>);
+
+impl MetricNames {
+    /// Returns the name of the metric of a variable, which is its [`gauge_name`] unless another
+    /// variable already has that name, in which case the first free numbered suffix, such as
+    /// `_2`, is added.
+    fn claim(&mut self, var_name: &str) -> String {
+        let name = gauge_name(var_name);
+        let mut candidate = name.clone();
+        for suffix in 2.. {
+            match self.0.get(&candidate) {
+                Some(owner) if owner == var_name => return candidate,
+                Some(_) => candidate = format!("{name}_{suffix}"),
+                None => break,
+            }
+        }
+        if candidate != name {
+            warn!(
+                "Variable {var_name} converts to metric name {name}, which is taken by variable {}, so it will be exported as {candidate}",
+                self.0[&name]
+            );
+        }
+        self.0.insert(candidate.clone(), var_name.to_string());
+        candidate
+    }
+}
+
+/// A gauge with the index of each variable sharing it as a label, such as `phase`. The handles of
+/// each variable's labeled gauges are stored with the basic or label gauges.
+#[derive(Debug)]
+struct IndexedFamily {
+    vec: GenericGaugeVec<AtomicF64>,
+    label: &'static str,
+    vars: Vec<Arc<str>>,
+}
+
+/// A gauge with a label for each possible state of a UPS variable, along with the handle of each
+/// labeled gauge, which are resolved once so updates never look them up or allocate.
+#[derive(Debug)]
+struct LabelGauge {
+    vec: GenericGaugeVec<AtomicF64>,
+    is_status: bool,
+    states: Vec<LabelState>,
+}
+
+/// One possible state of a [`LabelGauge`].
+#[derive(Debug)]
+struct LabelState {
+    name: Arc<str>,
+    flag: Option<UpsStatus>,
+    gauge: GenericGauge<AtomicF64>,
+}
+
+impl LabelGauge {
+    /// Resolves the handles of all states of a gauge for the given variable. The states of
+    /// `ups.status` are matched as status flags, and those of any other variable as whole words.
+    fn new(gauge_vec: &GenericGaugeVec<AtomicF64>, states: &[impl AsRef<str>], var_name: &str) -> Result<LabelGauge> {
+        LabelGauge::with_index(gauge_vec, states, var_name, None)
+    }
+
+    /// Like [`LabelGauge::new`], but for a gauge shared by variables that only differ in an index,
+    /// which is the first label of each state.
+    fn with_index(
+        gauge_vec: &GenericGaugeVec<AtomicF64>,
+        states: &[impl AsRef<str>],
+        var_name: &str,
+        index: Option<&str>,
+    ) -> Result<LabelGauge> {
+        let is_status = var_name == "ups.status";
+        let states = states
+            .iter()
+            .map(|state| {
+                let name = state.as_ref();
+                Ok(LabelState {
+                    name: Arc::from(name),
+                    flag: if is_status { UpsStatus::from_name(name) } else { None },
+                    gauge: match index {
+                        Some(index) => gauge_vec.get_metric_with_label_values(&[index, name])?,
+                        None => gauge_vec.get_metric_with_label_values(&[name])?,
+                    },
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(LabelGauge {
+            vec: gauge_vec.clone(),
+            is_status,
+            states,
+        })
+    }
+
+    /// Sets each labeled gauge to reflect whether its state is present in the current value of
+    /// the variable.
+    fn update(&self, value: &str) {
+        let status = self.is_status.then(|| UpsStatus::parse(value));
+        for state in &self.states {
+            let is_set = match status {
+                Some(status) => state.flag.is_some_and(|flag| status.contains(flag)),
+                None => value == &*state.name || value.split_whitespace().any(|word| word == &*state.name),
+            };
+            state.gauge.set(if is_set { 1.0 } else { 0.0 });
+        }
+    }
+
+    /// Sets every labeled gauge to zero.
+    fn reset(&self) {
+        for state in &self.states {
+            state.gauge.set(0.0);
+        }
+    }
+
+    /// Sets every labeled gauge to NaN.
+    fn blank(&self) {
+        for state in &self.states {
+            state.gauge.set(f64::NAN);
+        }
+    }
 }
 
 /// A collection of all registered Prometheus metrics, mapped to the name of the UPS variable they represent.
+///
+/// Every gauge is created and registered once when the metrics are built, and polls only set the
+/// values of the stored handles, so updating never touches the registry.
 #[derive(Debug)]
 pub struct Metrics {
-    basic_gauges: HashMap<String, GenericGauge<AtomicF64>>,
-    label_gauges: HashMap<String, (GenericGaugeVec<AtomicF64>, &'static [&'static str])>,
+    basic_gauges: BasicGauges,
+    indexed_families: IndexedFamilies,
+    label_gauges: LabelGauges,
+    clients: GenericGauge<AtomicF64>,
+    configured: GenericGauge<AtomicF64>,
+    driver_connected: GenericGauge<AtomicF64>,
+    monitored: GenericGauge<AtomicF64>,
+    commands: GenericGaugeVec<AtomicF64>,
+    status_duration: GenericGaugeVec<AtomicF64>,
+    registered: GenericGauge<AtomicF64>,
+    discovery_runs: IntCounter,
+    names: MetricNames,
+    /// The primary status of the UPS, and when it was first seen.
+    status_since: Mutex<Option<(&'static str, Instant)>>,
+    registry: Registry,
+    expiry: Mutex<Expiry>,
+}
+
+/// A registered collector, along with the names of all variables it exports.
+type SharedCollector = (Box<dyn Collector>, Vec<Arc<str>>);
+
+/// When each variable was last reported, and which gauges have been removed from the registry
+/// because their variable has not been reported for too long.
+#[derive(Debug, Default)]
+struct Expiry {
+    last_seen: HashMap<Arc<str>, Instant>,
+    expired: HashSet<Arc<str>>,
 }
 
 impl Metrics {
@@ -67,14 +320,131 @@ impl Metrics {
     ///
     /// An error will be returned if any of metrics cannot be created and registered with the
     /// Prometheus expoter, such as if two metrics attempt to use the same name.
-    pub fn build(ups_vars: &HashMap<String, (String, String)>) -> Result<Metrics, prometheus::Error> {
-        let basic_gauges = create_basic_gauges(ups_vars)?;
-        let label_gauges = create_label_gauges()?;
+    pub fn build(ups_vars: &HashMap<String, (String, String)>) -> Result<Metrics> {
+        Metrics::build_in(ups_vars, prometheus::default_registry())
+    }
 
-        Ok(Metrics {
+    /// Like [`Metrics::build`], but registers all metrics with the given registry instead of the
+    /// default one, so an application embedding pistachio can keep its metrics separate.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if any of metrics cannot be created and registered with the
+    /// registry, such as if two metrics attempt to use the same name.
+    pub fn build_in(ups_vars: &HashMap<String, (String, String)>, registry: &Registry) -> Result<Metrics> {
+        let mut names = MetricNames::default();
+        let mut label_gauges = create_label_gauges(registry, &mut names)?;
+        let (basic_gauges, mut indexed_families) = create_basic_gauges(ups_vars, registry, &mut names)?;
+        let clients = Gauge::new("ups_clients_connected", "Number of clients logged in to the UPS, such as upsmon")?;
+        registry.register(Box::new(clients.clone()))?;
+        let configured = Gauge::new("ups_configured", "Whether the NUT server knows the UPS, 0 if it answered UNKNOWN-UPS")?;
+        registry.register(Box::new(configured.clone()))?;
+        let driver_connected = Gauge::new(
+            "ups_driver_connected",
+            "Whether the driver of the UPS is connected to the NUT server, 0 if it answered DRIVER-NOT-CONNECTED",
+        )?;
+        registry.register(Box::new(driver_connected.clone()))?;
+        let monitored = Gauge::new("pistachio_monitored_ups", "Number of UPS whose variables are being read, 0 while polls of the UPS fail")?;
+        registry.register(Box::new(monitored.clone()))?;
+        let commands = GaugeVec::new(Opts::new("ups_command_supported", "Instant commands supported by the UPS"), &["command"])?;
+        registry.register(Box::new(commands.clone()))?;
+        let status_duration = GaugeVec::new(
+            Opts::new("ups_status_duration_seconds", "Time the UPS has held its current primary status, such as OB"),
+            &["status"],
+        )?;
+        registry.register(Box::new(status_duration.clone()))?;
+        let registered = Gauge::new("pistachio_registered_gauges", "Number of gauges of UPS variables currently exported")?;
+        registry.register(Box::new(registered.clone()))?;
+        let discovery_runs = IntCounter::new("pistachio_discovery_runs_total", "Number of times the variables of the UPS have been discovered")?;
+        registry.register(Box::new(discovery_runs.clone()))?;
+        let mut vars: Vec<_> = ups_vars.iter().collect();
+        vars.sort_by_key(|(name, _)| *name);
+        for (raw_name, (_, description)) in vars {
+            if let Some(states) = known_states(raw_name).filter(|_| !basic_gauges.contains_key(raw_name.as_str())) {
+                let states = IndexedStates { states, label: "status" };
+                let families = (&mut indexed_families, &mut names);
+                create_indexed_label_gauge(raw_name, description, states, registry, &mut label_gauges, families)?;
+            }
+        }
+
+        let metrics = Metrics {
             basic_gauges,
+            indexed_families,
             label_gauges,
-        })
+            clients,
+            configured,
+            driver_connected,
+            monitored,
+            commands,
+            status_duration,
+            registered,
+            discovery_runs,
+            names,
+            status_since: Mutex::default(),
+            registry: registry.clone(),
+            expiry: Mutex::default(),
+        };
+        metrics.count_registered(&Expiry::default());
+        Ok(metrics)
+    }
+
+    /// Like [`Metrics::build_in`], but also creates label gauges for enumerated variables with
+    /// values that are not numbers, using the types and allowed values in the metadata.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if any of metrics cannot be created and registered with the
+    /// registry, such as if two metrics attempt to use the same name.
+    pub fn build_from_metadata(metadata: &[metadata::VarMetadata], registry: &Registry) -> Result<Metrics> {
+        let mut metrics = Metrics::build_in(&HashMap::new(), registry)?;
+        metrics.extend_from_metadata(metadata, registry)?;
+        Ok(metrics)
+    }
+
+    /// Creates gauges for the variables in the metadata that do not have one yet, and keeps the
+    /// existing gauges of all others, so the metrics can be rebuilt whenever the variables of the
+    /// UPS are rediscovered without registering anything twice. Every call counts as a discovery
+    /// in `pistachio_discovery_runs_total`. Returns the number of gauges created.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if any of the new metrics cannot be created and registered with
+    /// the registry, such as if two variables map to the same metric name.
+    pub fn extend_from_metadata(&mut self, metadata: &[metadata::VarMetadata], registry: &Registry) -> Result<usize> {
+        self.discovery_runs.inc();
+        let mut created = 0;
+        let mut new_vars: Vec<&metadata::VarMetadata> = metadata
+            .iter()
+            .filter(|var| !self.basic_gauges.contains_key(var.name.as_str()) && !self.label_gauges.contains_key(var.name.as_str()))
+            .collect();
+        // Reversed before the stable sort so that of a variable listed more than once, the last
+        // one is kept, like when listing the variables of a UPS
+        new_vars.reverse();
+        new_vars.sort_by(|a, b| creation_order(&a.name).cmp(&creation_order(&b.name)).then_with(|| a.name.cmp(&b.name)));
+        new_vars.dedup_by(|a, b| a.name == b.name);
+        for var in new_vars {
+            let name = var.name.as_str();
+            let families = (&mut self.indexed_families, &mut self.names);
+            if parse_number(&var.value).is_some() {
+                create_basic_gauge(name, &var.description, registry, &mut self.basic_gauges, families)?;
+            } else if let Some(values) = var.enum_values().filter(|_| indexed::split(name).is_some()) {
+                let states = IndexedStates { states: values, label: "value" };
+                create_indexed_label_gauge(name, &var.description, states, registry, &mut self.label_gauges, families)?;
+            } else if let Some(values) = var.enum_values() {
+                let gauge = GaugeVec::new(Opts::new(self.names.claim(name), &var.description), &["value"])?;
+                registry.register(Box::new(gauge.clone()))?;
+                self.label_gauges.insert(Arc::from(name), LabelGauge::new(&gauge, values, name)?);
+                debug!("Label gauge created for enumerated variable {name}");
+            } else if let Some(states) = known_states(name) {
+                let states = IndexedStates { states, label: "status" };
+                create_indexed_label_gauge(name, &var.description, states, registry, &mut self.label_gauges, families)?;
+            } else {
+                continue;
+            }
+            created += 1;
+        }
+        self.count_registered(&self.expiry.lock().unwrap_or_else(PoisonError::into_inner));
+        Ok(created)
     }
 
     /// Returns the number of all gauges registered.
@@ -83,38 +453,195 @@ impl Metrics {
         self.basic_gauges.len() + self.label_gauges.len()
     }
 
+    /// Returns the name of the variable each gauge was created for, by metric name. Variables that
+    /// only differ in an index share the name of the variable without one.
+    #[must_use]
+    pub fn metric_vars(&self) -> HashMap<String, String> {
+        self.names.0.clone()
+    }
+
+    /// Exports the number of gauges that have not expired as `pistachio_registered_gauges`.
+    fn count_registered(&self, expiry: &Expiry) {
+        self.registered.set(self.count().saturating_sub(expiry.expired.len()) as f64);
+    }
+
     /// Takes a list of variable names and values to update all associated Prometheus metrics.
-    pub fn update(&self, var_list: &Vec<rups::Variable>) {
+    pub fn update(&self, var_list: &[Variable]) {
+        let mut expiry = self.expiry.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
         for var in var_list {
+            self.mark_seen(&mut expiry, var.name(), now);
             if let Some(gauge) = self.basic_gauges.get(var.name()) {
                 // Update basic gauges
-                if let Ok(value) = var.value().parse::<f64>() {
+                if let Some(value) = parse_number(var.value()) {
                     gauge.set(value);
                 } else {
                     warn!("Failed to update gauge {} because the value was not a float", var.name());
                 }
-            } else if let Some((label_gauge, states)) = self.label_gauges.get(var.name()) {
-                update_label_gauge(label_gauge, states, &var.value());
+            } else if let Some(label_gauge) = self.label_gauges.get(var.name()) {
+                label_gauge.update(var.value());
             } else {
                 debug!("Variable {} does not have an associated gauge to update", var.name());
             }
         }
     }
 
-    /// Resets all metrics to zero.
+    /// Records that a variable was reported, registering its gauge again if it had expired.
+    fn mark_seen(&self, expiry: &mut Expiry, name: &str, now: Instant) {
+        if let Some(last_seen) = expiry.last_seen.get_mut(name) {
+            *last_seen = now;
+        } else if let Some(key) = self.basic_gauges.keys().chain(self.label_gauges.keys()).find(|key| &***key == name) {
+            expiry.last_seen.insert(Arc::clone(key), now);
+        }
+        if !expiry.expired.contains(name) {
+            return;
+        }
+        if let Some((collector, vars)) = self.collector_of(name) {
+            for var in &vars {
+                expiry.expired.remove(var);
+            }
+            match self.registry.register(collector) {
+                Ok(()) => debug!("Restored gauge for variable {name}, which is reported again"),
+                Err(err) => warn!("Failed to restore gauge for variable {name}: {err}"),
+            }
+            self.count_registered(expiry);
+        }
+    }
+
+    /// Removes the gauges of variables that have not been reported for longer than `timeout`
+    /// from the registry, so they disappear from `/metrics` instead of reporting their last value
+    /// forever. A gauge shared by several variables is only removed once none of them is
+    /// reported. A removed gauge is registered again as soon as its variable is reported. Returns
+    /// the number of gauges removed.
+    pub fn expire_idle(&self, timeout: Duration) -> usize {
+        let mut expiry = self.expiry.lock().unwrap_or_else(PoisonError::into_inner);
+        let Expiry { last_seen, expired } = &mut *expiry;
+        let is_recent = |var: &str| last_seen.get(var).is_some_and(|seen| seen.elapsed() <= timeout);
+        let idle: Vec<Arc<str>> = last_seen
+            .keys()
+            .filter(|name| !is_recent(name) && !expired.contains(*name))
+            .cloned()
+            .collect();
+        let mut removed = 0;
+        for name in idle {
+            if expired.contains(&name) {
+                continue;
+            }
+            let Some((collector, vars)) = self.collector_of(&name) else {
+                continue;
+            };
+            if vars.iter().any(|var| is_recent(var)) {
+                continue;
+            }
+            match self.registry.unregister(collector) {
+                Ok(()) => {
+                    info!("Removed gauge for variable {name}, which has not been reported for over {}s", timeout.as_secs());
+                    expired.extend(vars);
+                    removed += 1;
+                }
+                Err(err) => warn!("Failed to remove gauge for variable {name}: {err}"),
+            }
+        }
+        self.count_registered(&expiry);
+        removed
+    }
+
+    /// Sets the gauges of variables that have not been reported for longer than `timeout` to NaN,
+    /// for deployments that would rather see a gap than have the series disappear. Returns the
+    /// number of gauges blanked.
+    pub fn blank_idle(&self, timeout: Duration) -> usize {
+        let expiry = self.expiry.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut blanked = 0;
+        for (name, seen) in &expiry.last_seen {
+            if seen.elapsed() <= timeout {
+                continue;
+            }
+            if let Some(gauge) = self.basic_gauges.get(name) {
+                gauge.set(f64::NAN);
+            } else if let Some(label_gauge) = self.label_gauges.get(name) {
+                label_gauge.blank();
+            } else {
+                continue;
+            }
+            debug!("Blanked gauge for variable {name}, which has not been reported for over {}s", timeout.as_secs());
+            blanked += 1;
+        }
+        blanked
+    }
+
+    /// Returns the collector registered for a variable, along with every variable sharing it.
+    fn collector_of(&self, name: &str) -> Option<SharedCollector> {
+        if let Some(family) = self.indexed_families.values().find(|family| family.vars.iter().any(|var| &**var == name)) {
+            return Some((Box::new(family.vec.clone()), family.vars.clone()));
+        }
+        if let Some((key, gauge)) = self.basic_gauges.get_key_value(name) {
+            return Some((Box::new(gauge.clone()), vec![Arc::clone(key)]));
+        }
+        let (key, label_gauge) = self.label_gauges.get_key_value(name)?;
+        Some((Box::new(label_gauge.vec.clone()), vec![Arc::clone(key)]))
+    }
+
+    /// Updates the number of clients logged in to the UPS.
+    pub fn update_clients(&self, count: usize) {
+        self.clients.set(count as f64);
+    }
+
+    /// Updates whether the UPS is being monitored, whether the NUT server knows it, and whether
+    /// its driver is connected, from the error of the last poll, or `None` if it succeeded. Other
+    /// errors, such as the server being unreachable, stop the UPS being monitored but say nothing
+    /// about the others, which keep their last values.
+    pub fn update_availability(&self, error: Option<&Error>) {
+        self.monitored.set(if error.is_none() { 1.0 } else { 0.0 });
+        let (configured, driver_connected) = match error {
+            None => (1.0, 1.0),
+            Some(Error::Protocol(rups::NutError::UnknownUps)) => (0.0, 0.0),
+            Some(Error::Protocol(rups::NutError::DriverNotConnected)) => (1.0, 0.0),
+            Some(_) => return,
+        };
+        self.configured.set(configured);
+        self.driver_connected.set(driver_connected);
+    }
+
+    /// Updates how long the UPS has held the primary status in the value of `ups.status`, counting
+    /// from `now` when the primary status changes. A status without a primary status, such as
+    /// `NOCOMM`, is not exported.
+    pub fn update_status_duration(&self, status: &str, now: Instant) {
+        let mut since = self.status_since.lock().unwrap_or_else(PoisonError::into_inner);
+        let primary = UpsStatus::parse(status).primary();
+        if since.map(|(held, _)| held) != primary {
+            self.status_duration.reset();
+            *since = primary.map(|primary| (primary, now));
+        }
+        if let Some((primary, start)) = *since {
+            let duration = now.saturating_duration_since(start);
+            self.status_duration.with_label_values(&[primary]).set(duration.as_secs_f64());
+        }
+    }
+
+    /// Marks the given instant commands as supported by the UPS.
+    pub fn update_commands(&self, commands: &[metadata::CommandMetadata]) {
+        for command in commands {
+            self.commands.with_label_values(&[&command.name]).set(1.0);
+        }
+    }
+
+    /// Resets all metrics to zero, except for the supported instant commands, which never change.
     ///
     /// # Errors
     ///
     /// An error will be returned if any of the metrics to be reset cannot be accessed.
-    pub fn reset(&self) -> Result<(), prometheus::Error> {
+    pub fn reset(&self) -> Result<()> {
         for gauge in self.basic_gauges.values() {
             gauge.set(0.0);
         }
-        for (label_gauge, states) in self.label_gauges.values() {
-            for state in *states {
-                let gauge = label_gauge.get_metric_with_label_values(&[state])?;
-                gauge.set(0.0);
-            }
+        for label_gauge in self.label_gauges.values() {
+            label_gauge.reset();
+        }
+        self.clients.set(0.0);
+        // The primary status is kept, so the duration carries on if it has not changed once the
+        // UPS is reachable again
+        if let Some((primary, _)) = *self.status_since.lock().unwrap_or_else(PoisonError::into_inner) {
+            self.status_duration.with_label_values(&[primary]).set(0.0);
         }
         Ok(())
     }
@@ -124,127 +651,368 @@ impl Metrics {
 ///
 /// # Errors
 ///
-/// An error will be returned if the UPS host and port in the provided [Args] cannot be used to
-/// create a valid [`rups::Host`].
-pub fn create_connection(args: &Args) -> Result<Connection, rups::ClientError> {
-    // Create connection to UPS
-    let rups_host = rups::Host::try_from((args.ups_host.clone(), args.ups_port))?;
-    let rups_config = rups::ConfigBuilder::new().with_host(rups_host).build();
-    Connection::new(&rups_config)
+/// An [`Error::Config`] will be returned if the UPS host and port in the provided [Config] cannot be
+/// used as a valid address, and a connection or protocol error will be returned if the NUT server
+/// cannot be reached.
+pub fn create_connection(config: &Config) -> Result<Connection> {
+    connection::connect(&config.ups_host, config.ups_port)
 }
 
-/// Connects to the NUT server to produce a map of all available UPS variables, along with their
+/// Queries the UPS client to produce a map of all available UPS variables, along with their
 /// values and descriptions.
 ///
 /// # Errors
 ///
 /// An error will be returned if the list of variables or their descriptions cannot be retrieved
 /// from the NUT server, such as if connection to the server is lost.
-pub fn get_ups_vars(args: &Args, conn: &mut Connection) -> Result<HashMap<String, (String, String)>, rups::ClientError> {
+pub fn get_ups_vars<C: UpsClient>(config: &Config, conn: &mut C) -> Result<HashMap<String, (String, String)>> {
     // Get available vars
-    let ups_name = args.ups_name.as_str();
-    let available_vars = conn.list_vars(ups_name)?;
+    let ups_name = config.ups_name.as_str();
+    let (available_vars, _) = variable::dedup(conn.list_vars(ups_name)?);
     let mut ups_vars = HashMap::new();
     for var in &available_vars {
         let description = conn.get_var_description(ups_name, var.name())?;
-        ups_vars.insert(var.name().to_string(), (var.value(), description));
+        ups_vars.insert(var.name().to_string(), (var.value().to_string(), description));
     }
     Ok(ups_vars)
 }
 
-/// Main loop that polls the NUT server and updates associated gauges
-pub fn run(args: &Args, conn: &mut Connection, metrics: &Metrics) {
+/// Main loop that polls the NUT server, updates associated gauges, and publishes variables and
+/// events to all sinks after every poll. Once `shutdown` is set, the loop exits after shutting
+/// down all sinks.
+pub fn monitor<C: UpsClient>(config: &Config, conn: &mut C, metrics: &Metrics, sinks: &mut [Box<dyn Sink>], shutdown: &AtomicBool) {
+    let (_, events) = mpsc::channel();
+    monitor_with_events(config, conn, metrics, sinks, shutdown, &events);
+}
+
+/// Like [`monitor`], but also publishes events received from `events`, such as the results of
+/// instant commands, to all sinks after the next poll.
+pub fn monitor_with_events<C: UpsClient>(
+    config: &Config,
+    conn: &mut C,
+    metrics: &Metrics,
+    sinks: &mut [Box<dyn Sink>],
+    shutdown: &AtomicBool,
+    external: &Receiver<Event>,
+) {
+    let mut detector = EventDetector::new();
     let mut is_failing = false;
-    loop {
-        debug!("Polling UPS...");
-        match conn.list_vars(args.ups_name.as_str()) {
-            Ok(var_list) => {
+    let mut failures = 0;
+    // Sampled variables come first, so they are sampled even if they are also in a poll group
+    let groups: Vec<_> = config.sample_group().into_iter().chain(config.poll_groups.iter().cloned()).collect();
+    let mut conn = groups::GroupedClient::new(conn, &groups, Duration::from_secs(config.poll_rate));
+    let interval = conn.tick();
+    let mut polls = snapshots(&mut conn, &config.ups_name, interval).until(shutdown);
+    while let Some(result) = polls.next() {
+        let mut events = Vec::new();
+        let error = result.as_ref().err().map(ToString::to_string);
+        match result {
+            Ok(snapshot) => {
+                let var_list = snapshot.variables();
                 metrics.update(&var_list);
+                metrics.update_availability(None);
+                if let Some(status) = var_list.iter().find(|var| var.name() == "ups.status") {
+                    metrics.update_status_duration(status.value(), Instant::now());
+                }
+                expire_idle(config, metrics);
+                if let Some(clients) = &snapshot.clients {
+                    metrics.update_clients(clients.len());
+                }
                 debug!("Metrics updated");
+                for sink in sinks.iter_mut() {
+                    if let Err(err) = sink.publish(&var_list) {
+                        warn!("Failed to publish variables to {} sink: {err}", sink.name());
+                    }
+                }
                 if is_failing {
-                    info!("Connection with the UPS has been reestablished");
+                    events.push(Event::ConnectionRestored);
                     is_failing = false;
                 }
+                failures = 0;
+                events.extend(detector.detect(&var_list));
+            }
+            Err(err) if failures < config.failure_grace_polls => {
+                failures += 1;
+                warn!(
+                    "Failed to connect to the UPS, keeping the last values for {} more failed polls: {err}",
+                    config.failure_grace_polls - failures
+                );
             }
             Err(err) => {
                 // Log warning and set gauges to 0 to indicate failure
-                warn!("Failed to connect to the UPS: {err}");
+                log_poll_error(config, &err);
+                metrics.update_availability(Some(&err));
                 metrics.reset().unwrap_or_else(|err| {
                     warn!("Failed to reset gauges to zero: {err}");
                 });
                 debug!("Reset gauges to zero because the UPS was unreachable");
-                is_failing = true;
+                // A UPS that stays unreachable expires like variables that are no longer reported
+                expire_idle(config, metrics);
+                if !is_failing {
+                    events.push(Event::ConnectionLost { error: err.to_string() });
+                    is_failing = true;
+                }
+            }
+        }
+        if let Some((started, duration)) = polls.last_poll() {
+            for sink in sinks.iter_mut() {
+                if let Err(err) = sink.polled(started, duration, error.as_deref()) {
+                    warn!("Failed to publish poll to {} sink: {err}", sink.name());
+                }
             }
         }
-        thread::sleep(Duration::from_secs(args.poll_rate));
+        events.extend(external.try_iter());
+        for event in &events {
+            logging::log_event(event.level(), event, &config.ups_name);
+            let variable_changed = matches!(event, Event::VariableChanged { .. });
+            for sink in sinks.iter_mut().filter(|sink| !variable_changed || sink.variable_changes()) {
+                if let Err(err) = sink.event(event) {
+                    warn!("Failed to publish {} event to {} sink: {err}", event.kind(), sink.name());
+                }
+            }
+        }
+        if config.once {
+            break;
+        }
+    }
+    for sink in sinks.iter_mut() {
+        if let Err(err) = sink.shutdown() {
+            warn!("Failed to shut down {} sink: {err}", sink.name());
+        }
+    }
+}
+
+/// Logs why a poll failed, with what to check for the errors that mean the NUT server is reachable
+/// but cannot serve the UPS.
+fn log_poll_error(config: &Config, err: &Error) {
+    // A UPS that is not known is a misconfiguration, which does not fix itself like a driver can
+    match (err, explain_unavailable(config, err)) {
+        (Error::Protocol(rups::NutError::UnknownUps), Some(explanation)) => error!("{explanation}"),
+        (_, Some(explanation)) => warn!("{explanation}"),
+        (err, None) => warn!("Failed to connect to the UPS: {err}"),
+    }
+}
+
+/// Explains what to check when the NUT server is reachable but cannot serve the UPS, because it
+/// does not know the UPS or its driver is not connected.
+pub(crate) fn explain_unavailable(config: &Config, err: &Error) -> Option<String> {
+    let (name, host, port) = (&config.ups_name, &config.ups_host, config.ups_port);
+    match err {
+        Error::Protocol(rups::NutError::UnknownUps) => Some(format!(
+            "The NUT server at {host}:{port} does not know UPS {name}, check that it is one of the UPSes listed by `upsc -l {host}:{port}`"
+        )),
+        Error::Protocol(rups::NutError::DriverNotConnected) => Some(format!(
+            "The driver of UPS {name} is not connected to the NUT server at {host}:{port}, check that the driver is running, such as with `upsdrvctl start {name}`"
+        )),
+        _ => None,
     }
 }
 
+/// Removes or blanks the gauges of variables that have not been reported within the idle timeout,
+/// if one is configured.
+fn expire_idle(config: &Config, metrics: &Metrics) {
+    let Some(timeout) = config.metric_idle_timeout.map(Duration::from_secs) else {
+        return;
+    };
+    match config.metric_idle_action {
+        MetricIdleAction::Remove => metrics.expire_idle(timeout),
+        MetricIdleAction::Nan => metrics.blank_idle(timeout),
+    };
+}
+
 /// Takes a map of UPS variables, values, and descriptions to create Prometheus gauges. Gauges are
-/// only created for variables with values that can be parsed as floats, since Prometheus gauges can
-/// only have floats as values.
-fn create_basic_gauges(vars: &HashMap<String, (String, String)>) -> Result<HashMap<String,GenericGauge<AtomicF64>>, prometheus::Error> {
+/// only created for variables with values that can be parsed as numbers, since Prometheus gauges
+/// can only have floats as values.
+fn create_basic_gauges(
+    vars: &HashMap<String, (String, String)>,
+    registry: &Registry,
+    names: &mut MetricNames,
+) -> Result<(BasicGauges, IndexedFamilies)> {
     let mut gauges = HashMap::new();
-    for (raw_name, (_, description)) in vars.iter().filter(|(_, (y, _))| y.parse::<f64>().is_ok()) {
-        let mut gauge_name = raw_name.replace('.', "_");
-        if !gauge_name.starts_with("ups") {
-            gauge_name.insert_str(0, "ups_");
+    let mut families = HashMap::new();
+    let mut numeric: Vec<_> = vars.iter().filter(|(_, (y, _))| parse_number(y).is_some()).collect();
+    numeric.sort_by(|(a, _), (b, _)| creation_order(a).cmp(&creation_order(b)).then_with(|| a.cmp(b)));
+    for (raw_name, (_, description)) in numeric {
+        create_basic_gauge(raw_name, description, registry, &mut gauges, (&mut families, names))?;
+    }
+    Ok((gauges, families))
+}
+
+/// Returns the order in which the gauges of variables are created: variables with an index go
+/// first, so those without one can join their gauge, and variables with names made only of
+/// letters, digits, and dots go before others, so they keep their metric name if another
+/// variable's name converts to the same one. Ties are broken by name, so the order never depends
+/// on the order variables are listed in.
+fn creation_order(var_name: &str) -> (bool, bool) {
+    let is_plain = var_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.');
+    (indexed::split(var_name).is_none(), !is_plain)
+}
+
+/// Creates the gauge of a variable with a numeric value. Variables that only differ in an index,
+/// such as `input.L1.voltage` and `input.L2.voltage`, share one gauge with the index as a label,
+/// which the variable without an index, such as `input.voltage`, joins with an empty label.
+fn create_basic_gauge(
+    raw_name: &str,
+    description: &str,
+    registry: &Registry,
+    gauges: &mut BasicGauges,
+    (families, names): (&mut IndexedFamilies, &mut MetricNames),
+) -> Result<()> {
+    let (metric_name, label, index) = match indexed::split(raw_name) {
+        Some(indexed) => (names.claim(&indexed.name), indexed.label, indexed.index),
+        None => {
+            let metric_name = names.claim(raw_name);
+            match families.get(&metric_name) {
+                Some(family) => (metric_name, family.label, String::new()),
+                None => {
+                    let gauge = Gauge::new(metric_name, description)?;
+                    registry.register(Box::new(gauge.clone()))?;
+                    gauges.insert(Arc::from(raw_name), gauge);
+                    debug!("Gauge created for variable {raw_name}");
+                    return Ok(());
+                }
+            }
+        }
+    };
+    let family = match families.entry(metric_name) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let vec = GaugeVec::new(Opts::new(entry.key().as_str(), description), &[label])?;
+            registry.register(Box::new(vec.clone()))?;
+            entry.insert(IndexedFamily {
+                vec,
+                label,
+                vars: Vec::new(),
+            })
         }
-        let gauge = register_gauge!(gauge_name, description)?;
-        gauges.insert(raw_name.to_string(), gauge);
-        debug!("Gauge created for variable {raw_name}");
+    };
+    if family.label != label {
+        return Err(Error::Config(format!(
+            "variable {raw_name} is labeled by {label}, but its gauge is already labeled by {}",
+            family.label
+        )));
+    }
+    let gauge = family.vec.get_metric_with_label_values(&[&index])?;
+    family.vars.push(Arc::from(raw_name));
+    gauges.insert(Arc::from(raw_name), gauge);
+    debug!("Gauge created for variable {raw_name} with {label} {index:?}");
+    Ok(())
+}
+
+/// The possible states of a variable with an index, and the name of the label holding the state.
+struct IndexedStates<'a, S> {
+    states: &'a [S],
+    label: &'static str,
+}
+
+/// Creates the label gauge of a variable with an index, such as `outlet.1.status`, which is
+/// shared with all variables that only differ in the index and labeled by both the index and the
+/// state.
+fn create_indexed_label_gauge<S: AsRef<str>>(
+    raw_name: &str,
+    description: &str,
+    states: IndexedStates<'_, S>,
+    registry: &Registry,
+    label_gauges: &mut LabelGauges,
+    (families, names): (&mut IndexedFamilies, &mut MetricNames),
+) -> Result<()> {
+    let Some(indexed) = indexed::split(raw_name) else {
+        return Err(Error::Config(format!("variable {raw_name} has no index")));
+    };
+    let family = match families.entry(names.claim(&indexed.name)) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let vec = GaugeVec::new(Opts::new(entry.key().as_str(), description), &[indexed.label, states.label])?;
+            registry.register(Box::new(vec.clone()))?;
+            entry.insert(IndexedFamily {
+                vec,
+                label: indexed.label,
+                vars: Vec::new(),
+            })
+        }
+    };
+    let label_gauge = LabelGauge::with_index(&family.vec, states.states, raw_name, Some(&indexed.index))?;
+    family.vars.push(Arc::from(raw_name));
+    label_gauges.insert(Arc::from(raw_name), label_gauge);
+    debug!("Label gauge created for variable {raw_name} with {} {:?}", indexed.label, indexed.index);
+    Ok(())
+}
+
+/// Returns the possible states of variables that are not numbers and are not reported as an
+/// enumeration by the NUT server, but are known to always have one of a few values.
+fn known_states(var_name: &str) -> Option<&'static [&'static str]> {
+    match indexed::split(var_name)?.name.as_str() {
+        "outlet.status" | "outlet.group.status" => Some(OUTLET_STATUSES),
+        _ => None,
+    }
+}
+
+/// Converts a UPS variable name into the name of its Prometheus gauge, replacing dots and any
+/// other characters that are not allowed in metric names, such as `-`, with underscores and
+/// adding a `ups_` prefix if not already present.
+pub(crate) fn gauge_name(var_name: &str) -> String {
+    let mut gauge_name: String = var_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !gauge_name.starts_with("ups") {
+        gauge_name.insert_str(0, "ups_");
     }
-    Ok(gauges)
+    gauge_name
+}
+
+/// Parses the value of a variable as a number, tolerating the padding, leading zeros, and
+/// hexadecimal `0x` prefix that some drivers report integers with.
+pub(crate) fn parse_number(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        return u64::from_str_radix(hex, 16).ok().map(|value| value as f64);
+    }
+    value.parse::<f64>().ok()
+}
+
+/// Returns the host name of the machine, from `HOSTNAME` or `/etc/hostname`.
+pub(crate) fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
 }
 
 /// Creates label gauges in Prometheus for UPS variables that represent a set of potential status.
 /// This currently only includes overall UPS status and beeper status.
-fn create_label_gauges() -> Result<HashMap<String,(GenericGaugeVec<AtomicF64>, &'static [&'static str])>, prometheus::Error> {
+fn create_label_gauges(registry: &Registry, names: &mut MetricNames) -> Result<LabelGauges> {
     let mut label_gauges = HashMap::new();
-    let status_gauge = register_gauge_vec!("ups_status", "UPS Status Code", &["status"])?;
-    let beeper_gauge = register_gauge_vec!("ups_beeper_status", "Beeper Status", &["status"])?;
+    let status_gauge = GaugeVec::new(Opts::new(names.claim("ups.status"), "UPS Status Code"), &["status"])?;
+    let beeper_gauge = GaugeVec::new(Opts::new(names.claim("ups.beeper.status"), "Beeper Status"), &["status"])?;
+    registry.register(Box::new(status_gauge.clone()))?;
+    registry.register(Box::new(beeper_gauge.clone()))?;
+    label_gauges.insert(Arc::from("ups.status"), LabelGauge::new(&status_gauge, STATUSES, "ups.status")?);
     label_gauges.insert(
-        String::from("ups.status"),
-        (status_gauge, STATUSES),
-    );
-    label_gauges.insert(
-        String::from("ups.beeper.status"),
-        (beeper_gauge, BEEPER_STATUSES),
+        Arc::from("ups.beeper.status"),
+        LabelGauge::new(&beeper_gauge, BEEPER_STATUSES, "ups.beeper.status")?,
     );
     Ok(label_gauges)
 }
 
-/// Takes a label gauge, all of it's possible states, and the current value of the variable from
-/// the UPS. Each label of the gauge is updated to reflect all current states present in the
-/// value from the UPS.
-fn update_label_gauge(label_gauge: &GenericGaugeVec<AtomicF64>, states: &[&str], value: &str) {
-    for state in states {
-        if let Ok(gauge) = label_gauge.get_metric_with_label_values(&[state]) {
-            if value.contains(state) {
-                gauge.set(1.0);
-            } else {
-                gauge.set(0.0);
-            }
-        } else {
-            warn!("Failed to update label gauge for {} state", state);
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use prometheus_exporter::prometheus::core::Collector;
+    use prometheus::core::Collector;
 
     #[test]
-    fn parse_default_args() {
-        let args = Args::parse();
-        assert_eq!(args.ups_name, DEFAULT_UPS_NAME);
-        assert_eq!(args.ups_host, DEFAULT_UPS_HOST);
-        assert_eq!(args.ups_port, DEFAULT_UPS_PORT);
-        assert_eq!(args.bind_ip, DEFAULT_BIND_IP);
-        assert_eq!(args.bind_port, DEFAULT_BIND_PORT);
-        assert_eq!(args.poll_rate, DEFAULT_POLL_RATE);
+    fn get_ups_vars_from_client() {
+        let config = Config::default();
+        let mut client = testing::MockUpsClient::new()
+            .with_var("battery.charge", "100")
+            .with_var("ups.status", "OL")
+            .with_description("battery.charge", "Battery charge (percent)");
+        let ups_vars = get_ups_vars(&config, &mut client).unwrap();
+        assert_eq!(ups_vars.len(), 2);
+        let (value, description) = &ups_vars["battery.charge"];
+        assert_eq!(value, "100");
+        assert_eq!(description, "Battery charge (percent)");
     }
 
     #[test]
@@ -269,14 +1037,14 @@ mod tests {
         );
 
         // Test creation function
-        let gauges = create_basic_gauges(&variables).unwrap();
+        let (gauges, _) = create_basic_gauges(&variables, prometheus::default_registry(), &mut MetricNames::default()).unwrap();
         assert_eq!(gauges.len(), variables.len());
         for (name, gauge) in &gauges {
             let gauge_desc = &gauge.desc().pop().unwrap().help;
             let gauge_name = &gauge.desc().pop().unwrap().fq_name;
             let (expected_name, (_, expected_desc)) =
-                variables.get_key_value(name.as_str()).unwrap();
-            assert_eq!(name, expected_name);
+                variables.get_key_value(&**name).unwrap();
+            assert_eq!(&**name, expected_name);
             assert_eq!(gauge_desc, expected_desc);
             assert!(gauge_name.starts_with("ups"));
             assert!(!gauge_name.contains("."));
@@ -294,14 +1062,14 @@ mod tests {
         );
 
         // Test creation function
-        let gauges = create_basic_gauges(&variables).unwrap();
+        let (gauges, _) = create_basic_gauges(&variables, prometheus::default_registry(), &mut MetricNames::default()).unwrap();
         assert_eq!(gauges.len(), variables.len());
         for (name, gauge) in &gauges {
             let gauge_desc = &gauge.desc().pop().unwrap().help;
             let gauge_name = &gauge.desc().pop().unwrap().fq_name;
             let (expected_name, (_, expected_desc)) =
-                variables.get_key_value(name.as_str()).unwrap();
-            assert_eq!(name, expected_name);
+                variables.get_key_value(&**name).unwrap();
+            assert_eq!(&**name, expected_name);
             assert_eq!(gauge_desc, expected_desc);
             assert!(gauge_name.starts_with("ups"));
             assert!(!gauge_name.contains("."));
@@ -319,11 +1087,47 @@ mod tests {
         );
 
         // Test creation function
-        let gauges = create_basic_gauges(&variables).unwrap();
+        let (gauges, _) = create_basic_gauges(&variables, prometheus::default_registry(), &mut MetricNames::default()).unwrap();
         assert_eq!(gauges.len(), 0);
         dbg!(gauges);
     }
 
+    #[test]
+    fn sanitize_metric_names() {
+        assert_eq!(gauge_name("battery.charge"), "ups_battery_charge");
+        assert_eq!(gauge_name("ambient.temperature-high"), "ups_ambient_temperature_high");
+        assert_eq!(gauge_name("input.voltage:min"), "ups_input_voltage_min");
+
+        // Variables that convert to the same name are told apart, whatever order they are listed in
+        let registry = Registry::new();
+        let variables = HashMap::from([
+            (String::from("battery-charge"), (String::from("90"), String::from("Odd battery charge"))),
+            (String::from("battery.charge"), (String::from("80"), String::from("Battery charge"))),
+            (String::from("battery_charge"), (String::from("70"), String::from("Other battery charge"))),
+        ]);
+        let metrics = Metrics::build_in(&variables, &registry).unwrap();
+        metrics.update(&variables.iter().map(|(name, (value, _))| Variable::new(name, value.clone())).collect::<Vec<_>>());
+        let families = registry.gather();
+        let value = |name: &str| {
+            let family = families.iter().find(|family| family.get_name() == name).unwrap();
+            family.get_metric()[0].get_gauge().get_value()
+        };
+        assert_eq!(value("ups_battery_charge"), 80.0);
+        assert_eq!(value("ups_battery_charge_2"), 90.0);
+        assert_eq!(value("ups_battery_charge_3"), 70.0);
+    }
+
+    #[test]
+    fn parse_tolerant_numbers() {
+        assert_eq!(parse_number(" 3 "), Some(3.0));
+        assert_eq!(parse_number("007"), Some(7.0));
+        assert_eq!(parse_number("0x1A"), Some(26.0));
+        assert_eq!(parse_number("0X00ff"), Some(255.0));
+        assert_eq!(parse_number("12.5"), Some(12.5));
+        assert_eq!(parse_number("0xZZ"), None);
+        assert_eq!(parse_number("CyberPower"), None);
+    }
+
     #[test]
     fn create_metrics() {
         // Setup
@@ -339,8 +1143,8 @@ mod tests {
         assert_eq!(3, metrics.count()); // Will have 3 since 2 label gauges are always created
 
         // Update metrics
-        let basic_var: rups::Variable = rups::Variable::parse("ups.var5", String::from("30"));
-        let label_var: rups::Variable = rups::Variable::parse("ups.status", String::from("OL"));
+        let basic_var: Variable = Variable::new("ups.var5", String::from("30"));
+        let label_var: Variable = Variable::new("ups.status", String::from("OL"));
         let var_list = vec![basic_var, label_var];
         metrics.update(&var_list);
 
@@ -377,4 +1181,280 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn update_availability_from_errors() {
+        let registry = Registry::new();
+        let metrics = Metrics::build_in(&HashMap::new(), &registry).unwrap();
+        let gauges = || {
+            let families = registry.gather();
+            let value = |name| families.iter().find(|family| family.get_name() == name).unwrap().get_metric()[0].get_gauge().get_value();
+            (value("pistachio_monitored_ups"), value("ups_configured"), value("ups_driver_connected"))
+        };
+        assert_eq!(gauges(), (0.0, 0.0, 0.0));
+        metrics.update_availability(None);
+        assert_eq!(gauges(), (1.0, 1.0, 1.0));
+        metrics.update_availability(Some(&Error::Protocol(rups::NutError::DriverNotConnected)));
+        assert_eq!(gauges(), (0.0, 1.0, 0.0));
+        metrics.update_availability(None);
+        metrics.update_availability(Some(&Error::Connection(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))));
+        assert_eq!(gauges(), (0.0, 1.0, 1.0));
+        metrics.update_availability(Some(&Error::Protocol(rups::NutError::UnknownUps)));
+        assert_eq!(gauges(), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn create_metrics_in_registry() {
+        let registry = Registry::new();
+        let variables = HashMap::from([(String::from("battery.charge"), (String::from("90"), String::from("Battery charge")))]);
+        let metrics = Metrics::build_in(&variables, &registry).unwrap();
+        metrics.update(&[Variable::new("battery.charge", String::from("80"))]);
+        metrics.update_clients(2);
+        metrics.update_commands(&[metadata::CommandMetadata {
+            name: String::from("beeper.mute"),
+            description: String::new(),
+        }]);
+        metrics.reset().unwrap();
+        metrics.update(&[Variable::new("battery.charge", String::from("80"))]);
+        metrics.update_clients(2);
+        let families = registry.gather();
+        let charge = families.iter().find(|family| family.get_name() == "ups_battery_charge").unwrap();
+        assert_eq!(charge.get_metric()[0].get_gauge().get_value(), 80.0);
+        let clients = families.iter().find(|family| family.get_name() == "ups_clients_connected").unwrap();
+        assert_eq!(clients.get_metric()[0].get_gauge().get_value(), 2.0);
+        let commands = families.iter().find(|family| family.get_name() == "ups_command_supported").unwrap();
+        assert_eq!(commands.get_metric()[0].get_label()[0].get_value(), "beeper.mute");
+        assert_eq!(commands.get_metric()[0].get_gauge().get_value(), 1.0);
+
+        // Metrics in a separate registry never collide with those in the default one
+        assert!(Metrics::build_in(&variables, &Registry::new()).is_ok());
+        assert!(Metrics::build_in(&variables, &registry).is_err());
+    }
+
+    #[test]
+    fn track_status_duration() {
+        let registry = Registry::new();
+        let metrics = Metrics::build_in(&HashMap::new(), &registry).unwrap();
+        let durations = || {
+            let families = registry.gather();
+            let family = families.iter().find(|family| family.get_name() == "ups_status_duration_seconds");
+            family.map_or_else(Vec::new, |family| {
+                family
+                    .get_metric()
+                    .iter()
+                    .map(|metric| (metric.get_label()[0].get_value().to_string(), metric.get_gauge().get_value()))
+                    .collect()
+            })
+        };
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        metrics.update_status_duration("OL CHRG", at(0));
+        metrics.update_status_duration("OL", at(30));
+        assert_eq!(durations(), vec![(String::from("OL"), 30.0)]);
+        metrics.update_status_duration("OB DISCHRG", at(40));
+        metrics.update_status_duration("OB DISCHRG LB", at(340));
+        assert_eq!(durations(), vec![(String::from("OB"), 300.0)]);
+        metrics.update_status_duration("NOCOMM", at(350));
+        assert!(durations().is_empty());
+    }
+
+    #[test]
+    fn expire_idle_gauges() {
+        let registry = Registry::new();
+        let variables = HashMap::from([(String::from("battery.charge"), (String::from("90"), String::from("Battery charge")))]);
+        let metrics = Metrics::build_in(&variables, &registry).unwrap();
+        let is_exported = || registry.gather().iter().any(|family| family.get_name() == "ups_battery_charge");
+        let registered = || {
+            let families = registry.gather();
+            let family = families.iter().find(|family| family.get_name() == "pistachio_registered_gauges").unwrap();
+            family.get_metric()[0].get_gauge().get_value()
+        };
+        metrics.update(&[Variable::new("battery.charge", String::from("80"))]);
+        assert_eq!(metrics.expire_idle(Duration::from_secs(60)), 0);
+        assert!(is_exported());
+
+        // Expire everything not reported in this instant
+        std::thread::sleep(Duration::from_millis(5));
+        metrics.update(&[Variable::new("ups.status", String::from("OL"))]);
+        assert_eq!(metrics.expire_idle(Duration::from_millis(1)), 1);
+        assert!(!is_exported());
+        assert_eq!(registered(), (metrics.count() - 1) as f64);
+        assert_eq!(metrics.expire_idle(Duration::from_millis(1)), 0);
+
+        // Reported again
+        metrics.update(&[Variable::new("battery.charge", String::from("70"))]);
+        assert!(is_exported());
+        assert_eq!(registered(), metrics.count() as f64);
+
+        // Blanked instead of removed
+        std::thread::sleep(Duration::from_millis(5));
+        metrics.update(&[Variable::new("ups.status", String::from("OL"))]);
+        assert_eq!(metrics.blank_idle(Duration::from_millis(1)), 1);
+        let families = registry.gather();
+        let charge = families.iter().find(|family| family.get_name() == "ups_battery_charge").unwrap();
+        assert!(charge.get_metric()[0].get_gauge().get_value().is_nan());
+    }
+
+    #[test]
+    fn create_phase_gauges() {
+        let registry = Registry::new();
+        let variables = HashMap::from([
+            (String::from("input.L1-N.voltage"), (String::from("230.1"), String::from("Input voltage (L1-N)"))),
+            (String::from("input.L2-N.voltage"), (String::from("229.8"), String::from("Input voltage (L2-N)"))),
+            (String::from("input.voltage"), (String::from("230.0"), String::from("Input voltage"))),
+            (String::from("input.L1.current"), (String::from("2.5"), String::from("Input current (L1)"))),
+        ]);
+        let metrics = Metrics::build_in(&variables, &registry).unwrap();
+        metrics.update(&[
+            Variable::new("input.L1-N.voltage", String::from("231.0")),
+            Variable::new("input.voltage", String::from("230.5")),
+        ]);
+        let families = registry.gather();
+        let voltage = families.iter().find(|family| family.get_name() == "ups_input_voltage").unwrap();
+        let value = |phase: &str| {
+            let metric = voltage.get_metric().iter().find(|metric| metric.get_label()[0].get_value() == phase).unwrap();
+            metric.get_gauge().get_value()
+        };
+        assert_eq!(voltage.get_metric().len(), 3);
+        assert_eq!(value("L1-N"), 231.0);
+        assert_eq!(value("L2-N"), 0.0);
+        assert_eq!(value(""), 230.5);
+        assert!(families.iter().any(|family| family.get_name() == "ups_input_current"));
+        assert!(!families.iter().any(|family| family.get_name().contains("L1")));
+
+        // The shared gauge is only removed once none of its variables is reported
+        std::thread::sleep(Duration::from_millis(5));
+        metrics.update(&[Variable::new("input.voltage", String::from("230.5"))]);
+        metrics.expire_idle(Duration::from_millis(1));
+        assert!(registry.gather().iter().any(|family| family.get_name() == "ups_input_voltage"));
+    }
+
+    #[test]
+    fn create_outlet_gauges() {
+        let registry = Registry::new();
+        let variables = HashMap::from([
+            (String::from("outlet.1.status"), (String::from("on"), String::from("Outlet 1 status"))),
+            (String::from("outlet.2.status"), (String::from("off"), String::from("Outlet 2 status"))),
+            (String::from("outlet.group.1.load"), (String::from("12"), String::from("Outlet group 1 load"))),
+        ]);
+        let metrics = Metrics::build_in(&variables, &registry).unwrap();
+        metrics.update(&[
+            Variable::new("outlet.1.status", String::from("on")),
+            Variable::new("outlet.2.status", String::from("off")),
+            Variable::new("outlet.group.1.load", String::from("15")),
+        ]);
+        let families = registry.gather();
+        let status = families.iter().find(|family| family.get_name() == "ups_outlet_status").unwrap();
+        assert_eq!(status.get_metric().len(), 4);
+        for metric in status.get_metric() {
+            let labels: Vec<_> = metric.get_label().iter().map(|label| (label.get_name(), label.get_value())).collect();
+            let expected = if labels.contains(&("outlet", "1")) == labels.contains(&("status", "on")) { 1.0 } else { 0.0 };
+            assert_eq!(metric.get_gauge().get_value(), expected, "{labels:?}");
+        }
+        let load = families.iter().find(|family| family.get_name() == "ups_outlet_group_load").unwrap();
+        assert_eq!(load.get_metric()[0].get_label()[0].get_name(), "group");
+        assert_eq!(load.get_metric()[0].get_gauge().get_value(), 15.0);
+    }
+
+    #[test]
+    fn create_battery_pack_gauges() {
+        let registry = Registry::new();
+        let variables = HashMap::from([
+            (String::from("battery.1.voltage"), (String::from("27.1"), String::from("Battery pack 1 voltage"))),
+            (String::from("battery.2.voltage"), (String::from("27.3"), String::from("Battery pack 2 voltage"))),
+            (String::from("battery.voltage"), (String::from("54.4"), String::from("Battery voltage"))),
+        ]);
+        let metrics = Metrics::build_in(&variables, &registry).unwrap();
+        metrics.update(&[Variable::new("battery.2.voltage", String::from("27.2"))]);
+        let families = registry.gather();
+        let voltage = families.iter().find(|family| family.get_name() == "ups_battery_voltage").unwrap();
+        let packs: Vec<_> = voltage.get_metric().iter().map(|metric| metric.get_label()[0].get_value()).collect();
+        assert_eq!(packs, ["", "1", "2"]);
+        assert_eq!(voltage.get_metric()[0].get_label()[0].get_name(), "pack");
+        assert_eq!(voltage.get_metric()[2].get_gauge().get_value(), 27.2);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn export_process_metrics() {
+        let families = prometheus::default_registry().gather();
+        for name in ["process_cpu_seconds_total", "process_open_fds", "process_resident_memory_bytes", "process_start_time_seconds"] {
+            assert!(families.iter().any(|family| family.get_name() == name), "{name} is not exported");
+        }
+    }
+
+    #[test]
+    fn update_label_gauge_whole_words() {
+        let gauge = GaugeVec::new(Opts::new("ups_status", "UPS Status Code"), &["status"]).unwrap();
+        let label_gauge = LabelGauge::new(&gauge, STATUSES, "ups.status").unwrap();
+        label_gauge.update("OB DISCHRG");
+        let value = |state: &str| gauge.with_label_values(&[state]).get();
+        assert_eq!(value("OB"), 1.0);
+        assert_eq!(value("DISCHRG"), 1.0);
+        assert_eq!(value("CHRG"), 0.0);
+        assert_eq!(value("OL"), 0.0);
+        label_gauge.update("TOLERANCE");
+        assert_eq!(value("OL"), 0.0);
+
+        let gauge = GaugeVec::new(Opts::new("ups_beeper_status", "Beeper Status"), &["status"]).unwrap();
+        let label_gauge = LabelGauge::new(&gauge, BEEPER_STATUSES, "ups.beeper.status").unwrap();
+        label_gauge.update("disabled");
+        assert_eq!(gauge.with_label_values(&["disabled"]).get(), 1.0);
+        assert_eq!(gauge.with_label_values(&["enabled"]).get(), 0.0);
+        label_gauge.reset();
+        assert_eq!(gauge.with_label_values(&["disabled"]).get(), 0.0);
+    }
+
+    #[test]
+    fn create_enum_label_gauges() {
+        let registry = Registry::new();
+        let metadata = vec![metadata::VarMetadata {
+            name: String::from("input.sensitivity"),
+            value: String::from("medium"),
+            description: String::from("Input power sensitivity"),
+            writable: true,
+            kind: metadata::VarKind::Enum {
+                values: vec![String::from("low"), String::from("medium"), String::from("high")],
+            },
+        }];
+        let mut metrics = Metrics::build_from_metadata(&metadata, &registry).unwrap();
+        assert_eq!(metrics.count(), 3);
+        assert_eq!(metrics.discovery_runs.get(), 1);
+        metrics.update(&[Variable::new("input.sensitivity", String::from("medium"))]);
+        let families = registry.gather();
+        let family = families.iter().find(|family| family.get_name() == "ups_input_sensitivity").unwrap();
+        for metric in family.get_metric() {
+            let expected = if metric.get_label()[0].get_value() == "medium" { 1.0 } else { 0.0 };
+            assert_eq!(metric.get_gauge().get_value(), expected);
+        }
+
+        // Rebuilding only creates gauges for new variables
+        let mut rediscovered = metadata.clone();
+        rediscovered.push(metadata::VarMetadata {
+            name: String::from("ups.temperature"),
+            value: String::from("31.5"),
+            description: String::from("UPS temperature"),
+            writable: false,
+            kind: metadata::VarKind::Number,
+        });
+        assert_eq!(metrics.extend_from_metadata(&rediscovered, &registry).unwrap(), 1);
+        assert_eq!(metrics.extend_from_metadata(&rediscovered, &registry).unwrap(), 0);
+        assert_eq!(metrics.count(), 4);
+        assert_eq!(metrics.discovery_runs.get(), 3);
+    }
+
+    #[test]
+    fn register_duplicate_variables_once() {
+        let registry = Registry::new();
+        let var = |value: &str| metadata::VarMetadata {
+            name: String::from("ups.temperature"),
+            value: value.to_string(),
+            description: String::from("UPS temperature"),
+            writable: false,
+            kind: metadata::VarKind::Number,
+        };
+        let metrics = Metrics::build_from_metadata(&[var("31.5"), var("32")], &registry).unwrap();
+        assert_eq!(metrics.count(), Metrics::build_in(&HashMap::new(), &Registry::new()).unwrap().count() + 1);
+        assert_eq!(registry.gather().iter().filter(|family| family.get_name() == "ups_temperature").count(), 1);
+    }
 }
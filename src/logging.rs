@@ -0,0 +1,392 @@
+//! Log targets for installs that run without a container runtime, so messages end up in syslog or
+//! the systemd journal with the priority of their level instead of on standard error.
+//!
+//! Messages are filtered with `RUST_LOG` like `env_logger`. Fields attached to a message, such as
+//! those of events logged with [`log_event`], are sent to the journal as structured fields, and
+//! appended to the message as `key=value` pairs for syslog. Everything but [`log_event`] requires
+//! the `cli` feature.
+
+use crate::events::Event;
+#[cfg(feature = "cli")]
+use log::kv::{self, Key, VisitSource};
+use log::{Level, LevelFilter, Record};
+#[cfg(feature = "cli")]
+use log::{Log, Metadata};
+use serde_json::Value;
+use std::fmt;
+#[cfg(feature = "cli")]
+use std::fmt::Write;
+#[cfg(feature = "cli")]
+use std::io;
+use std::time::SystemTime;
+
+/// Path of the socket of the local syslog daemon.
+#[cfg(all(unix, feature = "cli"))]
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Path of the socket the systemd journal receives native messages on.
+#[cfg(all(unix, feature = "cli"))]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Name messages are tagged with in syslog and the journal.
+#[cfg(feature = "cli")]
+const IDENTIFIER: &str = "pistachio";
+
+/// Syslog facility of system daemons, which messages are sent with.
+#[cfg(feature = "cli")]
+const FACILITY_DAEMON: u8 = 3;
+
+/// Where log messages are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LogTarget {
+    /// Standard error, formatted by `env_logger`.
+    #[default]
+    Stderr,
+    /// The local syslog daemon, through `/dev/log`.
+    Syslog,
+    /// The systemd journal, through its native protocol.
+    Journald,
+}
+
+impl fmt::Display for LogTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogTarget::Stderr => "stderr",
+            LogTarget::Syslog => "syslog",
+            LogTarget::Journald => "journald",
+        })
+    }
+}
+
+/// Level of log messages to show, overriding `RUST_LOG`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LogLevel {
+    /// No messages are shown.
+    Off,
+    /// Only errors are shown.
+    Error,
+    /// Warnings and errors are shown.
+    Warn,
+    /// Information, warnings, and errors are shown.
+    Info,
+    /// Debug messages are shown as well.
+    Debug,
+    /// Every message is shown.
+    Trace,
+}
+
+impl LogLevel {
+    /// Returns the filter for messages of this level and above.
+    #[must_use]
+    pub fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Whether log messages on standard error start with a timestamp.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LogTimestamps {
+    /// Messages start with the time in RFC 3339 format, in UTC.
+    #[default]
+    Rfc3339,
+    /// Messages have no timestamp, for when the log collector adds its own.
+    None,
+}
+
+/// When log messages on standard error are colored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LogColor {
+    /// Messages are colored when standard error is a terminal.
+    #[default]
+    Auto,
+    /// Messages are always colored.
+    Always,
+    /// Messages are never colored.
+    Never,
+}
+
+/// Options for formatting and filtering log messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogFormat {
+    /// Level of messages to show, or `None` to filter them with `RUST_LOG`, showing information
+    /// and above if it is not set.
+    pub level: Option<LogLevel>,
+    /// Whether messages start with a timestamp.
+    pub timestamps: LogTimestamps,
+    /// When messages are colored.
+    pub color: LogColor,
+}
+
+#[cfg(feature = "cli")]
+impl LogFormat {
+    /// Returns an `env_logger` builder that filters and formats messages with these options.
+    #[must_use]
+    pub fn builder(&self) -> env_logger::Builder {
+        let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+        if let Some(level) = self.level {
+            builder.filter_level(level.filter());
+        }
+        if self.timestamps == LogTimestamps::None {
+            builder.format_timestamp(None);
+        }
+        builder.write_style(match self.color {
+            LogColor::Auto => env_logger::WriteStyle::Auto,
+            LogColor::Always => env_logger::WriteStyle::Always,
+            LogColor::Never => env_logger::WriteStyle::Never,
+        });
+        builder
+    }
+}
+
+/// A logger that sends every message as a datagram to syslog or the systemd journal.
+#[cfg(feature = "cli")]
+#[derive(Debug)]
+pub struct SocketLogger {
+    target: LogTarget,
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    filter: env_logger::Logger,
+}
+
+#[cfg(feature = "cli")]
+impl SocketLogger {
+    /// Connects to the socket of the given target, with messages filtered by `filter`, which is
+    /// only used for its filter and never writes anything itself.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the target is standard error, or if its socket cannot be
+    /// connected to.
+    pub fn connect(target: LogTarget, filter: env_logger::Logger) -> io::Result<SocketLogger> {
+        #[cfg(unix)]
+        {
+            let path = match target {
+                LogTarget::Stderr => return Err(io::Error::new(io::ErrorKind::InvalidInput, "standard error has no socket")),
+                LogTarget::Syslog => SYSLOG_SOCKET,
+                LogTarget::Journald => JOURNALD_SOCKET,
+            };
+            let socket = std::os::unix::net::UnixDatagram::unbound()?;
+            socket.connect(path)?;
+            Ok(SocketLogger { target, socket, filter })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (target, filter);
+            Err(io::Error::new(io::ErrorKind::Unsupported, "syslog and journald are only supported on Unix"))
+        }
+    }
+
+    /// Sets this logger as the logger of the process.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if a logger has already been set.
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        log::set_max_level(self.filter.filter());
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Encodes a record in the format of the target.
+    fn encode(&self, record: &Record) -> Vec<u8> {
+        let mut fields = Fields::default();
+        // Fields are only collected from in-memory values, which never fails
+        let _ = record.key_values().visit(&mut fields);
+        let message = record.args().to_string();
+        match self.target {
+            LogTarget::Journald => journald_message(record.level(), record.target(), &message, &fields.0),
+            LogTarget::Stderr | LogTarget::Syslog => syslog_message(record.level(), &message, &fields.0),
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl Log for SocketLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let message = self.encode(record);
+        // There is nowhere left to report a message that could not be sent
+        #[cfg(unix)]
+        let _ = self.socket.send(&message);
+        #[cfg(not(unix))]
+        let _ = message;
+    }
+
+    fn flush(&self) {}
+}
+
+/// The fields attached to a record, as strings.
+#[cfg(feature = "cli")]
+#[derive(Debug, Default)]
+struct Fields(Vec<(String, String)>);
+
+#[cfg(feature = "cli")]
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+/// Logs an event at the given level, with the fields of its JSON attached as `event_type`,
+/// `event_ups`, and `event_` followed by the name of every other field.
+pub fn log_event(level: Level, event: &Event, ups_name: &str) {
+    if level > log::max_level() {
+        return;
+    }
+    let Value::Object(json) = event.to_json(ups_name, SystemTime::now()) else {
+        unreachable!("events are always serialized as objects");
+    };
+    let fields: Vec<(String, String)> = json
+        .into_iter()
+        .filter(|(name, _)| name != "timestamp")
+        .filter_map(|(name, value)| match value {
+            Value::Null => None,
+            Value::String(value) => Some((format!("event_{name}"), value)),
+            value => Some((format!("event_{name}"), value.to_string())),
+        })
+        .collect();
+    log::logger().log(
+        &Record::builder()
+            .level(level)
+            .target(module_path!())
+            .args(format_args!("{event}"))
+            .key_values(&fields.as_slice())
+            .build(),
+    );
+}
+
+/// Returns the syslog severity of a level.
+#[cfg(feature = "cli")]
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Formats a message for the local syslog daemon, which adds the time it was received.
+#[cfg(feature = "cli")]
+fn syslog_message(level: Level, message: &str, fields: &[(String, String)]) -> Vec<u8> {
+    let priority = FACILITY_DAEMON * 8 + severity(level);
+    let mut line = format!("<{priority}>{IDENTIFIER}[{}]: {message}", std::process::id());
+    for (key, value) in fields {
+        let _ = write!(line, " {key}={value}");
+    }
+    line.into_bytes()
+}
+
+/// Formats a message in the native protocol of the systemd journal, with fields named in upper
+/// case as the journal requires.
+#[cfg(feature = "cli")]
+fn journald_message(level: Level, target: &str, message: &str, fields: &[(String, String)]) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    let priority = severity(level).to_string();
+    let standard = [("PRIORITY", priority.as_str()), ("SYSLOG_IDENTIFIER", IDENTIFIER), ("TARGET", target), ("MESSAGE", message)];
+    for (name, value) in standard {
+        append_field(&mut datagram, name, value);
+    }
+    for (key, value) in fields {
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        // Fields starting with an underscore are reserved for the journal itself
+        let name = name.trim_start_matches(|c: char| c == '_' || c.is_ascii_digit());
+        if !name.is_empty() {
+            append_field(&mut datagram, name, value);
+        }
+    }
+    datagram
+}
+
+/// Appends a field to a journal message, in the binary form if its value spans several lines.
+#[cfg(feature = "cli")]
+fn append_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn format_messages() {
+        let fields = [(String::from("event_type"), String::from("status_changed"))];
+        let syslog = String::from_utf8(syslog_message(Level::Warn, "On battery", &fields)).unwrap();
+        assert_eq!(syslog, format!("<28>pistachio[{}]: On battery event_type=status_changed", std::process::id()));
+
+        let journald = journald_message(Level::Info, "pistachio", "two\nlines", &fields);
+        let mut expected = b"PRIORITY=6\nSYSLOG_IDENTIFIER=pistachio\nTARGET=pistachio\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\nEVENT_TYPE=status_changed\n");
+        assert_eq!(journald, expected);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn filter_with_level() {
+        let format = LogFormat {
+            level: Some(LogLevel::Warn),
+            ..LogFormat::default()
+        };
+        let logger = format.builder().build();
+        assert_eq!(logger.filter(), LevelFilter::Warn);
+        assert!(!logger.enabled(&Metadata::builder().level(Level::Info).target("pistachio").build()));
+    }
+
+    #[cfg(all(unix, feature = "cli"))]
+    #[test]
+    fn send_to_socket() {
+        let (socket, receiver) = std::os::unix::net::UnixDatagram::pair().unwrap();
+        let filter = env_logger::Builder::new().parse_filters("info").build();
+        let logger = SocketLogger {
+            target: LogTarget::Journald,
+            socket,
+            filter,
+        };
+        let fields = [("event_alarm", "Replace battery")];
+        logger.log(&Record::builder().level(Level::Debug).args(format_args!("hidden")).build());
+        logger.log(
+            &Record::builder()
+                .level(Level::Error)
+                .target("pistachio")
+                .args(format_args!("Alarm raised"))
+                .key_values(&fields)
+                .build(),
+        );
+        let mut buffer = [0; 512];
+        let received = receiver.recv(&mut buffer).unwrap();
+        let received = String::from_utf8_lossy(&buffer[..received]);
+        assert!(received.starts_with("PRIORITY=3\n"));
+        assert!(received.contains("MESSAGE=Alarm raised\n"));
+        assert!(received.ends_with("EVENT_ALARM=Replace battery\n"));
+    }
+}
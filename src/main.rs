@@ -1,46 +1,246 @@
 use clap::Parser;
-use env_logger::{Builder, Env};
-use log::{error, info};
-use std::net::SocketAddr;
+use log::{error, info, warn};
+use pistachio::diff::VarDiff;
+use pistachio::logging::{LogFormat, LogTarget, SocketLogger};
+use pistachio::replay::ReplayClient;
+use pistachio::simulate::Simulator;
+use pistachio::top::Fleet;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::io::{IsTerminal, Write};
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-fn main() {
-    // Initialize logging
-    Builder::from_env(Env::default().default_filter_or("info")).init();
+/// Time between checks of whether the simulator was asked to terminate.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
 
+/// Time between redraws of the table of `top`, so the age of the last polls keeps counting.
+const TOP_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+fn main() {
     // Parse configuration
-    let args = pistachio::Args::parse();
-    info!(
-        "UPS {}@{}:{} will be checked every {} seconds",
-        args.ups_name, args.ups_host, args.ups_port, args.poll_rate
-    );
+    let mut args = pistachio::Args::parse();
 
-    // Create connection to UPS
-    let mut conn = pistachio::create_connection(&args).unwrap_or_else(|err| {
-        error!("Could not connect to the UPS: {err}");
+    // Initialize logging
+    let format = LogFormat {
+        level: args.log_level,
+        timestamps: args.log_timestamps,
+        color: args.log_color,
+    };
+    // Spans only exist in `tracing`, whose messages cannot be sent to the other targets
+    #[cfg(feature = "tracing")]
+    if args.log_target != LogTarget::Stderr {
+        use clap::CommandFactory;
+        let message = format!("--log-target {} cannot be used with the `tracing` feature, which only logs to stderr", args.log_target);
+        pistachio::Args::command().error(clap::error::ErrorKind::ArgumentConflict, message).exit();
+    }
+    init_logging(args.log_target, format);
+    pistachio::crash::install_panic_hook();
+    let command = args.command.take();
+    match &command {
+        Some(pistachio::Command::Simulate(simulate)) => {
+            simulate_ups(simulate);
+            return;
+        }
+        Some(pistachio::Command::Diff(diff)) => diff_snapshots(diff),
+        Some(pistachio::Command::Top(top)) => {
+            show_top(&args, top);
+            return;
+        }
+        _ => {}
+    }
+    let dump_metadata = args.dump_metadata;
+    let config = pistachio::Config::from(args);
+    config.validate().unwrap_or_else(|err| {
+        error!("Invalid configuration: {err}");
         process::exit(1);
     });
+    if dump_metadata {
+        let metadata = pistachio::create_connection(&config)
+            .and_then(|mut conn| {
+                pistachio::metadata::get_metadata_concurrently(
+                    &mut conn,
+                    || pistachio::create_connection(&config),
+                    &config.ups_name,
+                    config.metadata_connections,
+                )
+            })
+            .unwrap_or_else(|err| {
+                error!("Could not get variable metadata from the UPS: {err}");
+                process::exit(1);
+            });
+        println!("{}", serde_json::to_string_pretty(&metadata).expect("metadata is always serializable"));
+        return;
+    }
+    info!(
+        "Starting pistachio {} (commit {}, built {}, features: {})",
+        pistachio::version::VERSION,
+        pistachio::version::GIT_COMMIT,
+        pistachio::version::BUILD_DATE,
+        pistachio::version::FEATURES
+    );
+    match command {
+        Some(pistachio::Command::Record(record)) => {
+            let shutdown = shutdown_flag();
+            exit_on_error(pistachio::record(&config, &record.output, &shutdown));
+            return;
+        }
+        Some(pistachio::Command::Replay(replay)) => {
+            let shutdown = shutdown_flag();
+            let client = pistachio::replay::load(&replay.input)
+                .and_then(|polls| ReplayClient::new(polls, replay.speed))
+                .map(|client| client.until(Arc::clone(&shutdown)));
+            let client = if replay.exit { client.map(ReplayClient::stop_when_finished) } else { client };
+            let client = client.unwrap_or_else(|err| {
+                error!("Could not replay {}: {err}", replay.input.display());
+                process::exit(1);
+            });
+            info!("Polls recorded in {} will be replayed at {}x speed", replay.input.display(), replay.speed);
+            exit_on_error(pistachio::replay(&config, client, &shutdown));
+            return;
+        }
+        Some(pistachio::Command::Simulate(_) | pistachio::Command::Diff(_) | pistachio::Command::Top(_)) | None => {}
+    }
+    match config.backend {
+        pistachio::Backend::Nut => info!(
+            "UPS {}@{}:{} will be checked every {} seconds",
+            config.ups_name, config.ups_host, config.ups_port, config.poll_rate
+        ),
+        backend => info!("UPS {} will be read with the {backend} backend every {} seconds", config.ups_name, config.poll_rate),
+    }
 
-    // Get list of available UPS vars
-    let ups_vars = pistachio::get_ups_vars(&args, &mut conn).unwrap_or_else(|err| {
-        error!("Could not get list of available variables from the UPS: {err}");
-        process::exit(1);
-    });
+    // Stop polling gracefully when asked to terminate
+    let shutdown = shutdown_flag();
+
+    // Run pistachio
+    exit_on_error(pistachio::run(&config, &shutdown));
+}
 
-    // Create Prometheus metrics from available ups variables
-    let metrics = pistachio::Metrics::build(&ups_vars).unwrap_or_else(|err| {
-        error!("Could not create prometheus gauges from UPS variables: {err}");
+/// Exits with an error if running failed, or logs that it shut down cleanly.
+fn exit_on_error(result: pistachio::Result<()>) {
+    if let Err(err) = result {
+        error!("{err}");
         process::exit(1);
-    });
-    info!("{} gauges will be exported", metrics.count());
+    }
+    info!("Shut down cleanly");
+}
+
+/// Returns a flag that is set when the process is asked to terminate.
+fn shutdown_flag() -> Arc<AtomicBool> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register(signal, Arc::clone(&shutdown)).unwrap_or_else(|err| {
+            error!("Failed to register signal handler: {err}");
+            process::exit(1);
+        });
+    }
+    shutdown
+}
 
-    // Start prometheus exporter
-    let bind_addr = SocketAddr::new(args.bind_ip, args.bind_port);
-    prometheus_exporter::start(bind_addr).unwrap_or_else(|err| {
-        error!("Failed to start prometheus exporter: {err}");
+/// Prints the differences between two snapshots of a UPS, and exits with status 1 if there are
+/// any, like `diff`.
+fn diff_snapshots(args: &pistachio::DiffArgs) -> ! {
+    let load = |source: &str| {
+        pistachio::diff::load(source).unwrap_or_else(|err| {
+            error!("Could not read the variables of {source}: {err}");
+            process::exit(2);
+        })
+    };
+    let mut diff = VarDiff::new(&load(&args.before), &load(&args.after));
+    if args.ignore_values {
+        diff = diff.without_changes();
+    }
+    println!("{diff}");
+    process::exit(i32::from(!diff.is_empty()));
+}
+
+/// Shows a live table of several UPSes until the process is asked to terminate. The screen is
+/// redrawn in place on a terminal, and every table is printed after the previous one otherwise.
+fn show_top(args: &pistachio::Args, top: &pistachio::TopArgs) {
+    let mut targets = if top.targets.is_empty() { args.ups.clone() } else { top.targets.clone() };
+    if targets.is_empty() {
+        targets.push(pistachio::UpsTarget {
+            name: args.ups_name.clone(),
+            host: args.ups_host.clone(),
+            port: args.ups_port,
+        });
+    }
+    let shutdown = shutdown_flag();
+    let fleet = Fleet::start(targets, Duration::from_secs(top.interval.max(1)), &shutdown);
+    let is_terminal = std::io::stdout().is_terminal();
+    while !shutdown.load(Ordering::Relaxed) {
+        let table = pistachio::top::render(&fleet.rows(top.sort), Instant::now());
+        if is_terminal {
+            // Move to the top left corner and clear the screen before drawing
+            print!("\x1b[H\x1b[2J{table}");
+        } else {
+            println!("{table}");
+        }
+        let _ = std::io::stdout().flush();
+        let deadline = Instant::now() + TOP_REFRESH_INTERVAL;
+        while !shutdown.load(Ordering::Relaxed) && Instant::now() < deadline {
+            thread::sleep(SHUTDOWN_CHECK_INTERVAL);
+        }
+    }
+}
+
+/// Serves a simulated UPS over the NUT protocol until the process is asked to terminate.
+fn simulate_ups(args: &pistachio::SimulateArgs) {
+    let shutdown = shutdown_flag();
+    let simulator = Simulator::new(&args.name, args.scenario, Duration::from_secs(args.delay));
+    if let Err(err) = simulator.start(args.listen) {
+        error!("Failed to serve the simulated UPS on {}: {err}", args.listen);
         process::exit(1);
-    });
+    }
+    while !shutdown.load(Ordering::Relaxed) {
+        thread::sleep(SHUTDOWN_CHECK_INTERVAL);
+    }
+    info!("Shut down cleanly");
+}
 
-    // Run pistachio
-    pistachio::run(&args, &mut conn, &metrics);
+/// Sends log messages to the given target, falling back to standard error if it cannot be
+/// reached.
+fn init_logging(target: LogTarget, format: LogFormat) {
+    let err = match target {
+        LogTarget::Stderr => None,
+        target => match SocketLogger::connect(target, format.builder().build()) {
+            Ok(logger) => {
+                logger.init().expect("the logger is only set once");
+                return;
+            }
+            Err(err) => Some(err),
+        },
+    };
+    #[cfg(not(feature = "tracing"))]
+    format.builder().init();
+    #[cfg(feature = "tracing")]
+    init_tracing(format);
+    if let Some(err) = err {
+        warn!("Could not log to {target}, logging to standard error instead: {err}");
+    }
+}
+
+/// Sends all log messages through `tracing`, so they are emitted with the spans around polls,
+/// reconnects and HTTP requests they happened in. Filtered with `RUST_LOG` like `env_logger`
+/// unless a level is given.
+#[cfg(feature = "tracing")]
+fn init_tracing(format: LogFormat) {
+    use pistachio::logging::{LogColor, LogTimestamps};
+    use tracing_subscriber::EnvFilter;
+    let filter = match format.level {
+        Some(level) => EnvFilter::new(level.filter().as_str()),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let ansi = match format.color {
+        LogColor::Auto => std::io::stderr().is_terminal(),
+        LogColor::Always => true,
+        LogColor::Never => false,
+    };
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter).with_ansi(ansi).with_writer(std::io::stderr);
+    match format.timestamps {
+        LogTimestamps::Rfc3339 => subscriber.init(),
+        LogTimestamps::None => subscriber.without_time().init(),
+    }
 }
@@ -0,0 +1,449 @@
+//! Metadata describing the variables of a UPS: their descriptions, types, and allowed values, as
+//! well as the instant commands it supports.
+//!
+//! Some NUT servers describe variables poorly, with an empty description or `Description
+//! unavailable`, in which case the description of common variables is taken from a built-in
+//! dictionary. Descriptions can also be replaced or extended per variable with [`HelpText`].
+
+use crate::client::UpsClient;
+use crate::variable::dedup;
+use crate::{Error, Result, Variable, VariableDefinition};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::thread;
+
+/// Descriptions of common variables, as given by NUT, used when the server does not describe
+/// them, and by the backends and the simulator that serve them without a NUT server.
+const KNOWN_DESCRIPTIONS: &[(&str, &str)] = &[
+    ("ambient.humidity", "Ambient relative humidity (percent)"),
+    ("ambient.temperature", "Ambient temperature (degrees C)"),
+    ("battery.charge", "Battery charge (percent of full)"),
+    ("battery.charge.low", "Remaining battery level when UPS switches to LB (percent)"),
+    ("battery.charge.warning", "Battery level when UPS switches to Warning state (percent)"),
+    ("battery.current", "Battery current (A)"),
+    ("battery.date", "Battery change date"),
+    ("battery.mfr.date", "Battery manufacturing date"),
+    ("battery.packs", "Number of battery packs"),
+    ("battery.packs.bad", "Number of bad battery packs"),
+    ("battery.runtime", "Battery runtime (seconds)"),
+    ("battery.runtime.low", "Remaining battery runtime when UPS switches to LB (seconds)"),
+    ("battery.temperature", "Battery temperature (degrees C)"),
+    ("battery.type", "Battery chemistry"),
+    ("battery.voltage", "Battery voltage (V)"),
+    ("battery.voltage.nominal", "Nominal battery voltage (V)"),
+    ("device.mfr", "Device manufacturer"),
+    ("device.model", "Device model"),
+    ("device.serial", "Device serial number"),
+    ("device.type", "Device type"),
+    ("device.uptime", "Device uptime (seconds)"),
+    ("driver.name", "Driver name"),
+    ("input.current", "Input current (A)"),
+    ("input.frequency", "Input line frequency (Hz)"),
+    ("input.frequency.nominal", "Nominal input line frequency (Hz)"),
+    ("input.sensitivity", "Input power sensitivity"),
+    ("input.transfer.high", "High voltage transfer point (V)"),
+    ("input.transfer.low", "Low voltage transfer point (V)"),
+    ("input.transfer.reason", "Reason for last transfer to battery"),
+    ("input.voltage", "Input voltage (V)"),
+    ("input.voltage.nominal", "Nominal input voltage (V)"),
+    ("output.current", "Output current (A)"),
+    ("output.frequency", "Output frequency (Hz)"),
+    ("output.frequency.nominal", "Nominal output frequency (Hz)"),
+    ("output.voltage", "Output voltage (V)"),
+    ("output.voltage.nominal", "Nominal output voltage (V)"),
+    ("ups.beeper.status", "UPS beeper status"),
+    ("ups.date", "UPS system date"),
+    ("ups.delay.shutdown", "Interval to wait after shutdown with delay command (seconds)"),
+    ("ups.delay.start", "Interval to wait before (re)starting the load (seconds)"),
+    ("ups.firmware", "UPS firmware"),
+    ("ups.load", "Load on UPS (percent of full)"),
+    ("ups.mfr", "UPS manufacturer"),
+    ("ups.mfr.date", "UPS manufacturing date"),
+    ("ups.model", "UPS model"),
+    ("ups.power", "Current value of apparent power (VA)"),
+    ("ups.power.nominal", "UPS power rating (VA)"),
+    ("ups.realpower", "Current value of real power (W)"),
+    ("ups.realpower.nominal", "UPS real power rating (W)"),
+    ("ups.serial", "UPS serial number"),
+    ("ups.status", "UPS status"),
+    ("ups.temperature", "UPS temperature (degrees C)"),
+    ("ups.test.result", "Results of last self test"),
+    ("ups.time", "UPS system time"),
+    ("ups.timer.shutdown", "Time before the load will be shutdown (seconds)"),
+    ("ups.timer.start", "Time before the load will be started (seconds)"),
+];
+
+/// The type of a UPS variable, along with the values it may take.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VarKind {
+    /// A number.
+    Number,
+    /// A string, with its maximum length if known.
+    String {
+        /// Maximum length of the string.
+        max_length: Option<usize>,
+    },
+    /// One of an enumeration of values.
+    Enum {
+        /// Every allowed value.
+        values: Vec<String>,
+    },
+    /// A number within one of several ranges.
+    Range {
+        /// Every allowed range, as inclusive minimum and maximum values.
+        ranges: Vec<(String, String)>,
+    },
+}
+
+/// Everything known about a variable of a UPS.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VarMetadata {
+    /// Name of the variable.
+    pub name: String,
+    /// Value of the variable when the metadata was retrieved.
+    pub value: String,
+    /// Description of the variable.
+    pub description: String,
+    /// Whether the variable can be changed.
+    pub writable: bool,
+    /// Type of the variable.
+    #[serde(flatten)]
+    pub kind: VarKind,
+}
+
+impl VarMetadata {
+    /// Returns the allowed values of an enumerated variable.
+    #[must_use]
+    pub fn enum_values(&self) -> Option<&[String]> {
+        match &self.kind {
+            VarKind::Enum { values } => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the description NUT gives a common variable, if it is one.
+pub(crate) fn known_description(name: &str) -> Option<&'static str> {
+    KNOWN_DESCRIPTIONS.iter().find(|(known, _)| *known == name).map(|(_, description)| *description)
+}
+
+/// A description given for a variable, written as `VAR=TEXT` to replace the description of the
+/// server, or as `VAR+=TEXT` to append to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HelpText {
+    /// Name of the variable.
+    pub variable: String,
+    /// The description, or the text appended to it.
+    pub text: String,
+    /// Whether the text is appended to the description instead of replacing it.
+    pub append: bool,
+}
+
+impl FromStr for HelpText {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<HelpText> {
+        let (variable, text) = input
+            .split_once('=')
+            .ok_or_else(|| Error::Parse(format!("expected VAR=TEXT or VAR+=TEXT, got `{input}`")))?;
+        let (variable, append) = match variable.strip_suffix('+') {
+            Some(variable) => (variable, true),
+            None => (variable, false),
+        };
+        let (variable, text) = (variable.trim(), text.trim());
+        if variable.is_empty() || text.is_empty() {
+            return Err(Error::Parse(format!("expected VAR=TEXT or VAR+=TEXT, got `{input}`")));
+        }
+        Ok(HelpText {
+            variable: variable.to_string(),
+            text: text.to_string(),
+            append,
+        })
+    }
+}
+
+impl TryFrom<String> for HelpText {
+    type Error = Error;
+
+    fn try_from(input: String) -> Result<HelpText> {
+        input.parse()
+    }
+}
+
+impl From<HelpText> for String {
+    fn from(help: HelpText) -> String {
+        let operator = if help.append { "+=" } else { "=" };
+        format!("{}{operator}{}", help.variable, help.text)
+    }
+}
+
+/// Returns whether a description tells anything about a variable, unlike the empty descriptions
+/// and `Description unavailable` reported by some servers.
+fn is_useful_description(name: &str, description: &str) -> bool {
+    let description = description.trim();
+    !description.is_empty() && !description.eq_ignore_ascii_case("description unavailable") && description != name
+}
+
+/// Fills in the description of variables the server does not describe from the built-in
+/// dictionary, then replaces or extends the descriptions given in `help`.
+pub fn apply_help_texts(metadata: &mut [VarMetadata], help: &[HelpText]) {
+    for var in metadata {
+        if !is_useful_description(&var.name, &var.description) {
+            if let Some(known) = known_description(&var.name) {
+                debug!("Variable {} is not described by the server, so it is described as {known:?}", var.name);
+                var.description = known.to_string();
+            }
+        }
+        if let Some(help) = help.iter().find(|help| help.variable == var.name) {
+            var.description = if help.append && is_useful_description(&var.name, &var.description) {
+                format!("{} {}", var.description.trim(), help.text)
+            } else {
+                help.text.clone()
+            };
+        }
+    }
+}
+
+/// An instant command supported by a UPS.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommandMetadata {
+    /// Name of the command, such as `test.battery.start`.
+    pub name: String,
+    /// Description of the command.
+    pub description: String,
+}
+
+/// Retrieves every instant command supported by a UPS, sorted by name.
+///
+/// Descriptions that cannot be retrieved are left empty instead of failing, since not every NUT
+/// server supports them.
+///
+/// # Errors
+///
+/// An error will be returned if the commands cannot be listed, such as if the connection to the
+/// server is lost.
+pub fn get_commands<C: UpsClient>(client: &mut C, ups_name: &str) -> Result<Vec<CommandMetadata>> {
+    let mut commands = Vec::new();
+    for name in client.list_commands(ups_name)? {
+        let description = match client.get_command_description(ups_name, &name) {
+            Ok(description) => description,
+            Err(Error::Protocol(err)) => {
+                debug!("Could not get description of command {name}: {err}");
+                String::new()
+            }
+            Err(err) => return Err(err),
+        };
+        commands.push(CommandMetadata { name, description });
+    }
+    commands.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(commands)
+}
+
+/// Retrieves the metadata of every variable of a UPS. A variable listed more than once is only
+/// described once, with the value listed last.
+///
+/// Some NUT servers report types that are not understood, in which case the type is guessed from
+/// the current value instead of failing.
+///
+/// # Errors
+///
+/// An error will be returned if the variables or their descriptions cannot be retrieved, such as
+/// if the connection to the server is lost.
+pub fn get_metadata<C: UpsClient>(client: &mut C, ups_name: &str) -> Result<Vec<VarMetadata>> {
+    let (vars, _) = dedup(client.list_vars(ups_name)?);
+    let mut metadata = describe_all(client, ups_name, &vars)?;
+    metadata.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(metadata)
+}
+
+/// Like [`get_metadata`], but spreads the requests for descriptions and types over up to
+/// `connections` connections at once, each opened with `connect`, which is much faster when
+/// every request is a slow round trip to a distant server. The variables themselves are listed
+/// with `client`, which is also used as one of the connections.
+///
+/// # Errors
+///
+/// An error will be returned if any connection cannot be opened, or if the variables or their
+/// descriptions cannot be retrieved.
+pub fn get_metadata_concurrently<C, F>(client: &mut C, connect: F, ups_name: &str, connections: usize) -> Result<Vec<VarMetadata>>
+where
+    C: UpsClient,
+    F: Fn() -> Result<C> + Sync,
+{
+    let (vars, _) = dedup(client.list_vars(ups_name)?);
+    let chunk_size = vars.len().div_ceil(connections.max(1)).max(1);
+    let mut chunks = vars.chunks(chunk_size);
+    let first = chunks.next().unwrap_or_default();
+    let mut metadata = thread::scope(|scope| {
+        let workers: Vec<_> = chunks
+            .map(|chunk| {
+                let connect = &connect;
+                scope.spawn(move || {
+                    let mut client = connect()?;
+                    let metadata = describe_all(&mut client, ups_name, chunk)?;
+                    if let Err(err) = client.close() {
+                        debug!("Failed to close metadata connection: {err}");
+                    }
+                    Ok(metadata)
+                })
+            })
+            .collect();
+        let mut metadata = describe_all(client, ups_name, first)?;
+        for worker in workers {
+            let result: Result<Vec<VarMetadata>> = worker.join().unwrap_or_else(|_| Err(Error::Config(String::from("metadata worker panicked"))));
+            metadata.extend(result?);
+        }
+        Ok::<_, Error>(metadata)
+    })?;
+    debug!("Retrieved metadata of {} variables over {} connections", metadata.len(), vars.len().div_ceil(chunk_size));
+    metadata.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(metadata)
+}
+
+/// Retrieves the metadata of each of the given variables.
+fn describe_all<C: UpsClient>(client: &mut C, ups_name: &str, vars: &[Variable]) -> Result<Vec<VarMetadata>> {
+    let mut metadata = Vec::with_capacity(vars.len());
+    for var in vars {
+        let name = var.name().to_string();
+        let value = var.value().to_string();
+        let description = client.get_var_description(ups_name, &name)?;
+        let (writable, kind) = match client.get_var_type(ups_name, &name) {
+            Ok(definition) => (definition.is_mutable(), var_kind(client, ups_name, &definition)?),
+            Err(Error::Protocol(err)) => {
+                debug!("Could not get type of variable {name}, guessing from its value: {err}");
+                (false, guess_kind(&value))
+            }
+            Err(err) => return Err(err),
+        };
+        metadata.push(VarMetadata {
+            name,
+            value,
+            description,
+            writable,
+            kind,
+        });
+    }
+    Ok(metadata)
+}
+
+/// Converts a variable definition into its kind, fetching the allowed values if needed.
+fn var_kind<C: UpsClient>(client: &mut C, ups_name: &str, definition: &VariableDefinition) -> Result<VarKind> {
+    let name = definition.name();
+    Ok(if definition.is_enum() {
+        VarKind::Enum {
+            values: client.list_var_enum(ups_name, name)?,
+        }
+    } else if definition.is_range() {
+        let ranges = client.list_var_range(ups_name, name)?;
+        VarKind::Range {
+            ranges: ranges.into_iter().map(|range| (range.min, range.max)).collect(),
+        }
+    } else if definition.is_string() {
+        VarKind::String {
+            max_length: definition.get_string_length(),
+        }
+    } else {
+        VarKind::Number
+    })
+}
+
+/// Guesses the kind of a variable from its value.
+fn guess_kind(value: &str) -> VarKind {
+    if crate::parse_number(value).is_some() {
+        VarKind::Number
+    } else {
+        VarKind::String { max_length: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockUpsClient;
+
+    fn client() -> MockUpsClient {
+        MockUpsClient::new()
+            .with_var("battery.charge", "100")
+            .with_var("input.sensitivity", "medium")
+            .with_var("input.transfer.low", "90")
+            .with_var("ups.mfr", "CyberPower")
+            .with_description("battery.charge", "Battery charge (percent)")
+            .with_enum("input.sensitivity", &["low", "medium", "high"])
+            .with_range("input.transfer.low", "80", "100")
+    }
+
+    #[test]
+    fn get_var_metadata() {
+        let metadata = get_metadata(&mut client(), "ups").unwrap();
+        assert_eq!(metadata.len(), 4);
+        assert_eq!(metadata[0].name, "battery.charge");
+        assert_eq!(metadata[0].description, "Battery charge (percent)");
+        assert_eq!(metadata[0].kind, VarKind::Number);
+        assert_eq!(metadata[1].enum_values().unwrap(), ["low", "medium", "high"]);
+        assert_eq!(
+            metadata[2].kind,
+            VarKind::Range {
+                ranges: vec![(String::from("80"), String::from("100"))]
+            }
+        );
+        assert_eq!(metadata[3].kind, VarKind::String { max_length: Some(64) });
+
+        let json = serde_json::to_value(&metadata[1]).unwrap();
+        assert_eq!(json["type"], "enum");
+        assert_eq!(json["values"][2], "high");
+    }
+
+    #[test]
+    fn apply_help_texts_to_metadata() {
+        let mut client = client()
+            .with_var("ups.load", "20")
+            .with_description("ups.load", "Description unavailable")
+            .with_description("ups.mfr", "");
+        let mut metadata = get_metadata(&mut client, "ups").unwrap();
+        let help: Vec<HelpText> = vec![
+            "battery.charge+=as reported by the UPS".parse().unwrap(),
+            "input.transfer.low=Lowest input voltage before switching to battery".parse().unwrap(),
+        ];
+        assert_eq!(String::from(help[0].clone()), "battery.charge+=as reported by the UPS");
+        apply_help_texts(&mut metadata, &help);
+        let descriptions: Vec<&str> = metadata.iter().map(|var| var.description.as_str()).collect();
+        assert_eq!(
+            descriptions,
+            [
+                "Battery charge (percent) as reported by the UPS",
+                "Input power sensitivity",
+                "Lowest input voltage before switching to battery",
+                "Load on UPS (percent of full)",
+                "UPS manufacturer",
+            ]
+        );
+        assert!("ups.load".parse::<HelpText>().is_err());
+        assert!("=Load".parse::<HelpText>().is_err());
+    }
+
+    #[test]
+    fn get_supported_commands() {
+        let mut client = MockUpsClient::new()
+            .with_command("test.battery.start", "Start a battery test")
+            .with_command("beeper.mute", "Temporarily mute the UPS beeper");
+        let commands = get_commands(&mut client, "ups").unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].name, "beeper.mute");
+        assert_eq!(commands[1].description, "Start a battery test");
+        assert!(get_commands(&mut MockUpsClient::new(), "ups").unwrap().is_empty());
+    }
+
+    #[test]
+    fn get_metadata_over_many_connections() {
+        let expected = get_metadata(&mut client(), "ups").unwrap();
+        for connections in [1, 3, 100] {
+            let metadata = get_metadata_concurrently(&mut client(), || Ok(client()), "ups", connections).unwrap();
+            assert_eq!(metadata, expected);
+        }
+        let err = get_metadata_concurrently(&mut client(), || Err(Error::Config(String::from("refused"))), "ups", 2);
+        assert!(matches!(err, Err(Error::Config(_))));
+    }
+}
@@ -0,0 +1,428 @@
+//! A backend that reads industrial UPSes and inverters over Modbus TCP, with the registers of the
+//! device described in a register map file, since Modbus devices have no standard layout.
+//!
+//! The register map is a JSON file naming the NUT variable each register is read as, so the rest
+//! of the exporter works the same as with a NUT server:
+//!
+//! ```json
+//! {
+//!   "unit_id": 1,
+//!   "registers": [
+//!     { "name": "battery.charge", "address": 100, "scale": 0.1 },
+//!     { "name": "battery.runtime", "address": 102, "type": "u32", "table": "input" },
+//!     { "name": "ups.status", "address": 110, "flags": { "OL": 0, "OB": 1, "LB": 2 } }
+//!   ]
+//! }
+//! ```
+
+use crate::client::UpsClient;
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use log::debug;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+/// Maximum time allowed for connecting to the device and for each request.
+const MODBUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Which table of the device a register is read from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Table {
+    /// Holding registers, read with function code 3.
+    #[default]
+    Holding,
+    /// Input registers, read with function code 4.
+    Input,
+}
+
+/// How the value of one or two registers is decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataType {
+    /// An unsigned 16 bit integer in one register.
+    #[default]
+    U16,
+    /// A signed 16 bit integer in one register.
+    I16,
+    /// An unsigned 32 bit integer in two registers.
+    U32,
+    /// A signed 32 bit integer in two registers.
+    I32,
+    /// A 32 bit floating point number in two registers.
+    F32,
+}
+
+impl DataType {
+    /// Returns the number of registers a value takes.
+    fn registers(self) -> u16 {
+        match self {
+            DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+        }
+    }
+}
+
+/// Order in which the registers of 32 bit values are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WordOrder {
+    /// The most significant register comes first.
+    #[default]
+    Big,
+    /// The least significant register comes first.
+    Little,
+}
+
+/// A register of the device, read as a NUT variable.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Register {
+    /// Name of the NUT variable, such as `battery.charge`.
+    pub name: String,
+    /// Description of the variable.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Address of the first register of the value, starting at 0.
+    pub address: u16,
+    /// Table the register is read from.
+    #[serde(default)]
+    pub table: Table,
+    /// How the value is decoded.
+    #[serde(default, rename = "type")]
+    pub data_type: DataType,
+    /// Factor the value is multiplied by, such as `0.1` for a value in tenths.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// Number added to the value after scaling it.
+    #[serde(default)]
+    pub offset: f64,
+    /// Flags of `ups.status` set by each bit of the value, numbered from the least significant.
+    /// If given, the variable is the list of flags whose bits are set instead of a number.
+    #[serde(default)]
+    pub flags: BTreeMap<String, u8>,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_unit_id() -> u8 {
+    1
+}
+
+/// The registers of a device, loaded from a register map file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterMap {
+    /// Unit identifier of the device, which matters when it is behind a gateway.
+    #[serde(default = "default_unit_id")]
+    pub unit_id: u8,
+    /// Order in which the registers of 32 bit values are stored.
+    #[serde(default)]
+    pub word_order: WordOrder,
+    /// Registers read on every poll.
+    pub registers: Vec<Register>,
+}
+
+impl RegisterMap {
+    /// Loads a register map from a JSON file.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the file cannot be read or is not a valid register map, such
+    /// as when a flag is set by a bit the value does not have.
+    pub fn load(path: &Path) -> Result<RegisterMap> {
+        let contents = fs::read_to_string(path).map_err(|source| Error::Io {
+            context: format!("could not read register map {}", path.display()),
+            source,
+        })?;
+        let map: RegisterMap =
+            serde_json::from_str(&contents).map_err(|err| Error::Config(format!("invalid register map {}: {err}", path.display())))?;
+        for register in &map.registers {
+            let bits = 16 * u32::from(register.data_type.registers());
+            if let Some((flag, bit)) = register.flags.iter().find(|(_, bit)| u32::from(**bit) >= bits) {
+                return Err(Error::Config(format!(
+                    "invalid register map {}: flag {flag} of {} is set by bit {bit}, but the value only has {bits} bits",
+                    path.display(),
+                    register.name
+                )));
+            }
+        }
+        Ok(map)
+    }
+
+    /// Decodes the value of a register from the words read from the device.
+    fn decode(&self, register: &Register, words: &[u16]) -> Option<String> {
+        if words.len() != usize::from(register.data_type.registers()) {
+            return None;
+        }
+        let raw = match words {
+            [word] => u32::from(*word),
+            [first, second] => {
+                let (high, low) = match self.word_order {
+                    WordOrder::Big => (first, second),
+                    WordOrder::Little => (second, first),
+                };
+                u32::from(*high) << 16 | u32::from(*low)
+            }
+            _ => return None,
+        };
+        if !register.flags.is_empty() {
+            let set = |bit: &u8| raw.checked_shr(u32::from(*bit)).is_some_and(|raw| raw & 1 == 1);
+            let mut flags: Vec<(&String, &u8)> = register.flags.iter().filter(|(_, bit)| set(bit)).collect();
+            flags.sort_by_key(|(_, bit)| **bit);
+            return Some(flags.into_iter().map(|(flag, _)| flag.as_str()).collect::<Vec<_>>().join(" "));
+        }
+        let value = match register.data_type {
+            DataType::U16 | DataType::U32 => f64::from(raw),
+            DataType::I16 => f64::from(raw as u16 as i16),
+            DataType::I32 => f64::from(raw as i32),
+            DataType::F32 => f64::from(f32::from_bits(raw)),
+        };
+        let value = value * register.scale + register.offset;
+        value.is_finite().then(|| ((value * 1000.0).round() / 1000.0).to_string())
+    }
+}
+
+/// A client that reads a UPS from a Modbus TCP device, instead of from a NUT server.
+#[derive(Debug)]
+pub struct ModbusClient {
+    host: String,
+    port: u16,
+    map: RegisterMap,
+    stream: Option<TcpStream>,
+    transaction: u16,
+}
+
+impl ModbusClient {
+    /// Creates a client for the device at the given host and port, which is usually `502`. The
+    /// connection is opened on the first request, and reopened after any error.
+    #[must_use]
+    pub fn new(host: &str, port: u16, map: RegisterMap) -> ModbusClient {
+        ModbusClient {
+            host: host.to_string(),
+            port,
+            map,
+            stream: None,
+            transaction: 0,
+        }
+    }
+
+    /// Reads consecutive registers from the device.
+    fn read_registers(&mut self, table: Table, address: u16, count: u16) -> io::Result<Vec<u16>> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                let addr = (self.host.as_str(), self.port)
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to an address", self.host)))?;
+                let stream = TcpStream::connect_timeout(&addr, MODBUS_TIMEOUT)?;
+                stream.set_read_timeout(Some(MODBUS_TIMEOUT))?;
+                stream.set_write_timeout(Some(MODBUS_TIMEOUT))?;
+                self.stream.insert(stream)
+            }
+        };
+        self.transaction = self.transaction.wrapping_add(1);
+        let function = match table {
+            Table::Holding => 0x03,
+            Table::Input => 0x04,
+        };
+
+        let mut request = Vec::with_capacity(12);
+        request.extend(self.transaction.to_be_bytes());
+        request.extend([0, 0, 0, 6, self.map.unit_id, function]);
+        request.extend(address.to_be_bytes());
+        request.extend(count.to_be_bytes());
+        stream.write_all(&request)?;
+
+        let mut header = [0; 7];
+        stream.read_exact(&mut header)?;
+        let len = usize::from(u16::from_be_bytes([header[4], header[5]])).saturating_sub(1);
+        let mut pdu = vec![0; len];
+        stream.read_exact(&mut pdu)?;
+        if u16::from_be_bytes([header[0], header[1]]) != self.transaction {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "response to another request"));
+        }
+        match pdu[..] {
+            [code, exception, ..] if code == function | 0x80 => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, format!("device returned exception {exception}")))
+            }
+            [code, bytes, ref data @ ..] if code == function && usize::from(bytes) == data.len() && data.len() == 2 * usize::from(count) => {
+                Ok(data.chunks(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect())
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed Modbus response")),
+        }
+    }
+}
+
+impl UpsClient for ModbusClient {
+    fn list_vars(&mut self, _ups_name: &str) -> Result<Vec<Variable>> {
+        let mut vars = Vec::new();
+        for register in self.map.registers.clone() {
+            match self.read_registers(register.table, register.address, register.data_type.registers()) {
+                Ok(words) => {
+                    if let Some(value) = self.map.decode(&register, &words) {
+                        vars.push(Variable::new(&register.name, value));
+                    }
+                }
+                // The device rejected the request, but the connection still works
+                Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+                    debug!("Could not read {} from register {}: {err}", register.name, register.address);
+                }
+                Err(err) => {
+                    self.stream = None;
+                    return Err(Error::Connection(err));
+                }
+            }
+        }
+        Ok(vars)
+    }
+
+    fn get_var_description(&mut self, _ups_name: &str, var_name: &str) -> Result<String> {
+        let description = self
+            .map
+            .registers
+            .iter()
+            .find(|register| register.name == var_name)
+            .and_then(|register| register.description.clone());
+        Ok(description.unwrap_or_else(|| String::from("Description unavailable")))
+    }
+
+    fn get_var_type(&mut self, _ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        let flags = self
+            .map
+            .registers
+            .iter()
+            .any(|register| register.name == var_name && !register.flags.is_empty());
+        let kind = if flags { "STRING:64" } else { "NUMBER" };
+        VariableDefinition::parse(var_name, &[kind])
+    }
+
+    fn list_var_enum(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_var_range(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<VariableRange>> {
+        Ok(Vec::new())
+    }
+
+    fn list_clients(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_commands(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn get_command_description(&mut self, _ups_name: &str, _command: &str) -> Result<String> {
+        Err(Error::Protocol(rups::NutError::CmdNotSupported))
+    }
+
+    fn close(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const MAP: &str = r#"{
+        "registers": [
+            { "name": "battery.charge", "address": 100, "scale": 0.1, "description": "Battery charge (percent of full)" },
+            { "name": "battery.runtime", "address": 102, "type": "u32", "table": "input" },
+            { "name": "ups.temperature", "address": 104, "type": "i16" },
+            { "name": "ups.load", "address": 200 },
+            { "name": "ups.status", "address": 110, "flags": { "OL": 0, "OB": 1, "LB": 2 } }
+        ]
+    }"#;
+
+    #[test]
+    fn decode_registers() {
+        let map: RegisterMap = serde_json::from_str(MAP).unwrap();
+        assert_eq!(map.unit_id, 1);
+        let registers = &map.registers;
+        assert_eq!(map.decode(&registers[0], &[875]), Some(String::from("87.5")));
+        assert_eq!(map.decode(&registers[1], &[0x0001, 0x0000]), Some(String::from("65536")));
+        assert_eq!(map.decode(&registers[2], &[0xFFF6]), Some(String::from("-10")));
+        assert_eq!(map.decode(&registers[4], &[0b110]), Some(String::from("OB LB")));
+        assert_eq!(map.decode(&registers[0], &[1, 2]), None);
+
+        let little = RegisterMap {
+            word_order: WordOrder::Little,
+            ..map.clone()
+        };
+        assert_eq!(little.decode(&registers[1], &[0x0001, 0x0000]), Some(String::from("1")));
+        assert!(serde_json::from_str::<RegisterMap>(r#"{"registers": [{"name": "x", "address": 1, "type": "u8"}]}"#).is_err());
+    }
+
+    #[test]
+    fn load_register_maps() {
+        let path = std::env::temp_dir().join(format!("pistachio-register-map-{}.json", std::process::id()));
+        fs::write(&path, MAP).unwrap();
+        assert_eq!(RegisterMap::load(&path).unwrap(), serde_json::from_str(MAP).unwrap());
+        fs::write(&path, r#"{"registers": [{"name": "ups.status", "address": 110, "flags": {"OL": 0, "OB": 16}}]}"#).unwrap();
+        assert!(matches!(RegisterMap::load(&path), Err(Error::Config(_))));
+        let map = r#"{"registers": [{"name": "ups.status", "address": 110, "type": "u32", "flags": {"OL": 31, "OB": 32}}]}"#;
+        fs::write(&path, map).unwrap();
+        assert!(matches!(RegisterMap::load(&path), Err(Error::Config(_))));
+        // Maps built without loading them never panic on such bits
+        let map: RegisterMap = serde_json::from_str(map).unwrap();
+        assert_eq!(map.decode(&map.registers[0], &[0x8000, 0]), Some(String::from("OL")));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_device() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let registers: HashMap<(u8, u16), u16> = [((3, 100), 875), ((4, 102), 0), ((4, 103), 1200), ((3, 104), 25), ((3, 110), 0b001)].into();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 12];
+            while stream.read_exact(&mut request).is_ok() {
+                let function = request[7];
+                let address = u16::from_be_bytes([request[8], request[9]]);
+                let count = u16::from_be_bytes([request[10], request[11]]);
+                let words: Option<Vec<u16>> = (address..address + count).map(|address| registers.get(&(function, address)).copied()).collect();
+                let pdu = match words {
+                    Some(words) => [vec![function, (2 * words.len()) as u8], words.iter().flat_map(|word| word.to_be_bytes()).collect()].concat(),
+                    None => vec![function | 0x80, 2],
+                };
+                let mut response = request[..4].to_vec();
+                response.extend((u16::try_from(pdu.len()).unwrap() + 1).to_be_bytes());
+                response.push(request[6]);
+                response.extend(pdu);
+                stream.write_all(&response).unwrap();
+            }
+        });
+
+        let map: RegisterMap = serde_json::from_str(MAP).unwrap();
+        let mut client = ModbusClient::new("127.0.0.1", port, map);
+        let vars: HashMap<String, String> = client
+            .list_vars("ups")
+            .unwrap()
+            .into_iter()
+            .map(|var| (var.name().to_string(), var.value().to_string()))
+            .collect();
+        assert_eq!(vars["battery.charge"], "87.5");
+        assert_eq!(vars["battery.runtime"], "1200");
+        assert_eq!(vars["ups.temperature"], "25");
+        assert_eq!(vars["ups.status"], "OL");
+        // An address the device does not have is skipped
+        assert!(!vars.contains_key("ups.load"));
+        assert_eq!(client.get_var_description("ups", "battery.charge").unwrap(), "Battery charge (percent of full)");
+        assert!(client.get_var_type("ups", "ups.status").unwrap().is_string());
+        drop(client);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Naming schemes that export the gauges of UPS variables under the names and labels used by
+//! other NUT exporters, so dashboards and alerts written for them keep working with pistachio.
+//!
+//! Gauges are renamed when `/metrics` is served, from the variable each gauge was created for.
+//! Metrics of pistachio itself and gauges of variables that a scheme has no name for keep their
+//! own names.
+
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Names of gauges in the scheme of `HON95/prometheus-nut-exporter`, by variable, along with the
+/// factor values are multiplied by, since percentages are exported as ratios.
+const HON95_NAMES: &[(&str, &str, f64)] = &[
+    ("battery.charge", "nut_battery_charge", 0.01),
+    ("battery.runtime", "nut_battery_runtime_seconds", 1.0),
+    ("battery.temperature", "nut_battery_temperature_celsius", 1.0),
+    ("battery.voltage", "nut_battery_voltage_volts", 1.0),
+    ("battery.voltage.nominal", "nut_battery_voltage_nominal_volts", 1.0),
+    ("input.current", "nut_input_current_amperes", 1.0),
+    ("input.frequency", "nut_input_frequency_hertz", 1.0),
+    ("input.voltage", "nut_input_voltage_volts", 1.0),
+    ("input.voltage.nominal", "nut_input_voltage_nominal_volts", 1.0),
+    ("output.current", "nut_output_current_amperes", 1.0),
+    ("output.frequency", "nut_output_frequency_hertz", 1.0),
+    ("output.voltage", "nut_output_voltage_volts", 1.0),
+    ("output.voltage.nominal", "nut_output_voltage_nominal_volts", 1.0),
+    ("ups.beeper.status", "nut_beeper_status", 1.0),
+    ("ups.load", "nut_load", 0.01),
+    ("ups.realpower", "nut_real_power_watts", 1.0),
+    ("ups.realpower.nominal", "nut_power_nominal_watts", 1.0),
+    ("ups.status", "nut_status", 1.0),
+    ("ups.temperature", "nut_temperature_celsius", 1.0),
+];
+
+/// Prefix of every gauge in the scheme of `DRuggeri/nut_exporter`.
+const NUT_EXPORTER_PREFIX: &str = "network_ups_tools_";
+
+/// Names and labels that the gauges of UPS variables are exported with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum NamingScheme {
+    /// The names of pistachio, such as `ups_battery_charge`.
+    #[default]
+    Pistachio,
+    /// The names of `HON95/prometheus-nut-exporter`, such as `nut_battery_charge` as a ratio,
+    /// with a `ups` label.
+    Hon95,
+    /// The names of the Go `DRuggeri/nut_exporter`, such as `network_ups_tools_battery_charge`,
+    /// with a `ups` label and the flags of `ups.status` in a `flag` label.
+    #[cfg_attr(feature = "cli", value(name = "nut_exporter"))]
+    NutExporter,
+}
+
+impl fmt::Display for NamingScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NamingScheme::Pistachio => "pistachio",
+            NamingScheme::Hon95 => "hon95",
+            NamingScheme::NutExporter => "nut_exporter",
+        })
+    }
+}
+
+/// How a gauge is exported in another scheme.
+struct Mapping {
+    name: String,
+    factor: f64,
+    /// Labels that are renamed, as the name in pistachio and the name in the scheme.
+    labels: &'static [(&'static str, &'static str)],
+}
+
+/// Renames the gauges of UPS variables to the names of a [`NamingScheme`].
+#[derive(Debug, Clone, Default)]
+pub struct Renamer {
+    scheme: NamingScheme,
+    ups_name: String,
+    /// Name of the variable each gauge was created for, by metric name.
+    vars: HashMap<String, String>,
+}
+
+impl Renamer {
+    /// Creates a renamer for the gauges of the given UPS, from the name of the variable each gauge
+    /// was created for by metric name, as returned by [`crate::Metrics::metric_vars`].
+    #[must_use]
+    pub fn new(scheme: NamingScheme, ups_name: &str, vars: HashMap<String, String>) -> Renamer {
+        Renamer {
+            scheme,
+            ups_name: ups_name.to_string(),
+            vars,
+        }
+    }
+
+    /// Renames the gauges of UPS variables among the families, keeping them sorted by name.
+    pub fn rename(&self, families: &mut [MetricFamily]) {
+        if self.scheme == NamingScheme::Pistachio {
+            return;
+        }
+        for family in families.iter_mut() {
+            let Some(mapping) = self.vars.get(family.get_name()).and_then(|var| self.mapping(var)) else {
+                continue;
+            };
+            family.set_name(mapping.name);
+            let is_gauge = family.get_field_type() == MetricType::GAUGE;
+            for metric in family.mut_metric().iter_mut() {
+                if is_gauge && mapping.factor != 1.0 {
+                    let value = metric.get_gauge().get_value();
+                    metric.mut_gauge().set_value(value * mapping.factor);
+                }
+                for label in metric.mut_label().iter_mut() {
+                    if let Some((_, renamed)) = mapping.labels.iter().find(|(name, _)| *name == label.get_name()) {
+                        label.set_name((*renamed).to_string());
+                    }
+                }
+                if !metric.get_label().iter().any(|label| label.get_name() == "ups") {
+                    let mut label = LabelPair::default();
+                    label.set_name(String::from("ups"));
+                    label.set_value(self.ups_name.clone());
+                    metric.mut_label().push(label);
+                }
+                metric.mut_label().sort_by(|a, b| a.get_name().cmp(b.get_name()));
+            }
+        }
+        families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+    }
+
+    /// Returns how the gauge of a variable is exported in the scheme, if the scheme has a name for
+    /// it.
+    fn mapping(&self, var: &str) -> Option<Mapping> {
+        match self.scheme {
+            NamingScheme::Pistachio => None,
+            NamingScheme::Hon95 => HON95_NAMES
+                .iter()
+                .find(|(name, _, _)| *name == var)
+                .map(|(_, name, factor)| Mapping {
+                    name: (*name).to_string(),
+                    factor: *factor,
+                    labels: &[],
+                }),
+            NamingScheme::NutExporter => Some(Mapping {
+                name: format!("{NUT_EXPORTER_PREFIX}{}", var.replace(|c: char| !c.is_ascii_alphanumeric(), "_")),
+                factor: 1.0,
+                labels: if var == "ups.status" { &[("status", "flag")] } else { &[] },
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Gauge, GaugeVec, Opts, Registry};
+
+    fn families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let charge = Gauge::new("ups_battery_charge", "Battery charge").unwrap();
+        let load = Gauge::new("ups_load", "Load").unwrap();
+        let status = GaugeVec::new(Opts::new("ups_status", "UPS Status Code"), &["status"]).unwrap();
+        let uptime = Gauge::new("pistachio_uptime", "Not a UPS variable").unwrap();
+        registry.register(Box::new(charge.clone())).unwrap();
+        registry.register(Box::new(load.clone())).unwrap();
+        registry.register(Box::new(status.clone())).unwrap();
+        registry.register(Box::new(uptime.clone())).unwrap();
+        charge.set(87.0);
+        load.set(20.0);
+        status.with_label_values(&["OL"]).set(1.0);
+        uptime.set(1.0);
+        registry.gather()
+    }
+
+    fn renamer(scheme: NamingScheme) -> Renamer {
+        let vars = [("ups_battery_charge", "battery.charge"), ("ups_load", "ups.load"), ("ups_status", "ups.status")];
+        let vars = vars.iter().map(|(metric, var)| ((*metric).to_string(), (*var).to_string())).collect();
+        Renamer::new(scheme, "rack", vars)
+    }
+
+    /// The name, labels, and value of a sample.
+    type Sample = (String, Vec<(String, String)>, f64);
+
+    fn summarize(families: &[MetricFamily]) -> Vec<Sample> {
+        families
+            .iter()
+            .flat_map(|family| {
+                family.get_metric().iter().map(|metric| {
+                    let labels = metric
+                        .get_label()
+                        .iter()
+                        .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                        .collect();
+                    (family.get_name().to_string(), labels, metric.get_gauge().get_value())
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rename_to_schemes() {
+        let ups = (String::from("ups"), String::from("rack"));
+        let mut unchanged = families();
+        renamer(NamingScheme::Pistachio).rename(&mut unchanged);
+        assert_eq!(summarize(&unchanged), summarize(&families()));
+
+        let mut hon95 = families();
+        renamer(NamingScheme::Hon95).rename(&mut hon95);
+        assert_eq!(
+            summarize(&hon95),
+            vec![
+                (String::from("nut_battery_charge"), vec![ups.clone()], 0.87),
+                (String::from("nut_load"), vec![ups.clone()], 0.2),
+                (String::from("nut_status"), vec![(String::from("status"), String::from("OL")), ups.clone()], 1.0),
+                (String::from("pistachio_uptime"), Vec::new(), 1.0),
+            ]
+        );
+
+        let mut nut_exporter = families();
+        renamer(NamingScheme::NutExporter).rename(&mut nut_exporter);
+        assert_eq!(
+            summarize(&nut_exporter),
+            vec![
+                (String::from("network_ups_tools_battery_charge"), vec![ups.clone()], 87.0),
+                (String::from("network_ups_tools_ups_load"), vec![ups.clone()], 20.0),
+                (String::from("network_ups_tools_ups_status"), vec![(String::from("flag"), String::from("OL")), ups], 1.0),
+                (String::from("pistachio_uptime"), Vec::new(), 1.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! The OpenAPI description of the JSON API, served at `/api/v1/openapi.json` for web frontends
+//! and client generators.
+//!
+//! The document is generated from the routes the server was started with, so endpoints that are
+//! disabled, such as the one for running instant commands, are left out of it.
+
+use serde_json::{json, Map, Value};
+
+/// Path at which the document is served.
+pub const PATH: &str = "/api/v1/openapi.json";
+
+/// Returns a parameter of the query string.
+fn query(name: &str, kind: &str, description: &str) -> Value {
+    json!({ "name": name, "in": "query", "required": false, "description": description, "schema": { "type": kind } })
+}
+
+/// Returns a JSON request body of an object with string properties, all of which are required
+/// except those in `optional`.
+fn body(properties: &[(&str, &str)], optional: &[&str]) -> Value {
+    let schema: Map<String, Value> = properties
+        .iter()
+        .map(|(name, description)| ((*name).to_string(), json!({ "type": "string", "description": description })))
+        .collect();
+    let required: Vec<&str> = properties.iter().map(|(name, _)| *name).filter(|name| !optional.contains(name)).collect();
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": { "type": "object", "properties": schema, "required": required } } },
+    })
+}
+
+/// Returns a JSON response with the given description.
+fn response(description: &str) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": { "type": "object" } } } })
+}
+
+/// Describes an operation of the JSON API, or returns `None` for routes that are not part of it,
+/// such as `/metrics`.
+fn operation(method: &str, path: &str) -> Option<Value> {
+    let mut operation = match (method, path) {
+        ("GET", "/api/v1/variables") => json!({
+            "summary": "List every variable of the UPS with its value, description, type, and whether it is writable",
+            "responses": { "200": response("The variables of the UPS") },
+        }),
+        ("GET", "/api/v1/commands") => json!({
+            "summary": "List the instant commands supported by the UPS",
+            "responses": { "200": response("The instant commands of the UPS") },
+        }),
+        ("GET", "/api/v1/events") => json!({
+            "summary": "List recent events, oldest first",
+            "parameters": [
+                query("type", "string", "Only list events of this type, such as status_changed"),
+                query("limit", "integer", "Only list this many of the most recent events"),
+            ],
+            "responses": { "200": response("The events in the journal"), "400": { "description": "Invalid query parameter" } },
+        }),
+        ("GET", "/api/v1/history") => json!({
+            "summary": "List recorded values and status transitions",
+            "parameters": [
+                query("since", "integer", "Only list records since this UNIX timestamp"),
+                query("var", "string", "Only list values of this variable"),
+                query("limit", "integer", "Maximum number of records to list"),
+            ],
+            "responses": { "200": response("The recorded history"), "400": { "description": "Invalid query parameter" } },
+        }),
+        ("POST", "/api/v1/command") => json!({
+            "summary": "Run an instant command with the credentials of a NUT user",
+            "security": [{ "basic": [] }],
+            "requestBody": body(&[("command", "Name of the command, such as beeper.mute"), ("param", "Parameter of the command")], &["param"]),
+            "responses": {
+                "200": response("The command was run"),
+                "400": { "description": "Invalid request" },
+                "401": { "description": "Missing or rejected credentials" },
+            },
+        }),
+        ("POST", "/api/v1/variable") => json!({
+            "summary": "Set a writable variable with the credentials of a NUT user",
+            "security": [{ "basic": [] }],
+            "requestBody": body(&[("name", "Name of the variable, such as battery.charge.low"), ("value", "New value of the variable")], &[]),
+            "responses": {
+                "200": response("The variable was set"),
+                "400": { "description": "Invalid request" },
+                "401": { "description": "Missing or rejected credentials" },
+            },
+        }),
+        ("GET", PATH) => json!({
+            "summary": "Describe the JSON API",
+            "responses": { "200": response("This document") },
+        }),
+        _ => return None,
+    };
+    operation["operationId"] = json!(format!("{}_{}", method.to_ascii_lowercase(), path.trim_start_matches("/api/v1/").replace(['/', '.'], "_")));
+    Some(operation)
+}
+
+/// Generates the document of the endpoints among the given routes, as methods and paths.
+#[must_use]
+pub fn document<'a>(routes: impl IntoIterator<Item = (&'a str, &'a str)>) -> Value {
+    let mut paths = Map::new();
+    for (method, path) in routes {
+        if let Some(operation) = operation(method, path) {
+            let item = paths.entry(path).or_insert_with(|| json!({}));
+            item[method.to_ascii_lowercase()] = operation;
+        }
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "pistachio",
+            "description": "JSON API of the pistachio exporter for UPS devices monitored by NUT",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "securitySchemes": { "basic": { "type": "http", "scheme": "basic" } } },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_served_routes() {
+        let routes = [("GET", "/metrics"), ("GET", "/api/v1/variables"), ("GET", "/api/v1/events"), ("POST", "/api/v1/command"), ("GET", PATH)];
+        let document = document(routes);
+        let paths = document["paths"].as_object().unwrap();
+        assert_eq!(paths.keys().collect::<Vec<_>>(), ["/api/v1/command", "/api/v1/events", "/api/v1/openapi.json", "/api/v1/variables"]);
+        assert_eq!(document["paths"]["/api/v1/events"]["get"]["operationId"], "get_events");
+        assert_eq!(document["paths"]["/api/v1/events"]["get"]["parameters"][1]["name"], "limit");
+        let command = &document["paths"]["/api/v1/command"]["post"];
+        assert_eq!(command["operationId"], "post_command");
+        assert_eq!(command["requestBody"]["content"]["application/json"]["schema"]["required"], json!(["command"]));
+        assert_eq!(document["info"]["version"], env!("CARGO_PKG_VERSION"));
+    }
+}
@@ -0,0 +1,205 @@
+//! The OpenMetrics text format, served from `/metrics` to scrapers that ask for it.
+//!
+//! Unlike the Prometheus text format, every sample of the UPS carries the time of the poll it was
+//! read in, so Prometheus can tell when the values stop changing because polls are failing, and
+//! metrics named with a unit suffix, such as `_seconds`, are described with a `# UNIT` line.
+
+use crate::sink::Sink;
+use crate::Variable;
+use prometheus::proto::{MetricFamily, MetricType};
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Content type of the OpenMetrics text format.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Units that metric names can end with, as they are written in the name.
+const UNITS: &[&str] = &[
+    "watt_hours",
+    "volt_amperes",
+    "seconds",
+    "bytes",
+    "volts",
+    "amperes",
+    "watts",
+    "hertz",
+    "celsius",
+    "percent",
+    "ratio",
+];
+
+/// Prefix of metrics read from the UPS on every poll, which are stamped with the time of the poll.
+const POLLED_PREFIX: &str = "ups_";
+
+/// A sink that keeps the time of the latest successful poll, for stamping samples read from the
+/// UPS with it.
+#[derive(Debug, Clone, Default)]
+pub struct LastPoll(Arc<Mutex<Option<SystemTime>>>);
+
+impl LastPoll {
+    /// Returns the time of the latest successful poll, if any.
+    #[must_use]
+    pub fn get(&self) -> Option<SystemTime> {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Sink for LastPoll {
+    fn name(&self) -> &str {
+        "last poll"
+    }
+
+    fn publish(&mut self, _vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(SystemTime::now());
+        Ok(())
+    }
+}
+
+/// Returns whether the value of an `Accept` header asks for the OpenMetrics text format.
+#[must_use]
+pub fn accepts(accept: &str) -> bool {
+    accept
+        .split(',')
+        .any(|media| media.split(';').next().is_some_and(|kind| kind.trim() == "application/openmetrics-text"))
+}
+
+/// Encodes metric families in the OpenMetrics text format. Samples of metrics read from the UPS
+/// are stamped with `poll_time`, if given.
+#[must_use]
+pub fn encode(families: &[MetricFamily], poll_time: Option<SystemTime>) -> String {
+    let mut out = String::new();
+    for family in families {
+        let kind = family.get_field_type();
+        let name = match kind {
+            MetricType::COUNTER => family.get_name().strip_suffix("_total").unwrap_or(family.get_name()),
+            _ => family.get_name(),
+        };
+        let type_name = match kind {
+            MetricType::COUNTER => "counter",
+            MetricType::GAUGE => "gauge",
+            MetricType::HISTOGRAM => "histogram",
+            MetricType::SUMMARY => "summary",
+            MetricType::UNTYPED => "unknown",
+        };
+        let timestamp = poll_time
+            .filter(|_| name.starts_with(POLLED_PREFIX))
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since| format!(" {:.3}", since.as_secs_f64()))
+            .unwrap_or_default();
+
+        let _ = writeln!(out, "# TYPE {name} {type_name}");
+        if let Some(unit) = UNITS.iter().find(|unit| name.ends_with(&format!("_{unit}"))) {
+            let _ = writeln!(out, "# UNIT {name} {unit}");
+        }
+        let _ = writeln!(out, "# HELP {name} {}", escape(family.get_help()));
+        for metric in family.get_metric() {
+            let labels: Vec<(&str, String)> = metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name(), label.get_value().to_string()))
+                .collect();
+            let mut sample = |suffix: &str, extra: Option<(&str, String)>, value: f64| {
+                let labels: Vec<String> = labels
+                    .iter()
+                    .cloned()
+                    .chain(extra)
+                    .map(|(label, value)| format!("{label}=\"{}\"", escape(&value)))
+                    .collect();
+                let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels.join(",")) };
+                let _ = writeln!(out, "{name}{suffix}{labels} {}{timestamp}", format_value(value));
+            };
+            match kind {
+                MetricType::COUNTER => sample("_total", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => sample("", None, metric.get_gauge().get_value()),
+                MetricType::UNTYPED => sample("", None, metric.get_untyped().get_value()),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        let le = format_value(bucket.get_upper_bound());
+                        sample("_bucket", Some(("le", le)), bucket.get_cumulative_count() as f64);
+                    }
+                    let count = histogram.get_sample_count() as f64;
+                    sample("_bucket", Some(("le", String::from("+Inf"))), count);
+                    sample("_count", None, count);
+                    sample("_sum", None, histogram.get_sample_sum());
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        sample("", Some(("quantile", format_value(quantile.get_quantile()))), quantile.get_value());
+                    }
+                    sample("_count", None, summary.get_sample_count() as f64);
+                    sample("_sum", None, summary.get_sample_sum());
+                }
+            }
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+/// Formats a value, with infinities and NaN spelled the way OpenMetrics expects.
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value.is_infinite() {
+        String::from(if value > 0.0 { "+Inf" } else { "-Inf" })
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escapes backslashes, double quotes, and line feeds in help text and label values.
+fn escape(text: &str) -> String {
+    text.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Counter, Gauge, GaugeVec, Opts, Registry};
+    use std::time::Duration;
+
+    #[test]
+    fn encode_openmetrics() {
+        let registry = Registry::new();
+        let runtime = Gauge::new("ups_battery_runtime_predicted_seconds", "Predicted runtime").unwrap();
+        let status = GaugeVec::new(Opts::new("ups_status", "UPS \"Status\" Code"), &["status"]).unwrap();
+        let outages = Counter::new("ups_outages_total", "Number of outages").unwrap();
+        let threads = Gauge::new("process_threads", "Number of OS threads").unwrap();
+        registry.register(Box::new(runtime.clone())).unwrap();
+        registry.register(Box::new(status.clone())).unwrap();
+        registry.register(Box::new(outages.clone())).unwrap();
+        registry.register(Box::new(threads.clone())).unwrap();
+        runtime.set(1234.5);
+        status.with_label_values(&["OL"]).set(1.0);
+        outages.inc();
+        threads.set(f64::INFINITY);
+
+        let poll_time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        let encoded = encode(&registry.gather(), Some(poll_time));
+        assert_eq!(
+            encoded,
+            "# TYPE process_threads gauge\n\
+             # HELP process_threads Number of OS threads\n\
+             process_threads +Inf\n\
+             # TYPE ups_battery_runtime_predicted_seconds gauge\n\
+             # UNIT ups_battery_runtime_predicted_seconds seconds\n\
+             # HELP ups_battery_runtime_predicted_seconds Predicted runtime\n\
+             ups_battery_runtime_predicted_seconds 1234.5 1700000000.250\n\
+             # TYPE ups_outages counter\n\
+             # HELP ups_outages Number of outages\n\
+             ups_outages_total 1 1700000000.250\n\
+             # TYPE ups_status gauge\n\
+             # HELP ups_status UPS \\\"Status\\\" Code\n\
+             ups_status{status=\"OL\"} 1 1700000000.250\n\
+             # EOF\n"
+        );
+        assert!(!encode(&registry.gather(), None).contains(" 1700000000.250"));
+
+        assert!(accepts("application/openmetrics-text; version=1.0.0,text/plain;version=0.0.4;q=0.5"));
+        assert!(!accepts("text/plain;version=0.0.4"));
+    }
+}
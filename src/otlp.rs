@@ -0,0 +1,231 @@
+//! Export of polls as trace spans and of events as log records with the OpenTelemetry protocol,
+//! so they can be correlated with the rest of the telemetry of a system in backends such as
+//! Tempo and Loki.
+//!
+//! Spans and logs are sent to an OTLP collector with the JSON encoding of OTLP over HTTP, so no
+//! OpenTelemetry SDK or async runtime is required. Every poll is sent as a `poll` span, and the
+//! events it caused are sent as log records within that span.
+
+use crate::events::Event;
+use crate::sink::Sink;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt::Write;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ureq::Agent;
+
+/// Maximum time allowed for a single request to the collector.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// IDs of the span of the latest poll, which the log records of its events belong to.
+#[derive(Debug, Clone)]
+struct SpanContext {
+    trace_id: String,
+    span_id: String,
+}
+
+/// A sink that sends every poll as a span and every event as a log record to an OTLP collector.
+#[derive(Debug)]
+pub struct Otlp {
+    agent: Agent,
+    endpoint: String,
+    service_name: String,
+    ups_name: String,
+    span: Option<SpanContext>,
+}
+
+impl Otlp {
+    /// Creates a sink that sends to the OTLP/HTTP collector at `endpoint`, such as
+    /// `http://localhost:4318`, as the given service.
+    #[must_use]
+    pub fn new(endpoint: &str, service_name: &str, ups_name: &str) -> Otlp {
+        let agent = Agent::config_builder()
+            .timeout_global(Some(REQUEST_TIMEOUT))
+            .build()
+            .into();
+        Otlp {
+            agent,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            service_name: service_name.to_string(),
+            ups_name: ups_name.to_string(),
+            span: None,
+        }
+    }
+
+    /// Returns the resource and instrumentation scope that spans and logs are sent with.
+    fn resource(&self) -> (Value, Value) {
+        let resource = json!({ "attributes": [attribute("service.name", &self.service_name)] });
+        let scope = json!({ "name": "pistachio", "version": env!("CARGO_PKG_VERSION") });
+        (resource, scope)
+    }
+
+    /// Builds the trace request with the span of a poll.
+    fn span_request(&self, span: &SpanContext, started: SystemTime, duration: Duration, error: Option<&str>) -> Value {
+        let status = match error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 1 }),
+        };
+        let (resource, scope) = self.resource();
+        json!({
+            "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{
+                    "scope": scope,
+                    "spans": [{
+                        "traceId": span.trace_id,
+                        "spanId": span.span_id,
+                        "name": "poll",
+                        "kind": 1,
+                        "startTimeUnixNano": nanos(started).to_string(),
+                        "endTimeUnixNano": nanos(started + duration).to_string(),
+                        "attributes": [attribute("ups.name", &self.ups_name)],
+                        "status": status,
+                    }],
+                }],
+            }],
+        })
+    }
+
+    /// Builds the logs request with the record of an event, within the span of the latest poll.
+    fn log_request(&self, event: &Event, time: SystemTime) -> Value {
+        let mut attributes = vec![attribute("ups.name", &self.ups_name)];
+        if let Value::Object(fields) = event.to_json(&self.ups_name, time) {
+            for (name, value) in fields {
+                let value = match value {
+                    Value::Null => continue,
+                    Value::String(value) => value,
+                    value => value.to_string(),
+                };
+                if name != "ups" && name != "timestamp" {
+                    attributes.push(attribute(&format!("event.{name}"), &value));
+                }
+            }
+        }
+        let level = event.level();
+        let mut record = json!({
+            "timeUnixNano": nanos(time).to_string(),
+            "severityNumber": severity(level),
+            "severityText": level.as_str(),
+            "body": { "stringValue": event.to_string() },
+            "attributes": attributes,
+        });
+        if let Some(span) = &self.span {
+            record["traceId"] = json!(span.trace_id);
+            record["spanId"] = json!(span.span_id);
+        }
+        let (resource, scope) = self.resource();
+        json!({
+            "resourceLogs": [{
+                "resource": resource,
+                "scopeLogs": [{ "scope": scope, "logRecords": [record] }],
+            }],
+        })
+    }
+
+    /// Sends a request to a path of the collector in the background, so a slow collector never
+    /// delays the next poll.
+    fn send(&self, path: &'static str, request: &Value) {
+        let url = format!("{}{path}", self.endpoint);
+        let body = request.to_string();
+        let agent = self.agent.clone();
+        thread::spawn(move || match agent.post(&url).header("content-type", "application/json").send(body) {
+            Ok(_) => debug!("Sent telemetry to {url}"),
+            Err(err) => warn!("Failed to send telemetry to {url}: {err}"),
+        });
+    }
+}
+
+/// Returns an attribute with a string value.
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// Returns the nanoseconds since the UNIX epoch of a time.
+fn nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
+
+/// Returns the OpenTelemetry severity number of a level.
+fn severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 17,
+        log::Level::Warn => 13,
+        log::Level::Info => 9,
+        log::Level::Debug => 5,
+        log::Level::Trace => 1,
+    }
+}
+
+/// Returns a random ID of the given number of bytes as hexadecimal, from the random keys of the
+/// standard hasher, since IDs only need to be unique.
+fn random_id(bytes: usize) -> String {
+    let mut id = String::with_capacity(bytes * 2);
+    while id.len() < bytes * 2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos(SystemTime::now()));
+        let _ = write!(id, "{:016x}", hasher.finish());
+    }
+    id.truncate(bytes * 2);
+    id
+}
+
+impl Sink for Otlp {
+    fn name(&self) -> &str {
+        "otlp"
+    }
+
+    fn polled(&mut self, started: SystemTime, duration: Duration, error: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let span = SpanContext {
+            trace_id: random_id(16),
+            span_id: random_id(8),
+        };
+        self.send("/v1/traces", &self.span_request(&span, started, duration, error));
+        self.span = Some(span);
+        Ok(())
+    }
+
+    fn event(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        self.send("/v1/logs", &self.log_request(event, SystemTime::now()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_spans_and_logs() {
+        let mut otlp = Otlp::new("http://localhost:4318/", "pistachio", "rack");
+        assert_eq!(otlp.endpoint, "http://localhost:4318");
+        let span = SpanContext {
+            trace_id: random_id(16),
+            span_id: random_id(8),
+        };
+        assert_eq!((span.trace_id.len(), span.span_id.len()), (32, 16));
+        assert_ne!(span.trace_id, random_id(16));
+
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let request = otlp.span_request(&span, started, Duration::from_millis(5), Some("connection refused"));
+        let sent = &request["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(sent["name"], "poll");
+        assert_eq!(sent["startTimeUnixNano"], "1700000000000000000");
+        assert_eq!(sent["endTimeUnixNano"], "1700000000005000000");
+        assert_eq!(sent["status"], json!({ "code": 2, "message": "connection refused" }));
+
+        otlp.span = Some(span.clone());
+        let event = Event::StatusChanged {
+            previous: Some(String::from("OL")),
+            current: String::from("OB"),
+        };
+        let request = otlp.log_request(&event, started);
+        let record = &request["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["severityText"], "INFO");
+        assert_eq!(record["traceId"], json!(span.trace_id));
+        assert!(record["attributes"].as_array().unwrap().contains(&attribute("event.type", "status_changed")));
+    }
+}
@@ -0,0 +1,57 @@
+//! Dead man's switch support, for pinging an external service such as Healthchecks.io after
+//! every successful poll of the UPS.
+
+use crate::sink::Sink;
+use crate::Variable;
+use log::{debug, warn};
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+use ureq::Agent;
+
+/// Maximum time allowed for a single ping request.
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends a GET request to a configured URL to signal that pistachio is alive and polling.
+#[derive(Debug, Clone)]
+pub struct Pinger {
+    url: String,
+    agent: Agent,
+}
+
+impl Pinger {
+    /// Creates a new pinger for the given URL.
+    #[must_use]
+    pub fn new(url: &str) -> Pinger {
+        let agent = Agent::config_builder()
+            .timeout_global(Some(PING_TIMEOUT))
+            .build()
+            .into();
+        Pinger {
+            url: url.to_string(),
+            agent,
+        }
+    }
+
+    /// Pings the configured URL in the background so a slow or unreachable endpoint never delays
+    /// the next poll. Failures are logged but otherwise ignored.
+    pub fn ping(&self) {
+        let url = self.url.clone();
+        let agent = self.agent.clone();
+        thread::spawn(move || match agent.get(&url).call() {
+            Ok(_) => debug!("Pinged {url}"),
+            Err(err) => warn!("Failed to ping {url}: {err}"),
+        });
+    }
+}
+
+impl Sink for Pinger {
+    fn name(&self) -> &str {
+        "ping"
+    }
+
+    fn publish(&mut self, _vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        self.ping();
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+//! Detection of power quality events on the input of the UPS: voltage sags and swells, and
+//! frequency deviations, approximating the power quality log that some UPSes keep internally.
+//!
+//! An event starts when a poll finds the input outside the configured tolerance of its nominal
+//! value, and lasts until a poll finds it back within the tolerance. Events shorter than the poll
+//! rate can only be caught by sampling `input.voltage` and `input.frequency` with `--sample-vars`.
+
+use crate::sink::Sink;
+use crate::Variable;
+use log::info;
+use prometheus::{
+    register_gauge_with_registry, register_int_counter_with_registry, register_int_gauge_vec_with_registry, Gauge, IntCounter, IntGaugeVec, Registry,
+};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Nominal input voltages of mains around the world, the one nearest to the first reading is
+/// assumed if the UPS does not report `input.voltage.nominal`.
+const STANDARD_VOLTAGES: [f64; 8] = [100.0, 110.0, 120.0, 127.0, 208.0, 220.0, 230.0, 240.0];
+
+/// Nominal frequencies of mains around the world.
+const STANDARD_FREQUENCIES: [f64; 2] = [50.0, 60.0];
+
+/// A kind of power quality event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerEventKind {
+    /// The input voltage dropped below the tolerance.
+    Sag,
+    /// The input voltage rose above the tolerance.
+    Swell,
+    /// The input frequency deviated from nominal by more than the tolerance.
+    FrequencyDeviation,
+}
+
+impl PowerEventKind {
+    /// Returns the name of the kind, used in the `type` label.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PowerEventKind::Sag => "sag",
+            PowerEventKind::Swell => "swell",
+            PowerEventKind::FrequencyDeviation => "frequency_deviation",
+        }
+    }
+
+    /// Returns whether `value` deviates further from `nominal` than `extreme` does.
+    fn is_further(self, value: f64, extreme: f64, nominal: f64) -> bool {
+        match self {
+            PowerEventKind::Sag => value < extreme,
+            PowerEventKind::Swell => value > extreme,
+            PowerEventKind::FrequencyDeviation => (value - nominal).abs() > (extreme - nominal).abs(),
+        }
+    }
+}
+
+impl fmt::Display for PowerEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerEventKind::Sag => write!(f, "voltage sag"),
+            PowerEventKind::Swell => write!(f, "voltage swell"),
+            PowerEventKind::FrequencyDeviation => write!(f, "frequency deviation"),
+        }
+    }
+}
+
+/// Tolerances of the input outside of which a power quality event starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerances {
+    /// Percentage below the nominal voltage at which a sag starts.
+    pub sag_percent: f64,
+    /// Percentage above the nominal voltage at which a swell starts.
+    pub swell_percent: f64,
+    /// Deviation in hertz from the nominal frequency at which a frequency deviation starts.
+    pub frequency_hz: f64,
+}
+
+/// An event in progress, with the value that deviated furthest from nominal so far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Ongoing {
+    kind: PowerEventKind,
+    extreme: f64,
+}
+
+/// A sink that detects power quality events, counting them in `ups_voltage_sags_total`,
+/// `ups_voltage_swells_total`, and `ups_frequency_deviations_total`, and describing the latest
+/// one in `ups_last_power_event_info`, `ups_last_power_event_timestamp_seconds`, and
+/// `ups_last_power_event_value`.
+#[derive(Debug)]
+pub struct PowerQuality {
+    tolerances: Tolerances,
+    voltage: Option<Ongoing>,
+    frequency: Option<Ongoing>,
+    assumed_voltage: Option<f64>,
+    assumed_frequency: Option<f64>,
+    latest: Option<PowerEventKind>,
+    sags: IntCounter,
+    swells: IntCounter,
+    frequency_deviations: IntCounter,
+    last_kind: IntGaugeVec,
+    last_time: Gauge,
+    last_value: Gauge,
+}
+
+impl PowerQuality {
+    /// Registers the counters and gauges of power quality events.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the metrics cannot be registered with Prometheus.
+    pub fn new(tolerances: Tolerances, registry: &Registry) -> crate::Result<PowerQuality> {
+        Ok(PowerQuality {
+            tolerances,
+            voltage: None,
+            frequency: None,
+            assumed_voltage: None,
+            assumed_frequency: None,
+            latest: None,
+            sags: register_int_counter_with_registry!(
+                "ups_voltage_sags_total",
+                "Number of times the input voltage dropped below the tolerance",
+                registry
+            )?,
+            swells: register_int_counter_with_registry!(
+                "ups_voltage_swells_total",
+                "Number of times the input voltage rose above the tolerance",
+                registry
+            )?,
+            frequency_deviations: register_int_counter_with_registry!(
+                "ups_frequency_deviations_total",
+                "Number of times the input frequency deviated from nominal by more than the tolerance",
+                registry
+            )?,
+            last_kind: register_int_gauge_vec_with_registry!(
+                "ups_last_power_event_info",
+                "Type of the latest power quality event",
+                &["type"],
+                registry
+            )?,
+            last_time: register_gauge_with_registry!(
+                "ups_last_power_event_timestamp_seconds",
+                "Time the latest power quality event started",
+                registry
+            )?,
+            last_value: register_gauge_with_registry!(
+                "ups_last_power_event_value",
+                "Furthest the input deviated from nominal in the latest power quality event",
+                registry
+            )?,
+        })
+    }
+
+    /// Checks a poll for power quality events, returning the kind and value of every event that
+    /// started with it.
+    fn check(&mut self, values: &BTreeMap<String, String>) -> Vec<(PowerEventKind, f64)> {
+        let get = |name: &str| values.get(name).and_then(|value| crate::parse_number(value));
+        let mut started = Vec::new();
+        if let Some(voltage) = get("input.voltage").filter(|voltage| *voltage > 0.0) {
+            let nominal = get("input.voltage.nominal")
+                .unwrap_or_else(|| *self.assumed_voltage.get_or_insert_with(|| nearest(&STANDARD_VOLTAGES, voltage)));
+            let kind = if voltage < nominal * (1.0 - self.tolerances.sag_percent / 100.0) {
+                Some(PowerEventKind::Sag)
+            } else if voltage > nominal * (1.0 + self.tolerances.swell_percent / 100.0) {
+                Some(PowerEventKind::Swell)
+            } else {
+                None
+            };
+            if let Some(event) = track(&mut self.voltage, kind, voltage, nominal, "V") {
+                started.push(event);
+            }
+        }
+        if let Some(frequency) = get("input.frequency").filter(|frequency| *frequency > 0.0) {
+            let nominal = get("input.frequency.nominal")
+                .unwrap_or_else(|| *self.assumed_frequency.get_or_insert_with(|| nearest(&STANDARD_FREQUENCIES, frequency)));
+            let kind = ((frequency - nominal).abs() > self.tolerances.frequency_hz).then_some(PowerEventKind::FrequencyDeviation);
+            if let Some(event) = track(&mut self.frequency, kind, frequency, nominal, "Hz") {
+                started.push(event);
+            }
+        }
+        started
+    }
+}
+
+/// Returns the value of `standards` nearest to `value`.
+fn nearest(standards: &[f64], value: f64) -> f64 {
+    standards.iter().copied().fold(standards[0], |best, standard| {
+        if (standard - value).abs() < (best - value).abs() {
+            standard
+        } else {
+            best
+        }
+    })
+}
+
+/// Follows an event of the input from one poll to the next, returning the kind and value of the
+/// event if `kind` starts a new one.
+fn track(ongoing: &mut Option<Ongoing>, kind: Option<PowerEventKind>, value: f64, nominal: f64, unit: &str) -> Option<(PowerEventKind, f64)> {
+    match (*ongoing, kind) {
+        (Some(event), Some(kind)) if event.kind == kind => {
+            if kind.is_further(value, event.extreme, nominal) {
+                *ongoing = Some(Ongoing { kind, extreme: value });
+            }
+            None
+        }
+        (previous, Some(kind)) => {
+            if let Some(previous) = previous {
+                info!("Input {} ended, deviating furthest at {} {unit}", previous.kind, previous.extreme);
+            }
+            info!("Input {kind} started at {value} {unit}, nominal is {nominal} {unit}");
+            *ongoing = Some(Ongoing { kind, extreme: value });
+            Some((kind, value))
+        }
+        (Some(previous), None) => {
+            info!("Input {} ended, deviating furthest at {} {unit}", previous.kind, previous.extreme);
+            *ongoing = None;
+            None
+        }
+        (None, None) => None,
+    }
+}
+
+impl Sink for PowerQuality {
+    fn name(&self) -> &str {
+        "power quality"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let values = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        for (kind, _) in self.check(&values) {
+            match kind {
+                PowerEventKind::Sag => self.sags.inc(),
+                PowerEventKind::Swell => self.swells.inc(),
+                PowerEventKind::FrequencyDeviation => self.frequency_deviations.inc(),
+            }
+            self.last_kind.reset();
+            self.last_kind.with_label_values(&[kind.as_str()]).set(1);
+            self.last_time.set(now);
+            self.latest = Some(kind);
+        }
+        // Keep the value of the latest event up to date while it deviates further
+        if let Some(ongoing) = self.voltage.into_iter().chain(self.frequency).find(|ongoing| Some(ongoing.kind) == self.latest) {
+            self.last_value.set(ongoing.extreme);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_power_events() {
+        let tolerances = Tolerances {
+            sag_percent: 10.0,
+            swell_percent: 10.0,
+            frequency_hz: 1.0,
+        };
+        let mut power = PowerQuality::new(tolerances, &Registry::new()).unwrap();
+        let mut poll = |voltage: &str, frequency: &str| {
+            let values = [("input.voltage", voltage), ("input.frequency", frequency)];
+            power.check(&values.iter().map(|(name, value)| ((*name).to_string(), (*value).to_string())).collect())
+        };
+        assert_eq!(poll("230", "50.0"), []);
+        assert_eq!(poll("200", "50.2"), [(PowerEventKind::Sag, 200.0)]);
+        assert_eq!(poll("190", "50.2"), []);
+        assert_eq!(poll("258", "48.7"), [(PowerEventKind::Swell, 258.0), (PowerEventKind::FrequencyDeviation, 48.7)]);
+        assert_eq!(poll("231", "50.0"), []);
+        assert_eq!(power.voltage, None);
+        assert_eq!(nearest(&STANDARD_VOLTAGES, 118.0), 120.0);
+        assert_eq!(nearest(&STANDARD_FREQUENCIES, 59.1), 60.0);
+    }
+}
@@ -0,0 +1,228 @@
+//! Predictions derived from consecutive polls, which are steadier than the estimates reported by
+//! many drivers.
+
+use crate::sink::Sink;
+use crate::status::UpsStatus;
+use crate::Variable;
+use log::debug;
+use prometheus::{register_gauge_with_registry, Gauge, Registry};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Time over which the runtime prediction is smoothed. A change in the runtime reported at a
+/// constant load is about two thirds reflected in the prediction after this long.
+const RUNTIME_SMOOTHING: Duration = Duration::from_secs(60);
+
+/// Time over which the charging rate is smoothed. Drivers mostly report the charge in whole
+/// percents, which only change every few minutes near the end of a charge, so this is longer.
+const CHARGE_SMOOTHING: Duration = Duration::from_secs(600);
+
+/// A sink that predicts the runtime on battery from `battery.runtime`, smoothed over time and
+/// compensated for changes in load.
+///
+/// Drivers estimate the runtime from the current load, so their estimate jumps whenever the load
+/// does. The runtime multiplied by the load, which is roughly the energy left in the battery, is
+/// smoothed instead, and divided by the current load to predict the runtime. Without `ups.load`,
+/// the runtime itself is smoothed.
+#[derive(Debug)]
+pub struct RuntimePredictor {
+    smoothed: Option<Smoothed>,
+    gauge: Gauge,
+}
+
+/// The smoothed value of the last poll.
+#[derive(Debug, Clone, Copy)]
+struct Smoothed {
+    value: f64,
+    load_compensated: bool,
+    at: Instant,
+}
+
+impl RuntimePredictor {
+    /// Registers the prediction gauge.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the gauge cannot be registered with Prometheus.
+    pub fn new(registry: &Registry) -> crate::Result<RuntimePredictor> {
+        let gauge = register_gauge_with_registry!(
+            "ups_battery_runtime_predicted_seconds",
+            "Runtime on battery, smoothed and compensated for changes in load",
+            registry
+        )?;
+        Ok(RuntimePredictor { smoothed: None, gauge })
+    }
+
+    /// Updates the prediction with a poll made at `now`, returning the predicted runtime in
+    /// seconds.
+    fn predict(&mut self, values: &BTreeMap<String, String>, now: Instant) -> Option<f64> {
+        let get = |name: &str| values.get(name).and_then(|value| value.trim().parse::<f64>().ok());
+        let runtime = get("battery.runtime").filter(|runtime| *runtime >= 0.0)?;
+        let load = get("ups.load").filter(|load| *load > 0.0);
+        let value = load.map_or(runtime, |load| runtime * load);
+        let smoothed = match self.smoothed {
+            // Start over if the load appears or disappears, since the values are not comparable
+            Some(previous) if previous.load_compensated == load.is_some() => {
+                let elapsed = now.saturating_duration_since(previous.at).as_secs_f64();
+                let alpha = 1.0 - (-elapsed / RUNTIME_SMOOTHING.as_secs_f64()).exp();
+                previous.value + alpha * (value - previous.value)
+            }
+            _ => value,
+        };
+        self.smoothed = Some(Smoothed {
+            value: smoothed,
+            load_compensated: load.is_some(),
+            at: now,
+        });
+        Some(load.map_or(smoothed, |load| smoothed / load))
+    }
+}
+
+impl Sink for RuntimePredictor {
+    fn name(&self) -> &str {
+        "predict"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let values = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        match self.predict(&values, Instant::now()) {
+            Some(runtime) => self.gauge.set(runtime),
+            None => debug!("No runtime to predict from"),
+        }
+        Ok(())
+    }
+}
+
+/// A sink that estimates the time until the battery is fully charged from the rate at which
+/// `battery.charge` rises between polls, since most drivers do not report it.
+///
+/// The rate is measured between polls that reported a different charge, and smoothed over time.
+/// The estimate is NaN while the UPS is not charging or before the charge first rose, and 0 once
+/// the battery is full.
+#[derive(Debug)]
+pub struct ChargeTimePredictor {
+    charging: Option<Charging>,
+    gauge: Gauge,
+}
+
+/// The charge of the battery when it last changed while charging, and the smoothed rate it has
+/// been rising at, in percent per second.
+#[derive(Debug, Clone, Copy)]
+struct Charging {
+    charge: f64,
+    at: Instant,
+    rate: Option<f64>,
+}
+
+impl ChargeTimePredictor {
+    /// Registers the estimate gauge.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the gauge cannot be registered with Prometheus.
+    pub fn new(registry: &Registry) -> crate::Result<ChargeTimePredictor> {
+        let gauge = register_gauge_with_registry!(
+            "ups_battery_time_to_full_seconds",
+            "Estimated time until the battery is fully charged, from the smoothed rate at which the charge rises",
+            registry
+        )?;
+        gauge.set(f64::NAN);
+        Ok(ChargeTimePredictor { charging: None, gauge })
+    }
+
+    /// Updates the estimate with a poll made at `now`, returning the time to full charge in
+    /// seconds, or `None` if it cannot be estimated.
+    fn predict(&mut self, values: &BTreeMap<String, String>, now: Instant) -> Option<f64> {
+        let charge = values.get("battery.charge").and_then(|value| value.trim().parse::<f64>().ok());
+        let status = values.get("ups.status").map(|status| UpsStatus::parse(status)).unwrap_or_default();
+        let Some(charge) = charge.filter(|_| status.is_online() && !status.is_on_battery()) else {
+            self.charging = None;
+            return None;
+        };
+        if charge >= 100.0 {
+            self.charging = None;
+            return Some(0.0);
+        }
+        let charging = self.charging.get_or_insert(Charging { charge, at: now, rate: None });
+        let elapsed = now.saturating_duration_since(charging.at).as_secs_f64();
+        if charge > charging.charge && elapsed > 0.0 {
+            let rate = (charge - charging.charge) / elapsed;
+            let alpha = 1.0 - (-elapsed / CHARGE_SMOOTHING.as_secs_f64()).exp();
+            let rate = charging.rate.map_or(rate, |previous| previous + alpha * (rate - previous));
+            *charging = Charging { charge, at: now, rate: Some(rate) };
+        } else if charge < charging.charge {
+            // Some UPSes recalibrate the charge while charging, so measure from the new value
+            *charging = Charging { charge, at: now, ..*charging };
+        }
+        // The charge has most likely risen since it last changed, by less than one reported step
+        let elapsed = now.saturating_duration_since(charging.at).as_secs_f64();
+        charging.rate.map(|rate| ((100.0 - charge) / rate - elapsed).max(0.0))
+    }
+}
+
+impl Sink for ChargeTimePredictor {
+    fn name(&self) -> &str {
+        "predict"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let values = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        let estimate = self.predict(&values, Instant::now());
+        self.gauge.set(estimate.unwrap_or(f64::NAN));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn predict_runtime() {
+        let mut predictor = RuntimePredictor::new(&Registry::new()).unwrap();
+        let start = Instant::now();
+        assert_eq!(predictor.predict(&values(&[("ups.load", "20")]), start), None);
+        assert_eq!(predictor.predict(&values(&[("battery.runtime", "1200"), ("ups.load", "20")]), start), Some(1200.0));
+
+        // Doubling the load halves the prediction right away
+        let prediction = predictor.predict(&values(&[("battery.runtime", "600"), ("ups.load", "40")]), start + Duration::from_secs(10));
+        assert!((prediction.unwrap() - 600.0).abs() < 1e-9);
+
+        // A jump in the estimate at the same load is smoothed
+        let prediction = predictor.predict(&values(&[("battery.runtime", "1200"), ("ups.load", "40")]), start + Duration::from_secs(20)).unwrap();
+        assert!(prediction > 600.0 && prediction < 700.0, "{prediction}");
+        let later = start + Duration::from_secs(20) + RUNTIME_SMOOTHING * 10;
+        let prediction = predictor.predict(&values(&[("battery.runtime", "1200"), ("ups.load", "40")]), later).unwrap();
+        assert!((prediction - 1200.0).abs() < 1.0, "{prediction}");
+
+        // Without a load, the runtime itself is smoothed
+        assert_eq!(predictor.predict(&values(&[("battery.runtime", "900")]), later), Some(900.0));
+    }
+
+    #[test]
+    fn predict_time_to_full() {
+        let mut predictor = ChargeTimePredictor::new(&Registry::new()).unwrap();
+        let start = Instant::now();
+        let poll = |status, charge| values(&[("ups.status", status), ("battery.charge", charge)]);
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(predictor.predict(&poll("OB DISCHRG", "40"), start), None);
+        assert_eq!(predictor.predict(&poll("OL CHRG", "40"), start), None);
+        assert_eq!(predictor.predict(&poll("OL CHRG", "40"), at(30)), None);
+
+        // One percent per minute, with 59 percent left
+        assert_eq!(predictor.predict(&poll("OL CHRG", "41"), at(60)), Some(3540.0));
+        assert_eq!(predictor.predict(&poll("OL CHRG", "41"), at(90)), Some(3510.0));
+
+        // Charging slows down, which is smoothed
+        let estimate = predictor.predict(&poll("OL CHRG", "42"), at(240)).unwrap();
+        assert!(estimate > 3480.0 && estimate < 58.0 * 180.0, "{estimate}");
+
+        assert_eq!(predictor.predict(&poll("OL", "100"), at(300)), Some(0.0));
+        assert_eq!(predictor.predict(&poll("OB", "99"), at(360)), None);
+    }
+}
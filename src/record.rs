@@ -0,0 +1,226 @@
+//! Recording of raw polled data to CSV or JSON lines files, with size and age based rotation.
+
+use crate::sink::Sink;
+use crate::time::format_rfc3339;
+use crate::Variable;
+use log::info;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The format in which polled data is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One `timestamp,variable,value` row per variable per poll.
+    Csv,
+    /// One JSON object per poll, containing the timestamp and all variables.
+    JsonLines,
+}
+
+impl Format {
+    /// Picks the format from the file extension, using JSON lines for `.jsonl`, `.ndjson`, and
+    /// `.json` files and CSV for everything else.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "ndjson" | "json") => Format::JsonLines,
+            _ => Format::Csv,
+        }
+    }
+}
+
+/// Limits after which the record file is rotated.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rotation {
+    /// Rotate once the file reaches this many bytes.
+    pub max_size: Option<u64>,
+    /// Rotate once the file has been written to for this long.
+    pub max_age: Option<Duration>,
+    /// Number of rotated files to keep, named `<file>.1` (newest) through `<file>.<keep>`.
+    pub keep: usize,
+}
+
+/// A sink that appends every poll to a file.
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    format: Format,
+    rotation: Rotation,
+    writer: BufWriter<File>,
+    size: u64,
+    opened_at: SystemTime,
+}
+
+impl Recorder {
+    /// Opens the file at the given path for appending, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the file cannot be opened.
+    pub fn open(path: &Path, rotation: Rotation) -> io::Result<Recorder> {
+        let format = Format::from_path(path);
+        let (writer, size, opened_at) = open_file(path, format)?;
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            format,
+            rotation,
+            writer,
+            size,
+            opened_at,
+        })
+    }
+
+    /// Writes a poll that happened at the given time, rotating the file first if needed.
+    fn record(&mut self, time: SystemTime, vars: &[Variable]) -> io::Result<()> {
+        if self.needs_rotation(time) {
+            self.rotate()?;
+        }
+        let timestamp = format_rfc3339(time);
+        let mut output = String::new();
+        match self.format {
+            Format::Csv => {
+                for var in vars {
+                    output.push_str(&format!("{timestamp},{},{}\n", csv_field(var.name()), csv_field(var.value())));
+                }
+            }
+            Format::JsonLines => {
+                let vars: Map<String, Value> = vars.iter().map(|var| (var.name().to_string(), Value::String(var.value().to_string()))).collect();
+                output.push_str(&json!({"timestamp": timestamp, "vars": vars}).to_string());
+                output.push('\n');
+            }
+        }
+        self.writer.write_all(output.as_bytes())?;
+        self.writer.flush()?;
+        self.size += output.len() as u64;
+        Ok(())
+    }
+
+    /// Returns true if the current file has exceeded any of the rotation limits.
+    fn needs_rotation(&self, now: SystemTime) -> bool {
+        let too_big = self.rotation.max_size.is_some_and(|max| self.size >= max);
+        let too_old = self
+            .rotation
+            .max_age
+            .is_some_and(|max| now.duration_since(self.opened_at).unwrap_or_default() >= max);
+        too_big || too_old
+    }
+
+    /// Shifts all rotated files up by one, moves the current file to `<file>.1`, and starts a new
+    /// file.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for i in (1..self.rotation.keep).rev() {
+                let from = rotated_path(&self.path, i);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, i + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        (self.writer, self.size, self.opened_at) = open_file(&self.path, self.format)?;
+        info!("Rotated record file {}", self.path.display());
+        Ok(())
+    }
+}
+
+impl Sink for Recorder {
+    fn name(&self) -> &str {
+        "record"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        self.record(SystemTime::now(), vars)?;
+        Ok(())
+    }
+}
+
+/// Opens a file for appending, writing the CSV header if the file is new.
+fn open_file(path: &Path, format: Format) -> io::Result<(BufWriter<File>, u64, SystemTime)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let metadata = file.metadata()?;
+    let opened_at = metadata.created().unwrap_or_else(|_| SystemTime::now());
+    let mut size = metadata.len();
+    let mut writer = BufWriter::new(file);
+    if size == 0 && format == Format::Csv {
+        let header = "timestamp,variable,value\n";
+        writer.write_all(header.as_bytes())?;
+        writer.flush()?;
+        size = header.len() as u64;
+    }
+    Ok((writer, size, opened_at))
+}
+
+/// Returns the path of the `index`th rotated copy of a file.
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_os_string();
+    rotated.push(format!(".{index}"));
+    PathBuf::from(rotated)
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote, or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pistachio-record-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn quote_csv_fields() {
+        assert_eq!(csv_field("ups.status"), "ups.status");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn record_csv_and_rotate() {
+        let path = temp_path("polls.csv");
+        let rotation = Rotation {
+            max_size: Some(100),
+            max_age: None,
+            keep: 2,
+        };
+        let mut recorder = Recorder::open(&path, rotation).unwrap();
+        let vars = vec![Variable::new("ups.status", String::from("OL CHRG"))];
+        for _ in 0..10 {
+            recorder.record(UNIX_EPOCH, &vars).unwrap();
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("timestamp,variable,value\n"));
+        assert!(contents.contains("1970-01-01T00:00:00Z,ups.status,OL CHRG\n"));
+        assert!(rotated_path(&path, 1).exists());
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn record_json_lines() {
+        let path = temp_path("polls.jsonl");
+        assert_eq!(Format::from_path(&path), Format::JsonLines);
+        let mut recorder = Recorder::open(&path, Rotation::default()).unwrap();
+        let vars = vec![Variable::new("battery.charge", String::from("100"))];
+        recorder.record(UNIX_EPOCH, &vars).unwrap();
+        let line: Value = serde_json::from_str(fs::read_to_string(&path).unwrap().trim()).unwrap();
+        assert_eq!(line["timestamp"], "1970-01-01T00:00:00Z");
+        assert_eq!(line["vars"]["battery.charge"], "100");
+    }
+}
@@ -0,0 +1,283 @@
+//! Replay of polls recorded with `pistachio record` or `--record`, so the variables of a UPS can
+//! be fed through the exporter again, such as to reproduce a bug reported with a UPS model that
+//! is not at hand.
+//!
+//! Polls are replayed at the pace they were recorded at, or faster with a speed above 1. Once
+//! every poll has been replayed, the last one keeps being served like a UPS that stopped
+//! changing.
+
+use crate::client::UpsClient;
+use crate::record::Format;
+use crate::time::parse_rfc3339;
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use log::info;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Longest time a replay waits for the next poll before checking whether it should stop.
+const WAIT_STEP: Duration = Duration::from_millis(100);
+
+/// The variables of a single recorded poll.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedPoll {
+    /// Time at which the poll was recorded.
+    pub timestamp: SystemTime,
+    /// Every variable of the poll, along with its value.
+    pub vars: Vec<Variable>,
+}
+
+/// Reads the polls recorded in a file, which is read as JSON lines or CSV like the file was
+/// written by [`crate::record::Recorder`], depending on its extension.
+///
+/// # Errors
+///
+/// An error will be returned if the file cannot be read, if a line cannot be parsed, or if it has
+/// no polls.
+pub fn load(path: &Path) -> Result<Vec<RecordedPoll>> {
+    let contents = fs::read_to_string(path).map_err(|source| Error::Io {
+        context: format!("failed to read recording {}", path.display()),
+        source,
+    })?;
+    let polls = match Format::from_path(path) {
+        Format::JsonLines => parse_json_lines(&contents),
+        Format::Csv => parse_csv(&contents),
+    }
+    .map_err(|err| Error::Parse(format!("recording {}: {err}", path.display())))?;
+    if polls.is_empty() {
+        return Err(Error::Parse(format!("recording {} has no polls", path.display())));
+    }
+    Ok(polls)
+}
+
+/// Parses polls written as one JSON object per line.
+fn parse_json_lines(contents: &str) -> std::result::Result<Vec<RecordedPoll>, String> {
+    let mut polls = Vec::new();
+    for (number, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let invalid = |reason: &str| format!("line {} {reason}", number + 1);
+        let poll: Value = serde_json::from_str(line).map_err(|err| invalid(&format!("is not valid JSON: {err}")))?;
+        let timestamp = poll["timestamp"]
+            .as_str()
+            .and_then(parse_rfc3339)
+            .ok_or_else(|| invalid("does not have a valid timestamp"))?;
+        let vars = poll["vars"].as_object().ok_or_else(|| invalid("does not have variables"))?;
+        let vars = vars
+            .iter()
+            .map(|(name, value)| Some(Variable::new(name, value.as_str()?)))
+            .collect::<Option<_>>()
+            .ok_or_else(|| invalid("has a variable that is not a string"))?;
+        polls.push(RecordedPoll { timestamp, vars });
+    }
+    Ok(polls)
+}
+
+/// Parses polls written as `timestamp,variable,value` rows, where consecutive rows with the same
+/// timestamp belong to the same poll.
+fn parse_csv(contents: &str) -> std::result::Result<Vec<RecordedPoll>, String> {
+    let mut polls: Vec<RecordedPoll> = Vec::new();
+    for (number, line) in contents.lines().enumerate().skip(1).filter(|(_, line)| !line.trim().is_empty()) {
+        let invalid = || format!("line {} is not a valid timestamp,variable,value row", number + 1);
+        let [timestamp, name, value]: [String; 3] = csv_fields(line).try_into().map_err(|_| invalid())?;
+        let timestamp = parse_rfc3339(&timestamp).ok_or_else(invalid)?;
+        match polls.last_mut() {
+            Some(poll) if poll.timestamp == timestamp => poll.vars.push(Variable::new(name, value)),
+            _ => polls.push(RecordedPoll {
+                timestamp,
+                vars: vec![Variable::new(name, value)],
+            }),
+        }
+    }
+    Ok(polls)
+}
+
+/// Splits a CSV row into its fields, unquoting fields written with quotes.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("there is always a field");
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    fields
+}
+
+/// A client that serves recorded polls in order, each at the time it is due after the first
+/// poll, instead of reading a UPS.
+#[derive(Debug, Clone)]
+pub struct ReplayClient {
+    polls: Arc<[RecordedPoll]>,
+    speed: f64,
+    next: usize,
+    started: Option<Instant>,
+    shutdown: Option<Arc<AtomicBool>>,
+    stop_when_finished: bool,
+}
+
+impl ReplayClient {
+    /// Creates a client that replays the polls `speed` times faster than they were recorded.
+    ///
+    /// # Errors
+    ///
+    /// An [`Error::Config`] will be returned if there are no polls, or if the speed is not a
+    /// positive number.
+    pub fn new(polls: Vec<RecordedPoll>, speed: f64) -> Result<ReplayClient> {
+        if polls.is_empty() {
+            return Err(Error::Config(String::from("there are no polls to replay")));
+        }
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(Error::Config(format!("the replay speed must be a positive number, got {speed}")));
+        }
+        Ok(ReplayClient {
+            polls: polls.into(),
+            speed,
+            next: 0,
+            started: None,
+            shutdown: None,
+            stop_when_finished: false,
+        })
+    }
+
+    /// Stops waiting for the next poll once `shutdown` is set.
+    #[must_use]
+    pub fn until(mut self, shutdown: Arc<AtomicBool>) -> ReplayClient {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Sets the flag given to [`ReplayClient::until`] once the last poll has been served, so the
+    /// polling loop stops at the end of the recording.
+    #[must_use]
+    pub fn stop_when_finished(mut self) -> ReplayClient {
+        self.stop_when_finished = true;
+        self
+    }
+
+    /// Returns how long after the start of the replay a poll is due.
+    fn due(&self, index: usize) -> Duration {
+        let offset = self.polls[index]
+            .timestamp
+            .duration_since(self.polls[0].timestamp)
+            .unwrap_or_default();
+        offset.div_f64(self.speed)
+    }
+
+    /// Waits until the deadline, or until the replay is asked to stop.
+    fn wait_until(&self, deadline: Instant) {
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.shutdown.as_ref().is_some_and(|shutdown| shutdown.load(Ordering::Relaxed)) {
+                break;
+            }
+            thread::sleep(remaining.min(WAIT_STEP));
+        }
+    }
+}
+
+impl UpsClient for ReplayClient {
+    fn list_vars(&mut self, _ups_name: &str) -> Result<Vec<Variable>> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let index = self.next.min(self.polls.len() - 1);
+        self.wait_until(started + self.due(index));
+        if self.next < self.polls.len() {
+            self.next += 1;
+            if self.next == self.polls.len() {
+                info!("Replayed all {} recorded polls", self.polls.len());
+                if let Some(shutdown) = self.shutdown.as_ref().filter(|_| self.stop_when_finished) {
+                    shutdown.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(self.polls[index].vars.clone())
+    }
+
+    fn get_var_description(&mut self, _ups_name: &str, _var_name: &str) -> Result<String> {
+        Ok(String::from("Description unavailable"))
+    }
+
+    /// Types are not recorded, so they are guessed from the values of variables instead.
+    fn get_var_type(&mut self, _ups_name: &str, _var_name: &str) -> Result<VariableDefinition> {
+        Err(Error::Protocol(rups::NutError::VarNotSupported))
+    }
+
+    fn list_var_enum(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_var_range(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<VariableRange>> {
+        Ok(Vec::new())
+    }
+
+    fn list_clients(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_commands(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn get_command_description(&mut self, _ups_name: &str, _command: &str) -> Result<String> {
+        Err(Error::Protocol(rups::NutError::CmdNotSupported))
+    }
+
+    fn close(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn parse_recordings() {
+        let json = concat!(
+            r#"{"timestamp":"2024-02-29T12:34:56Z","vars":{"battery.charge":"100","ups.status":"OL"}}"#,
+            "\n",
+            r#"{"timestamp":"2024-02-29T12:35:06Z","vars":{"battery.charge":"90","ups.status":"OB"}}"#,
+            "\n"
+        );
+        let csv = "timestamp,variable,value\n\
+                   2024-02-29T12:34:56Z,battery.charge,100\n\
+                   2024-02-29T12:34:56Z,ups.status,OL\n\
+                   2024-02-29T12:35:06Z,battery.charge,90\n\
+                   2024-02-29T12:35:06Z,ups.status,OB\n";
+        let polls = parse_json_lines(json).unwrap();
+        assert_eq!(polls, parse_csv(csv).unwrap());
+        assert_eq!(polls[1].timestamp, UNIX_EPOCH + Duration::from_secs(1_709_210_106));
+        assert_eq!(polls[1].vars, [Variable::new("battery.charge", "90"), Variable::new("ups.status", "OB")]);
+        assert_eq!(csv_fields(r#"2024-02-29T12:34:56Z,ups.mfr,"Say ""hi"", A""#), ["2024-02-29T12:34:56Z", "ups.mfr", "Say \"hi\", A"]);
+        assert!(parse_json_lines("{\"timestamp\":\"yesterday\",\"vars\":{}}").unwrap_err().starts_with("line 1"));
+    }
+
+    #[test]
+    fn replay_polls_in_order() {
+        let poll = |secs, status| RecordedPoll {
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs),
+            vars: vec![Variable::new("ups.status", status)],
+        };
+        let polls = vec![poll(0, "OL"), poll(1, "OB"), poll(2, "OL")];
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let mut client = ReplayClient::new(polls, 20.0).unwrap().until(Arc::clone(&shutdown)).stop_when_finished();
+        let started = Instant::now();
+        let statuses: Vec<String> = (0..4).map(|_| client.list_vars("ups").unwrap()[0].value().to_string()).collect();
+        assert_eq!(statuses, ["OL", "OB", "OL", "OL"]);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(shutdown.load(Ordering::Relaxed));
+        assert!(matches!(ReplayClient::new(Vec::new(), 1.0), Err(Error::Config(_))));
+        assert!(matches!(ReplayClient::new(vec![poll(0, "OL")], 0.0), Err(Error::Config(_))));
+    }
+}
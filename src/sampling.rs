@@ -0,0 +1,144 @@
+//! Sampling of fast-changing variables, such as `input.voltage`, more often than the poll rate,
+//! to export their minimum, maximum, and average over a window as long as the scrape interval.
+//!
+//! A single gauge only shows the value at the time of the poll, so a sag or swell lasting a few
+//! seconds between polls is missed. The sampled variables are polled like a poll group at the
+//! sample interval, and [`Sampler`] keeps the samples within the window to export
+//! `ups_input_voltage_min`, `ups_input_voltage_max`, and `ups_input_voltage_avg`.
+
+use crate::groups::PollGroup;
+use crate::sink::Sink;
+use crate::Variable;
+use prometheus::{register_gauge_with_registry, Gauge, Registry};
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// The minimum, maximum, and average of the samples of a variable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    /// Lowest sampled value.
+    pub min: f64,
+    /// Highest sampled value.
+    pub max: f64,
+    /// Average of the sampled values.
+    pub avg: f64,
+}
+
+impl Stats {
+    /// Summarizes the given values, or returns `None` if there are none.
+    fn of(values: impl Iterator<Item = f64>) -> Option<Stats> {
+        let mut count = 0.0;
+        let mut stats = Stats {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            avg: 0.0,
+        };
+        for value in values {
+            count += 1.0;
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+            stats.avg += value;
+        }
+        stats.avg /= count;
+        (count > 0.0).then_some(stats)
+    }
+}
+
+/// A sink that keeps the samples of the variables of a group within a window, exporting their
+/// minimum, maximum, and average after every sample. Gauges are registered for a variable once it
+/// is first sampled with a numeric value.
+#[derive(Debug)]
+pub struct Sampler {
+    group: PollGroup,
+    window: Duration,
+    samples: BTreeMap<String, VecDeque<(Instant, f64)>>,
+    gauges: BTreeMap<String, [Gauge; 3]>,
+    registry: Registry,
+}
+
+impl Sampler {
+    /// Creates a sampler of the variables of `group`, exporting statistics over `window` with
+    /// gauges registered in `registry`.
+    #[must_use]
+    pub fn new(group: PollGroup, window: Duration, registry: &Registry) -> Sampler {
+        Sampler {
+            group,
+            window,
+            samples: BTreeMap::new(),
+            gauges: BTreeMap::new(),
+            registry: registry.clone(),
+        }
+    }
+
+    /// Adds the values of the sampled variables at `now`, forgetting samples older than the
+    /// window, and returns the statistics of every variable with samples left.
+    fn sample(&mut self, vars: &[Variable], now: Instant) -> BTreeMap<String, Stats> {
+        for var in vars.iter().filter(|var| self.group.contains(var.name())) {
+            if let Some(value) = crate::parse_number(var.value()) {
+                self.samples.entry(var.name().to_string()).or_default().push_back((now, value));
+            }
+        }
+        let window = self.window;
+        self.samples.retain(|_, samples| {
+            while samples.front().is_some_and(|(at, _)| now.duration_since(*at) > window) {
+                samples.pop_front();
+            }
+            !samples.is_empty()
+        });
+        self.samples
+            .iter()
+            .filter_map(|(name, samples)| Some((name.clone(), Stats::of(samples.iter().map(|(_, value)| *value))?)))
+            .collect()
+    }
+}
+
+impl Sink for Sampler {
+    fn name(&self) -> &str {
+        "sampling"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        for (name, stats) in self.sample(vars, Instant::now()) {
+            let gauges = match self.gauges.get(&name) {
+                Some(gauges) => gauges,
+                None => {
+                    let gauge = |suffix: &str, what: &str| {
+                        register_gauge_with_registry!(
+                            format!("{}_{suffix}", crate::gauge_name(&name)),
+                            format!("{what} of {name} over the sampling window"),
+                            &self.registry
+                        )
+                    };
+                    let gauges = [gauge("min", "Lowest value")?, gauge("max", "Highest value")?, gauge("avg", "Average value")?];
+                    self.gauges.entry(name).or_insert(gauges)
+                }
+            };
+            gauges[0].set(stats.min);
+            gauges[1].set(stats.max);
+            gauges[2].set(stats.avg);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarize_samples_in_window() {
+        let group: PollGroup = "input.voltage=1".parse().unwrap();
+        let mut sampler = Sampler::new(group, Duration::from_secs(10), &Registry::new());
+        let start = Instant::now();
+        let poll = |voltage: &str| [Variable::new("input.voltage", voltage), Variable::new("battery.charge", "100")];
+        sampler.sample(&poll("230"), start);
+        sampler.sample(&poll("190"), start + Duration::from_secs(1));
+        let stats = sampler.sample(&poll("240"), start + Duration::from_secs(2));
+        assert_eq!(stats.keys().collect::<Vec<_>>(), ["input.voltage"]);
+        assert_eq!(stats["input.voltage"], Stats { min: 190.0, max: 240.0, avg: 220.0 });
+        let stats = sampler.sample(&poll("n/a"), start + Duration::from_millis(11_500));
+        assert_eq!(stats["input.voltage"], Stats { min: 240.0, max: 240.0, avg: 240.0 });
+        assert!(sampler.sample(&[], start + Duration::from_secs(30)).is_empty());
+    }
+}
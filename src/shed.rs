@@ -0,0 +1,229 @@
+//! Load-shedding advice during an outage, so downstream automation can progressively power off
+//! non-critical gear as the battery drains.
+//!
+//! A tier is written as `NAME=SECONDS/PERCENT`, such as `lab=900/80`, and is recommended for
+//! shedding once the UPS is on battery and `battery.runtime` drops to `SECONDS` or
+//! `battery.charge` drops to `PERCENT`. Either threshold can be left out, as in `lab=900` or
+//! `lab=/80`. Tiers are numbered from 1 in the order they are configured, and the recommended tier
+//! is the highest one whose threshold was reached.
+
+use crate::events::Event;
+use crate::sink::Sink;
+use crate::status::UpsStatus;
+use crate::{Error, Variable};
+use log::debug;
+use prometheus::{register_int_gauge_with_registry, IntGauge, Registry};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+
+/// A group of loads shed together, with the battery thresholds at which it should be powered off.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ShedTier {
+    /// Name of the tier.
+    pub name: String,
+    /// Remaining runtime in seconds at or below which the tier should be shed.
+    pub runtime: Option<u64>,
+    /// Battery charge in percent at or below which the tier should be shed.
+    pub charge: Option<f64>,
+}
+
+impl ShedTier {
+    /// Returns whether the tier should be shed with the given runtime and charge.
+    fn reached(&self, runtime: Option<f64>, charge: Option<f64>) -> bool {
+        let runtime_reached = self.runtime.zip(runtime).is_some_and(|(threshold, runtime)| runtime <= threshold as f64);
+        let charge_reached = self.charge.zip(charge).is_some_and(|(threshold, charge)| charge <= threshold);
+        runtime_reached || charge_reached
+    }
+}
+
+impl FromStr for ShedTier {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<ShedTier, Error> {
+        let invalid = || Error::Parse(format!("expected NAME=SECONDS/PERCENT, got `{input}`"));
+        let (name, thresholds) = input.split_once('=').ok_or_else(invalid)?;
+        let (runtime, charge) = thresholds.split_once('/').unwrap_or((thresholds, ""));
+        let runtime = match runtime.trim() {
+            "" => None,
+            runtime => Some(runtime.trim_end_matches('s').parse().map_err(|_| invalid())?),
+        };
+        let charge = match charge.trim().trim_end_matches('%') {
+            "" => None,
+            charge => Some(charge.parse::<f64>().ok().filter(|charge| (0.0..=100.0).contains(charge)).ok_or_else(invalid)?),
+        };
+        let name = name.trim();
+        if name.is_empty() || (runtime.is_none() && charge.is_none()) {
+            return Err(invalid());
+        }
+        Ok(ShedTier {
+            name: name.to_string(),
+            runtime,
+            charge,
+        })
+    }
+}
+
+impl TryFrom<String> for ShedTier {
+    type Error = Error;
+
+    fn try_from(input: String) -> Result<ShedTier, Error> {
+        input.parse()
+    }
+}
+
+impl From<ShedTier> for String {
+    fn from(tier: ShedTier) -> String {
+        tier.to_string()
+    }
+}
+
+impl fmt::Display for ShedTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=", self.name)?;
+        if let Some(runtime) = self.runtime {
+            write!(f, "{runtime}")?;
+        }
+        if let Some(charge) = self.charge {
+            write!(f, "/{charge}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A sink that exports the tier recommended for shedding as `ups_recommended_shed_tier`, and
+/// sends [`Event::ShedTierChanged`] events when it changes.
+///
+/// While on battery, the recommended tier only ever rises, so loads are not powered back on when
+/// the runtime estimate recovers as the load drops. It returns to 0 once the UPS is back on line
+/// power.
+#[derive(Debug)]
+pub struct LoadShedding {
+    tiers: Vec<ShedTier>,
+    tier: usize,
+    events: Option<Sender<Event>>,
+    gauge: IntGauge,
+}
+
+impl LoadShedding {
+    /// Registers the recommended tier gauge, with no tier recommended. Events are sent to `events`
+    /// if given, such as the external events channel of [`crate::monitor_with_events`].
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the gauge cannot be registered with Prometheus.
+    pub fn new(tiers: Vec<ShedTier>, events: Option<Sender<Event>>, registry: &Registry) -> crate::Result<LoadShedding> {
+        let gauge = register_int_gauge_with_registry!(
+            "ups_recommended_shed_tier",
+            "Highest load-shedding tier whose loads should be powered off, or 0 if none should",
+            registry
+        )?;
+        Ok(LoadShedding {
+            tiers,
+            tier: 0,
+            events,
+            gauge,
+        })
+    }
+
+    /// Updates the recommended tier from a poll, returning the event caused if it changed.
+    fn evaluate(&mut self, values: &HashMap<String, String>) -> Option<Event> {
+        let get = |name: &str| values.get(name).and_then(|value| crate::parse_number(value));
+        let on_battery = values.get("ups.status").is_some_and(|status| UpsStatus::parse(status).is_on_battery());
+        let tier = if on_battery {
+            let (runtime, charge) = (get("battery.runtime"), get("battery.charge"));
+            let reached = self.tiers.iter().rposition(|tier| tier.reached(runtime, charge)).map_or(0, |index| index + 1);
+            reached.max(self.tier)
+        } else {
+            0
+        };
+        if tier == self.tier {
+            return None;
+        }
+        let previous = std::mem::replace(&mut self.tier, tier);
+        self.gauge.set(i64::try_from(tier).unwrap_or(i64::MAX));
+        Some(Event::ShedTierChanged {
+            previous,
+            tier,
+            name: tier.checked_sub(1).map(|index| self.tiers[index].name.clone()),
+        })
+    }
+}
+
+impl Sink for LoadShedding {
+    fn name(&self) -> &str {
+        "load shedding"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn StdError>> {
+        let values = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        if let Some(event) = self.evaluate(&values) {
+            match &self.events {
+                Some(sender) => sender.send(event)?,
+                None => debug!("{event}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn parse_tiers() {
+        let tier: ShedTier = "lab=900/80".parse().unwrap();
+        assert_eq!(tier.name, "lab");
+        assert_eq!(tier.runtime, Some(900));
+        assert_eq!(tier.charge, Some(80.0));
+        assert_eq!(tier.to_string(), "lab=900/80");
+        assert_eq!("nas=/50%".parse::<ShedTier>().unwrap().runtime, None);
+        assert_eq!("network=300s".parse::<ShedTier>().unwrap().to_string(), "network=300");
+        assert!("lab=".parse::<ShedTier>().is_err());
+        assert!("=900".parse::<ShedTier>().is_err());
+        assert!("lab=900/120".parse::<ShedTier>().is_err());
+    }
+
+    #[test]
+    fn recommend_tiers() {
+        let tiers = vec!["lab=900/80".parse().unwrap(), "nas=600".parse().unwrap(), "network=/20".parse().unwrap()];
+        let mut shedding = LoadShedding::new(tiers, None, &Registry::new()).unwrap();
+        let poll = |status, runtime, charge| values(&[("ups.status", status), ("battery.runtime", runtime), ("battery.charge", charge)]);
+        assert_eq!(shedding.evaluate(&poll("OL", "300", "10")), None);
+        assert_eq!(shedding.evaluate(&poll("OB DISCHRG", "1200", "90")), None);
+        let event = shedding.evaluate(&poll("OB DISCHRG", "1000", "75"));
+        assert_eq!(
+            event,
+            Some(Event::ShedTierChanged {
+                previous: 0,
+                tier: 1,
+                name: Some(String::from("lab")),
+            })
+        );
+        assert_eq!(shedding.gauge.get(), 1);
+        assert!(matches!(shedding.evaluate(&poll("OB DISCHRG", "500", "60")), Some(Event::ShedTierChanged { tier: 2, .. })));
+
+        // The runtime estimate recovers as loads are shed, which does not lower the tier
+        assert_eq!(shedding.evaluate(&poll("OB DISCHRG", "1100", "55")), None);
+        assert!(matches!(shedding.evaluate(&poll("OB DISCHRG", "900", "15")), Some(Event::ShedTierChanged { tier: 3, .. })));
+        let event = shedding.evaluate(&poll("OL CHRG", "900", "16"));
+        assert_eq!(
+            event,
+            Some(Event::ShedTierChanged {
+                previous: 3,
+                tier: 0,
+                name: None,
+            })
+        );
+        assert_eq!(shedding.gauge.get(), 0);
+    }
+}
@@ -0,0 +1,127 @@
+//! Participation in the forced shutdown sequence of NUT, which sets `FSD` in `ups.status` when
+//! every system powered by the UPS must shut down.
+
+use crate::audit::{Action, AuditEntry, AuditLog};
+use crate::events::Event;
+use crate::sink::Sink;
+use crate::status::UpsStatus;
+use crate::Variable;
+use log::{info, warn};
+use prometheus::{register_gauge_with_registry, Gauge, Registry};
+use std::error::Error;
+use std::io;
+use std::process::{Command, ExitStatus};
+use std::thread;
+
+/// A sink that exports whether a forced shutdown is in progress as `ups_fsd_active`, and runs a
+/// shell command when one starts.
+#[derive(Debug)]
+pub struct ForcedShutdown {
+    ups_name: String,
+    command: Option<String>,
+    audit: AuditLog,
+    gauge: Gauge,
+}
+
+impl ForcedShutdown {
+    /// Registers the forced shutdown gauge. If a command is given, it is run with `sh -c` at the
+    /// start of every forced shutdown.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the gauge cannot be registered with Prometheus.
+    pub fn new(ups_name: &str, command: Option<&str>, registry: &Registry) -> crate::Result<ForcedShutdown> {
+        let gauge = register_gauge_with_registry!("ups_fsd_active", "Whether the UPS is in a forced shutdown", registry)?;
+        Ok(ForcedShutdown {
+            ups_name: ups_name.to_string(),
+            command: command.map(String::from),
+            audit: AuditLog::default(),
+            gauge,
+        })
+    }
+
+    /// Records every run of the shutdown command to `audit`, instead of only logging it.
+    #[must_use]
+    pub fn with_audit(mut self, audit: AuditLog) -> ForcedShutdown {
+        self.audit = audit;
+        self
+    }
+
+    /// Runs the shutdown command and waits for it to finish.
+    fn run(ups_name: &str, command: &str, status: &str) -> io::Result<ExitStatus> {
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("UPS_NAME", ups_name)
+            .env("UPS_STATUS", status)
+            .status()
+    }
+}
+
+impl Sink for ForcedShutdown {
+    fn name(&self) -> &str {
+        "shutdown"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let forced = vars
+            .iter()
+            .find(|var| var.name() == "ups.status")
+            .is_some_and(|var| UpsStatus::parse(var.value()).contains(UpsStatus::FORCED_SHUTDOWN));
+        self.gauge.set(if forced { 1.0 } else { 0.0 });
+        Ok(())
+    }
+
+    fn event(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        if let (Event::ForcedShutdown { status }, Some(command)) = (event, &self.command) {
+            // Run in the background, since the command may well take longer than a poll
+            let ups_name = self.ups_name.clone();
+            let command = command.clone();
+            let status = status.clone();
+            let audit = self.audit.clone();
+            thread::spawn(move || {
+                let error = match ForcedShutdown::run(&ups_name, &command, &status) {
+                    Ok(exit) if exit.success() => {
+                        info!("Shutdown command `{command}` finished");
+                        None
+                    }
+                    Ok(exit) => {
+                        warn!("Shutdown command `{command}` failed with {exit}");
+                        Some(exit.to_string())
+                    }
+                    Err(err) => {
+                        warn!("Failed to run shutdown command `{command}`: {err}");
+                        Some(err.to_string())
+                    }
+                };
+                audit.record(&AuditEntry {
+                    action: Action::ShutdownCommand { command },
+                    ups: ups_name,
+                    source: None,
+                    user: None,
+                    result: String::from(if error.is_none() { "succeeded" } else { "failed" }),
+                    error,
+                });
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forced_shutdown() {
+        let mut shutdown = ForcedShutdown::new("ups", Some("true"), &Registry::new()).unwrap();
+        shutdown.publish(&[Variable::new("ups.status", String::from("FSD OB LB"))]).unwrap();
+        assert_eq!(shutdown.gauge.get(), 1.0);
+        shutdown.publish(&[Variable::new("ups.status", String::from("OL"))]).unwrap();
+        assert_eq!(shutdown.gauge.get(), 0.0);
+
+        let exit = ForcedShutdown::run("ups", r#"test "$UPS_NAME $UPS_STATUS" = "ups FSD OB""#, "FSD OB").unwrap();
+        assert!(exit.success());
+        assert!(!ForcedShutdown::run("ups", "exit 3", "FSD OB").unwrap().success());
+    }
+}
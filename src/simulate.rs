@@ -0,0 +1,279 @@
+//! A minimal NUT server that serves a simulated UPS, for demos, integration tests, and building
+//! dashboards without hardware.
+//!
+//! The server speaks enough of the protocol of upsd for pistachio and other NUT clients to read
+//! the UPS, such as `LIST VAR`, `GET VAR`, and `GET DESC`. The variables of the UPS follow a
+//! [`Scenario`], such as an outage that drains the battery some time after the server starts.
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Version of the NUT protocol reported by the server.
+const PROTOCOL_VERSION: &str = "1.3";
+
+/// Charge of the battery below which the simulated UPS reports a low battery.
+const LOW_CHARGE: f64 = 20.0;
+
+/// Seconds it takes for the battery to lose one percent of its charge on battery.
+const DISCHARGE_RATE: f64 = 3.0;
+
+/// Seconds of runtime the battery holds for each percent of its charge.
+const RUNTIME_PER_PERCENT: f64 = 36.0;
+
+/// How the variables of a simulated UPS change over time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum Scenario {
+    /// The UPS stays online with a full battery.
+    #[default]
+    Online,
+    /// The UPS goes on battery after the delay and drains it until the battery is empty.
+    Outage,
+    /// The UPS switches between online and on battery every time the delay passes.
+    Flapping,
+    /// The UPS stops updating its variables after the delay, so the server reports stale data.
+    Stale,
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scenario::Online => "online",
+            Scenario::Outage => "outage",
+            Scenario::Flapping => "flapping",
+            Scenario::Stale => "stale",
+        })
+    }
+}
+
+/// A NUT server that serves a single simulated UPS following a [`Scenario`].
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    ups_name: String,
+    scenario: Scenario,
+    delay: Duration,
+    started: Instant,
+}
+
+impl Simulator {
+    /// Creates a server for a UPS with the given name, which follows the scenario from now, with
+    /// `delay` as the time until the scenario starts or between the changes of a flapping UPS.
+    #[must_use]
+    pub fn new(ups_name: &str, scenario: Scenario, delay: Duration) -> Simulator {
+        Simulator {
+            ups_name: ups_name.to_string(),
+            scenario,
+            delay,
+            started: Instant::now(),
+        }
+    }
+
+    /// Binds to the address and serves clients on background threads, returning the address that
+    /// was bound, which has the port picked by the system if the address has port `0`.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the server cannot bind to the address.
+    pub fn start(self, addr: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        info!("Simulating UPS {} with the {} scenario on {addr}", self.ups_name, self.scenario);
+        let simulator = Arc::new(self);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let simulator = Arc::clone(&simulator);
+                        thread::spawn(move || {
+                            if let Err(err) = simulator.handle_connection(&stream) {
+                                debug!("Simulated NUT connection closed: {err}");
+                            }
+                        });
+                    }
+                    Err(err) => warn!("Failed to accept simulated NUT connection: {err}"),
+                }
+            }
+        });
+        Ok(addr)
+    }
+
+    /// Answers every line read from the connection until the client logs out or disconnects.
+    fn handle_connection(&self, stream: &TcpStream) -> io::Result<()> {
+        let mut writer = stream;
+        for line in BufReader::new(stream).lines() {
+            let words = words(&line?);
+            writer.write_all(self.respond(&words, self.started.elapsed()).as_bytes())?;
+            if words.first().is_some_and(|command| command == "LOGOUT") {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the response to a command after the server has been running for `elapsed`.
+    fn respond(&self, words: &[String], elapsed: Duration) -> String {
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let ups = self.ups_name.as_str();
+        let stale = self.scenario == Scenario::Stale && elapsed >= self.delay;
+        match words.as_slice() {
+            ["VER"] => format!("Network UPS Tools upsd {} - pistachio simulator\n", env!("CARGO_PKG_VERSION")),
+            ["NETVER"] => format!("{PROTOCOL_VERSION}\n"),
+            ["USERNAME" | "PASSWORD", _] | ["LOGIN", _] => String::from("OK\n"),
+            ["LOGOUT"] => String::from("OK Goodbye\n"),
+            ["STARTTLS"] => String::from("ERR FEATURE-NOT-CONFIGURED\n"),
+            ["LIST", "UPS"] => format!("BEGIN LIST UPS\nUPS {ups} \"Simulated UPS\"\nEND LIST UPS\n"),
+            ["LIST" | "GET" | "SET", _, name, ..] if *name != ups => String::from("ERR UNKNOWN-UPS\n"),
+            ["LIST", "VAR", _] if stale => String::from("ERR DATA-STALE\n"),
+            ["LIST", "VAR", _] => {
+                let vars: String = self
+                    .vars(elapsed)
+                    .iter()
+                    .map(|(name, value)| format!("VAR {ups} {name} {}\n", quote(value)))
+                    .collect();
+                format!("BEGIN LIST VAR {ups}\n{vars}END LIST VAR {ups}\n")
+            }
+            ["LIST", kind @ ("RW" | "CMD" | "CLIENT"), _] => format!("BEGIN LIST {kind} {ups}\nEND LIST {kind} {ups}\n"),
+            ["LIST", kind @ ("ENUM" | "RANGE"), _, var] => format!("BEGIN LIST {kind} {ups} {var}\nEND LIST {kind} {ups} {var}\n"),
+            ["GET", "VAR", _, _] if stale => String::from("ERR DATA-STALE\n"),
+            ["GET", kind @ ("VAR" | "DESC" | "TYPE"), _, var] => {
+                let vars = self.vars(elapsed);
+                let Some((_, value)) = vars.iter().find(|(name, _)| name == var) else {
+                    return String::from("ERR VAR-NOT-SUPPORTED\n");
+                };
+                match *kind {
+                    "VAR" => format!("VAR {ups} {var} {}\n", quote(value)),
+                    "DESC" => {
+                        let description = crate::metadata::known_description(var).unwrap_or("Description unavailable");
+                        format!("DESC {ups} {var} {}\n", quote(description))
+                    }
+                    _ if crate::parse_number(value).is_some() => format!("TYPE {ups} {var} NUMBER\n"),
+                    _ => format!("TYPE {ups} {var} STRING:64\n"),
+                }
+            }
+            ["GET", "UPSDESC", _] => format!("UPSDESC {ups} \"Simulated UPS\"\n"),
+            ["GET", "NUMLOGINS", _] => format!("NUMLOGINS {ups} 0\n"),
+            ["GET", "CMDDESC", ..] | ["INSTCMD", ..] => String::from("ERR CMD-NOT-SUPPORTED\n"),
+            ["SET", "VAR", ..] => String::from("ERR READONLY\n"),
+            _ => String::from("ERR UNKNOWN-COMMAND\n"),
+        }
+    }
+
+    /// Returns the variables of the UPS and their values after the server has been running for
+    /// `elapsed`.
+    fn vars(&self, elapsed: Duration) -> Vec<(&'static str, String)> {
+        // Time spent on battery, if the UPS is on battery
+        let on_battery = match self.scenario {
+            Scenario::Outage => elapsed.checked_sub(self.delay),
+            Scenario::Flapping if !self.delay.is_zero() && (elapsed.as_millis() / self.delay.as_millis()) % 2 == 1 => {
+                Some(Duration::from_millis((elapsed.as_millis() % self.delay.as_millis()) as u64))
+            }
+            Scenario::Online | Scenario::Flapping | Scenario::Stale => None,
+        };
+        let (status, charge, input_voltage) = match on_battery {
+            Some(time) => {
+                let charge = (100.0 - time.as_secs_f64() / DISCHARGE_RATE).max(0.0).round();
+                let status = if charge <= LOW_CHARGE { "OB DISCHRG LB" } else { "OB DISCHRG" };
+                (status, charge, 0.0)
+            }
+            None => ("OL", 100.0, 230.0),
+        };
+        vec![
+            ("battery.charge", charge.to_string()),
+            ("battery.charge.low", LOW_CHARGE.to_string()),
+            ("battery.runtime", (charge * RUNTIME_PER_PERCENT).to_string()),
+            ("device.mfr", String::from("Pistachio")),
+            ("device.model", String::from("Simulated UPS")),
+            ("device.type", String::from("ups")),
+            ("driver.name", String::from("pistachio-simulate")),
+            ("input.voltage", format!("{input_voltage:.1}")),
+            ("output.voltage", String::from("230.0")),
+            ("ups.load", String::from("20")),
+            ("ups.mfr", String::from("Pistachio")),
+            ("ups.model", String::from("Simulated UPS")),
+            ("ups.realpower.nominal", String::from("900")),
+            ("ups.status", String::from(status)),
+        ]
+    }
+}
+
+/// Splits a line of the NUT protocol into words, where a quoted word may contain spaces and
+/// escaped quotes.
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = line.trim().chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => word.extend(chars.next()),
+            '"' => quoted = !quoted,
+            ' ' if !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Quotes a value for the NUT protocol, escaping quotes and backslashes.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UpsClient;
+
+    fn status(simulator: &Simulator, secs: u64) -> String {
+        let words = words("GET VAR ups ups.status");
+        simulator.respond(&words, Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn follow_scenarios() {
+        let outage = Simulator::new("ups", Scenario::Outage, Duration::from_secs(30));
+        assert_eq!(status(&outage, 10), "VAR ups ups.status \"OL\"\n");
+        assert_eq!(status(&outage, 60), "VAR ups ups.status \"OB DISCHRG\"\n");
+        assert_eq!(status(&outage, 300), "VAR ups ups.status \"OB DISCHRG LB\"\n");
+        let charge = outage.respond(&words("GET VAR ups battery.charge"), Duration::from_secs(60));
+        assert_eq!(charge, "VAR ups battery.charge \"90\"\n");
+
+        let flapping = Simulator::new("ups", Scenario::Flapping, Duration::from_secs(10));
+        assert_eq!(status(&flapping, 5), "VAR ups ups.status \"OL\"\n");
+        assert_eq!(status(&flapping, 15), "VAR ups ups.status \"OB DISCHRG\"\n");
+        assert_eq!(status(&flapping, 25), "VAR ups ups.status \"OL\"\n");
+
+        let stale = Simulator::new("ups", Scenario::Stale, Duration::from_secs(30));
+        assert_eq!(status(&stale, 10), "VAR ups ups.status \"OL\"\n");
+        assert_eq!(status(&stale, 30), "ERR DATA-STALE\n");
+        assert_eq!(stale.respond(&words("LIST VAR other"), Duration::ZERO), "ERR UNKNOWN-UPS\n");
+        assert_eq!(words(r#"SET VAR ups ups.id "rack \"A\"""#), ["SET", "VAR", "ups", "ups.id", "rack \"A\""]);
+    }
+
+    #[test]
+    fn serve_nut_clients() {
+        let simulator = Simulator::new("rack", Scenario::Online, Duration::from_secs(30));
+        let addr = simulator.start("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut client = crate::Connection::open("127.0.0.1", addr.port()).unwrap();
+        let vars = client.list_vars("rack").unwrap();
+        assert!(vars.contains(&crate::Variable::new("ups.status", "OL")));
+        assert_eq!(client.get_var_description("rack", "battery.charge").unwrap(), "Battery charge (percent of full)");
+        assert!(client.get_var_type("rack", "battery.charge").unwrap().is_number());
+        assert!(client.list_vars("other").is_err());
+        client.close().unwrap();
+    }
+}
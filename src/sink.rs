@@ -0,0 +1,67 @@
+//! Destinations that receive UPS variables after every successful poll, and events whenever the
+//! state of the UPS changes.
+
+use crate::events::Event;
+use crate::Variable;
+use std::error::Error;
+use std::time::{Duration, SystemTime};
+
+/// A destination for the variables collected by each successful poll of the UPS and the events
+/// detected between polls, such as a database or an external monitoring service.
+pub trait Sink {
+    /// Returns a short name for the sink, used in log messages.
+    fn name(&self) -> &str;
+
+    /// Receives the full list of variables from a successful poll. Does nothing by default.
+    ///
+    /// # Errors
+    ///
+    /// An error should be returned if the variables could not be delivered. Errors are logged by
+    /// the polling loop, but do not interrupt polling.
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let _ = vars;
+        Ok(())
+    }
+
+    /// Receives an event, after the variables of the poll that caused it have been published. Does
+    /// nothing by default.
+    ///
+    /// # Errors
+    ///
+    /// An error should be returned if the event could not be delivered. Errors are logged by the
+    /// polling loop, but do not interrupt polling.
+    fn event(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        let _ = event;
+        Ok(())
+    }
+
+    /// Receives when every poll started, how long it took, and the error it failed with, if any,
+    /// before the events it caused. Does nothing by default.
+    ///
+    /// # Errors
+    ///
+    /// An error should be returned if the poll could not be delivered. Errors are logged by the
+    /// polling loop, but do not interrupt polling.
+    fn polled(&mut self, started: SystemTime, duration: Duration, error: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let _ = (started, duration, error);
+        Ok(())
+    }
+
+    /// Returns whether the sink receives [`Event::VariableChanged`]. Any number of variables can
+    /// change on every poll, so these events are only delivered to sinks that opt in, such as an
+    /// [`crate::EventBus`]. Returns `false` by default.
+    fn variable_changes(&self) -> bool {
+        false
+    }
+
+    /// Called once when pistachio is shutting down, to flush or save anything the sink holds.
+    /// Does nothing by default.
+    ///
+    /// # Errors
+    ///
+    /// An error should be returned if the sink could not be shut down cleanly. Errors are logged,
+    /// but do not prevent other sinks from shutting down.
+    fn shutdown(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
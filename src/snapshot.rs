@@ -0,0 +1,197 @@
+//! Point-in-time views of all variables of a UPS, for using pistachio as a NUT data library
+//! without Prometheus.
+
+use crate::client::UpsClient;
+use crate::status::UpsStatus;
+use crate::{Result, Variable};
+use log::debug;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// All variables of a UPS from a single poll.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpsSnapshot {
+    /// Name of the UPS that was polled.
+    pub ups_name: String,
+    /// Time at which the poll completed.
+    pub timestamp: SystemTime,
+    /// Value of every variable, keyed by name.
+    pub vars: BTreeMap<String, String>,
+    /// Parsed value of `ups.status`, empty if the UPS did not report one.
+    pub status: UpsStatus,
+    /// Addresses of the clients logged in to the UPS, if they could be listed.
+    pub clients: Option<Vec<String>>,
+}
+
+impl UpsSnapshot {
+    /// Creates a snapshot from a list of variables polled at the given time.
+    #[must_use]
+    pub fn from_vars(ups_name: &str, vars: &[Variable], timestamp: SystemTime) -> UpsSnapshot {
+        let vars: BTreeMap<String, String> = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        let status = vars.get("ups.status").map(|value| UpsStatus::parse(value)).unwrap_or_default();
+        UpsSnapshot {
+            ups_name: ups_name.to_string(),
+            timestamp,
+            vars,
+            status,
+            clients: None,
+        }
+    }
+
+    /// Returns the raw value of a variable.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    /// Returns the value of a variable parsed as a number.
+    #[must_use]
+    pub fn get_f64(&self, name: &str) -> Option<f64> {
+        self.get(name).and_then(|value| value.trim().parse().ok())
+    }
+
+    /// Converts the snapshot back into the list of variables reported by the NUT server.
+    #[must_use]
+    pub fn variables(&self) -> Vec<Variable> {
+        self.vars
+            .iter()
+            .map(|(name, value)| Variable::new(name, value.clone()))
+            .collect()
+    }
+}
+
+/// Polls all variables of a UPS once, along with the clients logged in to it.
+///
+/// # Errors
+///
+/// An error will be returned if the variables cannot be retrieved from the NUT server. Failing
+/// to list the clients is not an error, and leaves [`UpsSnapshot::clients`] empty.
+pub fn poll<C: UpsClient>(client: &mut C, ups_name: &str) -> Result<UpsSnapshot> {
+    let vars = client.list_vars(ups_name)?;
+    let mut snapshot = UpsSnapshot::from_vars(ups_name, &vars, SystemTime::now());
+    snapshot.clients = client
+        .list_clients(ups_name)
+        .map_err(|err| debug!("Failed to list clients of UPS {ups_name}: {err}"))
+        .ok();
+    Ok(snapshot)
+}
+
+/// Polls a UPS repeatedly, waiting `interval` between the end of one poll and the start of the
+/// next. The first poll happens immediately.
+///
+/// Failed polls are yielded as errors and polling continues, so the iterator only ends once the
+/// flag given to [`Snapshots::until`] is set.
+pub fn snapshots<'a, C: UpsClient>(client: &'a mut C, ups_name: &str, interval: Duration) -> Snapshots<'a, C> {
+    Snapshots {
+        client,
+        ups_name: ups_name.to_string(),
+        interval,
+        shutdown: None,
+        polled: false,
+        last: None,
+    }
+}
+
+/// An iterator over the snapshots of a UPS, created by [`snapshots`].
+#[derive(Debug)]
+pub struct Snapshots<'a, C> {
+    client: &'a mut C,
+    ups_name: String,
+    interval: Duration,
+    shutdown: Option<&'a AtomicBool>,
+    polled: bool,
+    /// When the latest poll started and how long it took.
+    last: Option<(SystemTime, Duration)>,
+}
+
+impl<'a, C: UpsClient> Snapshots<'a, C> {
+    /// Stops the iterator once `shutdown` is set, waking early from the wait between polls.
+    #[must_use]
+    pub fn until(mut self, shutdown: &'a AtomicBool) -> Snapshots<'a, C> {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Returns when the latest poll started and how long it took, if there has been one.
+    #[must_use]
+    pub fn last_poll(&self) -> Option<(SystemTime, Duration)> {
+        self.last
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.is_some_and(|shutdown| shutdown.load(Ordering::Relaxed))
+    }
+
+    /// Sleeps for the given duration, waking early if shutdown is requested.
+    fn sleep(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while !self.is_shutdown() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            thread::sleep(remaining.min(Duration::from_millis(100)));
+        }
+    }
+}
+
+impl<C: UpsClient> Iterator for Snapshots<'_, C> {
+    type Item = Result<UpsSnapshot>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.polled {
+            self.sleep(self.interval);
+        }
+        if self.is_shutdown() {
+            return None;
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("poll", ups = %self.ups_name).entered();
+        debug!("Polling UPS...");
+        self.polled = true;
+        let (started, start) = (SystemTime::now(), Instant::now());
+        let result = poll(self.client, &self.ups_name);
+        self.last = Some((started, start.elapsed()));
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockUpsClient;
+
+    #[test]
+    fn poll_snapshot() {
+        let mut client = MockUpsClient::new()
+            .with_var("battery.charge", "87")
+            .with_var("ups.status", "OB DISCHRG")
+            .with_client("192.168.1.10");
+        let snapshot = poll(&mut client, "ups").unwrap();
+        assert_eq!(snapshot.ups_name, "ups");
+        assert_eq!(snapshot.get("ups.status"), Some("OB DISCHRG"));
+        assert_eq!(snapshot.get_f64("battery.charge"), Some(87.0));
+        assert!(snapshot.status.is_on_battery());
+        assert_eq!(snapshot.variables().len(), 2);
+        assert_eq!(snapshot.clients, Some(vec![String::from("192.168.1.10")]));
+    }
+
+    #[test]
+    fn iterate_until_shutdown() {
+        let shutdown = std::sync::Arc::new(AtomicBool::new(false));
+        let mut client = MockUpsClient::new()
+            .with_var("ups.status", "OL")
+            .then_fail()
+            .then_status("OB")
+            .stop_when_exhausted(std::sync::Arc::clone(&shutdown));
+        let mut polls = snapshots(&mut client, "ups", Duration::ZERO).until(&shutdown);
+        assert_eq!(polls.last_poll(), None);
+        let results: Vec<_> = polls.by_ref().collect();
+        assert!(polls.last_poll().is_some());
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert!(results[1].as_ref().unwrap().status.is_on_battery());
+    }
+}
@@ -0,0 +1,413 @@
+//! A backend that reads a UPS over SNMP with the standard UPS-MIB (RFC 1628), for UPSes with a
+//! network management card, such as those of APC and Eaton, that are not behind a NUT server.
+//!
+//! The objects of the MIB are translated to the NUT variables they correspond to, the same as the
+//! `ietf` MIB of the `snmp-ups` driver of NUT, so the rest of the exporter works the same as with
+//! a NUT server. Only SNMP v2c is supported, and only the first line of the input and output
+//! tables is read.
+
+use crate::client::UpsClient;
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use log::debug;
+use std::collections::HashMap;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// Maximum time allowed for each attempt at reading the UPS.
+const SNMP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of times a request is sent before giving up, since UDP packets can be lost.
+const SNMP_ATTEMPTS: usize = 2;
+
+/// Prefix of the objects of the UPS-MIB.
+const UPS_MIB: &str = "1.3.6.1.2.1.33.1";
+
+/// How the value of an object is converted to the value of a NUT variable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Conversion {
+    /// An integer multiplied by a factor, such as `0.1` for a value in tenths of a volt.
+    Scaled(f64),
+    /// Text, such as the model of the UPS.
+    Text,
+}
+
+/// Objects of the UPS-MIB below [`UPS_MIB`], with the NUT variables they correspond to.
+const OBJECTS: &[(&str, &str, Conversion, &str)] = &[
+    ("1.1.0", "ups.mfr", Conversion::Text, "UPS manufacturer"),
+    ("1.2.0", "ups.model", Conversion::Text, "UPS model"),
+    ("1.3.0", "ups.firmware", Conversion::Text, "UPS firmware"),
+    ("2.3.0", "battery.runtime", Conversion::Scaled(60.0), "Battery runtime (seconds)"),
+    ("2.4.0", "battery.charge", Conversion::Scaled(1.0), "Battery charge (percent of full)"),
+    ("2.5.0", "battery.voltage", Conversion::Scaled(0.1), "Battery voltage (V)"),
+    ("2.6.0", "battery.current", Conversion::Scaled(0.1), "Battery current (A)"),
+    ("2.7.0", "battery.temperature", Conversion::Scaled(1.0), "Battery temperature (degrees C)"),
+    ("3.3.1.2.1", "input.frequency", Conversion::Scaled(0.1), "Input line frequency (Hz)"),
+    ("3.3.1.3.1", "input.voltage", Conversion::Scaled(1.0), "Input voltage (V)"),
+    ("4.2.0", "output.frequency", Conversion::Scaled(0.1), "Output frequency (Hz)"),
+    ("4.4.1.2.1", "output.voltage", Conversion::Scaled(1.0), "Output voltage (V)"),
+    ("4.4.1.3.1", "output.current", Conversion::Scaled(0.1), "Output current (A)"),
+    ("4.4.1.4.1", "ups.realpower", Conversion::Scaled(1.0), "Current value of real power (W)"),
+    ("4.4.1.5.1", "ups.load", Conversion::Scaled(1.0), "Load on UPS (percent of full)"),
+    ("9.1.0", "input.voltage.nominal", Conversion::Scaled(1.0), "Nominal input voltage (V)"),
+    ("9.3.0", "output.voltage.nominal", Conversion::Scaled(1.0), "Nominal output voltage (V)"),
+    ("9.5.0", "ups.power.nominal", Conversion::Scaled(1.0), "UPS power rating (VA)"),
+    ("9.6.0", "ups.realpower.nominal", Conversion::Scaled(1.0), "UPS real power rating (W)"),
+];
+
+/// `upsBatteryStatus`, which is `3` when the battery is low and `4` when it is depleted.
+const BATTERY_STATUS: &str = "2.1.0";
+/// `upsOutputSource`, which tells whether the load is powered from line power or the battery.
+const OUTPUT_SOURCE: &str = "4.1.0";
+/// `upsAlarmsPresent`, the number of active alarms.
+const ALARMS_PRESENT: &str = "6.1.0";
+
+/// Flags of `ups.status` for each value of `upsOutputSource`.
+const OUTPUT_SOURCES: &[(i64, &str)] = &[(2, "OFF"), (3, "OL"), (4, "OL BYPASS"), (5, "OB"), (6, "OL BOOST"), (7, "OL TRIM")];
+
+/// A value of an object read from the agent.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(i64),
+    Text(String),
+}
+
+/// Parses a dotted object identifier such as `1.3.6.1`.
+fn parse_oid(oid: &str) -> Vec<u32> {
+    oid.split('.').filter_map(|arc| arc.parse().ok()).collect()
+}
+
+/// Returns the full identifier of an object of the UPS-MIB.
+fn mib_oid(suffix: &str) -> Vec<u32> {
+    parse_oid(&format!("{UPS_MIB}.{suffix}"))
+}
+
+/// Encodes a BER type, length, and value.
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    match content.len() {
+        len @ 0..=0x7F => encoded.push(len as u8),
+        len @ 0x80..=0xFF => encoded.extend([0x81, len as u8]),
+        len => encoded.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+/// Encodes a BER integer in as few bytes as possible.
+fn integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7 && ((bytes[start] == 0 && bytes[start + 1] < 0x80) || (bytes[start] == 0xFF && bytes[start + 1] >= 0x80)) {
+        start += 1;
+    }
+    tlv(0x02, &bytes[start..])
+}
+
+/// Encodes a BER object identifier.
+fn oid(arcs: &[u32]) -> Vec<u8> {
+    let mut content = Vec::new();
+    if let [first, second, rest @ ..] = arcs {
+        content.push((first * 40 + second) as u8);
+        for arc in rest {
+            let mut chunks = vec![(arc & 0x7F) as u8];
+            let mut remaining = arc >> 7;
+            while remaining > 0 {
+                chunks.push((remaining & 0x7F) as u8 | 0x80);
+                remaining >>= 7;
+            }
+            content.extend(chunks.into_iter().rev());
+        }
+    }
+    tlv(0x06, &content)
+}
+
+/// Encodes an SNMP v2c message holding a PDU of the given type.
+fn message(community: &str, pdu_type: u8, request_id: i64, varbinds: &[(Vec<u32>, Vec<u8>)]) -> Vec<u8> {
+    let varbinds: Vec<u8> = varbinds.iter().flat_map(|(name, value)| tlv(0x30, &[oid(name), value.clone()].concat())).collect();
+    let pdu = [integer(request_id), integer(0), integer(0), tlv(0x30, &varbinds)].concat();
+    let message = [integer(1), tlv(0x04, community.as_bytes()), tlv(pdu_type, &pdu)].concat();
+    tlv(0x30, &message)
+}
+
+/// Splits the first BER type, length, and value off the front of the data, returning the tag,
+/// the value, and the rest of the data.
+fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&len, mut data) = data.split_first()?;
+    let len = if len & 0x80 == 0 {
+        usize::from(len)
+    } else {
+        let count = usize::from(len & 0x7F);
+        let (bytes, rest) = data.split_at_checked(count)?;
+        data = rest;
+        bytes.iter().fold(0, |len, byte| len << 8 | usize::from(*byte))
+    };
+    let (content, rest) = data.split_at_checked(len)?;
+    Some((tag, content, rest))
+}
+
+/// Decodes the content of a BER integer, signed or not depending on its type.
+fn read_integer(tag: u8, content: &[u8]) -> i64 {
+    let negative = tag == 0x02 && content.first().is_some_and(|byte| byte & 0x80 != 0);
+    content.iter().fold(if negative { -1 } else { 0 }, |value, byte| value << 8 | i64::from(*byte))
+}
+
+/// Decodes the content of a BER object identifier.
+fn read_oid(content: &[u8]) -> Vec<u32> {
+    let Some((first, rest)) = content.split_first() else {
+        return Vec::new();
+    };
+    let mut arcs = vec![u32::from(first / 40), u32::from(first % 40)];
+    let mut arc = 0;
+    for byte in rest {
+        arc = arc << 7 | u32::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    arcs
+}
+
+/// Decodes a response to a request with the given ID, returning the value of every object that
+/// exists.
+fn read_response(data: &[u8], request_id: i64) -> io::Result<HashMap<Vec<u32>, Value>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let parse = || -> Option<(i64, i64, HashMap<Vec<u32>, Value>)> {
+        let (_, message, _) = read_tlv(data)?;
+        let (_, _version, message) = read_tlv(message)?;
+        let (_, _community, message) = read_tlv(message)?;
+        let (0xA2, pdu, _) = read_tlv(message)? else {
+            return None;
+        };
+        let (tag, id, pdu) = read_tlv(pdu)?;
+        let id = read_integer(tag, id);
+        let (tag, error, pdu) = read_tlv(pdu)?;
+        let error = read_integer(tag, error);
+        let (_, _index, pdu) = read_tlv(pdu)?;
+        let (_, mut varbinds, _) = read_tlv(pdu)?;
+        let mut values = HashMap::new();
+        while !varbinds.is_empty() {
+            let (_, varbind, rest) = read_tlv(varbinds)?;
+            varbinds = rest;
+            let (_, name, varbind) = read_tlv(varbind)?;
+            let (tag, value, _) = read_tlv(varbind)?;
+            let value = match tag {
+                0x02 | 0x41 | 0x42 | 0x43 | 0x46 => Value::Number(read_integer(tag, value)),
+                0x04 => Value::Text(String::from_utf8_lossy(value).trim().to_string()),
+                // Null, and the exceptions for objects the agent does not have
+                _ => continue,
+            };
+            values.insert(read_oid(name), value);
+        }
+        Some((id, error, values))
+    };
+    match parse() {
+        Some((id, _, _)) if id != request_id => Err(invalid("response to another request")),
+        Some((_, 0, values)) => Ok(values),
+        Some((_, error, _)) => Err(invalid(&format!("agent returned error status {error}"))),
+        None => Err(invalid("malformed SNMP response")),
+    }
+}
+
+/// Translates the objects read from the agent into NUT variables.
+fn translate(values: &HashMap<Vec<u32>, Value>) -> Vec<(String, String)> {
+    let number = |suffix: &str| match values.get(&mib_oid(suffix)) {
+        Some(Value::Number(number)) => Some(*number),
+        _ => None,
+    };
+    let mut vars = Vec::new();
+    for (suffix, name, conversion, _) in OBJECTS {
+        let value = match (conversion, values.get(&mib_oid(suffix))) {
+            (Conversion::Scaled(factor), Some(Value::Number(number))) => ((*number as f64 * factor * 10.0).round() / 10.0).to_string(),
+            (Conversion::Text, Some(Value::Text(text))) => text.clone(),
+            _ => continue,
+        };
+        vars.push((name.to_string(), value));
+    }
+
+    let mut status: Vec<&str> = number(OUTPUT_SOURCE)
+        .and_then(|source| OUTPUT_SOURCES.iter().find(|(value, _)| *value == source))
+        .map(|(_, flags)| flags.split(' ').collect())
+        .unwrap_or_default();
+    if matches!(number(BATTERY_STATUS), Some(3 | 4)) {
+        status.push("LB");
+    }
+    if number(ALARMS_PRESENT).is_some_and(|alarms| alarms > 0) {
+        status.push("ALARM");
+    }
+    if !status.is_empty() {
+        vars.push((String::from("ups.status"), status.join(" ")));
+    }
+    vars
+}
+
+/// A client that reads a UPS from an SNMP agent, instead of from a NUT server.
+#[derive(Debug, Clone)]
+pub struct SnmpClient {
+    host: String,
+    port: u16,
+    community: String,
+    request_id: i64,
+}
+
+impl SnmpClient {
+    /// Creates a client for the SNMP agent at the given host and port, which is usually `161`.
+    #[must_use]
+    pub fn new(host: &str, port: u16, community: &str) -> SnmpClient {
+        SnmpClient {
+            host: host.to_string(),
+            port,
+            community: community.to_string(),
+            request_id: 0,
+        }
+    }
+
+    /// Reads every object of the UPS-MIB known to the client with a single request.
+    fn get_all(&mut self) -> io::Result<HashMap<Vec<u32>, Value>> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to an address", self.host)))?;
+        let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.set_read_timeout(Some(SNMP_TIMEOUT))?;
+        socket.connect(addr)?;
+
+        let suffixes = OBJECTS.iter().map(|(suffix, ..)| *suffix).chain([BATTERY_STATUS, OUTPUT_SOURCE, ALARMS_PRESENT]);
+        let varbinds: Vec<_> = suffixes.map(|suffix| (mib_oid(suffix), tlv(0x05, &[]))).collect();
+        let mut last_error = io::Error::from(io::ErrorKind::TimedOut);
+        for _ in 0..SNMP_ATTEMPTS {
+            self.request_id = (self.request_id + 1) % i64::from(i32::MAX);
+            socket.send(&message(&self.community, 0xA0, self.request_id, &varbinds))?;
+            let mut buffer = [0; 65535];
+            match socket.recv(&mut buffer) {
+                Ok(len) => return read_response(&buffer[..len], self.request_id),
+                Err(err) => {
+                    debug!("No response from SNMP agent {addr}: {err}");
+                    last_error = err;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl UpsClient for SnmpClient {
+    fn list_vars(&mut self, _ups_name: &str) -> Result<Vec<Variable>> {
+        let values = self.get_all().map_err(Error::Connection)?;
+        Ok(translate(&values)
+            .into_iter()
+            .map(|(name, value)| Variable::new(&name, value))
+            .collect())
+    }
+
+    fn get_var_description(&mut self, _ups_name: &str, var_name: &str) -> Result<String> {
+        let description = OBJECTS
+            .iter()
+            .map(|(_, name, _, description)| (*name, *description))
+            .chain([("ups.status", "UPS status")])
+            .find(|(name, _)| *name == var_name)
+            .map_or("Description unavailable", |(_, description)| description);
+        Ok(description.to_string())
+    }
+
+    fn get_var_type(&mut self, _ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        let numeric = OBJECTS
+            .iter()
+            .any(|(_, name, conversion, _)| *name == var_name && *conversion != Conversion::Text);
+        let kind = if numeric { "NUMBER" } else { "STRING:64" };
+        VariableDefinition::parse(var_name, &[kind])
+    }
+
+    fn list_var_enum(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_var_range(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<VariableRange>> {
+        Ok(Vec::new())
+    }
+
+    fn list_clients(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_commands(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn get_command_description(&mut self, _ups_name: &str, _command: &str) -> Result<String> {
+        Err(Error::Protocol(rups::NutError::CmdNotSupported))
+    }
+
+    fn close(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn encode_ber() {
+        assert_eq!(integer(0), [0x02, 0x01, 0x00]);
+        assert_eq!(integer(128), [0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(-1), [0x02, 0x01, 0xFF]);
+        assert_eq!(oid(&parse_oid("1.3.6.1.2.1.33")), [0x06, 0x06, 0x2B, 0x06, 0x01, 0x02, 0x01, 0x21]);
+        assert_eq!(read_oid(&oid(&[1, 3, 6, 1, 4, 1, 318])[2..]), [1, 3, 6, 1, 4, 1, 318]);
+        let long = tlv(0x04, &[0; 200]);
+        assert_eq!(&long[..3], [0x04, 0x81, 200]);
+        assert_eq!(read_tlv(&long).unwrap().1.len(), 200);
+        assert_eq!(read_integer(0x02, &[0xFF, 0x38]), -200);
+        assert_eq!(read_integer(0x42, &[0xFF, 0x38]), 65336);
+    }
+
+    #[test]
+    fn read_ups_mib() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = agent.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut buffer = [0; 65535];
+            let (len, client) = agent.recv_from(&mut buffer).unwrap();
+            let (_, request, _) = read_tlv(&buffer[..len]).unwrap();
+            let (_, _, request) = read_tlv(request).unwrap();
+            let (_, community, request) = read_tlv(request).unwrap();
+            assert_eq!(community, b"private");
+            let (tag, pdu, _) = read_tlv(request).unwrap();
+            assert_eq!(tag, 0xA0);
+            let (tag, id, _) = read_tlv(pdu).unwrap();
+            let id = read_integer(tag, id);
+            let varbinds = [
+                (mib_oid("1.2.0"), tlv(0x04, b"Smart-UPS 1500 ")),
+                (mib_oid("2.3.0"), integer(42)),
+                (mib_oid("2.5.0"), tlv(0x02, &[0x01, 0x0F])),
+                (mib_oid("3.3.1.2.1"), integer(499)),
+                (mib_oid("4.4.1.5.1"), tlv(0x42, &[0x17])),
+                (mib_oid("9.6.0"), tlv(0x80, &[])),
+                (mib_oid(BATTERY_STATUS), integer(3)),
+                (mib_oid(OUTPUT_SOURCE), integer(5)),
+                (mib_oid(ALARMS_PRESENT), tlv(0x42, &[0])),
+            ];
+            agent.send_to(&message("private", 0xA2, id, &varbinds), client).unwrap();
+        });
+
+        let mut client = SnmpClient::new("127.0.0.1", port, "private");
+        let vars: HashMap<String, String> = client
+            .list_vars("ups")
+            .unwrap()
+            .into_iter()
+            .map(|var| (var.name().to_string(), var.value().to_string()))
+            .collect();
+        server.join().unwrap();
+        assert_eq!(vars["ups.model"], "Smart-UPS 1500");
+        assert_eq!(vars["battery.runtime"], "2520");
+        assert_eq!(vars["battery.voltage"], "27.1");
+        assert_eq!(vars["input.frequency"], "49.9");
+        assert_eq!(vars["ups.load"], "23");
+        assert_eq!(vars["ups.status"], "OB LB");
+        assert!(!vars.contains_key("ups.realpower.nominal"));
+        assert!(read_response(&message("private", 0xA2, 7, &[]), 8).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+//! Counters and accumulated values derived from polls, which can be saved to a state file
+//! periodically and on shutdown, and restored at startup so they survive restarts.
+
+use crate::cost::Pricing;
+use crate::events::Event;
+use crate::sink::Sink;
+use crate::status::UpsStatus;
+use crate::Variable;
+use log::{debug, info};
+use prometheus::core::{AtomicF64, GenericCounter};
+use prometheus::{register_counter_with_registry, Registry};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Everything saved to the state file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// UNIX timestamp at which the state was saved.
+    pub saved_at: u64,
+    /// Variables from the last successful poll.
+    pub vars: BTreeMap<String, String>,
+    /// Number of times the UPS status has changed.
+    pub status_changes: u64,
+    /// Number of times the UPS has gone on battery.
+    pub outages: u64,
+    /// Total time spent on battery, in seconds.
+    pub on_battery_seconds: f64,
+    /// Total energy delivered to the load, in watt-hours.
+    pub energy_watt_hours: f64,
+    /// Estimated total cost of the energy delivered to the load.
+    pub energy_cost: f64,
+}
+
+impl State {
+    /// Reads a state file.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the file cannot be read or is not a valid state file.
+    pub fn load(path: &Path) -> io::Result<State> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Writes a state file, replacing it atomically so a crash can never leave it half written.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the file cannot be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp_path = path.as_os_str().to_os_string();
+        temp_path.push(".tmp");
+        let contents = serde_json::to_string_pretty(self).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(&temp_path, contents)?;
+        fs::rename(&temp_path, path)
+    }
+}
+
+/// A sink that maintains counters and accumulated values, exports them as Prometheus counters,
+/// and saves them to an optional state file periodically and on shutdown.
+#[derive(Debug)]
+pub struct Accumulator {
+    state: State,
+    path: Option<PathBuf>,
+    max_gap: Duration,
+    last_poll: Option<Instant>,
+    save_interval: Option<Duration>,
+    last_save: Instant,
+    outages: Option<GenericCounter<AtomicF64>>,
+    status_changes: GenericCounter<AtomicF64>,
+    on_battery_seconds: GenericCounter<AtomicF64>,
+    energy_watt_hours: GenericCounter<AtomicF64>,
+    energy_cost: Option<(Pricing, GenericCounter<AtomicF64>)>,
+}
+
+impl Accumulator {
+    /// Registers the counters, starting from the values in `state`. Time between polls is only
+    /// accumulated if it is shorter than `max_gap`, so downtime is never counted. If a path is
+    /// given, the state will be saved there on shutdown.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the counters cannot be registered with Prometheus.
+    pub fn new(state: State, path: Option<&Path>, max_gap: Duration, registry: &Registry) -> crate::Result<Accumulator> {
+        let status_changes = register_counter_with_registry!(
+            "ups_status_changes_total",
+            "Number of times the UPS status has changed",
+            registry
+        )?;
+        let on_battery_seconds = register_counter_with_registry!(
+            "ups_on_battery_seconds_total",
+            "Total time the UPS has spent on battery",
+            registry
+        )?;
+        let energy_watt_hours = register_counter_with_registry!(
+            "ups_energy_watt_hours_total",
+            "Total energy delivered to the load",
+            registry
+        )?;
+        status_changes.inc_by(state.status_changes as f64);
+        on_battery_seconds.inc_by(state.on_battery_seconds);
+        energy_watt_hours.inc_by(state.energy_watt_hours);
+        Ok(Accumulator {
+            state,
+            path: path.map(Path::to_path_buf),
+            max_gap,
+            last_poll: None,
+            save_interval: None,
+            last_save: Instant::now(),
+            outages: None,
+            status_changes,
+            on_battery_seconds,
+            energy_watt_hours,
+            energy_cost: None,
+        })
+    }
+
+    /// Also estimates the cost of the energy delivered to the load with the given pricing, and
+    /// exports it as a counter.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the counter cannot be registered with Prometheus.
+    pub fn with_pricing(mut self, pricing: Pricing, registry: &Registry) -> crate::Result<Accumulator> {
+        let energy_cost = register_counter_with_registry!(
+            "ups_energy_cost_total",
+            "Estimated total cost of the energy delivered to the load",
+            registry
+        )?;
+        energy_cost.inc_by(self.state.energy_cost);
+        self.energy_cost = Some((pricing, energy_cost));
+        Ok(self)
+    }
+
+    /// Also saves the state file every `interval` while polling, so the counters survive a crash
+    /// or a restart that does not let pistachio shut down cleanly.
+    #[must_use]
+    pub fn with_save_interval(mut self, interval: Duration) -> Accumulator {
+        self.save_interval = Some(interval);
+        self
+    }
+
+    /// Also saves and restores the number of outages, which is counted by the journal. The counter
+    /// is increased by the number of outages in the state.
+    #[must_use]
+    pub fn with_outages(mut self, outages: GenericCounter<AtomicF64>) -> Accumulator {
+        outages.inc_by(self.state.outages as f64);
+        self.outages = Some(outages);
+        self
+    }
+
+    /// Saves the state file, if one is configured.
+    fn save(&mut self, now: SystemTime) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.path else {
+            debug!("No state file configured, state will not be saved");
+            return Ok(());
+        };
+        if let Some(outages) = &self.outages {
+            self.state.outages = outages.get() as u64;
+        }
+        self.state.saved_at = now.duration_since(UNIX_EPOCH)?.as_secs();
+        self.state.save(path)?;
+        self.last_save = Instant::now();
+        debug!("Saved state to {}", path.display());
+        Ok(())
+    }
+
+    /// Updates the accumulated values with a poll that happened `elapsed` after the previous one.
+    fn accumulate(&mut self, vars: &[Variable], elapsed: Option<Duration>) {
+        let values: BTreeMap<String, String> = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        if let Some(elapsed) = elapsed.filter(|elapsed| *elapsed < self.max_gap) {
+            let seconds = elapsed.as_secs_f64();
+            let on_battery = values
+                .get("ups.status")
+                .is_some_and(|status| UpsStatus::parse(status).is_on_battery());
+            if on_battery {
+                self.state.on_battery_seconds += seconds;
+                self.on_battery_seconds.inc_by(seconds);
+            }
+            if let Some(watts) = power_watts(&values) {
+                let watt_hours = watts * seconds / 3600.0;
+                self.state.energy_watt_hours += watt_hours;
+                self.energy_watt_hours.inc_by(watt_hours);
+                if let Some((pricing, counter)) = &self.energy_cost {
+                    let cost = watt_hours / 1000.0 * pricing.price_at(SystemTime::now());
+                    self.state.energy_cost += cost;
+                    counter.inc_by(cost);
+                }
+            }
+        }
+
+        // Count a change of status that happened while pistachio was not running
+        if self.last_poll.is_none() {
+            let saved = self.state.vars.get("ups.status");
+            if saved.is_some() && saved != values.get("ups.status") {
+                self.count_status_change();
+            }
+        }
+        self.state.vars = values;
+    }
+
+    fn count_status_change(&mut self) {
+        self.state.status_changes += 1;
+        self.status_changes.inc();
+    }
+}
+
+impl Sink for Accumulator {
+    fn name(&self) -> &str {
+        "state"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let elapsed = self.last_poll.map(|last| last.elapsed());
+        self.accumulate(vars, elapsed);
+        self.last_poll = Some(Instant::now());
+        if self.save_interval.is_some_and(|interval| self.last_save.elapsed() >= interval) {
+            self.save(SystemTime::now())?;
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        if let Event::StatusChanged { previous: Some(_), .. } = event {
+            self.count_status_change();
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Box<dyn Error>> {
+        self.save(SystemTime::now())?;
+        if let Some(path) = &self.path {
+            info!("Saved state to {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Returns the real power delivered to the load in watts, either as reported directly or as
+/// estimated from the load percentage and nominal real power.
+fn power_watts(values: &BTreeMap<String, String>) -> Option<f64> {
+    let get = |name: &str| values.get(name).and_then(|value| value.trim().parse::<f64>().ok());
+    get("ups.realpower").or_else(|| Some(get("ups.load")? * get("ups.realpower.nominal")? / 100.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str, value: &str) -> Variable {
+        Variable::new(name, value.to_string())
+    }
+
+    #[test]
+    fn estimate_power() {
+        let mut values = BTreeMap::new();
+        assert_eq!(power_watts(&values), None);
+        values.insert(String::from("ups.load"), String::from("50"));
+        values.insert(String::from("ups.realpower.nominal"), String::from("900"));
+        assert_eq!(power_watts(&values), Some(450.0));
+        values.insert(String::from("ups.realpower"), String::from("300"));
+        assert_eq!(power_watts(&values), Some(300.0));
+    }
+
+    #[test]
+    fn accumulate_and_save_state() {
+        let saved = State {
+            vars: BTreeMap::from([(String::from("ups.status"), String::from("OL"))]),
+            status_changes: 4,
+            on_battery_seconds: 10.0,
+            energy_watt_hours: 1.0,
+            outages: 2,
+            ..State::default()
+        };
+        let path = std::env::temp_dir().join(format!("pistachio-state-{}.json", std::process::id()));
+        let outages = GenericCounter::new("ups_outages_total", "Number of times the UPS has gone on battery").unwrap();
+        let registry = Registry::new();
+        let mut accumulator = Accumulator::new(saved, Some(&path), Duration::from_secs(60), &registry)
+            .unwrap()
+            .with_pricing(Pricing::new(0.25, Vec::new()), &registry)
+            .unwrap()
+            .with_outages(outages.clone());
+        assert_eq!(outages.get(), 2.0);
+
+        // A restart while the status changed counts as a change
+        accumulator.accumulate(&[var("ups.status", "OB"), var("ups.realpower", "360")], None);
+        assert_eq!(accumulator.state.status_changes, 5);
+
+        accumulator.accumulate(&[var("ups.status", "OB"), var("ups.realpower", "360")], Some(Duration::from_secs(10)));
+        assert_eq!(accumulator.state.on_battery_seconds, 20.0);
+        assert_eq!(accumulator.state.energy_watt_hours, 2.0);
+        assert_eq!(accumulator.state.energy_cost, 0.00025);
+
+        // Gaps longer than the maximum are ignored
+        accumulator.accumulate(&[var("ups.status", "OB")], Some(Duration::from_secs(120)));
+        assert_eq!(accumulator.state.on_battery_seconds, 20.0);
+
+        accumulator.shutdown().unwrap();
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.status_changes, 5);
+        assert_eq!(loaded.outages, 2);
+        assert_eq!(loaded.vars.get("ups.status").map(String::as_str), Some("OB"));
+
+        // The state is also saved while polling, once the interval has passed
+        let mut accumulator = accumulator.with_save_interval(Duration::ZERO);
+        outages.inc();
+        accumulator.publish(&[var("ups.status", "OL")]).unwrap();
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.outages, 3);
+        assert_eq!(loaded.vars.get("ups.status").map(String::as_str), Some("OL"));
+        fs::remove_file(&path).unwrap();
+    }
+
+}
@@ -0,0 +1,238 @@
+//! The overall status of a UPS, as reported in the `ups.status` variable.
+
+use crate::Error;
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+use std::str::FromStr;
+
+/// A set of UPS status flags, such as `OL` (on line) or `OB LB` (on battery, low battery).
+///
+/// Flags are matched as whole words, so an unrelated word such as `TOLERANCE` is never mistaken
+/// for `OL`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct UpsStatus(u32);
+
+impl UpsStatus {
+    /// On line power (`OL`).
+    pub const ONLINE: UpsStatus = UpsStatus(1);
+    /// On battery (`OB`).
+    pub const ON_BATTERY: UpsStatus = UpsStatus(1 << 1);
+    /// Low battery (`LB`).
+    pub const LOW_BATTERY: UpsStatus = UpsStatus(1 << 2);
+    /// High battery (`HB`).
+    pub const HIGH_BATTERY: UpsStatus = UpsStatus(1 << 3);
+    /// The battery needs to be replaced (`RB`).
+    pub const REPLACE_BATTERY: UpsStatus = UpsStatus(1 << 4);
+    /// The battery is charging (`CHRG`).
+    pub const CHARGING: UpsStatus = UpsStatus(1 << 5);
+    /// The battery is discharging (`DISCHRG`).
+    pub const DISCHARGING: UpsStatus = UpsStatus(1 << 6);
+    /// An alarm is active, described by `ups.alarm` (`ALARM`).
+    pub const ALARM: UpsStatus = UpsStatus(1 << 7);
+    /// The UPS is overloaded (`OVER`).
+    pub const OVERLOAD: UpsStatus = UpsStatus(1 << 8);
+    /// The UPS is trimming incoming voltage (`TRIM`).
+    pub const TRIM: UpsStatus = UpsStatus(1 << 9);
+    /// The UPS is boosting incoming voltage (`BOOST`).
+    pub const BOOST: UpsStatus = UpsStatus(1 << 10);
+    /// The UPS is on bypass (`BYPASS`).
+    pub const BYPASS: UpsStatus = UpsStatus(1 << 11);
+    /// The UPS is off (`OFF`).
+    pub const OFF: UpsStatus = UpsStatus(1 << 12);
+    /// The UPS is calibrating (`CAL`).
+    pub const CALIBRATING: UpsStatus = UpsStatus(1 << 13);
+    /// The UPS is running a test (`TEST`).
+    pub const TEST: UpsStatus = UpsStatus(1 << 14);
+    /// A forced shutdown is in progress (`FSD`).
+    pub const FORCED_SHUTDOWN: UpsStatus = UpsStatus(1 << 15);
+    /// The driver has lost communication with the UPS (`NOCOMM`).
+    pub const NO_COMM: UpsStatus = UpsStatus(1 << 16);
+
+    /// Every flag with the name used for it by NUT, in the order they are displayed.
+    pub const FLAGS: &'static [(UpsStatus, &'static str)] = &[
+        (UpsStatus::ONLINE, "OL"),
+        (UpsStatus::ON_BATTERY, "OB"),
+        (UpsStatus::LOW_BATTERY, "LB"),
+        (UpsStatus::HIGH_BATTERY, "HB"),
+        (UpsStatus::REPLACE_BATTERY, "RB"),
+        (UpsStatus::CHARGING, "CHRG"),
+        (UpsStatus::DISCHARGING, "DISCHRG"),
+        (UpsStatus::ALARM, "ALARM"),
+        (UpsStatus::OVERLOAD, "OVER"),
+        (UpsStatus::TRIM, "TRIM"),
+        (UpsStatus::BOOST, "BOOST"),
+        (UpsStatus::BYPASS, "BYPASS"),
+        (UpsStatus::OFF, "OFF"),
+        (UpsStatus::CALIBRATING, "CAL"),
+        (UpsStatus::TEST, "TEST"),
+        (UpsStatus::FORCED_SHUTDOWN, "FSD"),
+        (UpsStatus::NO_COMM, "NOCOMM"),
+    ];
+
+    /// Flags that say what the load is powered from, in order of precedence.
+    const PRIMARY: &'static [(UpsStatus, &'static str)] = &[
+        (UpsStatus::OFF, "OFF"),
+        (UpsStatus::BYPASS, "BYPASS"),
+        (UpsStatus::ON_BATTERY, "OB"),
+        (UpsStatus::ONLINE, "OL"),
+    ];
+
+    /// Returns a status with no flags set.
+    #[must_use]
+    pub const fn empty() -> UpsStatus {
+        UpsStatus(0)
+    }
+
+    /// Returns the flag with the given NUT name, such as `OL`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<UpsStatus> {
+        UpsStatus::FLAGS
+            .iter()
+            .find(|(_, flag_name)| *flag_name == name)
+            .map(|(flag, _)| *flag)
+    }
+
+    /// Parses the value of `ups.status`, a list of space separated flags, ignoring any flags that
+    /// are not known. Use [`str::parse`] to reject unknown flags instead.
+    #[must_use]
+    pub fn parse(value: &str) -> UpsStatus {
+        value
+            .split_whitespace()
+            .filter_map(UpsStatus::from_name)
+            .fold(UpsStatus::empty(), |status, flag| status | flag)
+    }
+
+    /// Returns true if every flag in `other` is set.
+    #[must_use]
+    pub const fn contains(self, other: UpsStatus) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if any flag in `other` is set.
+    #[must_use]
+    pub const fn intersects(self, other: UpsStatus) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Returns true if no flags are set.
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the raw bits of the flags.
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the NUT names of all set flags.
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        UpsStatus::FLAGS
+            .iter()
+            .filter(move |(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+    }
+
+    /// Returns the NUT name of the primary status, which says what the load is powered from:
+    /// `OFF`, `BYPASS`, `OB`, or `OL`, in that order of precedence if more than one is set.
+    #[must_use]
+    pub fn primary(self) -> Option<&'static str> {
+        UpsStatus::PRIMARY
+            .iter()
+            .find(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+    }
+
+    /// Returns true if the UPS is running on line power.
+    #[must_use]
+    pub const fn is_online(self) -> bool {
+        self.contains(UpsStatus::ONLINE)
+    }
+
+    /// Returns true if the UPS is running on battery.
+    #[must_use]
+    pub const fn is_on_battery(self) -> bool {
+        self.contains(UpsStatus::ON_BATTERY)
+    }
+
+    /// Returns true if the battery is low.
+    #[must_use]
+    pub const fn is_low_battery(self) -> bool {
+        self.contains(UpsStatus::LOW_BATTERY)
+    }
+}
+
+impl BitOr for UpsStatus {
+    type Output = UpsStatus;
+
+    fn bitor(self, other: UpsStatus) -> UpsStatus {
+        UpsStatus(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for UpsStatus {
+    fn bitor_assign(&mut self, other: UpsStatus) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for UpsStatus {
+    type Output = UpsStatus;
+
+    fn bitand(self, other: UpsStatus) -> UpsStatus {
+        UpsStatus(self.0 & other.0)
+    }
+}
+
+impl FromStr for UpsStatus {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<UpsStatus, Error> {
+        value.split_whitespace().try_fold(UpsStatus::empty(), |status, name| {
+            let flag = UpsStatus::from_name(name).ok_or_else(|| Error::Parse(format!("unknown UPS status flag `{name}`")))?;
+            Ok(status | flag)
+        })
+    }
+}
+
+impl fmt::Display for UpsStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.names().collect::<Vec<_>>().join(" "))
+    }
+}
+
+impl fmt::Debug for UpsStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UpsStatus({self})")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_status() {
+        let status = UpsStatus::parse("OB  LB");
+        assert!(status.is_on_battery());
+        assert!(status.is_low_battery());
+        assert!(!status.is_online());
+        assert_eq!(status, UpsStatus::ON_BATTERY | UpsStatus::LOW_BATTERY);
+        assert_eq!(status.to_string(), "OB LB");
+        assert!(!UpsStatus::parse("TOLERANCE").is_online());
+        assert!(UpsStatus::parse("").is_empty());
+        assert_eq!(status.primary(), Some("OB"));
+        assert_eq!(UpsStatus::parse("OL BYPASS").primary(), Some("BYPASS"));
+        assert_eq!(UpsStatus::parse("NOCOMM").primary(), None);
+    }
+
+    #[test]
+    fn parse_status_strictly() {
+        assert_eq!("OL CHRG".parse::<UpsStatus>().unwrap(), UpsStatus::ONLINE | UpsStatus::CHARGING);
+        assert!(matches!("OL TOLERANCE".parse::<UpsStatus>(), Err(Error::Parse(_))));
+        assert_eq!(UpsStatus::parse("OL TOLERANCE"), UpsStatus::ONLINE);
+        let status: UpsStatus = "FSD OB".parse().unwrap();
+        assert_eq!(status.to_string().parse::<UpsStatus>().unwrap(), status);
+    }
+}
@@ -0,0 +1,323 @@
+//! Utilities for testing code built on pistachio without a real NUT server.
+//!
+//! [`MockUpsClient`] serves a canned set of variables and can be scripted to change them or fail
+//! on specific polls, and [`CollectingSink`] captures everything published by the polling loop.
+
+use crate::client::UpsClient;
+use crate::events::Event;
+use crate::sink::Sink;
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A change applied by [`MockUpsClient`] when a poll is made.
+#[derive(Debug)]
+enum Step {
+    Set(Vec<(String, String)>),
+    Remove(String),
+    Fail(Error),
+}
+
+/// An in-memory [`UpsClient`] that serves canned variables.
+///
+/// Each call to [`UpsClient::list_vars`] consumes one scripted step, so a test can describe a
+/// sequence of polls such as "online, on battery, connection lost, online again". Once the script
+/// is exhausted, the current variables are returned on every poll.
+#[derive(Debug, Default)]
+pub struct MockUpsClient {
+    vars: BTreeMap<String, String>,
+    descriptions: BTreeMap<String, String>,
+    enums: BTreeMap<String, Vec<String>>,
+    ranges: BTreeMap<String, Vec<(String, String)>>,
+    clients: Vec<String>,
+    commands: BTreeMap<String, String>,
+    script: VecDeque<Step>,
+    polls: usize,
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl MockUpsClient {
+    /// Creates a client with no variables.
+    #[must_use]
+    pub fn new() -> MockUpsClient {
+        MockUpsClient::default()
+    }
+
+    /// Adds a variable to the canned set served on every poll.
+    #[must_use]
+    pub fn with_var(mut self, name: &str, value: &str) -> MockUpsClient {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets the description returned for a variable. Variables without one are described as
+    /// `Description unavailable`, like upsd does.
+    #[must_use]
+    pub fn with_description(mut self, name: &str, description: &str) -> MockUpsClient {
+        self.descriptions.insert(name.to_string(), description.to_string());
+        self
+    }
+
+    /// Makes a variable an enumeration of the given values.
+    #[must_use]
+    pub fn with_enum(mut self, name: &str, values: &[&str]) -> MockUpsClient {
+        self.enums.insert(name.to_string(), values.iter().map(|value| value.to_string()).collect());
+        self
+    }
+
+    /// Makes a variable a number limited to the given range.
+    #[must_use]
+    pub fn with_range(mut self, name: &str, min: &str, max: &str) -> MockUpsClient {
+        self.ranges
+            .entry(name.to_string())
+            .or_default()
+            .push((min.to_string(), max.to_string()));
+        self
+    }
+
+    /// Adds the address of a client logged in to the UPS.
+    #[must_use]
+    pub fn with_client(mut self, address: &str) -> MockUpsClient {
+        self.clients.push(address.to_string());
+        self
+    }
+
+    /// Adds an instant command supported by the UPS.
+    #[must_use]
+    pub fn with_command(mut self, name: &str, description: &str) -> MockUpsClient {
+        self.commands.insert(name.to_string(), description.to_string());
+        self
+    }
+
+    /// Scripts the next poll to return the current variables unchanged.
+    #[must_use]
+    pub fn then_poll(mut self) -> MockUpsClient {
+        self.script.push_back(Step::Set(Vec::new()));
+        self
+    }
+
+    /// Scripts the next poll to set a variable before returning.
+    #[must_use]
+    pub fn then_set(mut self, name: &str, value: &str) -> MockUpsClient {
+        self.script.push_back(Step::Set(vec![(name.to_string(), value.to_string())]));
+        self
+    }
+
+    /// Scripts the next poll to report the given `ups.status`.
+    #[must_use]
+    pub fn then_status(self, status: &str) -> MockUpsClient {
+        self.then_set("ups.status", status)
+    }
+
+    /// Scripts the next poll to remove a variable before returning.
+    #[must_use]
+    pub fn then_remove(mut self, name: &str) -> MockUpsClient {
+        self.script.push_back(Step::Remove(name.to_string()));
+        self
+    }
+
+    /// Scripts the next poll to fail as if the connection had been lost.
+    #[must_use]
+    pub fn then_fail(self) -> MockUpsClient {
+        self.then_fail_with(Error::Connection(io::Error::new(io::ErrorKind::ConnectionReset, "mock connection lost")))
+    }
+
+    /// Scripts the next poll to fail with the given error.
+    #[must_use]
+    pub fn then_fail_with(mut self, err: Error) -> MockUpsClient {
+        self.script.push_back(Step::Fail(err));
+        self
+    }
+
+    /// Sets the flag once the last scripted step has been served, which can be used as the
+    /// shutdown flag of [`crate::monitor`] to stop polling at the end of the script.
+    #[must_use]
+    pub fn stop_when_exhausted(mut self, flag: Arc<AtomicBool>) -> MockUpsClient {
+        self.stop = Some(flag);
+        self
+    }
+
+    /// Returns the number of polls made so far.
+    #[must_use]
+    pub fn polls(&self) -> usize {
+        self.polls
+    }
+
+    /// Returns the current value of a variable.
+    #[must_use]
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    fn current_vars(&self) -> Vec<Variable> {
+        self.vars
+            .iter()
+            .map(|(name, value)| Variable::new(name, value.clone()))
+            .collect()
+    }
+}
+
+impl UpsClient for MockUpsClient {
+    fn list_vars(&mut self, _ups_name: &str) -> Result<Vec<Variable>> {
+        self.polls += 1;
+        let step = self.script.pop_front();
+        if self.script.is_empty() {
+            if let Some(stop) = &self.stop {
+                stop.store(true, Ordering::Relaxed);
+            }
+        }
+        match step {
+            Some(Step::Set(changes)) => self.vars.extend(changes),
+            Some(Step::Remove(name)) => {
+                self.vars.remove(&name);
+            }
+            Some(Step::Fail(err)) => return Err(err),
+            None => {}
+        }
+        Ok(self.current_vars())
+    }
+
+    fn get_var(&mut self, _ups_name: &str, var_name: &str) -> Result<Variable> {
+        match self.vars.get(var_name) {
+            Some(value) => Ok(Variable::new(var_name, value.clone())),
+            None => Err(Error::Protocol(rups::NutError::VarNotSupported)),
+        }
+    }
+
+    fn get_var_description(&mut self, _ups_name: &str, var_name: &str) -> Result<String> {
+        Ok(self
+            .descriptions
+            .get(var_name)
+            .cloned()
+            .unwrap_or_else(|| String::from("Description unavailable")))
+    }
+
+    fn get_var_type(&mut self, _ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        let mut types = Vec::new();
+        if self.enums.contains_key(var_name) {
+            types.push("ENUM");
+        } else if self.ranges.contains_key(var_name) {
+            types.push("RANGE");
+        } else if self.vars.get(var_name).is_some_and(|value| value.parse::<f64>().is_ok()) {
+            types.push("NUMBER");
+        } else {
+            types.push("STRING:64");
+        }
+        VariableDefinition::parse(var_name, &types)
+    }
+
+    fn list_var_enum(&mut self, _ups_name: &str, var_name: &str) -> Result<Vec<String>> {
+        Ok(self.enums.get(var_name).cloned().unwrap_or_default())
+    }
+
+    fn list_var_range(&mut self, _ups_name: &str, var_name: &str) -> Result<Vec<VariableRange>> {
+        let ranges = self.ranges.get(var_name).map(Vec::as_slice).unwrap_or_default();
+        Ok(ranges
+            .iter()
+            .map(|(min, max)| VariableRange { min: min.clone(), max: max.clone() })
+            .collect())
+    }
+
+    fn list_clients(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(self.clients.clone())
+    }
+
+    fn list_commands(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(self.commands.keys().cloned().collect())
+    }
+
+    fn get_command_description(&mut self, _ups_name: &str, command: &str) -> Result<String> {
+        Ok(self
+            .commands
+            .get(command)
+            .cloned()
+            .unwrap_or_else(|| String::from("Description unavailable")))
+    }
+
+    fn close(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Everything a [`CollectingSink`] has received.
+#[derive(Debug, Default)]
+pub struct Collected {
+    /// Variables of every successful poll, in order.
+    pub polls: Vec<Vec<(String, String)>>,
+    /// Every event, in order.
+    pub events: Vec<Event>,
+    /// Whether the sink has been shut down.
+    pub shut_down: bool,
+}
+
+/// A [Sink] that stores everything it receives, for inspection after polling.
+#[derive(Debug, Clone, Default)]
+pub struct CollectingSink {
+    collected: Arc<Mutex<Collected>>,
+}
+
+impl CollectingSink {
+    /// Creates an empty sink.
+    #[must_use]
+    pub fn new() -> CollectingSink {
+        CollectingSink::default()
+    }
+
+    /// Runs a closure with everything collected so far.
+    pub fn with_collected<T>(&self, f: impl FnOnce(&Collected) -> T) -> T {
+        f(&self.collected.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Collected> {
+        self.collected.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Sink for CollectingSink {
+    fn name(&self) -> &str {
+        "collect"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let vars = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        self.lock().polls.push(vars);
+        Ok(())
+    }
+
+    fn event(&mut self, event: &Event) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        self.lock().events.push(event.clone());
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        self.lock().shut_down = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_script() {
+        let mut client = MockUpsClient::new()
+            .with_var("ups.status", "OL")
+            .with_description("ups.status", "UPS status")
+            .then_poll()
+            .then_status("OB")
+            .then_fail()
+            .then_remove("ups.status");
+
+        assert_eq!(client.list_vars("ups").unwrap()[0].value(), "OL");
+        assert_eq!(client.list_vars("ups").unwrap()[0].value(), "OB");
+        assert!(matches!(client.list_vars("ups"), Err(Error::Connection(_))));
+        assert!(client.list_vars("ups").unwrap().is_empty());
+        assert!(client.list_vars("ups").unwrap().is_empty());
+        assert_eq!(client.polls(), 5);
+        assert_eq!(client.get_var_description("ups", "ups.status").unwrap(), "UPS status");
+        assert_eq!(client.get_var_description("ups", "ups.load").unwrap(), "Description unavailable");
+    }
+}
@@ -0,0 +1,137 @@
+//! Helpers for formatting timestamps without pulling in a full date and time library.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A calendar date and time of day in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Civil {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: u64,
+    pub minute: u64,
+    pub second: u64,
+}
+
+impl Civil {
+    /// Converts a time into a UTC calendar date and time of day, with second precision.
+    pub fn from_system_time(time: SystemTime) -> Civil {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let days = i64::try_from(secs / 86400).unwrap_or(i64::MAX);
+        let rem = secs % 86400;
+
+        // Convert days since the epoch to a civil date
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        Civil {
+            year,
+            month,
+            day,
+            hour: rem / 3600,
+            minute: rem % 3600 / 60,
+            second: rem % 60,
+        }
+    }
+}
+
+/// Returns the number of days since the UNIX epoch of a calendar date.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Parses a date reported by a UPS, such as `battery.date`, as midnight UTC. Drivers report dates
+/// as `YYYY/MM/DD`, `YYYY-MM-DD`, `MM/DD/YYYY` or `MM/DD/YY`, with two-digit years taken to be in
+/// the 2000s.
+pub(crate) fn parse_date(value: &str) -> Option<SystemTime> {
+    let (year, month, day) = parse_civil_date(value)?;
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400))
+}
+
+/// Parses a date reported by a UPS into its year, month, and day, in any of the formats read by
+/// [`parse_date`].
+pub(crate) fn parse_civil_date(value: &str) -> Option<(i64, i64, i64)> {
+    let parts: Vec<i64> = value
+        .trim()
+        .split(['/', '-'])
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let [first, second, third] = parts[..] else {
+        return None;
+    };
+    let (year, month, day) = match value.trim().find(['/', '-']) {
+        Some(4) => (first, second, third),
+        _ if third < 100 => (2000 + third, first, second),
+        _ => (third, first, second),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+    Some((year, month, day))
+}
+
+/// Formats a time as an RFC 3339 timestamp in UTC, with second precision.
+pub(crate) fn format_rfc3339(time: SystemTime) -> String {
+    let c = Civil::from_system_time(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        c.year, c.month, c.day, c.hour, c.minute, c.second
+    )
+}
+
+/// Parses an RFC 3339 timestamp in UTC with second precision, as written by [`format_rfc3339`].
+pub(crate) fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.strip_suffix('Z')?.split_once('T')?;
+    let date: Vec<i64> = date.split('-').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let time: Vec<u64> = time.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let [year, month, day] = date[..] else {
+        return None;
+    };
+    let [hour, minute, second] = time[..] else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_timestamps() {
+        assert_eq!(format_rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(format_rfc3339(UNIX_EPOCH + Duration::from_secs(1_709_210_096)), "2024-02-29T12:34:56Z");
+        assert_eq!(parse_rfc3339("2024-02-29T12:34:56Z"), Some(UNIX_EPOCH + Duration::from_secs(1_709_210_096)));
+        assert_eq!(parse_rfc3339("2024-02-29 12:34:56"), None);
+    }
+
+    #[test]
+    fn parse_dates() {
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_164_800);
+        assert_eq!(parse_date("2024/02/29"), Some(leap_day));
+        assert_eq!(parse_date("2024-02-29"), Some(leap_day));
+        assert_eq!(parse_date("02/29/2024"), Some(leap_day));
+        assert_eq!(parse_date("02/29/24"), Some(leap_day));
+        assert_eq!(parse_date("1970/01/01"), Some(UNIX_EPOCH));
+        assert_eq!(parse_date("2024/13/01"), None);
+        assert_eq!(parse_date("not set"), None);
+    }
+}
@@ -0,0 +1,333 @@
+//! A live table of several UPSes for `pistachio top`, showing the status, charge, load, runtime,
+//! and age of the last poll of each, for keeping an eye on a fleet from a terminal.
+//!
+//! Every UPS is polled on its own thread, so one that cannot be reached does not hold up the
+//! others, and keeps showing the values of its last successful poll while its age grows.
+
+use crate::client::{Connection, UpsClient};
+use crate::{UpsStatus, UpsTarget, Variable};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest time a poller waits for its next poll before checking whether it should stop.
+const WAIT_STEP: Duration = Duration::from_millis(100);
+
+/// Column the table is sorted by. Every column other than the name is sorted with the UPS most
+/// in need of attention first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum SortColumn {
+    /// By target, alphabetically.
+    #[default]
+    Name,
+    /// UPSes that failed to be polled first, then those on battery, then with other flags.
+    Status,
+    /// Lowest battery charge first.
+    Charge,
+    /// Highest load first.
+    Load,
+    /// Shortest runtime first.
+    Runtime,
+    /// Oldest last poll first.
+    Age,
+}
+
+impl fmt::Display for SortColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SortColumn::Name => "name",
+            SortColumn::Status => "status",
+            SortColumn::Charge => "charge",
+            SortColumn::Load => "load",
+            SortColumn::Runtime => "runtime",
+            SortColumn::Age => "age",
+        })
+    }
+}
+
+/// The latest values of one UPS in the table.
+#[derive(Debug, Clone)]
+pub struct UpsRow {
+    /// The UPS polled for the row.
+    pub target: UpsTarget,
+    /// Value of `ups.status` at the last successful poll.
+    pub status: Option<String>,
+    /// Battery charge in percent at the last successful poll.
+    pub charge: Option<f64>,
+    /// Load in percent at the last successful poll.
+    pub load: Option<f64>,
+    /// Battery runtime in seconds at the last successful poll.
+    pub runtime: Option<f64>,
+    /// Time of the last successful poll.
+    pub last_poll: Option<Instant>,
+    /// Why the last poll failed, if it did.
+    pub error: Option<String>,
+}
+
+impl UpsRow {
+    /// Creates the row of a UPS that has not been polled yet.
+    #[must_use]
+    pub fn new(target: UpsTarget) -> UpsRow {
+        UpsRow {
+            target,
+            status: None,
+            charge: None,
+            load: None,
+            runtime: None,
+            last_poll: None,
+            error: None,
+        }
+    }
+
+    /// Takes the values of a successful poll.
+    pub fn update(&mut self, vars: &[Variable], now: Instant) {
+        let value = |name: &str| vars.iter().find(|var| var.name() == name).map(Variable::value);
+        let number = |name: &str| value(name).and_then(crate::parse_number);
+        self.status = value("ups.status").map(str::to_string);
+        self.charge = number("battery.charge");
+        self.load = number("ups.load");
+        self.runtime = number("battery.runtime");
+        self.last_poll = Some(now);
+        self.error = None;
+    }
+
+    /// Ranks the status for sorting, with the rows most in need of attention lowest.
+    fn status_rank(&self) -> u8 {
+        let status = self.status.as_deref().map(UpsStatus::parse).unwrap_or_default();
+        if self.error.is_some() || self.status.is_none() {
+            0
+        } else if status.intersects(UpsStatus::ON_BATTERY | UpsStatus::LOW_BATTERY) {
+            1
+        } else if status == UpsStatus::ONLINE {
+            3
+        } else {
+            2
+        }
+    }
+}
+
+/// Compares optional values, keeping rows without a value last.
+fn compare(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Sorts rows by a column, breaking ties by target.
+pub fn sort(rows: &mut [UpsRow], column: SortColumn, now: Instant) {
+    let age = |row: &UpsRow| row.last_poll.map(|time| now.duration_since(time).as_secs_f64());
+    rows.sort_by(|a, b| {
+        let order = match column {
+            SortColumn::Name => Ordering::Equal,
+            SortColumn::Status => a.status_rank().cmp(&b.status_rank()),
+            SortColumn::Charge => compare(a.charge, b.charge),
+            SortColumn::Load => compare(a.load.map(|load| -load), b.load.map(|load| -load)),
+            SortColumn::Runtime => compare(a.runtime, b.runtime),
+            // Rows never polled are the oldest of all
+            SortColumn::Age => match (age(a), age(b)) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Less,
+                (Some(_), None) => Ordering::Greater,
+                (Some(a), Some(b)) => b.total_cmp(&a),
+            },
+        };
+        order.then_with(|| a.target.to_string().cmp(&b.target.to_string()))
+    });
+}
+
+/// Formats a number of seconds as hours and minutes, or minutes and seconds under an hour.
+fn format_runtime(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    if seconds >= 3600 {
+        format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60)
+    } else {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    }
+}
+
+/// Renders rows as a table with a header, followed by the error of every row whose last poll
+/// failed.
+#[must_use]
+pub fn render(rows: &[UpsRow], now: Instant) -> String {
+    let missing = || String::from("-");
+    let percent = |value: Option<f64>| value.map_or_else(missing, |value| format!("{value:.0}%"));
+    let cells: Vec<[String; 6]> = rows
+        .iter()
+        .map(|row| {
+            let status = match (&row.error, &row.status) {
+                (Some(_), _) => String::from("ERROR"),
+                (None, Some(status)) => status.clone(),
+                (None, None) => missing(),
+            };
+            [
+                row.target.to_string(),
+                status,
+                percent(row.charge),
+                percent(row.load),
+                row.runtime.map_or_else(missing, format_runtime),
+                row.last_poll.map_or_else(missing, |time| format!("{}s", now.duration_since(time).as_secs())),
+            ]
+        })
+        .collect();
+    let header = ["UPS", "STATUS", "CHARGE", "LOAD", "RUNTIME", "AGE"].map(String::from);
+    let mut widths = header.clone().map(|cell| cell.len());
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&cells) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            // The name and status are aligned left, and numbers right
+            .map(|(column, (cell, width))| if column < 2 { format!("{cell:<width$}") } else { format!("{cell:>width$}") })
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    if rows.iter().any(|row| row.error.is_some()) {
+        table.push('\n');
+    }
+    for row in rows {
+        if let Some(error) = &row.error {
+            table.push_str(&format!("{}: {error}\n", row.target));
+        }
+    }
+    table
+}
+
+/// A set of UPSes that are each polled on their own thread, of which the latest values are kept
+/// for the table.
+#[derive(Debug, Clone)]
+pub struct Fleet {
+    rows: Arc<Mutex<Vec<UpsRow>>>,
+}
+
+impl Fleet {
+    /// Starts polling every target every `interval`, until `shutdown` is set.
+    #[must_use]
+    pub fn start(targets: Vec<UpsTarget>, interval: Duration, shutdown: &Arc<AtomicBool>) -> Fleet {
+        let rows = Arc::new(Mutex::new(targets.iter().cloned().map(UpsRow::new).collect::<Vec<_>>()));
+        for (index, target) in targets.into_iter().enumerate() {
+            let rows = Arc::clone(&rows);
+            let shutdown = Arc::clone(shutdown);
+            thread::spawn(move || poll_target(&target, index, &rows, interval, &shutdown));
+        }
+        Fleet { rows }
+    }
+
+    /// Returns the latest values of every UPS, sorted by a column.
+    #[must_use]
+    pub fn rows(&self, column: SortColumn) -> Vec<UpsRow> {
+        let mut rows = self.rows.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        sort(&mut rows, column, Instant::now());
+        rows
+    }
+}
+
+/// Polls a UPS until asked to stop, reconnecting on the next poll after a failure.
+fn poll_target(target: &UpsTarget, index: usize, rows: &Mutex<Vec<UpsRow>>, interval: Duration, shutdown: &AtomicBool) {
+    let mut conn: Option<Connection> = None;
+    while !shutdown.load(atomic::Ordering::Relaxed) {
+        let started = Instant::now();
+        let result = match conn.take() {
+            Some(conn) => Ok(conn),
+            None => Connection::open(&target.host, target.port),
+        }
+        .and_then(|mut client| {
+            let vars = client.list_vars(&target.name)?;
+            conn = Some(client);
+            Ok(vars)
+        });
+        {
+            let mut rows = rows.lock().unwrap_or_else(PoisonError::into_inner);
+            match result {
+                Ok(vars) => rows[index].update(&vars, Instant::now()),
+                Err(err) => rows[index].error = Some(err.to_string()),
+            }
+        }
+        let deadline = started + interval;
+        while !shutdown.load(atomic::Ordering::Relaxed) && Instant::now() < deadline {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()).min(WAIT_STEP));
+        }
+    }
+    if let Some(conn) = conn {
+        let _ = conn.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::{Scenario, Simulator};
+    use std::net::SocketAddr;
+
+    fn row(target: &str, vars: &[(&str, &str)], now: Instant) -> UpsRow {
+        let mut row = UpsRow::new(target.parse().unwrap());
+        let vars: Vec<Variable> = vars.iter().map(|(name, value)| Variable::new(*name, *value)).collect();
+        row.update(&vars, now);
+        row
+    }
+
+    #[test]
+    fn render_sorted_table() {
+        let now = Instant::now();
+        let earlier = now.checked_sub(Duration::from_secs(12)).unwrap();
+        let mut unreachable = row("closet@nut.local", &[("ups.status", "OL"), ("battery.charge", "100")], earlier);
+        unreachable.error = Some(String::from("connection refused"));
+        let mut rows = vec![
+            row("rack@nut.local", &[("ups.status", "OL CHRG"), ("battery.charge", "80"), ("ups.load", "35"), ("battery.runtime", "5400")], now),
+            unreachable,
+            row("desk@nut.local", &[("ups.status", "OB DISCHRG"), ("battery.charge", "45"), ("ups.load", "12"), ("battery.runtime", "290")], now),
+        ];
+        sort(&mut rows, SortColumn::Status, now);
+        assert_eq!(
+            render(&rows, now),
+            "UPS                    STATUS      CHARGE  LOAD  RUNTIME  AGE\n\
+             closet@nut.local:3493  ERROR         100%     -        -  12s\n\
+             desk@nut.local:3493    OB DISCHRG     45%   12%    4m50s   0s\n\
+             rack@nut.local:3493    OL CHRG        80%   35%    1h30m   0s\n\
+             \n\
+             closet@nut.local:3493: connection refused\n"
+        );
+        let order = |rows: &[UpsRow]| rows.iter().map(|row| row.target.name.clone()).collect::<Vec<_>>();
+        sort(&mut rows, SortColumn::Load, now);
+        assert_eq!(order(&rows), ["rack", "desk", "closet"]);
+        sort(&mut rows, SortColumn::Age, now);
+        assert_eq!(order(&rows), ["closet", "desk", "rack"]);
+        sort(&mut rows, SortColumn::Name, now);
+        assert_eq!(order(&rows), ["closet", "desk", "rack"]);
+    }
+
+    #[test]
+    fn poll_fleet() {
+        let addr = Simulator::new("sim", Scenario::Online, Duration::from_secs(30))
+            .start(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap();
+        let missing = UpsTarget {
+            name: String::from("missing"),
+            ..format!("sim@127.0.0.1:{}", addr.port()).parse().unwrap()
+        };
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let fleet = Fleet::start(vec![format!("sim@127.0.0.1:{}", addr.port()).parse().unwrap(), missing], Duration::from_millis(50), &shutdown);
+        thread::sleep(Duration::from_millis(300));
+        let rows = fleet.rows(SortColumn::Status);
+        shutdown.store(true, atomic::Ordering::Relaxed);
+        assert_eq!(rows[0].target.name, "missing");
+        assert!(rows[0].error.is_some());
+        assert_eq!(rows[1].status.as_deref(), Some("OL"));
+        assert_eq!(rows[1].charge, Some(100.0));
+    }
+}
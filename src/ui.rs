@@ -0,0 +1,170 @@
+//! A small dashboard served at `/ui`, showing the live state of the UPS and its recent events for
+//! users who do not run Grafana. Its assets are embedded in the binary, and it reads the journal
+//! at `/api/v1/events` and the recent values kept by [`Dashboard`] at `/ui/state`.
+
+use crate::http::{Request, Response};
+use crate::sink::Sink;
+use crate::time::format_rfc3339;
+use crate::Variable;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// The page of the dashboard, with its styles and scripts inline.
+const INDEX: &str = include_str!("ui/index.html");
+
+/// Number of polls of which values are kept for the charts of the dashboard.
+const SAMPLES: usize = 360;
+
+/// Variables of which every poll is kept for the charts of the dashboard.
+const CHARTED: &[&str] = &["battery.charge", "ups.load", "battery.runtime"];
+
+/// Values of the UPS shown by the dashboard.
+#[derive(Debug, Default)]
+struct Live {
+    vars: BTreeMap<String, String>,
+    updated: Option<SystemTime>,
+    samples: VecDeque<Value>,
+}
+
+/// A sink that keeps the latest variables and the values of recent polls for the dashboard.
+#[derive(Debug)]
+pub struct Dashboard {
+    ups_name: String,
+    live: Arc<Mutex<Live>>,
+}
+
+impl Dashboard {
+    /// Creates a dashboard for the given UPS.
+    #[must_use]
+    pub fn new(ups_name: &str) -> Dashboard {
+        Dashboard {
+            ups_name: ups_name.to_string(),
+            live: Arc::default(),
+        }
+    }
+
+    /// Returns a handle for serving the dashboard, which can be shared with the HTTP server.
+    #[must_use]
+    pub fn reader(&self) -> DashboardReader {
+        DashboardReader {
+            ups_name: self.ups_name.clone(),
+            live: Arc::clone(&self.live),
+        }
+    }
+
+    /// Records the variables of a poll that completed at the given time.
+    fn record(&self, vars: BTreeMap<String, String>, time: SystemTime) {
+        let mut sample = json!({ "timestamp": format_rfc3339(time) });
+        for name in CHARTED {
+            if let Some(value) = vars.get(*name).and_then(|value| value.parse::<f64>().ok()) {
+                sample[*name] = json!(value);
+            }
+        }
+        if let Some(status) = vars.get("ups.status") {
+            sample["ups.status"] = json!(status);
+        }
+        let mut live = self.live.lock().unwrap_or_else(PoisonError::into_inner);
+        if live.samples.len() == SAMPLES {
+            live.samples.pop_front();
+        }
+        live.samples.push_back(sample);
+        live.vars = vars;
+        live.updated = Some(time);
+    }
+}
+
+impl Sink for Dashboard {
+    fn name(&self) -> &str {
+        "dashboard"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let vars = vars.iter().map(|var| (var.name().to_string(), var.value().to_string())).collect();
+        self.record(vars, SystemTime::now());
+        Ok(())
+    }
+}
+
+/// A handle for serving the dashboard, which can be shared with the HTTP server.
+#[derive(Debug, Clone)]
+pub struct DashboardReader {
+    ups_name: String,
+    live: Arc<Mutex<Live>>,
+}
+
+impl DashboardReader {
+    /// Serves the page of the dashboard.
+    #[must_use]
+    pub fn page(_request: &Request) -> Response {
+        Response::new(200, "text/html; charset=utf-8", INDEX)
+    }
+
+    /// Serves the latest variables of the UPS, and the charted values of recent polls, oldest
+    /// first.
+    #[must_use]
+    pub fn state(&self, _request: &Request) -> Response {
+        let live = self.live.lock().unwrap_or_else(PoisonError::into_inner);
+        Response::json(
+            200,
+            &json!({
+                "ups": self.ups_name,
+                "updated": live.updated.map(format_rfc3339),
+                "variables": live.vars,
+                "samples": live.samples,
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn request() -> Request {
+        Request {
+            method: String::from("GET"),
+            path: String::from("/ui/state"),
+            query: std::collections::HashMap::new(),
+            headers: std::collections::HashMap::new(),
+            body: Vec::new(),
+            remote_addr: std::net::SocketAddr::from(([127, 0, 0, 1], 1234)),
+            client_cn: None,
+        }
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn dashboard_state() {
+        let dashboard = Dashboard::new("ups");
+        let reader = dashboard.reader();
+        let state: Value = serde_json::from_slice(&reader.state(&request()).body).unwrap();
+        assert_eq!(state["updated"], Value::Null);
+        assert_eq!(state["samples"], json!([]));
+
+        for secs in 0..=SAMPLES as u64 {
+            let charge = (100 - secs % 100).to_string();
+            dashboard.record(
+                vars(&[("battery.charge", &charge), ("ups.status", "OL"), ("ups.model", "Smart-UPS")]),
+                UNIX_EPOCH + Duration::from_secs(secs),
+            );
+        }
+        dashboard.record(vars(&[("ups.load", "12"), ("ups.status", "OB")]), UNIX_EPOCH + Duration::from_secs(1000));
+
+        let state: Value = serde_json::from_slice(&reader.state(&request()).body).unwrap();
+        assert_eq!(state["ups"], "ups");
+        assert_eq!(state["updated"], "1970-01-01T00:16:40Z");
+        assert_eq!(state["variables"], json!({ "ups.load": "12", "ups.status": "OB" }));
+        let samples = state["samples"].as_array().unwrap();
+        assert_eq!(samples.len(), SAMPLES);
+        assert_eq!(samples[0], json!({ "timestamp": "1970-01-01T00:00:02Z", "battery.charge": 98.0, "ups.status": "OL" }));
+        assert_eq!(samples[SAMPLES - 1], json!({ "timestamp": "1970-01-01T00:16:40Z", "ups.load": 12.0, "ups.status": "OB" }));
+        assert!(String::from_utf8(DashboardReader::page(&request()).body).unwrap().contains("/ui/state"));
+    }
+}
@@ -0,0 +1,591 @@
+//! A backend that reads a UPS connected over USB directly through the Linux hidraw driver, for
+//! small deployments where running a NUT server is overkill.
+//!
+//! Most UPSes implement the USB HID Power Device class, in which the report descriptor of the
+//! device describes every value it reports. The standard usages of the Power Device and Battery
+//! System usage pages are translated to the NUT variables they correspond to, so the rest of the
+//! exporter works the same as with a NUT server. Vendor specific usages are ignored, and instant
+//! commands are not supported.
+
+use crate::client::UpsClient;
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use log::debug;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Directory in which the kernel lists hidraw devices.
+const HIDRAW_CLASS: &str = "/sys/class/hidraw";
+
+/// Usages of the Power Device (`0x84`) and Battery System (`0x85`) usage pages, with the page in
+/// the upper 16 bits.
+mod usage {
+    pub const BATTERY: u32 = 0x0084_0012;
+    pub const INPUT: u32 = 0x0084_001A;
+    pub const OUTPUT: u32 = 0x0084_001C;
+    pub const POWER_SUMMARY: u32 = 0x0084_0024;
+    pub const VOLTAGE: u32 = 0x0084_0030;
+    pub const CURRENT: u32 = 0x0084_0031;
+    pub const FREQUENCY: u32 = 0x0084_0032;
+    pub const APPARENT_POWER: u32 = 0x0084_0033;
+    pub const ACTIVE_POWER: u32 = 0x0084_0034;
+    pub const PERCENT_LOAD: u32 = 0x0084_0035;
+    pub const TEMPERATURE: u32 = 0x0084_0036;
+    pub const CONFIG_VOLTAGE: u32 = 0x0084_0040;
+    pub const CONFIG_APPARENT_POWER: u32 = 0x0084_0043;
+    pub const CONFIG_ACTIVE_POWER: u32 = 0x0084_0044;
+    pub const DELAY_BEFORE_STARTUP: u32 = 0x0084_0056;
+    pub const DELAY_BEFORE_SHUTDOWN: u32 = 0x0084_0057;
+    pub const OVERLOAD: u32 = 0x0084_0065;
+    pub const SHUTDOWN_IMMINENT: u32 = 0x0084_0069;
+    pub const REMAINING_CAPACITY_LIMIT: u32 = 0x0085_0029;
+    pub const BELOW_REMAINING_CAPACITY_LIMIT: u32 = 0x0085_0042;
+    pub const CHARGING: u32 = 0x0085_0044;
+    pub const DISCHARGING: u32 = 0x0085_0045;
+    pub const NEED_REPLACEMENT: u32 = 0x0085_004B;
+    pub const REMAINING_CAPACITY: u32 = 0x0085_0066;
+    pub const RUN_TIME_TO_EMPTY: u32 = 0x0085_0068;
+    pub const AC_PRESENT: u32 = 0x0085_00D0;
+}
+
+/// Collections that tell apart usages measured in several places, such as the voltages of the
+/// input, the output, and the battery.
+const SCOPES: &[u32] = &[usage::BATTERY, usage::INPUT, usage::OUTPUT, usage::POWER_SUMMARY];
+
+/// A NUT variable read from a usage, within one of the given collections or anywhere if none are
+/// given.
+struct Mapping {
+    scopes: &'static [u32],
+    usage: u32,
+    name: &'static str,
+    description: &'static str,
+}
+
+const BATTERY_SCOPES: &[u32] = &[usage::BATTERY, usage::POWER_SUMMARY];
+
+/// Variables read from the UPS, with the same names and descriptions as the `usbhid-ups` driver
+/// of NUT.
+const MAPPINGS: &[Mapping] = &[
+    Mapping { scopes: BATTERY_SCOPES, usage: usage::REMAINING_CAPACITY, name: "battery.charge", description: "Battery charge (percent of full)" },
+    Mapping { scopes: BATTERY_SCOPES, usage: usage::REMAINING_CAPACITY_LIMIT, name: "battery.charge.low", description: "Remaining battery level when UPS switches to LB (percent)" },
+    Mapping { scopes: &[], usage: usage::RUN_TIME_TO_EMPTY, name: "battery.runtime", description: "Battery runtime (seconds)" },
+    Mapping { scopes: BATTERY_SCOPES, usage: usage::VOLTAGE, name: "battery.voltage", description: "Battery voltage (V)" },
+    Mapping { scopes: BATTERY_SCOPES, usage: usage::CONFIG_VOLTAGE, name: "battery.voltage.nominal", description: "Nominal battery voltage (V)" },
+    Mapping { scopes: &[usage::INPUT], usage: usage::VOLTAGE, name: "input.voltage", description: "Input voltage (V)" },
+    Mapping { scopes: &[usage::INPUT], usage: usage::CONFIG_VOLTAGE, name: "input.voltage.nominal", description: "Nominal input voltage (V)" },
+    Mapping { scopes: &[usage::INPUT], usage: usage::FREQUENCY, name: "input.frequency", description: "Input line frequency (Hz)" },
+    Mapping { scopes: &[usage::OUTPUT], usage: usage::VOLTAGE, name: "output.voltage", description: "Output voltage (V)" },
+    Mapping { scopes: &[usage::OUTPUT], usage: usage::CONFIG_VOLTAGE, name: "output.voltage.nominal", description: "Nominal output voltage (V)" },
+    Mapping { scopes: &[usage::OUTPUT], usage: usage::FREQUENCY, name: "output.frequency", description: "Output frequency (Hz)" },
+    Mapping { scopes: &[usage::OUTPUT], usage: usage::CURRENT, name: "output.current", description: "Output current (A)" },
+    Mapping { scopes: &[], usage: usage::PERCENT_LOAD, name: "ups.load", description: "Load on UPS (percent of full)" },
+    Mapping { scopes: &[], usage: usage::TEMPERATURE, name: "ups.temperature", description: "UPS temperature (degrees C)" },
+    Mapping { scopes: &[], usage: usage::ACTIVE_POWER, name: "ups.realpower", description: "Current value of real power (W)" },
+    Mapping { scopes: &[], usage: usage::APPARENT_POWER, name: "ups.power", description: "Current value of apparent power (VA)" },
+    Mapping { scopes: &[], usage: usage::CONFIG_ACTIVE_POWER, name: "ups.realpower.nominal", description: "UPS real power rating (W)" },
+    Mapping { scopes: &[], usage: usage::CONFIG_APPARENT_POWER, name: "ups.power.nominal", description: "UPS power rating (VA)" },
+    Mapping { scopes: &[], usage: usage::DELAY_BEFORE_SHUTDOWN, name: "ups.timer.shutdown", description: "Time before the load will be shutdown (seconds)" },
+    Mapping { scopes: &[], usage: usage::DELAY_BEFORE_STARTUP, name: "ups.timer.start", description: "Time before the load will be started (seconds)" },
+];
+
+/// Flags of `ups.status` set by a usage with a non-zero value. `OB` is set instead of `OL` if the
+/// UPS reports that AC power is not present.
+const STATUS_FLAGS: &[(u32, &str)] = &[
+    (usage::AC_PRESENT, "OL"),
+    (usage::CHARGING, "CHRG"),
+    (usage::DISCHARGING, "DISCHRG"),
+    (usage::BELOW_REMAINING_CAPACITY_LIMIT, "LB"),
+    (usage::SHUTDOWN_IMMINENT, "LB"),
+    (usage::NEED_REPLACEMENT, "RB"),
+    (usage::OVERLOAD, "OVER"),
+];
+
+/// Variables describing the USB device, read from sysfs, with their descriptions.
+const DEVICE_VARIABLES: &[(&str, &str, &str)] = &[
+    ("manufacturer", "device.mfr", "Device manufacturer"),
+    ("product", "device.model", "Device model"),
+    ("serial", "device.serial", "Device serial number"),
+];
+
+/// Units of the SI linear system, in which voltages and powers are expressed in multiples of
+/// 10^-7 of their usual units, since its base units are centimeters and grams.
+const UNIT_VOLT: u32 = 0x00F0_D121;
+const UNIT_WATT: u32 = 0x0000_D121;
+const UNIT_KELVIN: u32 = 0x0001_0001;
+
+/// Kinds of reports a value can be read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum ReportKind {
+    Input,
+    Feature,
+}
+
+/// Size in bytes of each report of a device, not counting the report ID.
+type ReportSizes = HashMap<(ReportKind, u8), usize>;
+
+/// A value in a report of the device, as described by its report descriptor.
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    kind: ReportKind,
+    report_id: u8,
+    /// Offset of the value in the report in bits, not counting the report ID.
+    offset: usize,
+    size: usize,
+    usage: u32,
+    /// Innermost collection of [`SCOPES`] the value is in.
+    scope: Option<u32>,
+    logical_min: i64,
+    logical_max: i64,
+    physical_min: i64,
+    physical_max: i64,
+    unit_exponent: i32,
+    unit: u32,
+}
+
+impl Field {
+    /// Reads the value from a report, converted to its usual unit.
+    fn read(&self, report: &[u8]) -> Option<f64> {
+        if self.size == 0 || self.size > 32 || (self.offset + self.size).div_ceil(8) > report.len() {
+            return None;
+        }
+        let mut raw: i64 = 0;
+        for bit in 0..self.size {
+            let position = self.offset + bit;
+            if report[position / 8] >> (position % 8) & 1 == 1 {
+                raw |= 1 << bit;
+            }
+        }
+        if self.logical_min < 0 && raw >> (self.size - 1) & 1 == 1 {
+            raw -= 1 << self.size;
+        }
+
+        let mut value = raw as f64;
+        if (self.physical_min, self.physical_max) != (0, 0) && self.logical_max != self.logical_min {
+            let scale = (self.physical_max - self.physical_min) as f64 / (self.logical_max - self.logical_min) as f64;
+            value = (raw - self.logical_min) as f64 * scale + self.physical_min as f64;
+        }
+        value *= 10f64.powi(self.unit_exponent);
+        Some(match self.unit {
+            UNIT_VOLT | UNIT_WATT => value * 1e-7,
+            UNIT_KELVIN => value - 273.15,
+            _ => value,
+        })
+    }
+}
+
+/// The global items of a report descriptor, which apply to every following main item.
+#[derive(Debug, Clone, Copy, Default)]
+struct Globals {
+    usage_page: u32,
+    logical_min: i64,
+    logical_max: i64,
+    physical_min: i64,
+    physical_max: i64,
+    unit_exponent: i32,
+    unit: u32,
+    report_size: usize,
+    report_id: u8,
+    report_count: usize,
+}
+
+/// Parses a report descriptor into the values of every report, along with the size in bytes of
+/// each report, not counting the report ID.
+fn parse_descriptor(descriptor: &[u8]) -> Result<(Vec<Field>, ReportSizes)> {
+    let truncated = || Error::Parse(String::from("truncated HID report descriptor"));
+    let mut fields = Vec::new();
+    let mut globals = Globals::default();
+    let mut stack = Vec::new();
+    let mut usages: Vec<u32> = Vec::new();
+    let mut usage_range: (Option<u32>, Option<u32>) = (None, None);
+    let mut collections: Vec<u32> = Vec::new();
+    let mut offsets: HashMap<(ReportKind, u8), usize> = HashMap::new();
+    let mut index = 0;
+    while index < descriptor.len() {
+        let prefix = descriptor[index];
+        // Long items are reserved, and never used by power devices
+        if prefix == 0xFE {
+            index += 3 + usize::from(*descriptor.get(index + 1).ok_or_else(truncated)?);
+            continue;
+        }
+        let size = match prefix & 0x03 {
+            3 => 4,
+            size => usize::from(size),
+        };
+        let data = descriptor.get(index + 1..index + 1 + size).ok_or_else(truncated)?;
+        index += 1 + size;
+        let unsigned = data.iter().rev().fold(0u32, |value, byte| value << 8 | u32::from(*byte));
+        let signed = match size {
+            0 => 0,
+            4 => i64::from(unsigned as i32),
+            _ => {
+                let shift = 64 - 8 * size;
+                (i64::from(unsigned) << shift) >> shift
+            }
+        };
+        let full_usage = if size == 4 { unsigned } else { globals.usage_page << 16 | unsigned };
+
+        match (prefix >> 2 & 0x03, prefix >> 4) {
+            // Input and Feature
+            (0, tag @ (0x8 | 0xB)) => {
+                let kind = if tag == 0x8 { ReportKind::Input } else { ReportKind::Feature };
+                let constant = unsigned & 0x01 != 0;
+                let variable = unsigned & 0x02 != 0;
+                let offset = offsets.entry((kind, globals.report_id)).or_default();
+                for count in 0..globals.report_count {
+                    let usage = match (usages.get(count).or(usages.last()), usage_range) {
+                        (Some(usage), _) => *usage,
+                        (None, (Some(min), Some(max))) => min.saturating_add(u32::try_from(count).unwrap_or(u32::MAX)).min(max),
+                        _ => 0,
+                    };
+                    if !constant && variable && usage != 0 {
+                        fields.push(Field {
+                            kind,
+                            report_id: globals.report_id,
+                            offset: *offset,
+                            size: globals.report_size,
+                            usage,
+                            scope: collections.iter().rev().find(|usage| SCOPES.contains(usage)).copied(),
+                            logical_min: globals.logical_min,
+                            logical_max: globals.logical_max,
+                            physical_min: globals.physical_min,
+                            physical_max: globals.physical_max,
+                            unit_exponent: globals.unit_exponent,
+                            unit: globals.unit,
+                        });
+                    }
+                    *offset += globals.report_size;
+                }
+            }
+            // Collection and End Collection
+            (0, 0xA) => collections.push(usages.first().copied().unwrap_or_default()),
+            (0, 0xC) => {
+                collections.pop();
+            }
+            (1, 0x0) => globals.usage_page = unsigned,
+            (1, 0x1) => globals.logical_min = signed,
+            // Many devices encode an unsigned maximum that only looks negative
+            (1, 0x2) => globals.logical_max = if signed < globals.logical_min { i64::from(unsigned) } else { signed },
+            (1, 0x3) => globals.physical_min = signed,
+            (1, 0x4) => globals.physical_max = if signed < globals.physical_min { i64::from(unsigned) } else { signed },
+            // The exponent is usually a signed nibble rather than a signed byte
+            (1, 0x5) => globals.unit_exponent = if unsigned <= 0x0F { (unsigned as i32 + 8) % 16 - 8 } else { signed as i32 },
+            (1, 0x6) => globals.unit = unsigned,
+            (1, 0x7) => globals.report_size = unsigned as usize,
+            (1, 0x8) => globals.report_id = unsigned as u8,
+            (1, 0x9) => globals.report_count = unsigned as usize,
+            (1, 0xA) => stack.push(globals),
+            (1, 0xB) => globals = stack.pop().unwrap_or_default(),
+            (2, 0x0) => usages.push(full_usage),
+            (2, 0x1) => usage_range.0 = Some(full_usage),
+            (2, 0x2) => usage_range.1 = Some(full_usage),
+            _ => {}
+        }
+        // Local items only apply to the next main item
+        if prefix >> 2 & 0x03 == 0 {
+            usages.clear();
+            usage_range = (None, None);
+        }
+    }
+    let sizes = offsets.into_iter().map(|(report, bits)| (report, bits.div_ceil(8))).collect();
+    Ok((fields, sizes))
+}
+
+/// Translates the values of every report into NUT variables.
+fn decode(fields: &[Field], reports: &HashMap<(ReportKind, u8), Vec<u8>>) -> Vec<(String, String)> {
+    let values: Vec<(&Field, f64)> = fields
+        .iter()
+        .filter_map(|field| Some((field, field.read(reports.get(&(field.kind, field.report_id))?)?)))
+        .collect();
+    let find = |usage: u32, scopes: &[u32]| {
+        values
+            .iter()
+            .find(|(field, _)| field.usage == usage && (scopes.is_empty() || field.scope.is_some_and(|scope| scopes.contains(&scope))))
+            .map(|(_, value)| *value)
+    };
+
+    let mut vars = Vec::new();
+    for mapping in MAPPINGS {
+        if let Some(value) = find(mapping.usage, mapping.scopes) {
+            vars.push((mapping.name.to_string(), ((value * 100.0).round() / 100.0).to_string()));
+        }
+    }
+    let mut status: Vec<&str> = Vec::new();
+    for (usage, flag) in STATUS_FLAGS {
+        match find(*usage, &[]) {
+            Some(value) if value != 0.0 && !status.contains(flag) => status.push(flag),
+            Some(_) if *usage == usage::AC_PRESENT => status.push("OB"),
+            _ => {}
+        }
+    }
+    if !status.is_empty() {
+        vars.push((String::from("ups.status"), status.join(" ")));
+    }
+    vars
+}
+
+/// Returns the name of the `ioctl` request that reads a report of the given kind and length from
+/// a hidraw device.
+fn report_request(kind: ReportKind, len: usize) -> libc::c_ulong {
+    let number = match kind {
+        ReportKind::Input => 0x0A,
+        ReportKind::Feature => 0x07,
+    };
+    // _IOC(_IOC_READ | _IOC_WRITE, 'H', number, len)
+    (3 << 30) | (len as libc::c_ulong) << 16 | (libc::c_ulong::from(b'H') << 8) | number
+}
+
+/// A client that reads a UPS from its hidraw device, instead of from a NUT server.
+#[derive(Debug)]
+pub struct HidClient {
+    path: PathBuf,
+    file: File,
+    fields: Vec<Field>,
+    reports: ReportSizes,
+    device: Vec<(String, String)>,
+}
+
+impl HidClient {
+    /// Opens the hidraw device at the given path, or the first device that is a UPS if none is
+    /// given.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the device cannot be opened or its report descriptor cannot
+    /// be read, or if no path is given and no UPS is found.
+    pub fn open(path: Option<&Path>) -> Result<HidClient> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => find_ups()?,
+        };
+        let name = path.file_name().map(PathBuf::from).unwrap_or_default();
+        let sysfs = Path::new(HIDRAW_CLASS).join(name).join("device");
+        let descriptor = fs::read(sysfs.join("report_descriptor")).map_err(|source| Error::Io {
+            context: format!("could not read the report descriptor of {}", path.display()),
+            source,
+        })?;
+        let (fields, reports) = parse_descriptor(&descriptor)?;
+        if !is_power_device(&fields) {
+            return Err(Error::Config(format!("{} is not a USB HID power device", path.display())));
+        }
+        let file = File::options().read(true).write(true).open(&path).map_err(|source| Error::Io {
+            context: format!("could not open {}", path.display()),
+            source,
+        })?;
+        // The USB device is the parent of the interface, which is the parent of the HID device
+        let device = DEVICE_VARIABLES
+            .iter()
+            .filter_map(|(file, name, _)| {
+                let value = fs::read_to_string(sysfs.join("../..").join(file)).ok()?;
+                Some((name.to_string(), value.trim().to_string()))
+            })
+            .collect();
+        debug!("Found {} values in {} reports of {}", fields.len(), reports.len(), path.display());
+        Ok(HidClient {
+            path,
+            file,
+            fields,
+            reports,
+            device,
+        })
+    }
+
+    /// Returns the path of the hidraw device.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads a report from the device, without its report ID.
+    fn read_report(&self, kind: ReportKind, report_id: u8, len: usize) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0; len + 1];
+        buffer[0] = report_id;
+        // SAFETY: the request tells the kernel the length of the buffer, which outlives the call
+        let read = unsafe { libc::ioctl(self.file.as_raw_fd(), report_request(kind, buffer.len()) as _, buffer.as_mut_ptr()) };
+        let read = usize::try_from(read).map_err(|_| io::Error::last_os_error())?;
+        // The report ID is only returned for devices that number their reports
+        let start = usize::from(report_id != 0);
+        buffer.truncate(read.min(buffer.len()));
+        Ok(buffer.split_off(start.min(buffer.len())))
+    }
+}
+
+/// Returns true if any value of a device comes from the Power Device or Battery System pages.
+fn is_power_device(fields: &[Field]) -> bool {
+    fields.iter().any(|field| matches!(field.usage >> 16, 0x84 | 0x85))
+}
+
+/// Finds the first hidraw device that is a UPS.
+fn find_ups() -> Result<PathBuf> {
+    let mut names: Vec<_> = fs::read_dir(HIDRAW_CLASS)
+        .map_err(|source| Error::Io {
+            context: format!("could not list hidraw devices in {HIDRAW_CLASS}"),
+            source,
+        })?
+        .filter_map(|entry| entry.ok().map(|entry| entry.file_name()))
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .find(|name| {
+            let descriptor = Path::new(HIDRAW_CLASS).join(name).join("device/report_descriptor");
+            fs::read(descriptor)
+                .ok()
+                .and_then(|descriptor| parse_descriptor(&descriptor).ok())
+                .is_some_and(|(fields, _)| is_power_device(&fields))
+        })
+        .map(|name| Path::new("/dev").join(name))
+        .ok_or_else(|| Error::Config(String::from("no USB HID power device was found")))
+}
+
+impl UpsClient for HidClient {
+    fn list_vars(&mut self, _ups_name: &str) -> Result<Vec<Variable>> {
+        let mut reports = HashMap::new();
+        let mut last_error = None;
+        for (&(kind, report_id), &len) in &self.reports {
+            match self.read_report(kind, report_id, len) {
+                Ok(report) => {
+                    reports.insert((kind, report_id), report);
+                }
+                Err(err) => {
+                    debug!("Failed to read {kind:?} report {report_id} from {}: {err}", self.path.display());
+                    last_error = Some(err);
+                }
+            }
+        }
+        // Some reports may legitimately fail, but none being read means the UPS is gone
+        if let (true, Some(err)) = (reports.is_empty(), last_error) {
+            return Err(Error::Connection(err));
+        }
+        let vars = decode(&self.fields, &reports).into_iter().chain(self.device.iter().cloned());
+        Ok(vars.map(|(name, value)| Variable::new(&name, value)).collect())
+    }
+
+    fn get_var_description(&mut self, _ups_name: &str, var_name: &str) -> Result<String> {
+        let description = MAPPINGS
+            .iter()
+            .map(|mapping| (mapping.name, mapping.description))
+            .chain(DEVICE_VARIABLES.iter().map(|(_, name, description)| (*name, *description)))
+            .chain([("ups.status", "UPS status")])
+            .find(|(name, _)| *name == var_name)
+            .map_or("Description unavailable", |(_, description)| description);
+        Ok(description.to_string())
+    }
+
+    fn get_var_type(&mut self, _ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        let kind = if MAPPINGS.iter().any(|mapping| mapping.name == var_name) { "NUMBER" } else { "STRING:64" };
+        VariableDefinition::parse(var_name, &[kind])
+    }
+
+    fn list_var_enum(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_var_range(&mut self, _ups_name: &str, _var_name: &str) -> Result<Vec<VariableRange>> {
+        Ok(Vec::new())
+    }
+
+    fn list_clients(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn list_commands(&mut self, _ups_name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn get_command_description(&mut self, _ups_name: &str, _command: &str) -> Result<String> {
+        Err(Error::Protocol(rups::NutError::CmdNotSupported))
+    }
+
+    fn close(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A trimmed down descriptor in the style of an APC Back-UPS, with a feature report holding
+    /// the charge and runtime, an input report holding the status, and an input voltage scaled
+    /// by a unit exponent.
+    const DESCRIPTOR: &[u8] = &[
+        0x05, 0x84, // Usage Page (Power Device)
+        0x09, 0x04, // Usage (UPS)
+        0xA1, 0x01, // Collection (Application)
+        0x09, 0x24, //   Usage (Power Summary)
+        0xA1, 0x02, //   Collection (Logical)
+        0x85, 0x01, //     Report ID (1)
+        0x05, 0x85, //     Usage Page (Battery System)
+        0x09, 0x66, //     Usage (Remaining Capacity)
+        0x09, 0x68, //     Usage (Run Time To Empty)
+        0x15, 0x00, //     Logical Minimum (0)
+        0x26, 0xFF, 0xFF, //     Logical Maximum (65535)
+        0x75, 0x10, //     Report Size (16)
+        0x95, 0x02, //     Report Count (2)
+        0xB1, 0x02, //     Feature (Data, Variable)
+        0x85, 0x02, //     Report ID (2)
+        0x09, 0xD0, //     Usage (AC Present)
+        0x09, 0x44, //     Usage (Charging)
+        0x09, 0x45, //     Usage (Discharging)
+        0x25, 0x01, //     Logical Maximum (1)
+        0x75, 0x01, //     Report Size (1)
+        0x95, 0x03, //     Report Count (3)
+        0x81, 0x02, //     Input (Data, Variable)
+        0x95, 0x05, //     Report Count (5)
+        0x81, 0x01, //     Input (Constant)
+        0xC0, //   End Collection
+        0x05, 0x84, //   Usage Page (Power Device)
+        0x09, 0x1A, //   Usage (Input)
+        0xA1, 0x02, //   Collection (Logical)
+        0x85, 0x03, //     Report ID (3)
+        0x09, 0x30, //     Usage (Voltage)
+        0x26, 0xFF, 0x7F, //     Logical Maximum (32767)
+        0x67, 0x21, 0xD1, 0xF0, 0x00, //     Unit (Volt)
+        0x55, 0x05, //     Unit Exponent (5)
+        0x75, 0x10, //     Report Size (16)
+        0x95, 0x01, //     Report Count (1)
+        0xB1, 0x02, //     Feature (Data, Variable)
+        0xC0, //   End Collection
+        0xC0, // End Collection
+    ];
+
+    #[test]
+    fn parse_report_descriptor() {
+        let (fields, sizes) = parse_descriptor(DESCRIPTOR).unwrap();
+        assert!(is_power_device(&fields));
+        assert_eq!(fields.len(), 6);
+        assert_eq!(sizes[&(ReportKind::Feature, 1)], 4);
+        assert_eq!(sizes[&(ReportKind::Input, 2)], 1);
+        assert_eq!(sizes[&(ReportKind::Feature, 3)], 2);
+        let runtime = &fields[1];
+        assert_eq!(runtime.usage, usage::RUN_TIME_TO_EMPTY);
+        assert_eq!((runtime.offset, runtime.size, runtime.logical_max), (16, 16, 65535));
+        assert_eq!(runtime.scope, Some(usage::POWER_SUMMARY));
+        let voltage = &fields[5];
+        assert_eq!((voltage.scope, voltage.unit, voltage.unit_exponent), (Some(usage::INPUT), UNIT_VOLT, 5));
+        assert!(parse_descriptor(&[0x05]).is_err());
+    }
+
+    #[test]
+    fn decode_reports() {
+        let (fields, _) = parse_descriptor(DESCRIPTOR).unwrap();
+        let mut reports = HashMap::new();
+        reports.insert((ReportKind::Feature, 1), vec![87, 0, 0xB0, 0x04]);
+        reports.insert((ReportKind::Input, 2), vec![0b101]);
+        reports.insert((ReportKind::Feature, 3), vec![0xD8, 0x59]);
+        let vars: HashMap<_, _> = decode(&fields, &reports).into_iter().collect();
+        assert_eq!(vars["battery.charge"], "87");
+        assert_eq!(vars["battery.runtime"], "1200");
+        assert_eq!(vars["input.voltage"], "230");
+        assert_eq!(vars["ups.status"], "OL DISCHRG");
+
+        // Without AC power, the UPS is on battery
+        reports.insert((ReportKind::Input, 2), vec![0b100]);
+        reports.remove(&(ReportKind::Feature, 3));
+        let vars: HashMap<_, _> = decode(&fields, &reports).into_iter().collect();
+        assert_eq!(vars["ups.status"], "OB DISCHRG");
+        assert!(!vars.contains_key("input.voltage"));
+    }
+}
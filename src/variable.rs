@@ -0,0 +1,227 @@
+//! The variables of a UPS and their definitions, as owned types of pistachio, so the NUT client
+//! used to read them stays an implementation detail of the library.
+
+use crate::{Error, Result};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A variable of a UPS along with its current value, such as `battery.charge` at `100`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Variable {
+    name: String,
+    value: String,
+}
+
+impl Variable {
+    /// Creates a variable with the given name and value.
+    #[must_use]
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Variable {
+        Variable {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    /// Returns the name of the variable, such as `battery.charge`.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the value of the variable as reported by the UPS.
+    #[must_use]
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.value)
+    }
+}
+
+impl From<rups::Variable> for Variable {
+    fn from(var: rups::Variable) -> Variable {
+        Variable::new(var.name(), var.value())
+    }
+}
+
+/// Removes the variables reported more than once, which some drivers do with different values,
+/// keeping the value reported last like a later update of the variable. The variables are
+/// returned sorted by name, along with the names of those reported with different values.
+pub(crate) fn dedup(vars: Vec<Variable>) -> (Vec<Variable>, Vec<String>) {
+    let mut values: BTreeMap<String, String> = BTreeMap::new();
+    let mut conflicting = Vec::new();
+    for Variable { name, value } in vars {
+        match values.entry(name) {
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+            Entry::Occupied(mut entry) => {
+                if *entry.get() != value && !conflicting.contains(entry.key()) {
+                    conflicting.push(entry.key().clone());
+                }
+                entry.insert(value);
+            }
+        }
+    }
+    let vars = values.into_iter().map(|(name, value)| Variable { name, value }).collect();
+    (vars, conflicting)
+}
+
+/// The type of a variable of a UPS, such as whether it is a number, a string, or one of an
+/// enumeration of values, and whether it is writable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VariableDefinition {
+    name: String,
+    mutable: bool,
+    is_enum: bool,
+    is_range: bool,
+    is_number: bool,
+    string_length: Option<usize>,
+}
+
+impl VariableDefinition {
+    /// Creates the definition of a variable from the types NUT reports for it, such as `RW`,
+    /// `ENUM`, `RANGE`, `NUMBER`, and `STRING:64`.
+    ///
+    /// # Errors
+    ///
+    /// An [`Error::Protocol`] will be returned if one of the types is not recognized.
+    pub fn parse(name: &str, types: &[&str]) -> Result<VariableDefinition> {
+        let mut definition = VariableDefinition {
+            name: name.to_string(),
+            ..VariableDefinition::default()
+        };
+        for kind in types {
+            match *kind {
+                "RW" => definition.mutable = true,
+                "ENUM" => definition.is_enum = true,
+                "RANGE" => definition.is_range = true,
+                "NUMBER" => definition.is_number = true,
+                other => {
+                    let length = other.strip_prefix("STRING:").and_then(|length| length.parse().ok());
+                    let Some(length) = length else {
+                        return Err(Error::Protocol(rups::NutError::Generic(format!("Unrecognized variable type: {other}"))));
+                    };
+                    definition.string_length = Some(length);
+                }
+            }
+        }
+        Ok(definition)
+    }
+
+    /// Returns the name of the variable.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns true if the variable can be written.
+    #[must_use]
+    pub fn is_mutable(&self) -> bool {
+        self.mutable
+    }
+
+    /// Returns true if the variable takes one of an enumeration of values.
+    #[must_use]
+    pub fn is_enum(&self) -> bool {
+        self.is_enum
+    }
+
+    /// Returns true if the variable is a string.
+    #[must_use]
+    pub fn is_string(&self) -> bool {
+        self.string_length.is_some()
+    }
+
+    /// Returns true if the variable is a number within one or more ranges.
+    #[must_use]
+    pub fn is_range(&self) -> bool {
+        self.is_range
+    }
+
+    /// Returns true if the variable is a number.
+    #[must_use]
+    pub fn is_number(&self) -> bool {
+        self.is_number
+    }
+
+    /// Returns the maximum length of the variable, if it is a string.
+    #[must_use]
+    pub fn get_string_length(&self) -> Option<usize> {
+        self.string_length
+    }
+}
+
+impl From<rups::VariableDefinition> for VariableDefinition {
+    fn from(definition: rups::VariableDefinition) -> VariableDefinition {
+        VariableDefinition {
+            name: definition.name().to_string(),
+            mutable: definition.is_mutable(),
+            is_enum: definition.is_enum(),
+            is_range: definition.is_range(),
+            is_number: definition.is_number(),
+            string_length: definition.get_string_length(),
+        }
+    }
+}
+
+/// A range of values allowed for a numeric variable of a UPS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariableRange {
+    /// The lowest value allowed.
+    pub min: String,
+    /// The highest value allowed.
+    pub max: String,
+}
+
+impl From<rups::VariableRange> for VariableRange {
+    fn from(range: rups::VariableRange) -> VariableRange {
+        VariableRange { min: range.0, max: range.1 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_from_rups() {
+        let var = Variable::from(rups::Variable::parse("battery.charge", String::from("87")));
+        assert_eq!(var, Variable::new("battery.charge", "87"));
+        assert_eq!(var.to_string(), "battery.charge: 87");
+
+        let definition = rups::VariableDefinition::try_from(("ups.id", vec!["RW", "STRING:32"])).unwrap();
+        assert_eq!(VariableDefinition::from(definition), VariableDefinition::parse("ups.id", &["RW", "STRING:32"]).unwrap());
+        let range = VariableRange::from(rups::VariableRange(String::from("80"), String::from("100")));
+        assert_eq!((range.min.as_str(), range.max.as_str()), ("80", "100"));
+    }
+
+    #[test]
+    fn dedup_variables() {
+        let vars = vec![
+            Variable::new("ups.status", "OL"),
+            Variable::new("battery.charge", "100"),
+            Variable::new("ups.status", "OB"),
+            Variable::new("battery.charge", "100"),
+        ];
+        let (vars, conflicting) = dedup(vars);
+        assert_eq!(vars, [Variable::new("battery.charge", "100"), Variable::new("ups.status", "OB")]);
+        assert_eq!(conflicting, ["ups.status"]);
+    }
+
+    #[test]
+    fn parse_definitions() {
+        let definition = VariableDefinition::parse("input.transfer.low", &["RW", "RANGE"]).unwrap();
+        assert!(definition.is_mutable() && definition.is_range());
+        assert!(!definition.is_string() && !definition.is_enum());
+        assert_eq!(definition.get_string_length(), None);
+        assert!(matches!(
+            VariableDefinition::parse("ups.id", &["STRING:long"]),
+            Err(Error::Protocol(rups::NutError::Generic(_)))
+        ));
+    }
+}
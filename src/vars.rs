@@ -0,0 +1,185 @@
+//! Typed accessors for common UPS variables.
+//!
+//! NUT drivers do not all report values the same way. Some append units such as `230 V` or
+//! `100 %`, some use a decimal comma, and some report negative placeholders when a value is
+//! unknown. The accessors here smooth over those differences so automation built on top of a
+//! [`UpsSnapshot`] does not have to.
+
+use crate::snapshot::UpsSnapshot;
+use std::fmt;
+use std::time::Duration;
+
+/// A percentage between 0 and 100.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Percent(pub f64);
+
+/// An electric potential in volts.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Volts(pub f64);
+
+/// A power in watts.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Watts(pub f64);
+
+/// A frequency in hertz.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Hertz(pub f64);
+
+/// A temperature in degrees Celsius.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Celsius(pub f64);
+
+impl fmt::Display for Percent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+impl fmt::Display for Volts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} V", self.0)
+    }
+}
+
+impl fmt::Display for Watts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} W", self.0)
+    }
+}
+
+impl fmt::Display for Hertz {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz", self.0)
+    }
+}
+
+impl fmt::Display for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} °C", self.0)
+    }
+}
+
+impl UpsSnapshot {
+    /// Returns a variable parsed as a number, tolerating unit suffixes and decimal commas.
+    /// Negative values are treated as unknown, since no variable read here can be negative.
+    fn measurement(&self, name: &str) -> Option<f64> {
+        self.get(name).and_then(parse_measurement).filter(|value| *value >= 0.0)
+    }
+
+    /// Returns the battery charge, from `battery.charge`.
+    #[must_use]
+    pub fn battery_charge(&self) -> Option<Percent> {
+        self.measurement("battery.charge").map(|value| Percent(value.min(100.0)))
+    }
+
+    /// Returns the estimated runtime on battery, from `battery.runtime`.
+    #[must_use]
+    pub fn battery_runtime(&self) -> Option<Duration> {
+        self.measurement("battery.runtime").map(Duration::from_secs_f64)
+    }
+
+    /// Returns the battery voltage, from `battery.voltage`.
+    #[must_use]
+    pub fn battery_voltage(&self) -> Option<Volts> {
+        self.measurement("battery.voltage").map(Volts)
+    }
+
+    /// Returns the input voltage, from `input.voltage`.
+    #[must_use]
+    pub fn input_voltage(&self) -> Option<Volts> {
+        self.measurement("input.voltage").map(Volts)
+    }
+
+    /// Returns the input frequency, from `input.frequency`.
+    #[must_use]
+    pub fn input_frequency(&self) -> Option<Hertz> {
+        self.measurement("input.frequency").map(Hertz)
+    }
+
+    /// Returns the output voltage, from `output.voltage`.
+    #[must_use]
+    pub fn output_voltage(&self) -> Option<Volts> {
+        self.measurement("output.voltage").map(Volts)
+    }
+
+    /// Returns the load on the UPS as a percentage of its capacity, from `ups.load`.
+    #[must_use]
+    pub fn load(&self) -> Option<Percent> {
+        self.measurement("ups.load").map(Percent)
+    }
+
+    /// Returns the real power delivered to the load, from `ups.realpower` or, for drivers that do
+    /// not report it, estimated from `ups.load` and `ups.realpower.nominal`.
+    #[must_use]
+    pub fn real_power(&self) -> Option<Watts> {
+        self.measurement("ups.realpower")
+            .or_else(|| Some(self.measurement("ups.load")? * self.measurement("ups.realpower.nominal")? / 100.0))
+            .map(Watts)
+    }
+
+    /// Returns the temperature of the UPS, from `ups.temperature`, falling back to
+    /// `battery.temperature` for drivers that only report that.
+    #[must_use]
+    pub fn temperature(&self) -> Option<Celsius> {
+        self.get("ups.temperature")
+            .or_else(|| self.get("battery.temperature"))
+            .and_then(parse_measurement)
+            .map(Celsius)
+    }
+}
+
+/// Parses the leading number of a value such as `230`, `13,6` or `100.0 %`.
+fn parse_measurement(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let end = value
+        .char_indices()
+        .find(|(i, c)| !(c.is_ascii_digit() || matches!(c, '.' | ',') || (*i == 0 && matches!(c, '-' | '+'))))
+        .map_or(value.len(), |(i, _)| i);
+    value[..end].replace(',', ".").parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Variable;
+    use std::time::UNIX_EPOCH;
+
+    fn snapshot(vars: &[(&str, &str)]) -> UpsSnapshot {
+        let vars: Vec<Variable> = vars
+            .iter()
+            .map(|(name, value)| Variable::new(*name, value.to_string()))
+            .collect();
+        UpsSnapshot::from_vars("ups", &vars, UNIX_EPOCH)
+    }
+
+    #[test]
+    fn parse_measurements() {
+        assert_eq!(parse_measurement("230"), Some(230.0));
+        assert_eq!(parse_measurement(" 13,6 "), Some(13.6));
+        assert_eq!(parse_measurement("100.0 %"), Some(100.0));
+        assert_eq!(parse_measurement("230V"), Some(230.0));
+        assert_eq!(parse_measurement("-1"), Some(-1.0));
+        assert_eq!(parse_measurement("unknown"), None);
+    }
+
+    #[test]
+    fn read_typed_vars() {
+        let snapshot = snapshot(&[
+            ("battery.charge", "101"),
+            ("battery.runtime", "1800"),
+            ("input.voltage", "229,5 V"),
+            ("output.voltage", "-1"),
+            ("ups.load", "25"),
+            ("ups.realpower.nominal", "800"),
+            ("battery.temperature", "30.5"),
+        ]);
+        assert_eq!(snapshot.battery_charge(), Some(Percent(100.0)));
+        assert_eq!(snapshot.battery_runtime(), Some(Duration::from_secs(1800)));
+        assert_eq!(snapshot.input_voltage(), Some(Volts(229.5)));
+        assert_eq!(snapshot.output_voltage(), None);
+        assert_eq!(snapshot.battery_voltage(), None);
+        assert_eq!(snapshot.real_power(), Some(Watts(200.0)));
+        assert_eq!(snapshot.temperature(), Some(Celsius(30.5)));
+        assert_eq!(snapshot.load().unwrap().to_string(), "25%");
+    }
+}
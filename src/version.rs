@@ -0,0 +1,50 @@
+//! Metadata of the build, embedded by the build script so the exact build of a deployment can be
+//! told from `--version`, the startup log, and `pistachio_build_info`.
+
+use prometheus::{register_int_gauge_vec_with_registry, Registry, Result};
+
+/// Version of the package.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Abbreviated commit the binary was built from, or `unknown` if it was not built from a git
+/// checkout.
+pub const GIT_COMMIT: &str = env!("PISTACHIO_GIT_COMMIT");
+
+/// UTC date of the build, as `YYYY-MM-DD`.
+pub const BUILD_DATE: &str = env!("PISTACHIO_BUILD_DATE");
+
+/// Cargo features enabled in the build, separated by commas.
+pub const FEATURES: &str = env!("PISTACHIO_FEATURES");
+
+/// Version of `rups` used to talk to NUT servers.
+pub const RUPS_VERSION: &str = env!("PISTACHIO_RUPS_VERSION");
+
+/// Output of `--version`, with every field of the build metadata.
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ncommit: ",
+    env!("PISTACHIO_GIT_COMMIT"),
+    "\nbuild date: ",
+    env!("PISTACHIO_BUILD_DATE"),
+    "\nfeatures: ",
+    env!("PISTACHIO_FEATURES"),
+    "\nrups: ",
+    env!("PISTACHIO_RUPS_VERSION"),
+);
+
+/// Exports the build metadata in `registry` as the labels of `pistachio_build_info`, which is
+/// always 1.
+///
+/// # Errors
+///
+/// An error will be returned if the gauge cannot be registered.
+pub fn register_build_info(registry: &Registry) -> Result<()> {
+    let info = register_int_gauge_vec_with_registry!(
+        "pistachio_build_info",
+        "Build metadata of pistachio, in labels",
+        &["version", "commit", "build_date", "features", "rups_version"],
+        registry
+    )?;
+    info.with_label_values(&[VERSION, GIT_COMMIT, BUILD_DATE, FEATURES, RUPS_VERSION]).set(1);
+    Ok(())
+}
@@ -0,0 +1,238 @@
+//! A watchdog around the requests made to the NUT server, so a connection that hangs without
+//! ever failing, such as when the server disappears without closing it, does not silently stop
+//! the polling loop while the exporter still looks healthy.
+//!
+//! Requests are made by a worker thread that owns the client. If one does not complete in time,
+//! the worker is abandoned along with its connection, `pistachio_poll_stalls_total` is
+//! incremented, and a new client is connected for the next request.
+
+use crate::client::UpsClient;
+use crate::{Error, Result, Variable, VariableDefinition, VariableRange};
+use log::warn;
+use prometheus::{register_int_counter_with_registry, IntCounter, Registry};
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// A request for the worker, made with the client it owns.
+type Call<C> = Box<dyn FnOnce(&mut C) + Send>;
+
+/// A function that creates a new client for the worker.
+type Connector<C> = Box<dyn Fn() -> C + Send>;
+
+/// A thread that makes requests with the client it owns.
+struct Worker<C> {
+    calls: Sender<Call<C>>,
+    /// Receives the result of closing the client once the worker stops.
+    closed: Receiver<Result<()>>,
+}
+
+/// A client that makes every request on a worker thread, and replaces the client with a new one
+/// when a request takes longer than the timeout.
+pub struct Watchdog<C> {
+    connect: Connector<C>,
+    timeout: Duration,
+    worker: Option<Worker<C>>,
+    stalls: IntCounter,
+}
+
+impl<C: UpsClient + Send + 'static> Watchdog<C> {
+    /// Creates a watchdog that makes requests with clients created by `connect`, and replaces the
+    /// client when a request takes longer than `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the counter of stalls cannot be registered with Prometheus.
+    pub fn new(connect: impl Fn() -> C + Send + 'static, timeout: Duration, registry: &Registry) -> Result<Watchdog<C>> {
+        let stalls = register_int_counter_with_registry!(
+            "pistachio_poll_stalls_total",
+            "Number of requests to the UPS that hung and had their connection recreated",
+            registry
+        )?;
+        Ok(Watchdog {
+            connect: Box::new(connect),
+            timeout,
+            worker: None,
+            stalls,
+        })
+    }
+
+    /// Runs a request on the worker, starting one with a new client if there is none, and waits
+    /// for it to complete until the timeout.
+    fn request<T: Send + 'static>(&mut self, f: impl FnOnce(&mut C) -> Result<T> + Send + 'static) -> Result<T> {
+        let worker = self.worker.get_or_insert_with(|| spawn((self.connect)()));
+        let (sender, receiver) = mpsc::channel();
+        let call: Call<C> = Box::new(move |client| {
+            let _ = sender.send(f(client));
+        });
+        if worker.calls.send(call).is_err() {
+            self.worker = None;
+            return Err(Error::Connection(io::Error::other("the worker making requests to the UPS stopped")));
+        }
+        match receiver.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                // The worker is left to finish or fail on its own, and is never sent to again
+                self.worker = None;
+                self.stalls.inc();
+                warn!("A request to the UPS did not complete in {:?}, so its connection will be recreated", self.timeout);
+                Err(Error::Connection(io::Error::new(io::ErrorKind::TimedOut, "the request to the UPS hung")))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                self.worker = None;
+                Err(Error::Connection(io::Error::other("the worker making requests to the UPS stopped")))
+            }
+        }
+    }
+}
+
+/// Starts a worker that makes requests with `client` until its sender is dropped, and then closes
+/// the client.
+fn spawn<C: UpsClient + Send + 'static>(mut client: C) -> Worker<C> {
+    let (calls, receiver) = mpsc::channel::<Call<C>>();
+    let (done, closed) = mpsc::channel();
+    thread::spawn(move || {
+        for call in receiver {
+            call(&mut client);
+        }
+        let _ = done.send(client.close());
+    });
+    Worker { calls, closed }
+}
+
+impl<C: UpsClient + Send + 'static> UpsClient for Watchdog<C> {
+    fn list_vars(&mut self, ups_name: &str) -> Result<Vec<Variable>> {
+        let ups_name = ups_name.to_string();
+        self.request(move |client| client.list_vars(&ups_name))
+    }
+
+    fn get_var(&mut self, ups_name: &str, var_name: &str) -> Result<Variable> {
+        let (ups_name, var_name) = (ups_name.to_string(), var_name.to_string());
+        self.request(move |client| client.get_var(&ups_name, &var_name))
+    }
+
+    fn get_var_description(&mut self, ups_name: &str, var_name: &str) -> Result<String> {
+        let (ups_name, var_name) = (ups_name.to_string(), var_name.to_string());
+        self.request(move |client| client.get_var_description(&ups_name, &var_name))
+    }
+
+    fn get_var_type(&mut self, ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+        let (ups_name, var_name) = (ups_name.to_string(), var_name.to_string());
+        self.request(move |client| client.get_var_type(&ups_name, &var_name))
+    }
+
+    fn list_var_enum(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<String>> {
+        let (ups_name, var_name) = (ups_name.to_string(), var_name.to_string());
+        self.request(move |client| client.list_var_enum(&ups_name, &var_name))
+    }
+
+    fn list_var_range(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<VariableRange>> {
+        let (ups_name, var_name) = (ups_name.to_string(), var_name.to_string());
+        self.request(move |client| client.list_var_range(&ups_name, &var_name))
+    }
+
+    fn list_clients(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        let ups_name = ups_name.to_string();
+        self.request(move |client| client.list_clients(&ups_name))
+    }
+
+    fn list_commands(&mut self, ups_name: &str) -> Result<Vec<String>> {
+        let ups_name = ups_name.to_string();
+        self.request(move |client| client.list_commands(&ups_name))
+    }
+
+    fn get_command_description(&mut self, ups_name: &str, command: &str) -> Result<String> {
+        let (ups_name, command) = (ups_name.to_string(), command.to_string());
+        self.request(move |client| client.get_command_description(&ups_name, &command))
+    }
+
+    /// Stops the worker and waits until the timeout for it to close its client.
+    fn close(self) -> Result<()> {
+        let Some(Worker { calls, closed }) = self.worker else {
+            return Ok(());
+        };
+        drop(calls);
+        closed.recv_timeout(self.timeout).unwrap_or(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockUpsClient;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A client that hangs on every request for variables if told to, or else behaves like the client
+    /// it wraps.
+    struct Hanging(MockUpsClient, bool);
+
+    impl UpsClient for Hanging {
+        fn list_vars(&mut self, ups_name: &str) -> Result<Vec<Variable>> {
+            if self.1 {
+                thread::sleep(Duration::from_secs(60));
+            }
+            self.0.list_vars(ups_name)
+        }
+
+        fn get_var_description(&mut self, ups_name: &str, var_name: &str) -> Result<String> {
+            self.0.get_var_description(ups_name, var_name)
+        }
+
+        fn get_var_type(&mut self, ups_name: &str, var_name: &str) -> Result<VariableDefinition> {
+            self.0.get_var_type(ups_name, var_name)
+        }
+
+        fn list_var_enum(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<String>> {
+            self.0.list_var_enum(ups_name, var_name)
+        }
+
+        fn list_var_range(&mut self, ups_name: &str, var_name: &str) -> Result<Vec<VariableRange>> {
+            self.0.list_var_range(ups_name, var_name)
+        }
+
+        fn list_clients(&mut self, ups_name: &str) -> Result<Vec<String>> {
+            self.0.list_clients(ups_name)
+        }
+
+        fn list_commands(&mut self, ups_name: &str) -> Result<Vec<String>> {
+            self.0.list_commands(ups_name)
+        }
+
+        fn get_command_description(&mut self, ups_name: &str, command: &str) -> Result<String> {
+            self.0.get_command_description(ups_name, command)
+        }
+
+        fn close(self) -> Result<()> {
+            self.0.close()
+        }
+    }
+
+    #[test]
+    fn recreate_hung_connections() {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&connects);
+        let mut watchdog = Watchdog {
+            connect: Box::new(move || {
+                let hang = counted.fetch_add(1, Ordering::SeqCst) == 0;
+                Hanging(MockUpsClient::new().with_var("ups.status", "OL"), hang)
+            }),
+            timeout: Duration::from_millis(100),
+            worker: None,
+            stalls: IntCounter::new("pistachio_poll_stalls_total", "Stalls").unwrap(),
+        };
+
+        let err = watchdog.list_vars("ups").unwrap_err();
+        assert!(matches!(err, Error::Connection(ref err) if err.kind() == io::ErrorKind::TimedOut));
+        assert_eq!(watchdog.stalls.get(), 1);
+
+        // The next request is made on a new connection
+        let vars = watchdog.list_vars("ups").unwrap();
+        assert_eq!(vars[0].value(), "OL");
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(watchdog.stalls.get(), 1);
+        watchdog.list_vars("ups").unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+}
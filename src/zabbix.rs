@@ -0,0 +1,173 @@
+//! Sending of UPS variables to a Zabbix server or proxy with the trapper protocol, the same one
+//! used by `zabbix_sender`.
+
+use crate::sink::Sink;
+use crate::Variable;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Maximum time allowed for connecting to and exchanging data with the Zabbix server.
+const ZABBIX_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default port of the Zabbix trapper.
+const DEFAULT_ZABBIX_PORT: u16 = 10051;
+
+/// Header that starts every message of the Zabbix protocol.
+const ZABBIX_HEADER: &[u8] = b"ZBXD\x01";
+
+/// Largest response accepted from the Zabbix server.
+const MAX_RESPONSE_BYTES: u64 = 64 * 1024;
+
+/// A sink that sends every polled variable as an item value to a Zabbix server.
+#[derive(Debug, Clone)]
+pub struct Zabbix {
+    address: String,
+    host: String,
+    key_template: String,
+    keys: HashMap<String, String>,
+}
+
+impl Zabbix {
+    /// Creates a sender for the Zabbix server at `server` (with an optional port), which will
+    /// report values for the given Zabbix host. Item keys are generated from `key_template` by
+    /// replacing `{var}` with the variable name, unless overridden in `keys`.
+    #[must_use]
+    pub fn new(server: &str, host: &str, key_template: &str, keys: HashMap<String, String>) -> Zabbix {
+        let address = if server.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+            server.to_string()
+        } else {
+            format!("{server}:{DEFAULT_ZABBIX_PORT}")
+        };
+        Zabbix {
+            address,
+            host: host.to_string(),
+            key_template: key_template.to_string(),
+            keys,
+        }
+    }
+
+    /// Returns the Zabbix item key for a variable.
+    fn key(&self, var_name: &str) -> String {
+        self.keys
+            .get(var_name)
+            .cloned()
+            .unwrap_or_else(|| self.key_template.replace("{var}", var_name))
+    }
+
+    /// Builds the `sender data` request for the variables of a poll at the given UNIX time.
+    fn request(&self, vars: &[Variable], clock: u64) -> Value {
+        let data: Vec<Value> = vars
+            .iter()
+            .map(|var| {
+                json!({
+                    "host": self.host,
+                    "key": self.key(var.name()),
+                    "value": var.value(),
+                    "clock": clock,
+                })
+            })
+            .collect();
+        json!({"request": "sender data", "data": data, "clock": clock})
+    }
+
+    /// Sends a request to the server and returns its response.
+    fn send(&self, request: &Value) -> io::Result<Value> {
+        let addr = self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Zabbix server did not resolve"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, ZABBIX_TIMEOUT)?;
+        stream.set_read_timeout(Some(ZABBIX_TIMEOUT))?;
+        stream.set_write_timeout(Some(ZABBIX_TIMEOUT))?;
+        stream.write_all(&encode(request.to_string().as_bytes()))?;
+        let mut response = Vec::new();
+        stream.take(MAX_RESPONSE_BYTES).read_to_end(&mut response)?;
+        decode(&response)
+    }
+}
+
+impl Sink for Zabbix {
+    fn name(&self) -> &str {
+        "zabbix"
+    }
+
+    fn publish(&mut self, vars: &[Variable]) -> Result<(), Box<dyn Error>> {
+        let clock = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let request = self.request(vars, clock);
+        let sender = self.clone();
+
+        // Send in the background so a slow server never delays the next poll
+        thread::spawn(move || match sender.send(&request) {
+            Ok(response) if response["response"] == "success" => {
+                debug!("Sent values to Zabbix: {}", response["info"].as_str().unwrap_or_default());
+            }
+            Ok(response) => warn!("Zabbix rejected values: {response}"),
+            Err(err) => warn!("Failed to send values to Zabbix: {err}"),
+        });
+        Ok(())
+    }
+}
+
+/// Frames a payload with the Zabbix protocol header and length.
+fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(ZABBIX_HEADER.len() + 8 + payload.len());
+    message.extend_from_slice(ZABBIX_HEADER);
+    message.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Parses a framed Zabbix protocol message into JSON.
+fn decode(message: &[u8]) -> io::Result<Value> {
+    let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+    let body = message
+        .strip_prefix(ZABBIX_HEADER)
+        .ok_or_else(|| invalid("missing Zabbix header"))?;
+    let (length, payload) = body.split_at_checked(8).ok_or_else(|| invalid("truncated Zabbix header"))?;
+    let length = u64::from_le_bytes(length.try_into().map_err(|_| invalid("invalid Zabbix length"))?);
+    let payload = usize::try_from(length)
+        .ok()
+        .and_then(|length| payload.get(..length))
+        .ok_or_else(|| invalid("truncated Zabbix response"))?;
+    serde_json::from_slice(payload).map_err(|err| invalid(&err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_request() {
+        let keys = HashMap::from([(String::from("ups.status"), String::from("ups.state"))]);
+        let zabbix = Zabbix::new("zabbix.local", "rack-ups", "nut[{var}]", keys);
+        assert_eq!(zabbix.address, "zabbix.local:10051");
+        let vars = vec![
+            Variable::new("battery.charge", String::from("100")),
+            Variable::new("ups.status", String::from("OL")),
+        ];
+        let request = zabbix.request(&vars, 1000);
+        assert_eq!(request["request"], "sender data");
+        assert_eq!(request["data"][0]["host"], "rack-ups");
+        assert_eq!(request["data"][0]["key"], "nut[battery.charge]");
+        assert_eq!(request["data"][0]["value"], "100");
+        assert_eq!(request["data"][0]["clock"], 1000);
+        assert_eq!(request["data"][1]["key"], "ups.state");
+    }
+
+    #[test]
+    fn encode_and_decode_messages() {
+        let message = encode(br#"{"response":"success"}"#);
+        assert_eq!(&message[..5], ZABBIX_HEADER);
+        assert_eq!(&message[5..13], &22u64.to_le_bytes());
+        assert_eq!(decode(&message).unwrap()["response"], "success");
+        assert!(decode(b"ZBXD\x01\x10\0\0\0\0\0\0\0{}").is_err());
+        assert!(decode(b"HTTP/1.1").is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use pistachio::simulate::{Scenario, Simulator};
+use pistachio::testing::{CollectingSink, MockUpsClient};
+use pistachio::{Config, Event, EventBus, Metrics, Sink};
+use prometheus::Registry;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn poll_scripted_ups() {
+    let config = Config::builder().poll_rate(1).build().unwrap();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let mut client = MockUpsClient::new()
+        .with_var("battery.charge", "100")
+        .with_var("ups.status", "OL")
+        .then_poll()
+        .then_status("OB")
+        .then_fail()
+        .then_status("OL")
+        .stop_when_exhausted(Arc::clone(&shutdown));
+    let ups_vars = HashMap::from([
+        (String::from("battery.charge"), (String::from("100"), String::from("Battery charge"))),
+        (String::from("ups.status"), (String::from("OL"), String::from("UPS status"))),
+    ]);
+    let metrics = Metrics::build(&ups_vars).unwrap();
+    let sink = CollectingSink::new();
+    let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(sink.clone())];
+
+    pistachio::monitor(&config, &mut client, &metrics, &mut sinks, &shutdown);
+
+    assert_eq!(client.polls(), 4);
+    sink.with_collected(|collected| {
+        assert_eq!(collected.polls.len(), 3);
+        assert!(collected.shut_down);
+        assert_eq!(
+            collected.events,
+            vec![
+                Event::StatusChanged {
+                    previous: None,
+                    current: String::from("OL")
+                },
+                Event::StatusChanged {
+                    previous: Some(String::from("OL")),
+                    current: String::from("OB")
+                },
+                Event::ConnectionLost {
+                    error: String::from("connection error: mock connection lost")
+                },
+                Event::ConnectionRestored,
+                Event::StatusChanged {
+                    previous: Some(String::from("OB")),
+                    current: String::from("OL")
+                },
+            ]
+        );
+    });
+}
+
+#[test]
+fn hold_values_during_failure_grace_polls() {
+    let config = Config::builder().poll_rate(1).failure_grace_polls(1).build().unwrap();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let mut client = MockUpsClient::new()
+        .with_var("ups.status", "OL")
+        .then_fail()
+        .then_poll()
+        .then_fail()
+        .then_fail()
+        .stop_when_exhausted(Arc::clone(&shutdown));
+    let ups_vars = HashMap::from([(String::from("ups.status"), (String::from("OL"), String::from("UPS status")))]);
+    let registry = Registry::new();
+    let metrics = Metrics::build_in(&ups_vars, &registry).unwrap();
+    let sink = CollectingSink::new();
+    let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(sink.clone())];
+
+    pistachio::monitor(&config, &mut client, &metrics, &mut sinks, &shutdown);
+
+    sink.with_collected(|collected| {
+        let lost = collected.events.iter().filter(|event| matches!(event, Event::ConnectionLost { .. })).count();
+        assert_eq!(lost, 1);
+        assert!(!collected.events.contains(&Event::ConnectionRestored));
+    });
+}
+
+#[test]
+fn deliver_variable_changes_to_event_bus() {
+    let config = Config::builder().poll_rate(1).build().unwrap();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let mut client = MockUpsClient::new()
+        .with_var("battery.charge", "100")
+        .with_var("ups.status", "OL")
+        .then_poll()
+        .then_set("battery.charge", "90")
+        .stop_when_exhausted(Arc::clone(&shutdown));
+    let ups_vars = HashMap::from([(String::from("battery.charge"), (String::from("100"), String::from("Battery charge")))]);
+    let registry = Registry::new();
+    let metrics = Metrics::build_in(&ups_vars, &registry).unwrap();
+    let (sink, bus) = (CollectingSink::new(), EventBus::new());
+    let received = bus.subscribe();
+    let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(sink.clone()), Box::new(bus)];
+
+    pistachio::monitor(&config, &mut client, &metrics, &mut sinks, &shutdown);
+
+    let changed = Event::VariableChanged {
+        name: String::from("battery.charge"),
+        previous: Some(String::from("100")),
+        current: String::from("90"),
+    };
+    assert!(received.try_iter().any(|event| event == changed));
+    sink.with_collected(|collected| assert!(!collected.events.contains(&changed)));
+}
+
+#[test]
+fn run_twice() {
+    let config = simulated_config();
+    let bind_port = config.bind_port;
+
+    // Metrics and listeners of the first run must not be left behind for the second
+    for _ in 0..2 {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let run = {
+            let (config, shutdown) = (config.clone(), Arc::clone(&shutdown));
+            thread::spawn(move || pistachio::run(&config, &shutdown))
+        };
+        let served = (0..50).any(|_| {
+            thread::sleep(Duration::from_millis(100));
+            scrape(bind_port).is_some_and(|metrics| metrics.contains("ups_battery_charge"))
+        });
+        shutdown.store(true, Ordering::Relaxed);
+        run.join().unwrap().unwrap();
+        assert!(served);
+    }
+    assert!(scrape(bind_port).is_none());
+}
+
+#[test]
+fn subscribe_to_events_of_run() {
+    let config = simulated_config();
+    let (registry, bus) = (Registry::new(), EventBus::new());
+    let received = bus.subscribe();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let run = {
+        let (registry, bus, shutdown) = (registry.clone(), bus.clone(), Arc::clone(&shutdown));
+        thread::spawn(move || pistachio::run_in(&config, &registry, &bus, &shutdown))
+    };
+    let event = received.recv_timeout(Duration::from_secs(5));
+    shutdown.store(true, Ordering::Relaxed);
+    run.join().unwrap().unwrap();
+    assert_eq!(
+        event.unwrap(),
+        Event::StatusChanged {
+            previous: None,
+            current: String::from("OL")
+        }
+    );
+    assert!(registry.gather().iter().any(|family| family.get_name() == "ups_battery_charge"));
+}
+
+/// Returns the configuration of an exporter reading a simulated UPS, and serving on a free port.
+fn simulated_config() -> Config {
+    let simulator = Simulator::new("ups", Scenario::Online, Duration::from_secs(60));
+    let addr = simulator.start(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let bind_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    Config::builder()
+        .ups_name("ups")
+        .ups_host("127.0.0.1")
+        .ups_port(addr.port())
+        .bind_ip("127.0.0.1")
+        .bind_port(bind_port)
+        .poll_rate(1)
+        .build()
+        .unwrap()
+}
+
+/// Returns the metrics served on the port, or `None` if they cannot be read.
+fn scrape(port: u16) -> Option<String> {
+    let mut stream = TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], port))).ok()?;
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nConnection: close\r\n\r\n").ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    Some(response)
+}